@@ -0,0 +1,114 @@
+use crate::bindings::theater::simple::message_server_host::send;
+use crate::bindings::theater::simple::random::generate_uuid;
+use crate::bindings::theater::simple::runtime::log;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// How long the approver has to answer when no timeout is configured.
+const DEFAULT_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/// The actor that approves calls the policy marks as requiring approval.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Approver {
+    /// Actor id the approval requests are sent to.
+    pub actor_id: String,
+    /// How long to wait for an answer before rejecting the call.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+impl Approver {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.actor_id.trim().is_empty() {
+            return Err("'approval.actor_id' must not be empty".to_string());
+        }
+        if self.timeout_ms == Some(0) {
+            return Err("'approval.timeout_ms' must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A call waiting on the approver.
+pub struct Call<'a> {
+    pub tool: &'a str,
+    pub arguments: &'a Value,
+    pub repository_path: &'a str,
+    /// Every command the call would run, in order.
+    pub commands: Vec<Vec<String>>,
+}
+
+/// What the approver sends back, as a message of its own:
+/// `{"type": "approval_response", "id": ..., "approved": ..., "reason": ...}`.
+#[derive(Deserialize, Debug)]
+pub struct Answer {
+    /// Id of the approval request being answered.
+    pub id: String,
+    approved: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl Answer {
+    /// Whether the call may run, or why not.
+    pub fn verdict(self) -> Result<(), String> {
+        match (self.approved, self.reason) {
+            (true, _) => Ok(()),
+            (false, Some(reason)) => Err(format!("The approver rejected the call: {}", reason)),
+            (false, None) => Err("The approver rejected the call".to_string()),
+        }
+    }
+}
+
+/// How long the approver has to answer.
+pub fn timeout_ms(approver: &Approver) -> u64 {
+    approver.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)
+}
+
+/// Ask the approver whether `call` may run, showing it `preview`, or why
+/// there is none, and when the call is rejected for want of an answer. The
+/// approval request is sent without waiting for the answer; returns its
+/// id, which the answer carries.
+pub fn send_request(
+    approver: &Approver,
+    call: &Call,
+    preview: Result<Value, String>,
+    expires_ms: u64,
+) -> Result<String, String> {
+    let id = generate_uuid().map_err(|e| format!("Failed to generate approval id: {}", e))?;
+    let (preview, preview_error) = match preview {
+        Ok(preview) => (preview, None),
+        Err(message) => (Value::Null, Some(message)),
+    };
+    let message = json!({
+        "type": "approval_request",
+        "id": id,
+        "tool": call.tool,
+        "arguments": call.arguments,
+        "repository_path": call.repository_path,
+        "commands": call.commands,
+        "preview": preview,
+        "preview_error": preview_error,
+        "expires_at_ms": expires_ms,
+    });
+    let message = serde_json::to_vec(&message)
+        .map_err(|e| format!("Failed to serialize approval request: {}", e))?;
+    send(&approver.actor_id, &message)
+        .map_err(|e| format!("Approval request to '{}' failed: {}", approver.actor_id, e))?;
+    Ok(id)
+}
+
+/// The answer `message` carries, if it is one.
+pub fn answer(message: &[u8]) -> Option<Answer> {
+    let value: Value = serde_json::from_slice(message).ok()?;
+    if value.get("type").and_then(Value::as_str) != Some("approval_response") {
+        return None;
+    }
+    match serde_json::from_value(value) {
+        Ok(answer) => Some(answer),
+        Err(e) => {
+            log(&format!("Invalid answer from the approver: {}", e));
+            None
+        }
+    }
+}
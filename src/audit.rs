@@ -0,0 +1,181 @@
+use crate::State;
+use crate::bindings::theater::simple::runtime::log;
+use crate::git;
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Store labels of the audit log, followed by the UTC date and the record's
+/// position within that day, e.g. `audit/2024-05-01/000042`. The state
+/// counts each day's records, so writing one touches no other.
+const LABEL_PREFIX: &str = "audit/";
+
+/// Records returned by `git-audit-log` when no `max_count` is given.
+const DEFAULT_MAX_COUNT: usize = 100;
+
+/// How the policy treated a call.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// Permitted without asking anyone.
+    Allowed,
+    /// Permitted by the approver.
+    Approved,
+    /// Refused by the policy, a protected branch or the approver; nothing ran.
+    Refused,
+}
+
+/// One git invocation, or one call refused before anything ran.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    /// JSON-RPC id of the request the invocation was for.
+    pub request_id: Value,
+    /// Session the request came in on. Message-server requests do not say
+    /// which actor sent them.
+    #[serde(default)]
+    pub caller: Option<String>,
+    pub repository_path: String,
+    pub args: Vec<String>,
+    /// Tool the invocation ran for, or the internal step it was, such as
+    /// `snapshot`.
+    pub purpose: String,
+    pub decision: Decision,
+    /// Why the call was refused, or why its child failed without a result.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Milliseconds since the epoch.
+    pub started_ms: u64,
+    #[serde(default)]
+    pub finished_ms: Option<u64>,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// Sizes before output limits were applied.
+    #[serde(default)]
+    pub stdout_bytes: usize,
+    #[serde(default)]
+    pub stderr_bytes: usize,
+}
+
+/// Filters of `git-audit-log`; records must match all that are given.
+#[derive(Deserialize, Debug, Default)]
+pub struct Query {
+    /// First UTC date to include, `YYYY-MM-DD`.
+    #[serde(default)]
+    pub since: Option<String>,
+    /// Last UTC date to include, `YYYY-MM-DD`.
+    #[serde(default)]
+    pub until: Option<String>,
+    #[serde(default)]
+    pub subcommand: Option<String>,
+    #[serde(default)]
+    pub purpose: Option<String>,
+    #[serde(default)]
+    pub decision: Option<Decision>,
+    #[serde(default)]
+    pub request_id: Option<Value>,
+    #[serde(default)]
+    pub max_count: Option<usize>,
+}
+
+fn label(day: &str, position: u64) -> String {
+    format!("{}{}/{:06}", LABEL_PREFIX, day, position)
+}
+
+/// Save `record` under a label of its own after its day's others. A failure
+/// is logged rather than failing the call the record is about.
+pub fn write(state: &mut State, record: Record) {
+    let day = date(record.started_ms);
+    let position = state.audit_days.get(&day).copied().unwrap_or_default();
+    match storage::put(state, &label(&day, position), &record) {
+        Ok(()) => {
+            state.audit_days.insert(day, position + 1);
+        }
+        Err(message) => log(&format!("Failed to write audit record: {}", message)),
+    }
+}
+
+/// Records matching `query`, and `repository_path` when given, newest first.
+pub fn query(
+    state: &State,
+    repository_path: Option<&str>,
+    query: &Query,
+) -> Result<Vec<Record>, String> {
+    for date in [&query.since, &query.until].into_iter().flatten() {
+        if !is_date(date) {
+            return Err(format!("'{}' is not a date of the form YYYY-MM-DD", date));
+        }
+    }
+    let days = state.audit_days.iter().rev().filter(|(day, _)| {
+        query
+            .since
+            .as_deref()
+            .is_none_or(|since| day.as_str() >= since)
+            && query
+                .until
+                .as_deref()
+                .is_none_or(|until| day.as_str() <= until)
+    });
+
+    let max_count = query.max_count.unwrap_or(DEFAULT_MAX_COUNT);
+    let mut found = Vec::new();
+    for (day, count) in days {
+        for position in (0..*count).rev() {
+            if found.len() >= max_count {
+                return Ok(found);
+            }
+            let Some(record) = storage::get::<Record>(state, &label(day, position))? else {
+                continue;
+            };
+            let matches = repository_path.is_none_or(|path| record.repository_path == path)
+                && query
+                    .subcommand
+                    .as_deref()
+                    .is_none_or(|name| git::subcommand(&record.args) == Some(name))
+                && query
+                    .purpose
+                    .as_deref()
+                    .is_none_or(|purpose| record.purpose == purpose)
+                && query
+                    .decision
+                    .is_none_or(|decision| record.decision == decision)
+                && query
+                    .request_id
+                    .as_ref()
+                    .is_none_or(|id| &record.request_id == id);
+            if matches {
+                found.push(record);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn is_date(value: &str) -> bool {
+    value.len() == 10
+        && value.char_indices().all(|(index, c)| match index {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// UTC date of `ms` milliseconds since the epoch, `YYYY-MM-DD`.
+fn date(ms: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = (ms / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
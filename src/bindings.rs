@@ -2141,6 +2141,4574 @@ pub mod theater {
                 }
             }
         }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod http_types {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            /// Raw binary data type
+            pub type Bytes = _rt::Vec<u8>;
+            #[derive(Clone)]
+            pub struct HttpRequest {
+                /// HTTP method (GET, POST, PUT, DELETE, etc.)
+                pub method: _rt::String,
+                /// Full request URI including query parameters
+                pub uri: _rt::String,
+                /// List of request headers as key-value pairs
+                pub headers: _rt::Vec<(_rt::String, _rt::String)>,
+                /// Optional request body as binary data
+                pub body: Option<Bytes>,
+            }
+            impl ::core::fmt::Debug for HttpRequest {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("HttpRequest")
+                        .field("method", &self.method)
+                        .field("uri", &self.uri)
+                        .field("headers", &self.headers)
+                        .field("body", &self.body)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct HttpResponse {
+                /// HTTP status code (e.g., 200, 404, 500)
+                pub status: u16,
+                /// List of response headers as key-value pairs
+                pub headers: _rt::Vec<(_rt::String, _rt::String)>,
+                /// Optional response body as binary data
+                pub body: Option<Bytes>,
+            }
+            impl ::core::fmt::Debug for HttpResponse {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("HttpResponse")
+                        .field("status", &self.status)
+                        .field("headers", &self.headers)
+                        .field("body", &self.body)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct TlsConfig {
+                /// Path to the certificate file
+                pub cert_path: _rt::String,
+                /// Path to the key file
+                pub key_path: _rt::String,
+            }
+            impl ::core::fmt::Debug for TlsConfig {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("TlsConfig")
+                        .field("cert-path", &self.cert_path)
+                        .field("key-path", &self.key_path)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct ServerConfig {
+                /// Port to listen on, 0 means system-assigned
+                pub port: Option<u16>,
+                /// Host address to bind to
+                pub host: Option<_rt::String>,
+                /// TLS configuration for HTTPS
+                pub tls_config: Option<TlsConfig>,
+            }
+            impl ::core::fmt::Debug for ServerConfig {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("ServerConfig")
+                        .field("port", &self.port)
+                        .field("host", &self.host)
+                        .field("tls-config", &self.tls_config)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct ServerInfo {
+                /// Server ID
+                pub id: u64,
+                /// Current listening port
+                pub port: u16,
+                /// Host address
+                pub host: _rt::String,
+                /// Whether the server is running
+                pub running: bool,
+                /// Number of active routes
+                pub routes_count: u32,
+                /// Number of active middleware
+                pub middleware_count: u32,
+                /// Whether WebSocket is enabled
+                pub websocket_enabled: bool,
+            }
+            impl ::core::fmt::Debug for ServerInfo {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("ServerInfo")
+                        .field("id", &self.id)
+                        .field("port", &self.port)
+                        .field("host", &self.host)
+                        .field("running", &self.running)
+                        .field("routes-count", &self.routes_count)
+                        .field("middleware-count", &self.middleware_count)
+                        .field("websocket-enabled", &self.websocket_enabled)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct MiddlewareResult {
+                /// Whether to continue processing the request
+                pub proceed: bool,
+                /// The potentially modified request
+                pub request: HttpRequest,
+            }
+            impl ::core::fmt::Debug for MiddlewareResult {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("MiddlewareResult")
+                        .field("proceed", &self.proceed)
+                        .field("request", &self.request)
+                        .finish()
+                }
+            }
+        }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod websocket_types {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[derive(Clone)]
+            pub enum MessageType {
+                /// A text message (UTF-8 encoded)
+                Text,
+                /// A binary message
+                Binary,
+                /// A new connection was established
+                Connect,
+                /// The connection was closed
+                Close,
+                /// A ping message (for keep-alive)
+                Ping,
+                /// A pong message (response to ping)
+                Pong,
+                /// Any other message type with string identifier
+                Other(_rt::String),
+            }
+            impl ::core::fmt::Debug for MessageType {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    match self {
+                        MessageType::Text => f.debug_tuple("MessageType::Text").finish(),
+                        MessageType::Binary => {
+                            f.debug_tuple("MessageType::Binary").finish()
+                        }
+                        MessageType::Connect => {
+                            f.debug_tuple("MessageType::Connect").finish()
+                        }
+                        MessageType::Close => {
+                            f.debug_tuple("MessageType::Close").finish()
+                        }
+                        MessageType::Ping => f.debug_tuple("MessageType::Ping").finish(),
+                        MessageType::Pong => f.debug_tuple("MessageType::Pong").finish(),
+                        MessageType::Other(e) => {
+                            f.debug_tuple("MessageType::Other").field(e).finish()
+                        }
+                    }
+                }
+            }
+            /// # WebSocket Message
+            ///
+            /// Represents a message sent or received over a WebSocket connection.
+            #[derive(Clone)]
+            pub struct WebsocketMessage {
+                /// The type of the message
+                pub ty: MessageType,
+                /// Binary data payload (used for binary messages)
+                pub data: Option<_rt::Vec<u8>>,
+                /// Text payload (used for text messages)
+                pub text: Option<_rt::String>,
+            }
+            impl ::core::fmt::Debug for WebsocketMessage {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("WebsocketMessage")
+                        .field("ty", &self.ty)
+                        .field("data", &self.data)
+                        .field("text", &self.text)
+                        .finish()
+                }
+            }
+        }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod http_framework {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            pub type ServerConfig = super::super::super::theater::simple::http_types::ServerConfig;
+            pub type ServerInfo = super::super::super::theater::simple::http_types::ServerInfo;
+            pub type WebsocketMessage = super::super::super::theater::simple::websocket_types::WebsocketMessage;
+            /// Core types
+            /// Unique identifier for an HTTP server instance
+            pub type ServerId = u64;
+            /// Unique identifier for a registered handler function
+            pub type HandlerId = u64;
+            /// Unique identifier for a registered route
+            pub type RouteId = u64;
+            /// Unique identifier for registered middleware
+            pub type MiddlewareId = u64;
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn create_server(
+                config: &ServerConfig,
+            ) -> Result<ServerId, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let super::super::super::theater::simple::http_types::ServerConfig {
+                        port: port0,
+                        host: host0,
+                        tls_config: tls_config0,
+                    } = config;
+                    let (result1_0, result1_1) = match port0 {
+                        Some(e) => (1i32, _rt::as_i32(e)),
+                        None => (0i32, 0i32),
+                    };
+                    let (result3_0, result3_1, result3_2) = match host0 {
+                        Some(e) => {
+                            let vec2 = e;
+                            let ptr2 = vec2.as_ptr().cast::<u8>();
+                            let len2 = vec2.len();
+                            (1i32, ptr2.cast_mut(), len2)
+                        }
+                        None => (0i32, ::core::ptr::null_mut(), 0usize),
+                    };
+                    let (result7_0, result7_1, result7_2, result7_3, result7_4) = match tls_config0 {
+                        Some(e) => {
+                            let super::super::super::theater::simple::http_types::TlsConfig {
+                                cert_path: cert_path4,
+                                key_path: key_path4,
+                            } = e;
+                            let vec5 = cert_path4;
+                            let ptr5 = vec5.as_ptr().cast::<u8>();
+                            let len5 = vec5.len();
+                            let vec6 = key_path4;
+                            let ptr6 = vec6.as_ptr().cast::<u8>();
+                            let len6 = vec6.len();
+                            (1i32, ptr5.cast_mut(), len5, ptr6.cast_mut(), len6)
+                        }
+                        None => {
+                            (
+                                0i32,
+                                ::core::ptr::null_mut(),
+                                0usize,
+                                ::core::ptr::null_mut(),
+                                0usize,
+                            )
+                        }
+                    };
+                    let ptr8 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "create-server"]
+                        fn wit_import9(
+                            _: i32,
+                            _: i32,
+                            _: i32,
+                            _: *mut u8,
+                            _: usize,
+                            _: i32,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import9(
+                        _: i32,
+                        _: i32,
+                        _: i32,
+                        _: *mut u8,
+                        _: usize,
+                        _: i32,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import9(
+                            result1_0,
+                            result1_1,
+                            result3_0,
+                            result3_1,
+                            result3_2,
+                            result7_0,
+                            result7_1,
+                            result7_2,
+                            result7_3,
+                            result7_4,
+                            ptr8,
+                        )
+                    };
+                    let l10 = i32::from(*ptr8.add(0).cast::<u8>());
+                    let result15 = match l10 {
+                        0 => {
+                            let e = {
+                                let l11 = *ptr8.add(8).cast::<i64>();
+                                l11 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l12 = *ptr8.add(8).cast::<*mut u8>();
+                                let l13 = *ptr8
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len14 = l13;
+                                let bytes14 = _rt::Vec::from_raw_parts(
+                                    l12.cast(),
+                                    len14,
+                                    len14,
+                                );
+                                _rt::string_lift(bytes14)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result15
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn get_server_info(
+                server_id: ServerId,
+            ) -> Result<ServerInfo, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 40 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 40
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "get-server-info"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(server_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result15 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0.add(8).cast::<i64>();
+                                let l4 = i32::from(*ptr0.add(16).cast::<u16>());
+                                let l5 = *ptr0
+                                    .add(16 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l6 = *ptr0
+                                    .add(16 + 2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                let l8 = i32::from(
+                                    *ptr0
+                                        .add(16 + 3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>(),
+                                );
+                                let l9 = *ptr0
+                                    .add(20 + 3 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<i32>();
+                                let l10 = *ptr0
+                                    .add(24 + 3 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<i32>();
+                                let l11 = i32::from(
+                                    *ptr0
+                                        .add(28 + 3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>(),
+                                );
+                                super::super::super::theater::simple::http_types::ServerInfo {
+                                    id: l3 as u64,
+                                    port: l4 as u16,
+                                    host: _rt::string_lift(bytes7),
+                                    running: _rt::bool_lift(l8 as u8),
+                                    routes_count: l9 as u32,
+                                    middleware_count: l10 as u32,
+                                    websocket_enabled: _rt::bool_lift(l11 as u8),
+                                }
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l12 = *ptr0.add(8).cast::<*mut u8>();
+                                let l13 = *ptr0
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len14 = l13;
+                                let bytes14 = _rt::Vec::from_raw_parts(
+                                    l12.cast(),
+                                    len14,
+                                    len14,
+                                );
+                                _rt::string_lift(bytes14)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result15
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn start_server(server_id: ServerId) -> Result<u16, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "start-server"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(server_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result7 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = i32::from(
+                                    *ptr0.add(::core::mem::size_of::<*const u8>()).cast::<u16>(),
+                                );
+                                l3 as u16
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn stop_server(server_id: ServerId) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "stop-server"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(server_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn destroy_server(server_id: ServerId) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "destroy-server"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(server_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn register_handler(
+                handler_name: &str,
+            ) -> Result<HandlerId, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = handler_name;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "register-handler"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result8 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1.add(8).cast::<i64>();
+                                l4 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr1.add(8).cast::<*mut u8>();
+                                let l6 = *ptr1
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn add_route(
+                server_id: ServerId,
+                path: &str,
+                method: &str,
+                handler_id: HandlerId,
+            ) -> Result<RouteId, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = method;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "add-route"]
+                        fn wit_import3(
+                            _: i64,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: i64,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: i64,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: i64,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(
+                            _rt::as_i64(server_id),
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            _rt::as_i64(handler_id),
+                            ptr2,
+                        )
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result9 = match l4 {
+                        0 => {
+                            let e = {
+                                let l5 = *ptr2.add(8).cast::<i64>();
+                                l5 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l6 = *ptr2.add(8).cast::<*mut u8>();
+                                let l7 = *ptr2
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                _rt::string_lift(bytes8)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result9
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn remove_route(route_id: RouteId) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "remove-route"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(route_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn add_middleware(
+                server_id: ServerId,
+                path: &str,
+                handler_id: HandlerId,
+            ) -> Result<MiddlewareId, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "add-middleware"]
+                        fn wit_import2(_: i64, _: *mut u8, _: usize, _: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(
+                        _: i64,
+                        _: *mut u8,
+                        _: usize,
+                        _: i64,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import2(
+                            _rt::as_i64(server_id),
+                            ptr0.cast_mut(),
+                            len0,
+                            _rt::as_i64(handler_id),
+                            ptr1,
+                        )
+                    };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result8 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1.add(8).cast::<i64>();
+                                l4 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr1.add(8).cast::<*mut u8>();
+                                let l6 = *ptr1
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn remove_middleware(
+                middleware_id: MiddlewareId,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "remove-middleware"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(middleware_id), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn enable_websocket(
+                server_id: ServerId,
+                path: &str,
+                connect_handler_id: Option<HandlerId>,
+                message_handler_id: HandlerId,
+                disconnect_handler_id: Option<HandlerId>,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let (result1_0, result1_1) = match connect_handler_id {
+                        Some(e) => (1i32, _rt::as_i64(e)),
+                        None => (0i32, 0i64),
+                    };
+                    let (result2_0, result2_1) = match disconnect_handler_id {
+                        Some(e) => (1i32, _rt::as_i64(e)),
+                        None => (0i32, 0i64),
+                    };
+                    let ptr3 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "enable-websocket"]
+                        fn wit_import4(
+                            _: i64,
+                            _: *mut u8,
+                            _: usize,
+                            _: i32,
+                            _: i64,
+                            _: i64,
+                            _: i32,
+                            _: i64,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import4(
+                        _: i64,
+                        _: *mut u8,
+                        _: usize,
+                        _: i32,
+                        _: i64,
+                        _: i64,
+                        _: i32,
+                        _: i64,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import4(
+                            _rt::as_i64(server_id),
+                            ptr0.cast_mut(),
+                            len0,
+                            result1_0,
+                            result1_1,
+                            _rt::as_i64(message_handler_id),
+                            result2_0,
+                            result2_1,
+                            ptr3,
+                        )
+                    };
+                    let l5 = i32::from(*ptr3.add(0).cast::<u8>());
+                    let result9 = match l5 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l6 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                _rt::string_lift(bytes8)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result9
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn send_websocket_message(
+                server_id: ServerId,
+                connection_id: u64,
+                message: &WebsocketMessage,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let super::super::super::theater::simple::websocket_types::WebsocketMessage {
+                        ty: ty0,
+                        data: data0,
+                        text: text0,
+                    } = message;
+                    use super::super::super::theater::simple::websocket_types::MessageType as V2;
+                    let (result3_0, result3_1, result3_2) = match ty0 {
+                        V2::Text => (0i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Binary => (1i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Connect => (2i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Close => (3i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Ping => (4i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Pong => (5i32, ::core::ptr::null_mut(), 0usize),
+                        V2::Other(e) => {
+                            let vec1 = e;
+                            let ptr1 = vec1.as_ptr().cast::<u8>();
+                            let len1 = vec1.len();
+                            (6i32, ptr1.cast_mut(), len1)
+                        }
+                    };
+                    let (result5_0, result5_1, result5_2) = match data0 {
+                        Some(e) => {
+                            let vec4 = e;
+                            let ptr4 = vec4.as_ptr().cast::<u8>();
+                            let len4 = vec4.len();
+                            (1i32, ptr4.cast_mut(), len4)
+                        }
+                        None => (0i32, ::core::ptr::null_mut(), 0usize),
+                    };
+                    let (result7_0, result7_1, result7_2) = match text0 {
+                        Some(e) => {
+                            let vec6 = e;
+                            let ptr6 = vec6.as_ptr().cast::<u8>();
+                            let len6 = vec6.len();
+                            (1i32, ptr6.cast_mut(), len6)
+                        }
+                        None => (0i32, ::core::ptr::null_mut(), 0usize),
+                    };
+                    let ptr8 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "send-websocket-message"]
+                        fn wit_import9(
+                            _: i64,
+                            _: i64,
+                            _: i32,
+                            _: *mut u8,
+                            _: usize,
+                            _: i32,
+                            _: *mut u8,
+                            _: usize,
+                            _: i32,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import9(
+                        _: i64,
+                        _: i64,
+                        _: i32,
+                        _: *mut u8,
+                        _: usize,
+                        _: i32,
+                        _: *mut u8,
+                        _: usize,
+                        _: i32,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import9(
+                            _rt::as_i64(server_id),
+                            _rt::as_i64(&connection_id),
+                            result3_0,
+                            result3_1,
+                            result3_2,
+                            result5_0,
+                            result5_1,
+                            result5_2,
+                            result7_0,
+                            result7_1,
+                            result7_2,
+                            ptr8,
+                        )
+                    };
+                    let l10 = i32::from(*ptr8.add(0).cast::<u8>());
+                    let result14 = match l10 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l11 = *ptr8
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l12 = *ptr8
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len13 = l12;
+                                let bytes13 = _rt::Vec::from_raw_parts(
+                                    l11.cast(),
+                                    len13,
+                                    len13,
+                                );
+                                _rt::string_lift(bytes13)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result14
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn close_websocket(
+                server_id: ServerId,
+                connection_id: u64,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/http-framework")]
+                    unsafe extern "C" {
+                        #[link_name = "close-websocket"]
+                        fn wit_import1(_: i64, _: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import1(
+                            _rt::as_i64(server_id),
+                            _rt::as_i64(&connection_id),
+                            ptr0,
+                        )
+                    };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+        }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod random {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn random_bytes(length: u32) -> Result<_rt::Vec<u8>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/random")]
+                    unsafe extern "C" {
+                        #[link_name = "random-bytes"]
+                        fn wit_import1(_: i32, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i32, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i32(&length), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result9 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                _rt::Vec::from_raw_parts(l3.cast(), len5, len5)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l6 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                _rt::string_lift(bytes8)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result9
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn random_range(min: u64, max: u64) -> Result<u64, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/random")]
+                    unsafe extern "C" {
+                        #[link_name = "random-range"]
+                        fn wit_import1(_: i64, _: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(&min), _rt::as_i64(&max), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result7 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0.add(8).cast::<i64>();
+                                l3 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr0.add(8).cast::<*mut u8>();
+                                let l5 = *ptr0
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn random_float() -> Result<f64, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/random")]
+                    unsafe extern "C" {
+                        #[link_name = "random-float"]
+                        fn wit_import1(_: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result7 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0.add(8).cast::<f64>();
+                                l3
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr0.add(8).cast::<*mut u8>();
+                                let l5 = *ptr0
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn generate_uuid() -> Result<_rt::String, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/random")]
+                    unsafe extern "C" {
+                        #[link_name = "generate-uuid"]
+                        fn wit_import1(_: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result9 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l6 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                _rt::string_lift(bytes8)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result9
+                }
+            }
+        }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod filesystem {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[derive(Clone)]
+            pub struct CommandSuccess {
+                pub stdout: _rt::String,
+                pub stderr: _rt::String,
+                pub exit_code: i32,
+            }
+            impl ::core::fmt::Debug for CommandSuccess {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("CommandSuccess")
+                        .field("stdout", &self.stdout)
+                        .field("stderr", &self.stderr)
+                        .field("exit-code", &self.exit_code)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub struct CommandError {
+                pub message: _rt::String,
+            }
+            impl ::core::fmt::Debug for CommandError {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("CommandError")
+                        .field("message", &self.message)
+                        .finish()
+                }
+            }
+            #[derive(Clone)]
+            pub enum CommandResult {
+                Success(CommandSuccess),
+                Error(CommandError),
+            }
+            impl ::core::fmt::Debug for CommandResult {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    match self {
+                        CommandResult::Success(e) => {
+                            f.debug_tuple("CommandResult::Success").field(e).finish()
+                        }
+                        CommandResult::Error(e) => {
+                            f.debug_tuple("CommandResult::Error").field(e).finish()
+                        }
+                    }
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn read_file(path: &str) -> Result<_rt::Vec<u8>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "read-file"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result10 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                _rt::Vec::from_raw_parts(l4.cast(), len6, len6)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l7 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l8 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len9 = l8;
+                                let bytes9 = _rt::Vec::from_raw_parts(
+                                    l7.cast(),
+                                    len9,
+                                    len9,
+                                );
+                                _rt::string_lift(bytes9)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result10
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn write_file(path: &str, content: &str) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = content;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "write-file"]
+                        fn wit_import3(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1, ptr2)
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result8 = match l4 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l6 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn list_files(path: &str) -> Result<_rt::Vec<_rt::String>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "list-files"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result13 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let base9 = l4;
+                                let len9 = l5;
+                                let mut result9 = _rt::Vec::with_capacity(len9);
+                                for i in 0..len9 {
+                                    let base = base9
+                                        .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                                    let e9 = {
+                                        let l6 = *base.add(0).cast::<*mut u8>();
+                                        let l7 = *base
+                                            .add(::core::mem::size_of::<*const u8>())
+                                            .cast::<usize>();
+                                        let len8 = l7;
+                                        let bytes8 = _rt::Vec::from_raw_parts(
+                                            l6.cast(),
+                                            len8,
+                                            len8,
+                                        );
+                                        _rt::string_lift(bytes8)
+                                    };
+                                    result9.push(e9);
+                                }
+                                _rt::cabi_dealloc(
+                                    base9,
+                                    len9 * (2 * ::core::mem::size_of::<*const u8>()),
+                                    ::core::mem::size_of::<*const u8>(),
+                                );
+                                result9
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l10 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l11 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len12 = l11;
+                                let bytes12 = _rt::Vec::from_raw_parts(
+                                    l10.cast(),
+                                    len12,
+                                    len12,
+                                );
+                                _rt::string_lift(bytes12)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result13
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn delete_file(path: &str) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "delete-file"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result7 = match l3 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn create_dir(path: &str) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "create-dir"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result7 = match l3 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn delete_dir(path: &str) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "delete-dir"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result7 = match l3 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn path_exists(path: &str) -> Result<bool, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = path;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "path-exists"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result8 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = i32::from(
+                                    *ptr1.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                                );
+                                _rt::bool_lift(l4 as u8)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l6 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn execute_command(
+                dir: &str,
+                command: &str,
+                args: &[_rt::String],
+            ) -> Result<CommandResult, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 7 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 7
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = dir;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = command;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let vec3 = args;
+                    let len3 = vec3.len();
+                    let layout3 = _rt::alloc::Layout::from_size_align_unchecked(
+                        vec3.len() * (2 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
+                    let result3 = if layout3.size() != 0 {
+                        let ptr = _rt::alloc::alloc(layout3).cast::<u8>();
+                        if ptr.is_null() {
+                            _rt::alloc::handle_alloc_error(layout3);
+                        }
+                        ptr
+                    } else {
+                        ::core::ptr::null_mut()
+                    };
+                    for (i, e) in vec3.into_iter().enumerate() {
+                        let base = result3
+                            .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                        {
+                            let vec2 = e;
+                            let ptr2 = vec2.as_ptr().cast::<u8>();
+                            let len2 = vec2.len();
+                            *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len2;
+                            *base.add(0).cast::<*mut u8>() = ptr2.cast_mut();
+                        }
+                    }
+                    let ptr4 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "execute-command"]
+                        fn wit_import5(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import5(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import5(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            result3,
+                            len3,
+                            ptr4,
+                        )
+                    };
+                    let l6 = i32::from(*ptr4.add(0).cast::<u8>());
+                    let result22 = match l6 {
+                        0 => {
+                            let e = {
+                                let l7 = i32::from(
+                                    *ptr4.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                                );
+                                let v18 = match l7 {
+                                    0 => {
+                                        let e18 = {
+                                            let l8 = *ptr4
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l9 = *ptr4
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len10 = l9;
+                                            let bytes10 = _rt::Vec::from_raw_parts(
+                                                l8.cast(),
+                                                len10,
+                                                len10,
+                                            );
+                                            let l11 = *ptr4
+                                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l12 = *ptr4
+                                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len13 = l12;
+                                            let bytes13 = _rt::Vec::from_raw_parts(
+                                                l11.cast(),
+                                                len13,
+                                                len13,
+                                            );
+                                            let l14 = *ptr4
+                                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<i32>();
+                                            CommandSuccess {
+                                                stdout: _rt::string_lift(bytes10),
+                                                stderr: _rt::string_lift(bytes13),
+                                                exit_code: l14,
+                                            }
+                                        };
+                                        CommandResult::Success(e18)
+                                    }
+                                    n => {
+                                        debug_assert_eq!(n, 1, "invalid enum discriminant");
+                                        let e18 = {
+                                            let l15 = *ptr4
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l16 = *ptr4
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len17 = l16;
+                                            let bytes17 = _rt::Vec::from_raw_parts(
+                                                l15.cast(),
+                                                len17,
+                                                len17,
+                                            );
+                                            CommandError {
+                                                message: _rt::string_lift(bytes17),
+                                            }
+                                        };
+                                        CommandResult::Error(e18)
+                                    }
+                                };
+                                v18
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l19 = *ptr4
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l20 = *ptr4
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len21 = l20;
+                                let bytes21 = _rt::Vec::from_raw_parts(
+                                    l19.cast(),
+                                    len21,
+                                    len21,
+                                );
+                                _rt::string_lift(bytes21)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    if layout3.size() != 0 {
+                        _rt::alloc::dealloc(result3.cast(), layout3);
+                    }
+                    result22
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            pub fn execute_nix_command(
+                dir: &str,
+                command: &str,
+            ) -> Result<CommandResult, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 7 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 7
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = dir;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = command;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/filesystem")]
+                    unsafe extern "C" {
+                        #[link_name = "execute-nix-command"]
+                        fn wit_import3(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1, ptr2)
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result20 = match l4 {
+                        0 => {
+                            let e = {
+                                let l5 = i32::from(
+                                    *ptr2.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                                );
+                                let v16 = match l5 {
+                                    0 => {
+                                        let e16 = {
+                                            let l6 = *ptr2
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l7 = *ptr2
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len8 = l7;
+                                            let bytes8 = _rt::Vec::from_raw_parts(
+                                                l6.cast(),
+                                                len8,
+                                                len8,
+                                            );
+                                            let l9 = *ptr2
+                                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l10 = *ptr2
+                                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len11 = l10;
+                                            let bytes11 = _rt::Vec::from_raw_parts(
+                                                l9.cast(),
+                                                len11,
+                                                len11,
+                                            );
+                                            let l12 = *ptr2
+                                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<i32>();
+                                            CommandSuccess {
+                                                stdout: _rt::string_lift(bytes8),
+                                                stderr: _rt::string_lift(bytes11),
+                                                exit_code: l12,
+                                            }
+                                        };
+                                        CommandResult::Success(e16)
+                                    }
+                                    n => {
+                                        debug_assert_eq!(n, 1, "invalid enum discriminant");
+                                        let e16 = {
+                                            let l13 = *ptr2
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l14 = *ptr2
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len15 = l14;
+                                            let bytes15 = _rt::Vec::from_raw_parts(
+                                                l13.cast(),
+                                                len15,
+                                                len15,
+                                            );
+                                            CommandError {
+                                                message: _rt::string_lift(bytes15),
+                                            }
+                                        };
+                                        CommandResult::Error(e16)
+                                    }
+                                };
+                                v16
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l17 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l18 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len19 = l18;
+                                let bytes19 = _rt::Vec::from_raw_parts(
+                                    l17.cast(),
+                                    len19,
+                                    len19,
+                                );
+                                _rt::string_lift(bytes19)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result20
+                }
+            }
+        }
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod environment {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[allow(unused_unsafe, clippy::all)]
+            /// Get a specific environment variable
+            /// Returns None if the variable doesn't exist or access is denied
+            pub fn get_var(name: &str) -> Option<_rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = name;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/environment")]
+                    unsafe extern "C" {
+                        #[link_name = "get-var"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result7 = match l3 {
+                        0 => None,
+                        1 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Some(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// List all accessible environment variables
+            /// Returns empty list if list_all is not enabled in config
+            pub fn list_vars() -> _rt::Vec<(_rt::String, _rt::String)> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 2
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/environment")]
+                    unsafe extern "C" {
+                        #[link_name = "list-vars"]
+                        fn wit_import1(_: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0) };
+                    let l2 = *ptr0.add(0).cast::<*mut u8>();
+                    let l3 = *ptr0
+                        .add(::core::mem::size_of::<*const u8>())
+                        .cast::<usize>();
+                    let base10 = l2;
+                    let len10 = l3;
+                    let mut result10 = _rt::Vec::with_capacity(len10);
+                    for i in 0..len10 {
+                        let base = base10
+                            .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                        let e10 = {
+                            let l4 = *base.add(0).cast::<*mut u8>();
+                            let l5 = *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len6 = l5;
+                            let bytes6 = _rt::Vec::from_raw_parts(l4.cast(), len6, len6);
+                            let l7 = *base
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l8 = *base
+                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len9 = l8;
+                            let bytes9 = _rt::Vec::from_raw_parts(l7.cast(), len9, len9);
+                            (_rt::string_lift(bytes6), _rt::string_lift(bytes9))
+                        };
+                        result10.push(e10);
+                    }
+                    _rt::cabi_dealloc(
+                        base10,
+                        len10 * (4 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
+                    let result11 = result10;
+                    result11
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// Check if a specific environment variable exists (and is accessible)
+            pub fn exists(name: &str) -> bool {
+                unsafe {
+                    let vec0 = name;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/environment")]
+                    unsafe extern "C" {
+                        #[link_name = "exists"]
+                        fn wit_import1(_: *mut u8, _: usize) -> i32;
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8, _: usize) -> i32 {
+                        unreachable!()
+                    }
+                    let ret = unsafe { wit_import1(ptr0.cast_mut(), len0) };
+                    _rt::bool_lift(ret as u8)
+                }
+            }
+        }
+        /// # Content Store
+        ///
+        /// Provides a content-addressable storage system for actors to store and retrieve data.
+        ///
+        /// ## Purpose
+        ///
+        /// The store interface allows actors to save and retrieve content using content-addressed
+        /// storage, where each piece of content is referenced by a hash of its data. This provides
+        /// immutability, deduplication, and integrity verification for all stored content.
+        ///
+        /// Additionally, the store supports a labeling system that allows human-readable names
+        /// to be attached to content references, making it easier to locate and manage content.
+        ///
+        /// ## Example
+        ///
+        /// ```rust
+        /// use ntwk::theater::store;
+        ///
+        /// // Create a new store
+        /// let store_id = store::new()?;
+        ///
+        /// // Store some content
+        /// let content = "Hello, Theater!".as_bytes().to_vec();
+        /// let content_ref = store::store(store_id, content)?;
+        ///
+        /// // Retrieve it by its content reference
+        /// let retrieved = store::get(store_id, content_ref.clone())?;
+        /// assert_eq!(retrieved, "Hello, Theater!".as_bytes());
+        ///
+        /// // Label the content for easier access
+        /// store::label(store_id, "greeting", content_ref.clone())?;
+        ///
+        /// // Later, retrieve by label
+        /// let label_ref = store::get_by_label(store_id, "greeting")?.unwrap();
+        /// let greeting = store::get(store_id, label_ref)?;
+        /// ```
+        ///
+        /// ## Security
+        ///
+        /// The content store is isolated per actor, preventing direct access to other actors' data.
+        /// All store operations are tracked in the actor's event chain, providing a complete
+        /// audit trail of data operations.
+        ///
+        /// ## Implementation Notes
+        ///
+        /// The store uses content-based addressing where the reference to content is derived from
+        /// a cryptographic hash of the content itself. This ensures:
+        ///
+        /// - Content cannot be modified without changing its reference
+        /// - Identical content is stored only once (automatic deduplication)
+        /// - Content integrity can be verified
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod store {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            /// # Content Reference
+            ///
+            /// A reference to content stored in the content-addressable store.
+            ///
+            /// ## Purpose
+            ///
+            /// ContentRef provides a stable, immutable reference to content based on its hash,
+            /// enabling content-addressable storage where data is referenced by its cryptographic hash
+            /// rather than by location or name.
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store::{content_ref, store};
+            ///
+            /// // Store content and get its reference
+            /// let store_id = store::new()?;
+            /// let data = b"Some important data".to_vec();
+            /// let ref = store::store(store_id, data)?;
+            ///
+            /// // The hash in the content ref is a SHA-256 digest
+            /// println!("Stored content with hash: {}", ref.hash);
+            /// ```
+            ///
+            /// ## Security
+            ///
+            /// Content references use cryptographic hashes that are collision-resistant,
+            /// ensuring that distinct content will have distinct references. This provides
+            /// integrity verification for all stored content.
+            #[derive(Clone)]
+            pub struct ContentRef {
+                /// Cryptographic hash of the content (SHA-256 in hexadecimal format)
+                pub hash: _rt::String,
+            }
+            impl ::core::fmt::Debug for ContentRef {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    f.debug_struct("ContentRef").field("hash", &self.hash).finish()
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Create a new store
+            ///
+            /// Creates a new content-addressable store instance.
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(string)` - The ID of the newly created store
+            /// * `Err(string)` - Error message if store creation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Create a new store
+            /// let store_id = store::new()?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// Each actor has access to its own isolated store instances. Store IDs are only
+            /// valid within the context of the actor that created them.
+            pub fn new() -> Result<_rt::String, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "new"]
+                        fn wit_import1(_: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result9 = match l2 {
+                        0 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l6 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                _rt::string_lift(bytes8)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result9
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Store content
+            ///
+            /// Stores content in the content-addressable store and returns a reference to it.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `content` - The content bytes to store
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(content-ref)` - Reference to the stored content
+            /// * `Err(string)` - Error message if storage fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Store some content
+            /// let data = serde_json::to_vec(&my_data)?;
+            /// let content_ref = store::store(store_id, data)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// If identical content already exists in the store, the existing content reference
+            /// will be returned without storing a duplicate copy.
+            pub fn store(
+                store_id: &str,
+                content: &[u8],
+            ) -> Result<ContentRef, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = content;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "store"]
+                        fn wit_import3(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1, ptr2)
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result11 = match l4 {
+                        0 => {
+                            let e = {
+                                let l5 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l6 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                ContentRef {
+                                    hash: _rt::string_lift(bytes7),
+                                }
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l8 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l9 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len10 = l9;
+                                let bytes10 = _rt::Vec::from_raw_parts(
+                                    l8.cast(),
+                                    len10,
+                                    len10,
+                                );
+                                _rt::string_lift(bytes10)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result11
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Retrieve content
+            ///
+            /// Retrieves content from the store using its content reference.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `content-ref` - Reference to the content to retrieve
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(list<u8>)` - The retrieved content bytes
+            /// * `Err(string)` - Error message if retrieval fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Retrieve content
+            /// let content = store::get(store_id, content_ref)?;
+            /// let my_data: MyData = serde_json::from_slice(&content)?;
+            /// ```
+            pub fn get(
+                store_id: &str,
+                content_ref: &ContentRef,
+            ) -> Result<_rt::Vec<u8>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ContentRef { hash: hash1 } = content_ref;
+                    let vec2 = hash1;
+                    let ptr2 = vec2.as_ptr().cast::<u8>();
+                    let len2 = vec2.len();
+                    let ptr3 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "get"]
+                        fn wit_import4(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import4(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import4(ptr0.cast_mut(), len0, ptr2.cast_mut(), len2, ptr3)
+                    };
+                    let l5 = i32::from(*ptr3.add(0).cast::<u8>());
+                    let result12 = match l5 {
+                        0 => {
+                            let e = {
+                                let l6 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                _rt::Vec::from_raw_parts(l6.cast(), len8, len8)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l9 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l10 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len11 = l10;
+                                let bytes11 = _rt::Vec::from_raw_parts(
+                                    l9.cast(),
+                                    len11,
+                                    len11,
+                                );
+                                _rt::string_lift(bytes11)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result12
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Check if content exists
+            ///
+            /// Checks if a particular content reference exists in the store.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to check
+            /// * `content-ref` - Reference to check for
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(bool)` - True if the content exists, false otherwise
+            /// * `Err(string)` - Error message if the check fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Check if content exists before attempting to retrieve it
+            /// if store::exists(store_id, content_ref)? {
+            /// let content = store::get(store_id, content_ref)?;
+            /// // Process content...
+            /// } else {
+            /// // Handle missing content case
+            /// }
+            /// ```
+            pub fn exists(
+                store_id: &str,
+                content_ref: &ContentRef,
+            ) -> Result<bool, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ContentRef { hash: hash1 } = content_ref;
+                    let vec2 = hash1;
+                    let ptr2 = vec2.as_ptr().cast::<u8>();
+                    let len2 = vec2.len();
+                    let ptr3 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "exists"]
+                        fn wit_import4(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import4(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import4(ptr0.cast_mut(), len0, ptr2.cast_mut(), len2, ptr3)
+                    };
+                    let l5 = i32::from(*ptr3.add(0).cast::<u8>());
+                    let result10 = match l5 {
+                        0 => {
+                            let e = {
+                                let l6 = i32::from(
+                                    *ptr3.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                                );
+                                _rt::bool_lift(l6 as u8)
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l7 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l8 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len9 = l8;
+                                let bytes9 = _rt::Vec::from_raw_parts(
+                                    l7.cast(),
+                                    len9,
+                                    len9,
+                                );
+                                _rt::string_lift(bytes9)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result10
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Attach a label to content
+            ///
+            /// Associates a human-readable label with a content reference.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The human-readable label to attach
+            /// * `content-ref` - Reference to the content to label
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Label was successfully attached
+            /// * `Err(string)` - Error message if labeling fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Store and label config data
+            /// let config_data = serde_json::to_vec(&my_config)?;
+            /// let ref = store::store(store_id, config_data)?;
+            /// store::label(store_id, "current-config", ref)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// A label can point to multiple content references, effectively acting as a collection.
+            /// Each call to this function adds the content reference to the label without removing
+            /// previous references.
+            pub fn label(
+                store_id: &str,
+                label: &str,
+                content_ref: &ContentRef,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ContentRef { hash: hash2 } = content_ref;
+                    let vec3 = hash2;
+                    let ptr3 = vec3.as_ptr().cast::<u8>();
+                    let len3 = vec3.len();
+                    let ptr4 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "label"]
+                        fn wit_import5(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import5(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import5(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            ptr3.cast_mut(),
+                            len3,
+                            ptr4,
+                        )
+                    };
+                    let l6 = i32::from(*ptr4.add(0).cast::<u8>());
+                    let result10 = match l6 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l7 = *ptr4
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l8 = *ptr4
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len9 = l8;
+                                let bytes9 = _rt::Vec::from_raw_parts(
+                                    l7.cast(),
+                                    len9,
+                                    len9,
+                                );
+                                _rt::string_lift(bytes9)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result10
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Get content reference by label
+            ///
+            /// Retrieves a content reference associated with a label.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to look up
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(option<content-ref>)` - The content reference if found, None if the label doesn't exist
+            /// * `Err(string)` - Error message if the lookup fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Retrieve the current configuration
+            /// if let Some(ref) = store::get_by_label(store_id, "current-config")? {
+            /// let config_data = store::get(store_id, ref)?;
+            /// let config: MyConfig = serde_json::from_slice(&config_data)?;
+            /// // Use configuration...
+            /// } else {
+            /// // No configuration found
+            /// }
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// If a label points to multiple content references, this function returns the most
+            /// recently added reference.
+            pub fn get_by_label(
+                store_id: &str,
+                label: &str,
+            ) -> Result<Option<ContentRef>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 4 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 4
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "get-by-label"]
+                        fn wit_import3(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1, ptr2)
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result12 = match l4 {
+                        0 => {
+                            let e = {
+                                let l5 = i32::from(
+                                    *ptr2.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                                );
+                                match l5 {
+                                    0 => None,
+                                    1 => {
+                                        let e = {
+                                            let l6 = *ptr2
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l7 = *ptr2
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let len8 = l7;
+                                            let bytes8 = _rt::Vec::from_raw_parts(
+                                                l6.cast(),
+                                                len8,
+                                                len8,
+                                            );
+                                            ContentRef {
+                                                hash: _rt::string_lift(bytes8),
+                                            }
+                                        };
+                                        Some(e)
+                                    }
+                                    _ => _rt::invalid_enum_discriminant(),
+                                }
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l9 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l10 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len11 = l10;
+                                let bytes11 = _rt::Vec::from_raw_parts(
+                                    l9.cast(),
+                                    len11,
+                                    len11,
+                                );
+                                _rt::string_lift(bytes11)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result12
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Remove a label
+            ///
+            /// Deletes a label and its associations with content references.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to remove
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Label was successfully removed
+            /// * `Err(string)` - Error message if removal fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Remove an obsolete label
+            /// store::remove_label(store_id, "old-config")?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// Removing a label does not delete the content it points to, only the association
+            /// between the label and the content references.
+            pub fn remove_label(store_id: &str, label: &str) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ptr2 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "remove-label"]
+                        fn wit_import3(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import3(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import3(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1, ptr2)
+                    };
+                    let l4 = i32::from(*ptr2.add(0).cast::<u8>());
+                    let result8 = match l4 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr2
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l6 = *ptr2
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Remove a specific content reference from a label
+            ///
+            /// Removes the association between a label and a specific content reference.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to modify
+            /// * `content-ref` - The content reference to remove from the label
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Reference was successfully removed from the label
+            /// * `Err(string)` - Error message if removal fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Remove a specific version from the "historical-configs" label
+            /// store::remove_from_label(store_id, "historical-configs", outdated_ref)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This operation only removes the association between the label and the content reference.
+            /// It does not delete the content itself.
+            pub fn remove_from_label(
+                store_id: &str,
+                label: &str,
+                content_ref: &ContentRef,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ContentRef { hash: hash2 } = content_ref;
+                    let vec3 = hash2;
+                    let ptr3 = vec3.as_ptr().cast::<u8>();
+                    let len3 = vec3.len();
+                    let ptr4 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "remove-from-label"]
+                        fn wit_import5(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import5(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import5(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            ptr3.cast_mut(),
+                            len3,
+                            ptr4,
+                        )
+                    };
+                    let l6 = i32::from(*ptr4.add(0).cast::<u8>());
+                    let result10 = match l6 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l7 = *ptr4
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l8 = *ptr4
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len9 = l8;
+                                let bytes9 = _rt::Vec::from_raw_parts(
+                                    l7.cast(),
+                                    len9,
+                                    len9,
+                                );
+                                _rt::string_lift(bytes9)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result10
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Store content and immediately label it
+            ///
+            /// Stores content and associates it with a label in a single operation.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to attach to the content
+            /// * `content` - The content bytes to store
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(content-ref)` - Reference to the stored content
+            /// * `Err(string)` - Error message if the operation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Store and label user data in one operation
+            /// let user_data = serde_json::to_vec(&user)?;
+            /// let ref = store::store_at_label(store_id, "user-profile", user_data)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This is a convenience function that combines `store` and `label` operations.
+            /// The label will point to the new content reference in addition to any existing
+            /// content references it may already point to.
+            pub fn store_at_label(
+                store_id: &str,
+                label: &str,
+                content: &[u8],
+            ) -> Result<ContentRef, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let vec2 = content;
+                    let ptr2 = vec2.as_ptr().cast::<u8>();
+                    let len2 = vec2.len();
+                    let ptr3 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "store-at-label"]
+                        fn wit_import4(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import4(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import4(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            ptr2.cast_mut(),
+                            len2,
+                            ptr3,
+                        )
+                    };
+                    let l5 = i32::from(*ptr3.add(0).cast::<u8>());
+                    let result12 = match l5 {
+                        0 => {
+                            let e = {
+                                let l6 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                ContentRef {
+                                    hash: _rt::string_lift(bytes8),
+                                }
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l9 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l10 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len11 = l10;
+                                let bytes11 = _rt::Vec::from_raw_parts(
+                                    l9.cast(),
+                                    len11,
+                                    len11,
+                                );
+                                _rt::string_lift(bytes11)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result12
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Replace content at a label
+            ///
+            /// Stores new content and makes the label point exclusively to it, removing any
+            /// previous associations.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to update
+            /// * `content` - The new content bytes to store
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(content-ref)` - Reference to the stored content
+            /// * `Err(string)` - Error message if the operation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Update configuration with new values
+            /// let new_config = serde_json::to_vec(&updated_config)?;
+            /// let ref = store::replace_content_at_label(store_id, "current-config", new_config)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This operation is atomic - the label will either point to the new content reference
+            /// or remain unchanged if the operation fails.
+            pub fn replace_content_at_label(
+                store_id: &str,
+                label: &str,
+                content: &[u8],
+            ) -> Result<ContentRef, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let vec2 = content;
+                    let ptr2 = vec2.as_ptr().cast::<u8>();
+                    let len2 = vec2.len();
+                    let ptr3 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "replace-content-at-label"]
+                        fn wit_import4(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import4(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import4(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            ptr2.cast_mut(),
+                            len2,
+                            ptr3,
+                        )
+                    };
+                    let l5 = i32::from(*ptr3.add(0).cast::<u8>());
+                    let result12 = match l5 {
+                        0 => {
+                            let e = {
+                                let l6 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l7 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len8 = l7;
+                                let bytes8 = _rt::Vec::from_raw_parts(
+                                    l6.cast(),
+                                    len8,
+                                    len8,
+                                );
+                                ContentRef {
+                                    hash: _rt::string_lift(bytes8),
+                                }
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l9 = *ptr3
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l10 = *ptr3
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len11 = l10;
+                                let bytes11 = _rt::Vec::from_raw_parts(
+                                    l9.cast(),
+                                    len11,
+                                    len11,
+                                );
+                                _rt::string_lift(bytes11)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result12
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Replace label with specific content reference
+            ///
+            /// Updates a label to point exclusively to an existing content reference.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to use
+            /// * `label` - The label to update
+            /// * `content-ref` - The content reference the label should point to
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Label was successfully updated
+            /// * `Err(string)` - Error message if the update fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Revert to a previous version
+            /// store::replace_at_label(store_id, "current-config", previous_version_ref)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This operation removes any existing associations between the label and other
+            /// content references. After this operation, the label will point only to the
+            /// specified content reference.
+            pub fn replace_at_label(
+                store_id: &str,
+                label: &str,
+                content_ref: &ContentRef,
+            ) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = label;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    let ContentRef { hash: hash2 } = content_ref;
+                    let vec3 = hash2;
+                    let ptr3 = vec3.as_ptr().cast::<u8>();
+                    let len3 = vec3.len();
+                    let ptr4 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "replace-at-label"]
+                        fn wit_import5(
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                            _: usize,
+                            _: *mut u8,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import5(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import5(
+                            ptr0.cast_mut(),
+                            len0,
+                            ptr1.cast_mut(),
+                            len1,
+                            ptr3.cast_mut(),
+                            len3,
+                            ptr4,
+                        )
+                    };
+                    let l6 = i32::from(*ptr4.add(0).cast::<u8>());
+                    let result10 = match l6 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l7 = *ptr4
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l8 = *ptr4
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len9 = l8;
+                                let bytes9 = _rt::Vec::from_raw_parts(
+                                    l7.cast(),
+                                    len9,
+                                    len9,
+                                );
+                                _rt::string_lift(bytes9)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result10
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # List all labels
+            ///
+            /// Retrieves a list of all labels in the store.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to query
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(list<string>)` - List of all labels in the store
+            /// * `Err(string)` - Error message if the operation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Get all available labels
+            /// let labels = store::list_labels(store_id)?;
+            /// for label in labels {
+            /// println!("Found label: {}", label);
+            /// }
+            /// ```
+            pub fn list_labels(
+                store_id: &str,
+            ) -> Result<_rt::Vec<_rt::String>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "list-labels"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result13 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let base9 = l4;
+                                let len9 = l5;
+                                let mut result9 = _rt::Vec::with_capacity(len9);
+                                for i in 0..len9 {
+                                    let base = base9
+                                        .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                                    let e9 = {
+                                        let l6 = *base.add(0).cast::<*mut u8>();
+                                        let l7 = *base
+                                            .add(::core::mem::size_of::<*const u8>())
+                                            .cast::<usize>();
+                                        let len8 = l7;
+                                        let bytes8 = _rt::Vec::from_raw_parts(
+                                            l6.cast(),
+                                            len8,
+                                            len8,
+                                        );
+                                        _rt::string_lift(bytes8)
+                                    };
+                                    result9.push(e9);
+                                }
+                                _rt::cabi_dealloc(
+                                    base9,
+                                    len9 * (2 * ::core::mem::size_of::<*const u8>()),
+                                    ::core::mem::size_of::<*const u8>(),
+                                );
+                                result9
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l10 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l11 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len12 = l11;
+                                let bytes12 = _rt::Vec::from_raw_parts(
+                                    l10.cast(),
+                                    len12,
+                                    len12,
+                                );
+                                _rt::string_lift(bytes12)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result13
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # List all content references
+            ///
+            /// Retrieves a list of all content references in the store.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to query
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(list<content-ref>)` - List of all content references in the store
+            /// * `Err(string)` - Error message if the operation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Get all content references
+            /// let refs = store::list_all_content(store_id)?;
+            /// println!("Store contains {} content items", refs.len());
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This operation may be expensive for stores with a large amount of content.
+            /// Consider using labels to organize and access content more efficiently.
+            pub fn list_all_content(
+                store_id: &str,
+            ) -> Result<_rt::Vec<ContentRef>, _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "list-all-content"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result13 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let base9 = l4;
+                                let len9 = l5;
+                                let mut result9 = _rt::Vec::with_capacity(len9);
+                                for i in 0..len9 {
+                                    let base = base9
+                                        .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                                    let e9 = {
+                                        let l6 = *base.add(0).cast::<*mut u8>();
+                                        let l7 = *base
+                                            .add(::core::mem::size_of::<*const u8>())
+                                            .cast::<usize>();
+                                        let len8 = l7;
+                                        let bytes8 = _rt::Vec::from_raw_parts(
+                                            l6.cast(),
+                                            len8,
+                                            len8,
+                                        );
+                                        ContentRef {
+                                            hash: _rt::string_lift(bytes8),
+                                        }
+                                    };
+                                    result9.push(e9);
+                                }
+                                _rt::cabi_dealloc(
+                                    base9,
+                                    len9 * (2 * ::core::mem::size_of::<*const u8>()),
+                                    ::core::mem::size_of::<*const u8>(),
+                                );
+                                result9
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l10 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l11 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len12 = l11;
+                                let bytes12 = _rt::Vec::from_raw_parts(
+                                    l10.cast(),
+                                    len12,
+                                    len12,
+                                );
+                                _rt::string_lift(bytes12)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result13
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Calculate total size
+            ///
+            /// Calculates the total size of all content in the store.
+            ///
+            /// ## Parameters
+            ///
+            /// * `store-id` - ID of the store to query
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(u64)` - Total size in bytes
+            /// * `Err(string)` - Error message if the calculation fails
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::store;
+            ///
+            /// // Check store size
+            /// let total_bytes = store::calculate_total_size(store_id)?;
+            /// println!("Store contains {} bytes of data", total_bytes);
+            ///
+            /// // Format as human-readable size
+            /// let size_mb = total_bytes as f64 / (1024.0 * 1024.0);
+            /// println!("Store size: {:.2} MB", size_mb);
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// This operation calculates the actual storage space used, accounting for
+            /// deduplication of identical content.
+            pub fn calculate_total_size(store_id: &str) -> Result<u64, _rt::String> {
+                unsafe {
+                    #[repr(align(8))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 8 + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 8
+                            + 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = store_id;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/store")]
+                    unsafe extern "C" {
+                        #[link_name = "calculate-total-size"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result8 = match l3 {
+                        0 => {
+                            let e = {
+                                let l4 = *ptr1.add(8).cast::<i64>();
+                                l4 as u64
+                            };
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l5 = *ptr1.add(8).cast::<*mut u8>();
+                                let l6 = *ptr1
+                                    .add(8 + 1 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len7 = l6;
+                                let bytes7 = _rt::Vec::from_raw_parts(
+                                    l5.cast(),
+                                    len7,
+                                    len7,
+                                );
+                                _rt::string_lift(bytes7)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result8
+                }
+            }
+        }
+        /// # Timing Interface
+        ///
+        /// Provides time-related functions for actors to get the current time and control execution timing.
+        ///
+        /// ## Purpose
+        ///
+        /// The timing interface gives actors access to time information and timing control
+        /// within the Theater runtime. It allows actors to:
+        /// - Get the current time
+        /// - Pause execution for specific durations
+        /// - Delay execution until specific points in time
+        ///
+        /// ## Example
+        ///
+        /// ```rust
+        /// use ntwk::theater::timing;
+        ///
+        /// async fn example() -> Result<(), String> {
+        /// // Get the current time
+        /// let now = timing::now();
+        /// println!("Current time: {}", now);
+        ///
+        /// // Sleep for 500 milliseconds
+        /// timing::sleep(500)?;
+        ///
+        /// // Wait until a specific future time
+        /// let five_seconds_later = now + 5000;
+        /// timing::deadline(five_seconds_later)?;
+        ///
+        /// Ok(())
+        /// }
+        /// ```
+        ///
+        /// ## Security
+        ///
+        /// The timing operations are managed by the Theater runtime, which may enforce:
+        /// - Rate limits on sleep operations to prevent resource exhaustion
+        /// - Maximum duration limits to prevent indefinite blocking
+        /// - Tracking and reporting of sleep patterns in the event chain
+        ///
+        /// ## Implementation Notes
+        ///
+        /// When actors call timing functions, the WebAssembly execution is suspended without
+        /// blocking the entire runtime. This allows the runtime to continue processing other
+        /// actors while an actor is waiting.
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod timing {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Get current time
+            ///
+            /// Returns the current time in milliseconds since the UNIX epoch (January 1, 1970 UTC).
+            ///
+            /// ## Returns
+            ///
+            /// The current timestamp in milliseconds
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::timing;
+            ///
+            /// // Get current timestamp
+            /// let now = timing::now();
+            ///
+            /// // Convert to seconds
+            /// let seconds_since_epoch = now / 1000;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// The time value is consistent across the entire Theater runtime, ensuring that
+            /// all actors have a synchronized view of time.
+            pub fn now() -> u64 {
+                unsafe {
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/timing")]
+                    unsafe extern "C" {
+                        #[link_name = "now"]
+                        fn wit_import0() -> i64;
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import0() -> i64 {
+                        unreachable!()
+                    }
+                    let ret = unsafe { wit_import0() };
+                    ret as u64
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Pause execution
+            ///
+            /// Pauses the execution of the actor for the specified number of milliseconds.
+            ///
+            /// ## Parameters
+            ///
+            /// * `duration` - Number of milliseconds to sleep
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Sleep completed successfully
+            /// * `Err(string)` - Error message if sleep was interrupted or not allowed
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::timing;
+            ///
+            /// // Sleep for 1 second
+            /// timing::sleep(1000)?;
+            ///
+            /// // Sleep for 100ms
+            /// timing::sleep(100)?;
+            /// ```
+            ///
+            /// ## Security
+            ///
+            /// The runtime may enforce limits on how long an actor can sleep to prevent
+            /// resource exhaustion or denial of service. Sleep operations are recorded
+            /// in the actor's event chain.
+            pub fn sleep(duration: u64) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/timing")]
+                    unsafe extern "C" {
+                        #[link_name = "sleep"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(&duration), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// # Wait until specific time
+            ///
+            /// Pauses execution until the specified timestamp is reached.
+            ///
+            /// ## Parameters
+            ///
+            /// * `timestamp` - Target time in milliseconds since UNIX epoch
+            ///
+            /// ## Returns
+            ///
+            /// * `Ok(_)` - Deadline was reached successfully
+            /// * `Err(string)` - Error message if the wait was interrupted or not allowed
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::timing;
+            ///
+            /// // Wait until a specific time
+            /// let target_time = 1672531200000; // Jan 1, 2023 00:00:00 UTC
+            /// timing::deadline(target_time)?;
+            ///
+            /// // Wait until 10 seconds from now
+            /// let now = timing::now();
+            /// let ten_seconds_later = now + 10000;
+            /// timing::deadline(ten_seconds_later)?;
+            /// ```
+            ///
+            /// ## Implementation Notes
+            ///
+            /// - If the specified timestamp is in the past, the function returns immediately
+            /// - The runtime may reject excessive deadline values that are too far in the future
+            /// - Deadline operations are recorded in the actor's event chain
+            pub fn deadline(timestamp: u64) -> Result<(), _rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "theater:simple/timing")]
+                    unsafe extern "C" {
+                        #[link_name = "deadline"]
+                        fn wit_import1(_: i64, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: i64, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(_rt::as_i64(&timestamp), ptr0) };
+                    let l2 = i32::from(*ptr0.add(0).cast::<u8>());
+                    let result6 = match l2 {
+                        0 => {
+                            let e = ();
+                            Ok(e)
+                        }
+                        1 => {
+                            let e = {
+                                let l3 = *ptr0
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l4 = *ptr0
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len5 = l4;
+                                let bytes5 = _rt::Vec::from_raw_parts(
+                                    l3.cast(),
+                                    len5,
+                                    len5,
+                                );
+                                _rt::string_lift(bytes5)
+                            };
+                            Err(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result6
+                }
+            }
+        }
     }
 }
 #[rustfmt::skip]
@@ -2223,21 +6791,353 @@ pub mod exports {
             pub mod actor {
                 #[used]
                 #[doc(hidden)]
-                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
-                use super::super::super::super::_rt;
+                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
+                use super::super::super::super::_rt;
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_init_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: *mut u8,
+                    arg4: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg4;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
+                    let result2 = T::init(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (_rt::string_lift(bytes1),),
+                    );
+                    let ptr3 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result2 {
+                        Ok(e) => {
+                            *ptr3.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t4_0,) = e;
+                            match t4_0 {
+                                Some(e) => {
+                                    *ptr3
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec5 = (e).into_boxed_slice();
+                                    let ptr5 = vec5.as_ptr().cast::<u8>();
+                                    let len5 = vec5.len();
+                                    ::core::mem::forget(vec5);
+                                    *ptr3
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len5;
+                                    *ptr3
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr5.cast_mut();
+                                }
+                                None => {
+                                    *ptr3
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr3.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec6 = (e.into_bytes()).into_boxed_slice();
+                            let ptr6 = vec6.as_ptr().cast::<u8>();
+                            let len6 = vec6.len();
+                            ::core::mem::forget(vec6);
+                            *ptr3
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len6;
+                            *ptr3
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr6.cast_mut();
+                        }
+                    };
+                    ptr3
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_init<T: Guest>(arg0: *mut u8) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l5 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l5, l6, 1);
+                        }
+                    }
+                }
+                pub trait Guest {
+                    /// # Initialize the actor
+                    ///
+                    /// Called when the actor is first started or restarted. This function is responsible
+                    /// for setting up the actor's initial state and responding to initialization parameters.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - Current state of the actor, or None if first initialization
+                    /// * `params` - Tuple of initialization parameters, typically including actor ID
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((state,))` - The updated state to store
+                    /// * `Err(string)` - An error message if initialization fails
+                    ///
+                    /// ## Implementation Notes
+                    ///
+                    /// - If state is None, the actor should create a new initial state
+                    /// - If state contains data, the actor should validate and use that state
+                    /// - The first parameter in the tuple is typically the actor's ID
+                    /// - Any error returned will cause the actor to fail to start
+                    fn init(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (_rt::String,),
+                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
+                }
+                #[doc(hidden)]
+                macro_rules! __export_theater_simple_actor_cabi {
+                    ($ty:ident with_types_in $($path_to_types:tt)*) => {
+                        const _ : () = { #[unsafe (export_name =
+                        "theater:simple/actor#init")] unsafe extern "C" fn
+                        export_init(arg0 : i32, arg1 : * mut u8, arg2 : usize, arg3 : *
+                        mut u8, arg4 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_init_cabi::<$ty > (arg0, arg1, arg2,
+                        arg3, arg4) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/actor#init")] unsafe extern "C" fn
+                        _post_return_init(arg0 : * mut u8,) { unsafe {
+                        $($path_to_types)*:: __post_return_init::<$ty > (arg0) } } };
+                    };
+                }
+                #[doc(hidden)]
+                pub(crate) use __export_theater_simple_actor_cabi;
+                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                struct _RetArea(
+                    [::core::mem::MaybeUninit<
+                        u8,
+                    >; 4 * ::core::mem::size_of::<*const u8>()],
+                );
+                static mut _RET_AREA: _RetArea = _RetArea(
+                    [::core::mem::MaybeUninit::uninit(); 4
+                        * ::core::mem::size_of::<*const u8>()],
+                );
+            }
+            /// # Message Server Client Interface
+            ///
+            /// Defines the callback handlers that actors must implement to receive messages through
+            /// the message server system.
+            ///
+            /// ## Purpose
+            ///
+            /// This interface enables actors to receive and process various types of messages:
+            /// - One-way messages (send)
+            /// - Request-response interactions (request)
+            /// - Bidirectional channel-based communication (channel operations)
+            ///
+            /// By implementing these handler functions, an actor can participate in different
+            /// communication patterns with other actors and external systems.
+            ///
+            /// ## Example
+            ///
+            /// ```rust
+            /// use ntwk::theater::message_server_client::Guest;
+            /// use ntwk::theater::types::{channel_accept, channel_id};
+            /// use serde_json::{json, Value};
+            ///
+            /// struct MyMessageHandler;
+            ///
+            /// impl Guest for MyMessageHandler {
+            /// fn handle_send(state: Option<Value>, params: (Value,))
+            /// -> Result<(Option<Value>,), String> {
+            /// let (message,) = params;
+            /// println!("Received message: {}", message);
+            ///
+            /// // Update state if needed
+            /// let new_state = if let Some(mut state) = state {
+            /// state["message_count"] = json!(state["message_count"].as_u64().unwrap_or(0) + 1);
+            /// Some(state)
+            /// } else {
+            /// Some(json!({"message_count": 1}))
+            /// };
+            ///
+            /// Ok((new_state,))
+            /// }
+            ///
+            /// // Implement other handlers...
+            /// }
+            /// ```
+            ///
+            /// ## Security
+            ///
+            /// The message handlers receive input from potentially untrusted sources, so they should:
+            /// - Validate all incoming message data
+            /// - Handle malformed messages gracefully
+            /// - Protect against common attack vectors like JSON injection
+            ///
+            /// ## Implementation Notes
+            ///
+            /// - All handlers receive and can update the actor's state
+            /// - Errors returned from handlers are logged and may trigger supervision
+            /// - Handler execution is tracked in the actor's event chain
+            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+            pub mod message_server_client {
+                #[used]
+                #[doc(hidden)]
+                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
+                use super::super::super::super::_rt;
+                pub type ChannelId = super::super::super::super::theater::simple::types::ChannelId;
+                pub type ChannelAccept = super::super::super::super::theater::simple::types::ChannelAccept;
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_handle_send_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: *mut u8,
+                    arg4: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg4;
+                    let result2 = T::handle_send(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (_rt::Vec::from_raw_parts(arg3.cast(), len1, len1),),
+                    );
+                    let ptr3 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result2 {
+                        Ok(e) => {
+                            *ptr3.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t4_0,) = e;
+                            match t4_0 {
+                                Some(e) => {
+                                    *ptr3
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec5 = (e).into_boxed_slice();
+                                    let ptr5 = vec5.as_ptr().cast::<u8>();
+                                    let len5 = vec5.len();
+                                    ::core::mem::forget(vec5);
+                                    *ptr3
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len5;
+                                    *ptr3
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr5.cast_mut();
+                                }
+                                None => {
+                                    *ptr3
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr3.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec6 = (e.into_bytes()).into_boxed_slice();
+                            let ptr6 = vec6.as_ptr().cast::<u8>();
+                            let len6 = vec6.len();
+                            ::core::mem::forget(vec6);
+                            *ptr3
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len6;
+                            *ptr3
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr6.cast_mut();
+                        }
+                    };
+                    ptr3
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_handle_send<T: Guest>(arg0: *mut u8) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l5 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l5, l6, 1);
+                        }
+                    }
+                }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_init_cabi<T: Guest>(
+                pub unsafe fn _export_handle_request_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
                     arg3: *mut u8,
                     arg4: usize,
+                    arg5: *mut u8,
+                    arg6: usize,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let len1 = arg4;
                     let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let result2 = T::init(
+                    let len2 = arg6;
+                    let result3 = T::handle_request(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -2249,55 +7149,246 @@ pub mod exports {
                             }
                             _ => _rt::invalid_enum_discriminant(),
                         },
-                        (_rt::string_lift(bytes1),),
+                        (
+                            _rt::string_lift(bytes1),
+                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
+                        ),
                     );
-                    let ptr3 = (&raw mut _RET_AREA.0).cast::<u8>();
-                    match result2 {
+                    let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result3 {
+                        Ok(e) => {
+                            *ptr4.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t5_0, t5_1) = e;
+                            match t5_0 {
+                                Some(e) => {
+                                    *ptr4
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec6 = (e).into_boxed_slice();
+                                    let ptr6 = vec6.as_ptr().cast::<u8>();
+                                    let len6 = vec6.len();
+                                    ::core::mem::forget(vec6);
+                                    *ptr4
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len6;
+                                    *ptr4
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr6.cast_mut();
+                                }
+                                None => {
+                                    *ptr4
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                            let (t7_0,) = t5_1;
+                            match t7_0 {
+                                Some(e) => {
+                                    *ptr4
+                                        .add(4 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec8 = (e).into_boxed_slice();
+                                    let ptr8 = vec8.as_ptr().cast::<u8>();
+                                    let len8 = vec8.len();
+                                    ::core::mem::forget(vec8);
+                                    *ptr4
+                                        .add(6 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len8;
+                                    *ptr4
+                                        .add(5 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr8.cast_mut();
+                                }
+                                None => {
+                                    *ptr4
+                                        .add(4 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr4.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec9 = (e.into_bytes()).into_boxed_slice();
+                            let ptr9 = vec9.as_ptr().cast::<u8>();
+                            let len9 = vec9.len();
+                            ::core::mem::forget(vec9);
+                            *ptr4
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len9;
+                            *ptr4
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr9.cast_mut();
+                        }
+                    };
+                    ptr4
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_handle_request<T: Guest>(arg0: *mut u8) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                            let l5 = i32::from(
+                                *arg0
+                                    .add(4 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<u8>(),
+                            );
+                            match l5 {
+                                0 => {}
+                                _ => {
+                                    let l6 = *arg0
+                                        .add(5 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l7 = *arg0
+                                        .add(6 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base8 = l6;
+                                    let len8 = l7;
+                                    _rt::cabi_dealloc(base8, len8 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l9 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l10 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l9, l10, 1);
+                        }
+                    }
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_handle_channel_open_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: *mut u8,
+                    arg4: usize,
+                    arg5: *mut u8,
+                    arg6: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg4;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
+                    let len2 = arg6;
+                    let result3 = T::handle_channel_open(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (
+                            _rt::string_lift(bytes1),
+                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
+                        ),
+                    );
+                    let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result3 {
                         Ok(e) => {
-                            *ptr3.add(0).cast::<u8>() = (0i32) as u8;
-                            let (t4_0,) = e;
-                            match t4_0 {
+                            *ptr4.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t5_0, t5_1) = e;
+                            match t5_0 {
                                 Some(e) => {
-                                    *ptr3
+                                    *ptr4
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (1i32) as u8;
-                                    let vec5 = (e).into_boxed_slice();
-                                    let ptr5 = vec5.as_ptr().cast::<u8>();
-                                    let len5 = vec5.len();
-                                    ::core::mem::forget(vec5);
-                                    *ptr3
+                                    let vec6 = (e).into_boxed_slice();
+                                    let ptr6 = vec6.as_ptr().cast::<u8>();
+                                    let len6 = vec6.len();
+                                    ::core::mem::forget(vec6);
+                                    *ptr4
                                         .add(3 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len5;
-                                    *ptr3
+                                        .cast::<usize>() = len6;
+                                    *ptr4
                                         .add(2 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr5.cast_mut();
+                                        .cast::<*mut u8>() = ptr6.cast_mut();
                                 }
                                 None => {
-                                    *ptr3
+                                    *ptr4
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (0i32) as u8;
                                 }
                             };
+                            let (t7_0,) = t5_1;
+                            let super::super::super::super::theater::simple::types::ChannelAccept {
+                                accepted: accepted8,
+                                message: message8,
+                            } = t7_0;
+                            *ptr4
+                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                .cast::<u8>() = (match accepted8 {
+                                true => 1,
+                                false => 0,
+                            }) as u8;
+                            match message8 {
+                                Some(e) => {
+                                    *ptr4
+                                        .add(5 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec9 = (e).into_boxed_slice();
+                                    let ptr9 = vec9.as_ptr().cast::<u8>();
+                                    let len9 = vec9.len();
+                                    ::core::mem::forget(vec9);
+                                    *ptr4
+                                        .add(7 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len9;
+                                    *ptr4
+                                        .add(6 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr9.cast_mut();
+                                }
+                                None => {
+                                    *ptr4
+                                        .add(5 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
                         }
                         Err(e) => {
-                            *ptr3.add(0).cast::<u8>() = (1i32) as u8;
-                            let vec6 = (e.into_bytes()).into_boxed_slice();
-                            let ptr6 = vec6.as_ptr().cast::<u8>();
-                            let len6 = vec6.len();
-                            ::core::mem::forget(vec6);
-                            *ptr3
+                            *ptr4.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec10 = (e.into_bytes()).into_boxed_slice();
+                            let ptr10 = vec10.as_ptr().cast::<u8>();
+                            let len10 = vec10.len();
+                            ::core::mem::forget(vec10);
+                            *ptr4
                                 .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>() = len6;
-                            *ptr3
+                                .cast::<usize>() = len10;
+                            *ptr4
                                 .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>() = ptr6.cast_mut();
+                                .cast::<*mut u8>() = ptr10.cast_mut();
                         }
                     };
-                    ptr3
+                    ptr4
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_init<T: Guest>(arg0: *mut u8) {
+                pub unsafe fn __post_return_handle_channel_open<T: Guest>(
+                    arg0: *mut u8,
+                ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -2318,141 +7409,153 @@ pub mod exports {
                                     _rt::cabi_dealloc(base4, len4 * 1, 1);
                                 }
                             }
+                            let l5 = i32::from(
+                                *arg0
+                                    .add(5 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<u8>(),
+                            );
+                            match l5 {
+                                0 => {}
+                                _ => {
+                                    let l6 = *arg0
+                                        .add(6 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l7 = *arg0
+                                        .add(7 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base8 = l6;
+                                    let len8 = l7;
+                                    _rt::cabi_dealloc(base8, len8 * 1, 1);
+                                }
+                            }
                         }
                         _ => {
-                            let l5 = *arg0
+                            let l9 = *arg0
                                 .add(::core::mem::size_of::<*const u8>())
                                 .cast::<*mut u8>();
-                            let l6 = *arg0
+                            let l10 = *arg0
                                 .add(2 * ::core::mem::size_of::<*const u8>())
                                 .cast::<usize>();
-                            _rt::cabi_dealloc(l5, l6, 1);
+                            _rt::cabi_dealloc(l9, l10, 1);
                         }
                     }
                 }
-                pub trait Guest {
-                    /// # Initialize the actor
-                    ///
-                    /// Called when the actor is first started or restarted. This function is responsible
-                    /// for setting up the actor's initial state and responding to initialization parameters.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - Current state of the actor, or None if first initialization
-                    /// * `params` - Tuple of initialization parameters, typically including actor ID
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok((state,))` - The updated state to store
-                    /// * `Err(string)` - An error message if initialization fails
-                    ///
-                    /// ## Implementation Notes
-                    ///
-                    /// - If state is None, the actor should create a new initial state
-                    /// - If state contains data, the actor should validate and use that state
-                    /// - The first parameter in the tuple is typically the actor's ID
-                    /// - Any error returned will cause the actor to fail to start
-                    fn init(
-                        state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String,),
-                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
-                }
-                #[doc(hidden)]
-                macro_rules! __export_theater_simple_actor_cabi {
-                    ($ty:ident with_types_in $($path_to_types:tt)*) => {
-                        const _ : () = { #[unsafe (export_name =
-                        "theater:simple/actor#init")] unsafe extern "C" fn
-                        export_init(arg0 : i32, arg1 : * mut u8, arg2 : usize, arg3 : *
-                        mut u8, arg4 : usize,) -> * mut u8 { unsafe {
-                        $($path_to_types)*:: _export_init_cabi::<$ty > (arg0, arg1, arg2,
-                        arg3, arg4) } } #[unsafe (export_name =
-                        "cabi_post_theater:simple/actor#init")] unsafe extern "C" fn
-                        _post_return_init(arg0 : * mut u8,) { unsafe {
-                        $($path_to_types)*:: __post_return_init::<$ty > (arg0) } } };
-                    };
-                }
                 #[doc(hidden)]
-                pub(crate) use __export_theater_simple_actor_cabi;
-                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
-                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
-                struct _RetArea(
-                    [::core::mem::MaybeUninit<
-                        u8,
-                    >; 4 * ::core::mem::size_of::<*const u8>()],
-                );
-                static mut _RET_AREA: _RetArea = _RetArea(
-                    [::core::mem::MaybeUninit::uninit(); 4
-                        * ::core::mem::size_of::<*const u8>()],
-                );
-            }
-            /// # Message Server Client Interface
-            ///
-            /// Defines the callback handlers that actors must implement to receive messages through
-            /// the message server system.
-            ///
-            /// ## Purpose
-            ///
-            /// This interface enables actors to receive and process various types of messages:
-            /// - One-way messages (send)
-            /// - Request-response interactions (request)
-            /// - Bidirectional channel-based communication (channel operations)
-            ///
-            /// By implementing these handler functions, an actor can participate in different
-            /// communication patterns with other actors and external systems.
-            ///
-            /// ## Example
-            ///
-            /// ```rust
-            /// use ntwk::theater::message_server_client::Guest;
-            /// use ntwk::theater::types::{channel_accept, channel_id};
-            /// use serde_json::{json, Value};
-            ///
-            /// struct MyMessageHandler;
-            ///
-            /// impl Guest for MyMessageHandler {
-            /// fn handle_send(state: Option<Value>, params: (Value,))
-            /// -> Result<(Option<Value>,), String> {
-            /// let (message,) = params;
-            /// println!("Received message: {}", message);
-            ///
-            /// // Update state if needed
-            /// let new_state = if let Some(mut state) = state {
-            /// state["message_count"] = json!(state["message_count"].as_u64().unwrap_or(0) + 1);
-            /// Some(state)
-            /// } else {
-            /// Some(json!({"message_count": 1}))
-            /// };
-            ///
-            /// Ok((new_state,))
-            /// }
-            ///
-            /// // Implement other handlers...
-            /// }
-            /// ```
-            ///
-            /// ## Security
-            ///
-            /// The message handlers receive input from potentially untrusted sources, so they should:
-            /// - Validate all incoming message data
-            /// - Handle malformed messages gracefully
-            /// - Protect against common attack vectors like JSON injection
-            ///
-            /// ## Implementation Notes
-            ///
-            /// - All handlers receive and can update the actor's state
-            /// - Errors returned from handlers are logged and may trigger supervision
-            /// - Handler execution is tracked in the actor's event chain
-            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
-            pub mod message_server_client {
-                #[used]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_handle_channel_message_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: *mut u8,
+                    arg4: usize,
+                    arg5: *mut u8,
+                    arg6: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg4;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
+                    let len2 = arg6;
+                    let result3 = T::handle_channel_message(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (
+                            _rt::string_lift(bytes1),
+                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
+                        ),
+                    );
+                    let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result3 {
+                        Ok(e) => {
+                            *ptr4.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t5_0,) = e;
+                            match t5_0 {
+                                Some(e) => {
+                                    *ptr4
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec6 = (e).into_boxed_slice();
+                                    let ptr6 = vec6.as_ptr().cast::<u8>();
+                                    let len6 = vec6.len();
+                                    ::core::mem::forget(vec6);
+                                    *ptr4
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len6;
+                                    *ptr4
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr6.cast_mut();
+                                }
+                                None => {
+                                    *ptr4
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr4.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec7 = (e.into_bytes()).into_boxed_slice();
+                            let ptr7 = vec7.as_ptr().cast::<u8>();
+                            let len7 = vec7.len();
+                            ::core::mem::forget(vec7);
+                            *ptr4
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len7;
+                            *ptr4
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr7.cast_mut();
+                        }
+                    };
+                    ptr4
+                }
                 #[doc(hidden)]
-                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
-                use super::super::super::super::_rt;
-                pub type ChannelId = super::super::super::super::theater::simple::types::ChannelId;
-                pub type ChannelAccept = super::super::super::super::theater::simple::types::ChannelAccept;
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_handle_channel_message<T: Guest>(
+                    arg0: *mut u8,
+                ) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l5 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l5, l6, 1);
+                        }
+                    }
+                }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_send_cabi<T: Guest>(
+                pub unsafe fn _export_handle_channel_close_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
@@ -2461,7 +7564,8 @@ pub mod exports {
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let len1 = arg4;
-                    let result2 = T::handle_send(
+                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
+                    let result2 = T::handle_channel_close(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -2473,7 +7577,7 @@ pub mod exports {
                             }
                             _ => _rt::invalid_enum_discriminant(),
                         },
-                        (_rt::Vec::from_raw_parts(arg3.cast(), len1, len1),),
+                        (_rt::string_lift(bytes1),),
                     );
                     let ptr3 = (&raw mut _RET_AREA.0).cast::<u8>();
                     match result2 {
@@ -2521,7 +7625,9 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_send<T: Guest>(arg0: *mut u8) {
+                pub unsafe fn __post_return_handle_channel_close<T: Guest>(
+                    arg0: *mut u8,
+                ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -2554,175 +7660,291 @@ pub mod exports {
                         }
                     }
                 }
+                pub trait Guest {
+                    /// # Handle one-way message
+                    ///
+                    /// Processes a one-way message that doesn't require a response.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - The current actor state or None if not initialized
+                    /// * `params` - Tuple containing:
+                    /// * `json` - The message payload
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
+                    /// * `Err(string)` - Error message if message handling fails
+                    ///
+                    /// ## Example
+                    ///
+                    /// ```rust
+                    /// fn handle_send(state: Option<Value>, params: (Value,)) -> Result<(Option<Value>,), String> {
+                    /// let (message,) = params;
+                    ///
+                    /// // Process the message...
+                    ///
+                    /// // Return updated state (or None to keep current state)
+                    /// Ok((Some(updated_state),))
+                    /// }
+                    /// ```
+                    fn handle_send(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (_rt::Vec<u8>,),
+                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
+                    /// # Handle request-response message
+                    ///
+                    /// Processes a request that requires a response.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - The current actor state or None if not initialized
+                    /// * `params` - Tuple containing:
+                    /// * `string` - The request ID
+                    /// * `json` - The request payload
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((option<list<u8>>, (option<list<u8>>,)))` - Tuple containing:
+                    /// * Updated actor state (or None)
+                    /// * Response message to send back (or None to send a response yet)
+                    /// * `Err(string)` - Error message if request handling fails
+                    ///
+                    /// ## Example
+                    ///
+                    /// ```rust
+                    /// fn handle_request(state: Option<Vec<u8>>, params: (String, Vec<u8>))
+                    /// -> Result<(Option<Vec<u8>>, (Option<Vec<u8>>,)), String> {
+                    /// let (request_id, request) = params;
+                    ///
+                    /// // Process the request...
+                    /// let response = json!({"status": "success", "data": "result"});
+                    ///
+                    /// // Return updated state and response
+                    /// Ok((Some(updated_state), (Some(response),)))
+                    /// }
+                    /// ```
+                    fn handle_request(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (_rt::String, _rt::Vec<u8>),
+                    ) -> Result<
+                        (Option<_rt::Vec<u8>>, (Option<_rt::Vec<u8>>,)),
+                        _rt::String,
+                    >;
+                    /// # Handle channel open request
+                    ///
+                    /// Called when another actor requests to open a communication channel.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - The current actor state or None if not initialized
+                    /// * `params` - Tuple containing:
+                    /// * `json` - The initial message payload
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((option<list<u8>>, (channel-accept,)))` - Tuple containing:
+                    /// * Updated actor state (or None to retain current state)
+                    /// * Channel acceptance decision
+                    /// * `Err(string)` - Error message if open handling fails
+                    ///
+                    /// ## Example
+                    ///
+                    /// ```rust
+                    /// fn handle_channel_open(state: Option<Value>, params: (Value,))
+                    /// -> Result<(Option<Value>, (channel_accept,)), String> {
+                    /// let (initial_message,) = params;
+                    ///
+                    /// // Decide whether to accept the channel
+                    /// let accept = channel_accept {
+                    /// accept: true,
+                    /// error_message: None,
+                    /// };
+                    ///
+                    /// // Return updated state and acceptance decision
+                    /// Ok((Some(updated_state), (accept,)))
+                    /// }
+                    /// ```
+                    ///
+                    /// ## Security
+                    ///
+                    /// The actor should validate the channel request and only accept channels from
+                    /// trusted sources. The acceptance mechanism provides a security checkpoint.
+                    fn handle_channel_open(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (_rt::String, _rt::Vec<u8>),
+                    ) -> Result<(Option<_rt::Vec<u8>>, (ChannelAccept,)), _rt::String>;
+                    /// # Handle channel message
+                    ///
+                    /// Processes a message received on an established channel.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - The current actor state or None if not initialized
+                    /// * `params` - Tuple containing:
+                    /// * `channel-id` - ID of the channel the message was received on
+                    /// * `json` - The message payload
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
+                    /// * `Err(string)` - Error message if message handling fails
+                    ///
+                    /// ## Example
+                    ///
+                    /// ```rust
+                    /// fn handle_channel_message(state: Option<Value>, params: (channel_id, Value))
+                    /// -> Result<(Option<Value>,), String> {
+                    /// let (channel_id, message) = params;
+                    ///
+                    /// // Process the channel message...
+                    /// println!("Received message on channel {}: {}", channel_id, message);
+                    ///
+                    /// // Return updated state (or None to keep current state)
+                    /// Ok((Some(updated_state),))
+                    /// }
+                    /// ```
+                    fn handle_channel_message(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (ChannelId, _rt::Vec<u8>),
+                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
+                    /// # Handle channel close
+                    ///
+                    /// Called when a communication channel is closed.
+                    ///
+                    /// ## Parameters
+                    ///
+                    /// * `state` - The current actor state or None if not initialized
+                    /// * `params` - Tuple containing:
+                    /// * `channel-id` - ID of the channel that was closed
+                    ///
+                    /// ## Returns
+                    ///
+                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
+                    /// * `Err(string)` - Error message if close handling fails
+                    ///
+                    /// ## Example
+                    ///
+                    /// ```rust
+                    /// fn handle_channel_close(state: Option<Value>, params: (channel_id,))
+                    /// -> Result<(Option<Value>,), String> {
+                    /// let (channel_id,) = params;
+                    ///
+                    /// // Clean up any resources associated with the channel
+                    /// println!("Channel {} closed", channel_id);
+                    ///
+                    /// // Return updated state (or None to keep current state)
+                    /// Ok((Some(updated_state),))
+                    /// }
+                    /// ```
+                    ///
+                    /// ## Implementation Notes
+                    ///
+                    /// This function should perform any necessary cleanup for the closed channel,
+                    /// such as releasing resources or updating internal state to reflect the channel closure.
+                    fn handle_channel_close(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (ChannelId,),
+                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
+                }
                 #[doc(hidden)]
-                #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_request_cabi<T: Guest>(
-                    arg0: i32,
-                    arg1: *mut u8,
-                    arg2: usize,
-                    arg3: *mut u8,
-                    arg4: usize,
-                    arg5: *mut u8,
-                    arg6: usize,
-                ) -> *mut u8 {
-                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
-                    let len1 = arg4;
-                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let len2 = arg6;
-                    let result3 = T::handle_request(
-                        match arg0 {
-                            0 => None,
-                            1 => {
-                                let e = {
-                                    let len0 = arg2;
-                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
-                                };
-                                Some(e)
-                            }
-                            _ => _rt::invalid_enum_discriminant(),
-                        },
-                        (
-                            _rt::string_lift(bytes1),
-                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
-                        ),
-                    );
-                    let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
-                    match result3 {
-                        Ok(e) => {
-                            *ptr4.add(0).cast::<u8>() = (0i32) as u8;
-                            let (t5_0, t5_1) = e;
-                            match t5_0 {
-                                Some(e) => {
-                                    *ptr4
-                                        .add(::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (1i32) as u8;
-                                    let vec6 = (e).into_boxed_slice();
-                                    let ptr6 = vec6.as_ptr().cast::<u8>();
-                                    let len6 = vec6.len();
-                                    ::core::mem::forget(vec6);
-                                    *ptr4
-                                        .add(3 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len6;
-                                    *ptr4
-                                        .add(2 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr6.cast_mut();
-                                }
-                                None => {
-                                    *ptr4
-                                        .add(::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (0i32) as u8;
-                                }
-                            };
-                            let (t7_0,) = t5_1;
-                            match t7_0 {
-                                Some(e) => {
-                                    *ptr4
-                                        .add(4 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (1i32) as u8;
-                                    let vec8 = (e).into_boxed_slice();
-                                    let ptr8 = vec8.as_ptr().cast::<u8>();
-                                    let len8 = vec8.len();
-                                    ::core::mem::forget(vec8);
-                                    *ptr4
-                                        .add(6 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len8;
-                                    *ptr4
-                                        .add(5 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr8.cast_mut();
-                                }
-                                None => {
-                                    *ptr4
-                                        .add(4 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (0i32) as u8;
-                                }
-                            };
-                        }
-                        Err(e) => {
-                            *ptr4.add(0).cast::<u8>() = (1i32) as u8;
-                            let vec9 = (e.into_bytes()).into_boxed_slice();
-                            let ptr9 = vec9.as_ptr().cast::<u8>();
-                            let len9 = vec9.len();
-                            ::core::mem::forget(vec9);
-                            *ptr4
-                                .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>() = len9;
-                            *ptr4
-                                .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>() = ptr9.cast_mut();
-                        }
+                macro_rules! __export_theater_simple_message_server_client_cabi {
+                    ($ty:ident with_types_in $($path_to_types:tt)*) => {
+                        const _ : () = { #[unsafe (export_name =
+                        "theater:simple/message-server-client#handle-send")] unsafe
+                        extern "C" fn export_handle_send(arg0 : i32, arg1 : * mut u8,
+                        arg2 : usize, arg3 : * mut u8, arg4 : usize,) -> * mut u8 {
+                        unsafe { $($path_to_types)*:: _export_handle_send_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/message-server-client#handle-send")]
+                        unsafe extern "C" fn _post_return_handle_send(arg0 : * mut u8,) {
+                        unsafe { $($path_to_types)*:: __post_return_handle_send::<$ty >
+                        (arg0) } } #[unsafe (export_name =
+                        "theater:simple/message-server-client#handle-request")] unsafe
+                        extern "C" fn export_handle_request(arg0 : i32, arg1 : * mut u8,
+                        arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : * mut u8,
+                        arg6 : usize,) -> * mut u8 { unsafe { $($path_to_types)*::
+                        _export_handle_request_cabi::<$ty > (arg0, arg1, arg2, arg3,
+                        arg4, arg5, arg6) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/message-server-client#handle-request")]
+                        unsafe extern "C" fn _post_return_handle_request(arg0 : * mut
+                        u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_request::<$ty > (arg0) } } #[unsafe
+                        (export_name =
+                        "theater:simple/message-server-client#handle-channel-open")]
+                        unsafe extern "C" fn export_handle_channel_open(arg0 : i32, arg1
+                        : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : *
+                        mut u8, arg6 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_channel_open_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6) } } #[unsafe
+                        (export_name =
+                        "cabi_post_theater:simple/message-server-client#handle-channel-open")]
+                        unsafe extern "C" fn _post_return_handle_channel_open(arg0 : *
+                        mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_channel_open::<$ty > (arg0) } } #[unsafe
+                        (export_name =
+                        "theater:simple/message-server-client#handle-channel-message")]
+                        unsafe extern "C" fn export_handle_channel_message(arg0 : i32,
+                        arg1 : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize,
+                        arg5 : * mut u8, arg6 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_channel_message_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6) } } #[unsafe
+                        (export_name =
+                        "cabi_post_theater:simple/message-server-client#handle-channel-message")]
+                        unsafe extern "C" fn _post_return_handle_channel_message(arg0 : *
+                        mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_channel_message::<$ty > (arg0) } } #[unsafe
+                        (export_name =
+                        "theater:simple/message-server-client#handle-channel-close")]
+                        unsafe extern "C" fn export_handle_channel_close(arg0 : i32, arg1
+                        : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize,) -> *
+                        mut u8 { unsafe { $($path_to_types)*::
+                        _export_handle_channel_close_cabi::<$ty > (arg0, arg1, arg2,
+                        arg3, arg4) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/message-server-client#handle-channel-close")]
+                        unsafe extern "C" fn _post_return_handle_channel_close(arg0 : *
+                        mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_channel_close::<$ty > (arg0) } } };
                     };
-                    ptr4
                 }
                 #[doc(hidden)]
-                #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_request<T: Guest>(arg0: *mut u8) {
-                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
-                    match l0 {
-                        0 => {
-                            let l1 = i32::from(
-                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
-                            );
-                            match l1 {
-                                0 => {}
-                                _ => {
-                                    let l2 = *arg0
-                                        .add(2 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>();
-                                    let l3 = *arg0
-                                        .add(3 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>();
-                                    let base4 = l2;
-                                    let len4 = l3;
-                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
-                                }
-                            }
-                            let l5 = i32::from(
-                                *arg0
-                                    .add(4 * ::core::mem::size_of::<*const u8>())
-                                    .cast::<u8>(),
-                            );
-                            match l5 {
-                                0 => {}
-                                _ => {
-                                    let l6 = *arg0
-                                        .add(5 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>();
-                                    let l7 = *arg0
-                                        .add(6 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>();
-                                    let base8 = l6;
-                                    let len8 = l7;
-                                    _rt::cabi_dealloc(base8, len8 * 1, 1);
-                                }
-                            }
-                        }
-                        _ => {
-                            let l9 = *arg0
-                                .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>();
-                            let l10 = *arg0
-                                .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>();
-                            _rt::cabi_dealloc(l9, l10, 1);
-                        }
-                    }
-                }
+                pub(crate) use __export_theater_simple_message_server_client_cabi;
+                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                struct _RetArea(
+                    [::core::mem::MaybeUninit<
+                        u8,
+                    >; 8 * ::core::mem::size_of::<*const u8>()],
+                );
+                static mut _RET_AREA: _RetArea = _RetArea(
+                    [::core::mem::MaybeUninit::uninit(); 8
+                        * ::core::mem::size_of::<*const u8>()],
+                );
+            }
+            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+            pub mod supervisor_handlers {
+                #[used]
+                #[doc(hidden)]
+                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
+                use super::super::super::super::_rt;
+                pub type WitActorError = super::super::super::super::theater::simple::types::WitActorError;
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_channel_open_cabi<T: Guest>(
+                pub unsafe fn _export_handle_child_error_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
                     arg3: *mut u8,
-                    arg4: usize,
-                    arg5: *mut u8,
-                    arg6: usize,
+                    arg4: usize,
+                    arg5: i32,
+                    arg6: i32,
+                    arg7: *mut u8,
+                    arg8: usize,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let len1 = arg4;
                     let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let len2 = arg6;
-                    let result3 = T::handle_channel_open(
+                    let result3 = T::handle_child_error(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -2736,14 +7958,29 @@ pub mod exports {
                         },
                         (
                             _rt::string_lift(bytes1),
-                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
+                            super::super::super::super::theater::simple::types::WitActorError {
+                                error_type: super::super::super::super::theater::simple::types::WitErrorType::_lift(
+                                    arg5 as u8,
+                                ),
+                                data: match arg6 {
+                                    0 => None,
+                                    1 => {
+                                        let e = {
+                                            let len2 = arg8;
+                                            _rt::Vec::from_raw_parts(arg7.cast(), len2, len2)
+                                        };
+                                        Some(e)
+                                    }
+                                    _ => _rt::invalid_enum_discriminant(),
+                                },
+                            },
                         ),
                     );
                     let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
                     match result3 {
                         Ok(e) => {
                             *ptr4.add(0).cast::<u8>() = (0i32) as u8;
-                            let (t5_0, t5_1) = e;
+                            let (t5_0,) = e;
                             match t5_0 {
                                 Some(e) => {
                                     *ptr4
@@ -2766,61 +8003,26 @@ pub mod exports {
                                         .cast::<u8>() = (0i32) as u8;
                                 }
                             };
-                            let (t7_0,) = t5_1;
-                            let super::super::super::super::theater::simple::types::ChannelAccept {
-                                accepted: accepted8,
-                                message: message8,
-                            } = t7_0;
-                            *ptr4
-                                .add(4 * ::core::mem::size_of::<*const u8>())
-                                .cast::<u8>() = (match accepted8 {
-                                true => 1,
-                                false => 0,
-                            }) as u8;
-                            match message8 {
-                                Some(e) => {
-                                    *ptr4
-                                        .add(5 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (1i32) as u8;
-                                    let vec9 = (e).into_boxed_slice();
-                                    let ptr9 = vec9.as_ptr().cast::<u8>();
-                                    let len9 = vec9.len();
-                                    ::core::mem::forget(vec9);
-                                    *ptr4
-                                        .add(7 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len9;
-                                    *ptr4
-                                        .add(6 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr9.cast_mut();
-                                }
-                                None => {
-                                    *ptr4
-                                        .add(5 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<u8>() = (0i32) as u8;
-                                }
-                            };
                         }
                         Err(e) => {
                             *ptr4.add(0).cast::<u8>() = (1i32) as u8;
-                            let vec10 = (e.into_bytes()).into_boxed_slice();
-                            let ptr10 = vec10.as_ptr().cast::<u8>();
-                            let len10 = vec10.len();
-                            ::core::mem::forget(vec10);
+                            let vec7 = (e.into_bytes()).into_boxed_slice();
+                            let ptr7 = vec7.as_ptr().cast::<u8>();
+                            let len7 = vec7.len();
+                            ::core::mem::forget(vec7);
                             *ptr4
                                 .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>() = len10;
+                                .cast::<usize>() = len7;
                             *ptr4
                                 .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>() = ptr10.cast_mut();
+                                .cast::<*mut u8>() = ptr7.cast_mut();
                         }
                     };
                     ptr4
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_channel_open<T: Guest>(
-                    arg0: *mut u8,
-                ) {
+                pub unsafe fn __post_return_handle_child_error<T: Guest>(arg0: *mut u8) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -2841,53 +8043,34 @@ pub mod exports {
                                     _rt::cabi_dealloc(base4, len4 * 1, 1);
                                 }
                             }
-                            let l5 = i32::from(
-                                *arg0
-                                    .add(5 * ::core::mem::size_of::<*const u8>())
-                                    .cast::<u8>(),
-                            );
-                            match l5 {
-                                0 => {}
-                                _ => {
-                                    let l6 = *arg0
-                                        .add(6 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>();
-                                    let l7 = *arg0
-                                        .add(7 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>();
-                                    let base8 = l6;
-                                    let len8 = l7;
-                                    _rt::cabi_dealloc(base8, len8 * 1, 1);
-                                }
-                            }
                         }
                         _ => {
-                            let l9 = *arg0
+                            let l5 = *arg0
                                 .add(::core::mem::size_of::<*const u8>())
                                 .cast::<*mut u8>();
-                            let l10 = *arg0
+                            let l6 = *arg0
                                 .add(2 * ::core::mem::size_of::<*const u8>())
                                 .cast::<usize>();
-                            _rt::cabi_dealloc(l9, l10, 1);
+                            _rt::cabi_dealloc(l5, l6, 1);
                         }
                     }
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_channel_message_cabi<T: Guest>(
+                pub unsafe fn _export_handle_child_exit_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
                     arg3: *mut u8,
                     arg4: usize,
-                    arg5: *mut u8,
-                    arg6: usize,
+                    arg5: i32,
+                    arg6: *mut u8,
+                    arg7: usize,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let len1 = arg4;
                     let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let len2 = arg6;
-                    let result3 = T::handle_channel_message(
+                    let result3 = T::handle_child_exit(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -2901,7 +8084,17 @@ pub mod exports {
                         },
                         (
                             _rt::string_lift(bytes1),
-                            _rt::Vec::from_raw_parts(arg5.cast(), len2, len2),
+                            match arg5 {
+                                0 => None,
+                                1 => {
+                                    let e = {
+                                        let len2 = arg7;
+                                        _rt::Vec::from_raw_parts(arg6.cast(), len2, len2)
+                                    };
+                                    Some(e)
+                                }
+                                _ => _rt::invalid_enum_discriminant(),
+                            },
                         ),
                     );
                     let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
@@ -2950,9 +8143,7 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_channel_message<T: Guest>(
-                    arg0: *mut u8,
-                ) {
+                pub unsafe fn __post_return_handle_child_exit<T: Guest>(arg0: *mut u8) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -2987,7 +8178,7 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_channel_close_cabi<T: Guest>(
+                pub unsafe fn _export_handle_child_external_stop_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
@@ -2997,7 +8188,7 @@ pub mod exports {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let len1 = arg4;
                     let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let result2 = T::handle_channel_close(
+                    let result2 = T::handle_child_external_stop(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -3057,7 +8248,7 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_channel_close<T: Guest>(
+                pub unsafe fn __post_return_handle_child_external_stop<T: Guest>(
                     arg0: *mut u8,
                 ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
@@ -3084,299 +8275,449 @@ pub mod exports {
                         _ => {
                             let l5 = *arg0
                                 .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>();
-                            let l6 = *arg0
-                                .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>();
-                            _rt::cabi_dealloc(l5, l6, 1);
-                        }
-                    }
-                }
-                pub trait Guest {
-                    /// # Handle one-way message
-                    ///
-                    /// Processes a one-way message that doesn't require a response.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - The current actor state or None if not initialized
-                    /// * `params` - Tuple containing:
-                    /// * `json` - The message payload
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
-                    /// * `Err(string)` - Error message if message handling fails
-                    ///
-                    /// ## Example
-                    ///
-                    /// ```rust
-                    /// fn handle_send(state: Option<Value>, params: (Value,)) -> Result<(Option<Value>,), String> {
-                    /// let (message,) = params;
-                    ///
-                    /// // Process the message...
-                    ///
-                    /// // Return updated state (or None to keep current state)
-                    /// Ok((Some(updated_state),))
-                    /// }
-                    /// ```
-                    fn handle_send(
-                        state: Option<_rt::Vec<u8>>,
-                        params: (_rt::Vec<u8>,),
-                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
-                    /// # Handle request-response message
-                    ///
-                    /// Processes a request that requires a response.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - The current actor state or None if not initialized
-                    /// * `params` - Tuple containing:
-                    /// * `string` - The request ID
-                    /// * `json` - The request payload
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok((option<list<u8>>, (option<list<u8>>,)))` - Tuple containing:
-                    /// * Updated actor state (or None)
-                    /// * Response message to send back (or None to send a response yet)
-                    /// * `Err(string)` - Error message if request handling fails
-                    ///
-                    /// ## Example
-                    ///
-                    /// ```rust
-                    /// fn handle_request(state: Option<Vec<u8>>, params: (String, Vec<u8>))
-                    /// -> Result<(Option<Vec<u8>>, (Option<Vec<u8>>,)), String> {
-                    /// let (request_id, request) = params;
-                    ///
-                    /// // Process the request...
-                    /// let response = json!({"status": "success", "data": "result"});
-                    ///
-                    /// // Return updated state and response
-                    /// Ok((Some(updated_state), (Some(response),)))
-                    /// }
-                    /// ```
-                    fn handle_request(
-                        state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String, _rt::Vec<u8>),
-                    ) -> Result<
-                        (Option<_rt::Vec<u8>>, (Option<_rt::Vec<u8>>,)),
-                        _rt::String,
-                    >;
-                    /// # Handle channel open request
-                    ///
-                    /// Called when another actor requests to open a communication channel.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - The current actor state or None if not initialized
-                    /// * `params` - Tuple containing:
-                    /// * `json` - The initial message payload
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok((option<list<u8>>, (channel-accept,)))` - Tuple containing:
-                    /// * Updated actor state (or None to retain current state)
-                    /// * Channel acceptance decision
-                    /// * `Err(string)` - Error message if open handling fails
-                    ///
-                    /// ## Example
-                    ///
-                    /// ```rust
-                    /// fn handle_channel_open(state: Option<Value>, params: (Value,))
-                    /// -> Result<(Option<Value>, (channel_accept,)), String> {
-                    /// let (initial_message,) = params;
-                    ///
-                    /// // Decide whether to accept the channel
-                    /// let accept = channel_accept {
-                    /// accept: true,
-                    /// error_message: None,
-                    /// };
-                    ///
-                    /// // Return updated state and acceptance decision
-                    /// Ok((Some(updated_state), (accept,)))
-                    /// }
-                    /// ```
-                    ///
-                    /// ## Security
-                    ///
-                    /// The actor should validate the channel request and only accept channels from
-                    /// trusted sources. The acceptance mechanism provides a security checkpoint.
-                    fn handle_channel_open(
-                        state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String, _rt::Vec<u8>),
-                    ) -> Result<(Option<_rt::Vec<u8>>, (ChannelAccept,)), _rt::String>;
-                    /// # Handle channel message
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l5, l6, 1);
+                        }
+                    }
+                }
+                pub trait Guest {
+                    /// # Handle a child actor error
                     ///
-                    /// Processes a message received on an established channel.
+                    /// Processes an error from a child actor, allowing the parent to react or log the error.
                     ///
                     /// ## Parameters
                     ///
-                    /// * `state` - The current actor state or None if not initialized
-                    /// * `params` - Tuple containing:
-                    /// * `channel-id` - ID of the channel the message was received on
-                    /// * `json` - The message payload
+                    /// * `state` - Optional state of the parent actor (for context)
+                    /// * `params` - Tuple containing the child ID and error data
                     ///
                     /// ## Returns
                     ///
-                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
-                    /// * `Err(string)` - Error message if message handling fails
-                    ///
-                    /// ## Example
-                    ///
-                    /// ```rust
-                    /// fn handle_channel_message(state: Option<Value>, params: (channel_id, Value))
-                    /// -> Result<(Option<Value>,), String> {
-                    /// let (channel_id, message) = params;
-                    ///
-                    /// // Process the channel message...
-                    /// println!("Received message on channel {}: {}", channel_id, message);
-                    ///
-                    /// // Return updated state (or None to keep current state)
-                    /// Ok((Some(updated_state),))
-                    /// }
-                    /// ```
-                    fn handle_channel_message(
+                    /// * `Ok(tuple<option<list<u8>>, string>)` - Updated state and result message
+                    /// * `Err(string)` - Error message if handling fails
+                    fn handle_child_error(
                         state: Option<_rt::Vec<u8>>,
-                        params: (ChannelId, _rt::Vec<u8>),
+                        params: (_rt::String, WitActorError),
                     ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
-                    /// # Handle channel close
-                    ///
-                    /// Called when a communication channel is closed.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - The current actor state or None if not initialized
-                    /// * `params` - Tuple containing:
-                    /// * `channel-id` - ID of the channel that was closed
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok((option<list<u8>>,))` - Updated actor state (or None to retain current state)
-                    /// * `Err(string)` - Error message if close handling fails
-                    ///
-                    /// ## Example
-                    ///
-                    /// ```rust
-                    /// fn handle_channel_close(state: Option<Value>, params: (channel_id,))
-                    /// -> Result<(Option<Value>,), String> {
-                    /// let (channel_id,) = params;
-                    ///
-                    /// // Clean up any resources associated with the channel
-                    /// println!("Channel {} closed", channel_id);
-                    ///
-                    /// // Return updated state (or None to keep current state)
-                    /// Ok((Some(updated_state),))
-                    /// }
-                    /// ```
-                    ///
-                    /// ## Implementation Notes
-                    ///
-                    /// This function should perform any necessary cleanup for the closed channel,
-                    /// such as releasing resources or updating internal state to reflect the channel closure.
-                    fn handle_channel_close(
+                    fn handle_child_exit(
                         state: Option<_rt::Vec<u8>>,
-                        params: (ChannelId,),
+                        params: (_rt::String, Option<_rt::Vec<u8>>),
+                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
+                    fn handle_child_external_stop(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (_rt::String,),
                     ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
                 }
                 #[doc(hidden)]
-                macro_rules! __export_theater_simple_message_server_client_cabi {
+                macro_rules! __export_theater_simple_supervisor_handlers_cabi {
                     ($ty:ident with_types_in $($path_to_types:tt)*) => {
                         const _ : () = { #[unsafe (export_name =
-                        "theater:simple/message-server-client#handle-send")] unsafe
-                        extern "C" fn export_handle_send(arg0 : i32, arg1 : * mut u8,
-                        arg2 : usize, arg3 : * mut u8, arg4 : usize,) -> * mut u8 {
-                        unsafe { $($path_to_types)*:: _export_handle_send_cabi::<$ty >
-                        (arg0, arg1, arg2, arg3, arg4) } } #[unsafe (export_name =
-                        "cabi_post_theater:simple/message-server-client#handle-send")]
-                        unsafe extern "C" fn _post_return_handle_send(arg0 : * mut u8,) {
-                        unsafe { $($path_to_types)*:: __post_return_handle_send::<$ty >
-                        (arg0) } } #[unsafe (export_name =
-                        "theater:simple/message-server-client#handle-request")] unsafe
-                        extern "C" fn export_handle_request(arg0 : i32, arg1 : * mut u8,
-                        arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : * mut u8,
-                        arg6 : usize,) -> * mut u8 { unsafe { $($path_to_types)*::
-                        _export_handle_request_cabi::<$ty > (arg0, arg1, arg2, arg3,
-                        arg4, arg5, arg6) } } #[unsafe (export_name =
-                        "cabi_post_theater:simple/message-server-client#handle-request")]
-                        unsafe extern "C" fn _post_return_handle_request(arg0 : * mut
+                        "theater:simple/supervisor-handlers#handle-child-error")] unsafe
+                        extern "C" fn export_handle_child_error(arg0 : i32, arg1 : * mut
+                        u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : i32, arg6
+                        : i32, arg7 : * mut u8, arg8 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_child_error_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8) } }
+                        #[unsafe (export_name =
+                        "cabi_post_theater:simple/supervisor-handlers#handle-child-error")]
+                        unsafe extern "C" fn _post_return_handle_child_error(arg0 : * mut
                         u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_request::<$ty > (arg0) } } #[unsafe
-                        (export_name =
-                        "theater:simple/message-server-client#handle-channel-open")]
-                        unsafe extern "C" fn export_handle_channel_open(arg0 : i32, arg1
-                        : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : *
-                        mut u8, arg6 : usize,) -> * mut u8 { unsafe {
-                        $($path_to_types)*:: _export_handle_channel_open_cabi::<$ty >
-                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6) } } #[unsafe
-                        (export_name =
-                        "cabi_post_theater:simple/message-server-client#handle-channel-open")]
-                        unsafe extern "C" fn _post_return_handle_channel_open(arg0 : *
-                        mut u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_channel_open::<$ty > (arg0) } } #[unsafe
+                        __post_return_handle_child_error::<$ty > (arg0) } } #[unsafe
                         (export_name =
-                        "theater:simple/message-server-client#handle-channel-message")]
-                        unsafe extern "C" fn export_handle_channel_message(arg0 : i32,
-                        arg1 : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize,
-                        arg5 : * mut u8, arg6 : usize,) -> * mut u8 { unsafe {
-                        $($path_to_types)*:: _export_handle_channel_message_cabi::<$ty >
-                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6) } } #[unsafe
+                        "theater:simple/supervisor-handlers#handle-child-exit")] unsafe
+                        extern "C" fn export_handle_child_exit(arg0 : i32, arg1 : * mut
+                        u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : i32, arg6
+                        : * mut u8, arg7 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_child_exit_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7) } } #[unsafe
                         (export_name =
-                        "cabi_post_theater:simple/message-server-client#handle-channel-message")]
-                        unsafe extern "C" fn _post_return_handle_channel_message(arg0 : *
-                        mut u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_channel_message::<$ty > (arg0) } } #[unsafe
+                        "cabi_post_theater:simple/supervisor-handlers#handle-child-exit")]
+                        unsafe extern "C" fn _post_return_handle_child_exit(arg0 : * mut
+                        u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_child_exit::<$ty > (arg0) } } #[unsafe
                         (export_name =
-                        "theater:simple/message-server-client#handle-channel-close")]
-                        unsafe extern "C" fn export_handle_channel_close(arg0 : i32, arg1
-                        : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 : usize,) -> *
-                        mut u8 { unsafe { $($path_to_types)*::
-                        _export_handle_channel_close_cabi::<$ty > (arg0, arg1, arg2,
-                        arg3, arg4) } } #[unsafe (export_name =
-                        "cabi_post_theater:simple/message-server-client#handle-channel-close")]
-                        unsafe extern "C" fn _post_return_handle_channel_close(arg0 : *
-                        mut u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_channel_close::<$ty > (arg0) } } };
+                        "theater:simple/supervisor-handlers#handle-child-external-stop")]
+                        unsafe extern "C" fn export_handle_child_external_stop(arg0 :
+                        i32, arg1 : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 :
+                        usize,) -> * mut u8 { unsafe { $($path_to_types)*::
+                        _export_handle_child_external_stop_cabi::<$ty > (arg0, arg1,
+                        arg2, arg3, arg4) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/supervisor-handlers#handle-child-external-stop")]
+                        unsafe extern "C" fn _post_return_handle_child_external_stop(arg0
+                        : * mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_child_external_stop::<$ty > (arg0) } } };
+                    };
+                }
+                #[doc(hidden)]
+                pub(crate) use __export_theater_simple_supervisor_handlers_cabi;
+                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                struct _RetArea(
+                    [::core::mem::MaybeUninit<
+                        u8,
+                    >; 4 * ::core::mem::size_of::<*const u8>()],
+                );
+                static mut _RET_AREA: _RetArea = _RetArea(
+                    [::core::mem::MaybeUninit::uninit(); 4
+                        * ::core::mem::size_of::<*const u8>()],
+                );
+            }
+            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+            pub mod http_handlers {
+                #[used]
+                #[doc(hidden)]
+                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
+                use super::super::super::super::_rt;
+                pub type HttpRequest = super::super::super::super::theater::simple::http_types::HttpRequest;
+                pub type HttpResponse = super::super::super::super::theater::simple::http_types::HttpResponse;
+                pub type WebsocketMessage = super::super::super::super::theater::simple::websocket_types::WebsocketMessage;
+                pub type MiddlewareResult = super::super::super::super::theater::simple::http_types::MiddlewareResult;
+                pub type HandlerId = super::super::super::super::theater::simple::http_framework::HandlerId;
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_handle_request_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: i64,
+                    arg4: *mut u8,
+                    arg5: usize,
+                    arg6: *mut u8,
+                    arg7: usize,
+                    arg8: *mut u8,
+                    arg9: usize,
+                    arg10: i32,
+                    arg11: *mut u8,
+                    arg12: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg5;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg4.cast(), len1, len1);
+                    let len2 = arg7;
+                    let bytes2 = _rt::Vec::from_raw_parts(arg6.cast(), len2, len2);
+                    let base9 = arg8;
+                    let len9 = arg9;
+                    let mut result9 = _rt::Vec::with_capacity(len9);
+                    for i in 0..len9 {
+                        let base = base9
+                            .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                        let e9 = {
+                            let l3 = *base.add(0).cast::<*mut u8>();
+                            let l4 = *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len5 = l4;
+                            let bytes5 = _rt::Vec::from_raw_parts(l3.cast(), len5, len5);
+                            let l6 = *base
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l7 = *base
+                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len8 = l7;
+                            let bytes8 = _rt::Vec::from_raw_parts(l6.cast(), len8, len8);
+                            (_rt::string_lift(bytes5), _rt::string_lift(bytes8))
+                        };
+                        result9.push(e9);
+                    }
+                    _rt::cabi_dealloc(
+                        base9,
+                        len9 * (4 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
+                    let result11 = T::handle_request(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (
+                            arg3 as u64,
+                            super::super::super::super::theater::simple::http_types::HttpRequest {
+                                method: _rt::string_lift(bytes1),
+                                uri: _rt::string_lift(bytes2),
+                                headers: result9,
+                                body: match arg10 {
+                                    0 => None,
+                                    1 => {
+                                        let e = {
+                                            let len10 = arg12;
+                                            _rt::Vec::from_raw_parts(arg11.cast(), len10, len10)
+                                        };
+                                        Some(e)
+                                    }
+                                    _ => _rt::invalid_enum_discriminant(),
+                                },
+                            },
+                        ),
+                    );
+                    let ptr12 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result11 {
+                        Ok(e) => {
+                            *ptr12.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t13_0, t13_1) = e;
+                            match t13_0 {
+                                Some(e) => {
+                                    *ptr12
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec14 = (e).into_boxed_slice();
+                                    let ptr14 = vec14.as_ptr().cast::<u8>();
+                                    let len14 = vec14.len();
+                                    ::core::mem::forget(vec14);
+                                    *ptr12
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len14;
+                                    *ptr12
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr14.cast_mut();
+                                }
+                                None => {
+                                    *ptr12
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                            let (t15_0,) = t13_1;
+                            let super::super::super::super::theater::simple::http_types::HttpResponse {
+                                status: status16,
+                                headers: headers16,
+                                body: body16,
+                            } = t15_0;
+                            *ptr12
+                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                .cast::<u16>() = (_rt::as_i32(status16)) as u16;
+                            let vec20 = headers16;
+                            let len20 = vec20.len();
+                            let layout20 = _rt::alloc::Layout::from_size_align_unchecked(
+                                vec20.len() * (4 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let result20 = if layout20.size() != 0 {
+                                let ptr = _rt::alloc::alloc(layout20).cast::<u8>();
+                                if ptr.is_null() {
+                                    _rt::alloc::handle_alloc_error(layout20);
+                                }
+                                ptr
+                            } else {
+                                ::core::ptr::null_mut()
+                            };
+                            for (i, e) in vec20.into_iter().enumerate() {
+                                let base = result20
+                                    .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let (t17_0, t17_1) = e;
+                                    let vec18 = (t17_0.into_bytes()).into_boxed_slice();
+                                    let ptr18 = vec18.as_ptr().cast::<u8>();
+                                    let len18 = vec18.len();
+                                    ::core::mem::forget(vec18);
+                                    *base
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len18;
+                                    *base.add(0).cast::<*mut u8>() = ptr18.cast_mut();
+                                    let vec19 = (t17_1.into_bytes()).into_boxed_slice();
+                                    let ptr19 = vec19.as_ptr().cast::<u8>();
+                                    let len19 = vec19.len();
+                                    ::core::mem::forget(vec19);
+                                    *base
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len19;
+                                    *base
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr19.cast_mut();
+                                }
+                            }
+                            *ptr12
+                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len20;
+                            *ptr12
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = result20;
+                            match body16 {
+                                Some(e) => {
+                                    *ptr12
+                                        .add(7 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec21 = (e).into_boxed_slice();
+                                    let ptr21 = vec21.as_ptr().cast::<u8>();
+                                    let len21 = vec21.len();
+                                    ::core::mem::forget(vec21);
+                                    *ptr12
+                                        .add(9 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len21;
+                                    *ptr12
+                                        .add(8 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr21.cast_mut();
+                                }
+                                None => {
+                                    *ptr12
+                                        .add(7 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr12.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec22 = (e.into_bytes()).into_boxed_slice();
+                            let ptr22 = vec22.as_ptr().cast::<u8>();
+                            let len22 = vec22.len();
+                            ::core::mem::forget(vec22);
+                            *ptr12
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len22;
+                            *ptr12
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr22.cast_mut();
+                        }
                     };
+                    ptr12
                 }
                 #[doc(hidden)]
-                pub(crate) use __export_theater_simple_message_server_client_cabi;
-                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
-                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
-                struct _RetArea(
-                    [::core::mem::MaybeUninit<
-                        u8,
-                    >; 8 * ::core::mem::size_of::<*const u8>()],
-                );
-                static mut _RET_AREA: _RetArea = _RetArea(
-                    [::core::mem::MaybeUninit::uninit(); 8
-                        * ::core::mem::size_of::<*const u8>()],
-                );
-            }
-            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
-            pub mod supervisor_handlers {
-                #[used]
-                #[doc(hidden)]
-                static __FORCE_SECTION_REF: fn() = super::super::super::super::__link_custom_section_describing_imports;
-                use super::super::super::super::_rt;
-                pub type WitActorError = super::super::super::super::theater::simple::types::WitActorError;
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_handle_request<T: Guest>(arg0: *mut u8) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                            let l5 = *arg0
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let base11 = l5;
+                            let len11 = l6;
+                            for i in 0..len11 {
+                                let base = base11
+                                    .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let l7 = *base.add(0).cast::<*mut u8>();
+                                    let l8 = *base
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    _rt::cabi_dealloc(l7, l8, 1);
+                                    let l9 = *base
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l10 = *base
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    _rt::cabi_dealloc(l9, l10, 1);
+                                }
+                            }
+                            _rt::cabi_dealloc(
+                                base11,
+                                len11 * (4 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let l12 = i32::from(
+                                *arg0
+                                    .add(7 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<u8>(),
+                            );
+                            match l12 {
+                                0 => {}
+                                _ => {
+                                    let l13 = *arg0
+                                        .add(8 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l14 = *arg0
+                                        .add(9 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base15 = l13;
+                                    let len15 = l14;
+                                    _rt::cabi_dealloc(base15, len15 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l16 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l17 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l16, l17, 1);
+                        }
+                    }
+                }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_child_error_cabi<T: Guest>(
+                pub unsafe fn _export_handle_middleware_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
-                    arg3: *mut u8,
-                    arg4: usize,
-                    arg5: i32,
-                    arg6: i32,
-                    arg7: *mut u8,
-                    arg8: usize,
+                    arg3: i64,
+                    arg4: *mut u8,
+                    arg5: usize,
+                    arg6: *mut u8,
+                    arg7: usize,
+                    arg8: *mut u8,
+                    arg9: usize,
+                    arg10: i32,
+                    arg11: *mut u8,
+                    arg12: usize,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
-                    let len1 = arg4;
-                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let result3 = T::handle_child_error(
+                    let len1 = arg5;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg4.cast(), len1, len1);
+                    let len2 = arg7;
+                    let bytes2 = _rt::Vec::from_raw_parts(arg6.cast(), len2, len2);
+                    let base9 = arg8;
+                    let len9 = arg9;
+                    let mut result9 = _rt::Vec::with_capacity(len9);
+                    for i in 0..len9 {
+                        let base = base9
+                            .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                        let e9 = {
+                            let l3 = *base.add(0).cast::<*mut u8>();
+                            let l4 = *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len5 = l4;
+                            let bytes5 = _rt::Vec::from_raw_parts(l3.cast(), len5, len5);
+                            let l6 = *base
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l7 = *base
+                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len8 = l7;
+                            let bytes8 = _rt::Vec::from_raw_parts(l6.cast(), len8, len8);
+                            (_rt::string_lift(bytes5), _rt::string_lift(bytes8))
+                        };
+                        result9.push(e9);
+                    }
+                    _rt::cabi_dealloc(
+                        base9,
+                        len9 * (4 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
+                    let result11 = T::handle_middleware(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -3389,17 +8730,17 @@ pub mod exports {
                             _ => _rt::invalid_enum_discriminant(),
                         },
                         (
-                            _rt::string_lift(bytes1),
-                            super::super::super::super::theater::simple::types::WitActorError {
-                                error_type: super::super::super::super::theater::simple::types::WitErrorType::_lift(
-                                    arg5 as u8,
-                                ),
-                                data: match arg6 {
+                            arg3 as u64,
+                            super::super::super::super::theater::simple::http_types::HttpRequest {
+                                method: _rt::string_lift(bytes1),
+                                uri: _rt::string_lift(bytes2),
+                                headers: result9,
+                                body: match arg10 {
                                     0 => None,
                                     1 => {
                                         let e = {
-                                            let len2 = arg8;
-                                            _rt::Vec::from_raw_parts(arg7.cast(), len2, len2)
+                                            let len10 = arg12;
+                                            _rt::Vec::from_raw_parts(arg11.cast(), len10, len10)
                                         };
                                         Some(e)
                                     }
@@ -3408,6 +8749,305 @@ pub mod exports {
                             },
                         ),
                     );
+                    let ptr12 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result11 {
+                        Ok(e) => {
+                            *ptr12.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t13_0, t13_1) = e;
+                            match t13_0 {
+                                Some(e) => {
+                                    *ptr12
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec14 = (e).into_boxed_slice();
+                                    let ptr14 = vec14.as_ptr().cast::<u8>();
+                                    let len14 = vec14.len();
+                                    ::core::mem::forget(vec14);
+                                    *ptr12
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len14;
+                                    *ptr12
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr14.cast_mut();
+                                }
+                                None => {
+                                    *ptr12
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                            let (t15_0,) = t13_1;
+                            let super::super::super::super::theater::simple::http_types::MiddlewareResult {
+                                proceed: proceed16,
+                                request: request16,
+                            } = t15_0;
+                            *ptr12
+                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                .cast::<u8>() = (match proceed16 {
+                                true => 1,
+                                false => 0,
+                            }) as u8;
+                            let super::super::super::super::theater::simple::http_types::HttpRequest {
+                                method: method17,
+                                uri: uri17,
+                                headers: headers17,
+                                body: body17,
+                            } = request16;
+                            let vec18 = (method17.into_bytes()).into_boxed_slice();
+                            let ptr18 = vec18.as_ptr().cast::<u8>();
+                            let len18 = vec18.len();
+                            ::core::mem::forget(vec18);
+                            *ptr12
+                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len18;
+                            *ptr12
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr18.cast_mut();
+                            let vec19 = (uri17.into_bytes()).into_boxed_slice();
+                            let ptr19 = vec19.as_ptr().cast::<u8>();
+                            let len19 = vec19.len();
+                            ::core::mem::forget(vec19);
+                            *ptr12
+                                .add(8 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len19;
+                            *ptr12
+                                .add(7 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr19.cast_mut();
+                            let vec23 = headers17;
+                            let len23 = vec23.len();
+                            let layout23 = _rt::alloc::Layout::from_size_align_unchecked(
+                                vec23.len() * (4 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let result23 = if layout23.size() != 0 {
+                                let ptr = _rt::alloc::alloc(layout23).cast::<u8>();
+                                if ptr.is_null() {
+                                    _rt::alloc::handle_alloc_error(layout23);
+                                }
+                                ptr
+                            } else {
+                                ::core::ptr::null_mut()
+                            };
+                            for (i, e) in vec23.into_iter().enumerate() {
+                                let base = result23
+                                    .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let (t20_0, t20_1) = e;
+                                    let vec21 = (t20_0.into_bytes()).into_boxed_slice();
+                                    let ptr21 = vec21.as_ptr().cast::<u8>();
+                                    let len21 = vec21.len();
+                                    ::core::mem::forget(vec21);
+                                    *base
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len21;
+                                    *base.add(0).cast::<*mut u8>() = ptr21.cast_mut();
+                                    let vec22 = (t20_1.into_bytes()).into_boxed_slice();
+                                    let ptr22 = vec22.as_ptr().cast::<u8>();
+                                    let len22 = vec22.len();
+                                    ::core::mem::forget(vec22);
+                                    *base
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len22;
+                                    *base
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr22.cast_mut();
+                                }
+                            }
+                            *ptr12
+                                .add(10 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len23;
+                            *ptr12
+                                .add(9 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = result23;
+                            match body17 {
+                                Some(e) => {
+                                    *ptr12
+                                        .add(11 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (1i32) as u8;
+                                    let vec24 = (e).into_boxed_slice();
+                                    let ptr24 = vec24.as_ptr().cast::<u8>();
+                                    let len24 = vec24.len();
+                                    ::core::mem::forget(vec24);
+                                    *ptr12
+                                        .add(13 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len24;
+                                    *ptr12
+                                        .add(12 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>() = ptr24.cast_mut();
+                                }
+                                None => {
+                                    *ptr12
+                                        .add(11 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<u8>() = (0i32) as u8;
+                                }
+                            };
+                        }
+                        Err(e) => {
+                            *ptr12.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec25 = (e.into_bytes()).into_boxed_slice();
+                            let ptr25 = vec25.as_ptr().cast::<u8>();
+                            let len25 = vec25.len();
+                            ::core::mem::forget(vec25);
+                            *ptr12
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len25;
+                            *ptr12
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = ptr25.cast_mut();
+                        }
+                    };
+                    ptr12
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn __post_return_handle_middleware<T: Guest>(arg0: *mut u8) {
+                    let l0 = i32::from(*arg0.add(0).cast::<u8>());
+                    match l0 {
+                        0 => {
+                            let l1 = i32::from(
+                                *arg0.add(::core::mem::size_of::<*const u8>()).cast::<u8>(),
+                            );
+                            match l1 {
+                                0 => {}
+                                _ => {
+                                    let l2 = *arg0
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l3 = *arg0
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base4 = l2;
+                                    let len4 = l3;
+                                    _rt::cabi_dealloc(base4, len4 * 1, 1);
+                                }
+                            }
+                            let l5 = *arg0
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l5, l6, 1);
+                            let l7 = *arg0
+                                .add(7 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l8 = *arg0
+                                .add(8 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l7, l8, 1);
+                            let l9 = *arg0
+                                .add(9 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l10 = *arg0
+                                .add(10 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let base15 = l9;
+                            let len15 = l10;
+                            for i in 0..len15 {
+                                let base = base15
+                                    .add(i * (4 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let l11 = *base.add(0).cast::<*mut u8>();
+                                    let l12 = *base
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    _rt::cabi_dealloc(l11, l12, 1);
+                                    let l13 = *base
+                                        .add(2 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l14 = *base
+                                        .add(3 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    _rt::cabi_dealloc(l13, l14, 1);
+                                }
+                            }
+                            _rt::cabi_dealloc(
+                                base15,
+                                len15 * (4 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let l16 = i32::from(
+                                *arg0
+                                    .add(11 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<u8>(),
+                            );
+                            match l16 {
+                                0 => {}
+                                _ => {
+                                    let l17 = *arg0
+                                        .add(12 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<*mut u8>();
+                                    let l18 = *arg0
+                                        .add(13 * ::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>();
+                                    let base19 = l17;
+                                    let len19 = l18;
+                                    _rt::cabi_dealloc(base19, len19 * 1, 1);
+                                }
+                            }
+                        }
+                        _ => {
+                            let l20 = *arg0
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l21 = *arg0
+                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            _rt::cabi_dealloc(l20, l21, 1);
+                        }
+                    }
+                }
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub unsafe fn _export_handle_websocket_connect_cabi<T: Guest>(
+                    arg0: i32,
+                    arg1: *mut u8,
+                    arg2: usize,
+                    arg3: i64,
+                    arg4: i64,
+                    arg5: *mut u8,
+                    arg6: usize,
+                    arg7: i32,
+                    arg8: *mut u8,
+                    arg9: usize,
+                ) -> *mut u8 {
+                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
+                    let len1 = arg6;
+                    let bytes1 = _rt::Vec::from_raw_parts(arg5.cast(), len1, len1);
+                    let result3 = T::handle_websocket_connect(
+                        match arg0 {
+                            0 => None,
+                            1 => {
+                                let e = {
+                                    let len0 = arg2;
+                                    _rt::Vec::from_raw_parts(arg1.cast(), len0, len0)
+                                };
+                                Some(e)
+                            }
+                            _ => _rt::invalid_enum_discriminant(),
+                        },
+                        (
+                            arg3 as u64,
+                            arg4 as u64,
+                            _rt::string_lift(bytes1),
+                            match arg7 {
+                                0 => None,
+                                1 => {
+                                    let e = {
+                                        let len2 = arg9;
+                                        let bytes2 = _rt::Vec::from_raw_parts(
+                                            arg8.cast(),
+                                            len2,
+                                            len2,
+                                        );
+                                        _rt::string_lift(bytes2)
+                                    };
+                                    Some(e)
+                                }
+                                _ => _rt::invalid_enum_discriminant(),
+                            },
+                        ),
+                    );
                     let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
                     match result3 {
                         Ok(e) => {
@@ -3454,7 +9094,9 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_child_error<T: Guest>(arg0: *mut u8) {
+                pub unsafe fn __post_return_handle_websocket_connect<T: Guest>(
+                    arg0: *mut u8,
+                ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -3489,20 +9131,46 @@ pub mod exports {
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_child_exit_cabi<T: Guest>(
+                pub unsafe fn _export_handle_websocket_message_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
-                    arg3: *mut u8,
-                    arg4: usize,
+                    arg3: i64,
+                    arg4: i64,
                     arg5: i32,
                     arg6: *mut u8,
                     arg7: usize,
+                    arg8: i32,
+                    arg9: *mut u8,
+                    arg10: usize,
+                    arg11: i32,
+                    arg12: *mut u8,
+                    arg13: usize,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
-                    let len1 = arg4;
-                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let result3 = T::handle_child_exit(
+                    use super::super::super::super::theater::simple::websocket_types::MessageType as V2;
+                    let v2 = match arg5 {
+                        0 => V2::Text,
+                        1 => V2::Binary,
+                        2 => V2::Connect,
+                        3 => V2::Close,
+                        4 => V2::Ping,
+                        5 => V2::Pong,
+                        n => {
+                            debug_assert_eq!(n, 6, "invalid enum discriminant");
+                            let e2 = {
+                                let len1 = arg7;
+                                let bytes1 = _rt::Vec::from_raw_parts(
+                                    arg6.cast(),
+                                    len1,
+                                    len1,
+                                );
+                                _rt::string_lift(bytes1)
+                            };
+                            V2::Other(e2)
+                        }
+                    };
+                    let result5 = T::handle_websocket_message(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -3515,67 +9183,200 @@ pub mod exports {
                             _ => _rt::invalid_enum_discriminant(),
                         },
                         (
-                            _rt::string_lift(bytes1),
-                            match arg5 {
-                                0 => None,
-                                1 => {
-                                    let e = {
-                                        let len2 = arg7;
-                                        _rt::Vec::from_raw_parts(arg6.cast(), len2, len2)
-                                    };
-                                    Some(e)
-                                }
-                                _ => _rt::invalid_enum_discriminant(),
+                            arg3 as u64,
+                            arg4 as u64,
+                            super::super::super::super::theater::simple::websocket_types::WebsocketMessage {
+                                ty: v2,
+                                data: match arg8 {
+                                    0 => None,
+                                    1 => {
+                                        let e = {
+                                            let len3 = arg10;
+                                            _rt::Vec::from_raw_parts(arg9.cast(), len3, len3)
+                                        };
+                                        Some(e)
+                                    }
+                                    _ => _rt::invalid_enum_discriminant(),
+                                },
+                                text: match arg11 {
+                                    0 => None,
+                                    1 => {
+                                        let e = {
+                                            let len4 = arg13;
+                                            let bytes4 = _rt::Vec::from_raw_parts(
+                                                arg12.cast(),
+                                                len4,
+                                                len4,
+                                            );
+                                            _rt::string_lift(bytes4)
+                                        };
+                                        Some(e)
+                                    }
+                                    _ => _rt::invalid_enum_discriminant(),
+                                },
                             },
                         ),
                     );
-                    let ptr4 = (&raw mut _RET_AREA.0).cast::<u8>();
-                    match result3 {
+                    let ptr6 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result5 {
                         Ok(e) => {
-                            *ptr4.add(0).cast::<u8>() = (0i32) as u8;
-                            let (t5_0,) = e;
-                            match t5_0 {
+                            *ptr6.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t7_0, t7_1) = e;
+                            match t7_0 {
                                 Some(e) => {
-                                    *ptr4
+                                    *ptr6
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (1i32) as u8;
-                                    let vec6 = (e).into_boxed_slice();
-                                    let ptr6 = vec6.as_ptr().cast::<u8>();
-                                    let len6 = vec6.len();
-                                    ::core::mem::forget(vec6);
-                                    *ptr4
+                                    let vec8 = (e).into_boxed_slice();
+                                    let ptr8 = vec8.as_ptr().cast::<u8>();
+                                    let len8 = vec8.len();
+                                    ::core::mem::forget(vec8);
+                                    *ptr6
                                         .add(3 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len6;
-                                    *ptr4
+                                        .cast::<usize>() = len8;
+                                    *ptr6
                                         .add(2 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr6.cast_mut();
+                                        .cast::<*mut u8>() = ptr8.cast_mut();
                                 }
                                 None => {
-                                    *ptr4
+                                    *ptr6
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (0i32) as u8;
                                 }
                             };
+                            let (t9_0,) = t7_1;
+                            let vec15 = t9_0;
+                            let len15 = vec15.len();
+                            let layout15 = _rt::alloc::Layout::from_size_align_unchecked(
+                                vec15.len() * (9 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let result15 = if layout15.size() != 0 {
+                                let ptr = _rt::alloc::alloc(layout15).cast::<u8>();
+                                if ptr.is_null() {
+                                    _rt::alloc::handle_alloc_error(layout15);
+                                }
+                                ptr
+                            } else {
+                                ::core::ptr::null_mut()
+                            };
+                            for (i, e) in vec15.into_iter().enumerate() {
+                                let base = result15
+                                    .add(i * (9 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let super::super::super::super::theater::simple::websocket_types::WebsocketMessage {
+                                        ty: ty10,
+                                        data: data10,
+                                        text: text10,
+                                    } = e;
+                                    use super::super::super::super::theater::simple::websocket_types::MessageType as V12;
+                                    match ty10 {
+                                        V12::Text => {
+                                            *base.add(0).cast::<u8>() = (0i32) as u8;
+                                        }
+                                        V12::Binary => {
+                                            *base.add(0).cast::<u8>() = (1i32) as u8;
+                                        }
+                                        V12::Connect => {
+                                            *base.add(0).cast::<u8>() = (2i32) as u8;
+                                        }
+                                        V12::Close => {
+                                            *base.add(0).cast::<u8>() = (3i32) as u8;
+                                        }
+                                        V12::Ping => {
+                                            *base.add(0).cast::<u8>() = (4i32) as u8;
+                                        }
+                                        V12::Pong => {
+                                            *base.add(0).cast::<u8>() = (5i32) as u8;
+                                        }
+                                        V12::Other(e) => {
+                                            *base.add(0).cast::<u8>() = (6i32) as u8;
+                                            let vec11 = (e.into_bytes()).into_boxed_slice();
+                                            let ptr11 = vec11.as_ptr().cast::<u8>();
+                                            let len11 = vec11.len();
+                                            ::core::mem::forget(vec11);
+                                            *base
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>() = len11;
+                                            *base
+                                                .add(::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>() = ptr11.cast_mut();
+                                        }
+                                    }
+                                    match data10 {
+                                        Some(e) => {
+                                            *base
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<u8>() = (1i32) as u8;
+                                            let vec13 = (e).into_boxed_slice();
+                                            let ptr13 = vec13.as_ptr().cast::<u8>();
+                                            let len13 = vec13.len();
+                                            ::core::mem::forget(vec13);
+                                            *base
+                                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>() = len13;
+                                            *base
+                                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>() = ptr13.cast_mut();
+                                        }
+                                        None => {
+                                            *base
+                                                .add(3 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<u8>() = (0i32) as u8;
+                                        }
+                                    };
+                                    match text10 {
+                                        Some(e) => {
+                                            *base
+                                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<u8>() = (1i32) as u8;
+                                            let vec14 = (e.into_bytes()).into_boxed_slice();
+                                            let ptr14 = vec14.as_ptr().cast::<u8>();
+                                            let len14 = vec14.len();
+                                            ::core::mem::forget(vec14);
+                                            *base
+                                                .add(8 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>() = len14;
+                                            *base
+                                                .add(7 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>() = ptr14.cast_mut();
+                                        }
+                                        None => {
+                                            *base
+                                                .add(6 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<u8>() = (0i32) as u8;
+                                        }
+                                    };
+                                }
+                            }
+                            *ptr6
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len15;
+                            *ptr6
+                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>() = result15;
                         }
                         Err(e) => {
-                            *ptr4.add(0).cast::<u8>() = (1i32) as u8;
-                            let vec7 = (e.into_bytes()).into_boxed_slice();
-                            let ptr7 = vec7.as_ptr().cast::<u8>();
-                            let len7 = vec7.len();
-                            ::core::mem::forget(vec7);
-                            *ptr4
+                            *ptr6.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec16 = (e.into_bytes()).into_boxed_slice();
+                            let ptr16 = vec16.as_ptr().cast::<u8>();
+                            let len16 = vec16.len();
+                            ::core::mem::forget(vec16);
+                            *ptr6
                                 .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>() = len7;
-                            *ptr4
+                                .cast::<usize>() = len16;
+                            *ptr6
                                 .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>() = ptr7.cast_mut();
+                                .cast::<*mut u8>() = ptr16.cast_mut();
                         }
                     };
-                    ptr4
+                    ptr6
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_child_exit<T: Guest>(arg0: *mut u8) {
+                pub unsafe fn __post_return_handle_websocket_message<T: Guest>(
+                    arg0: *mut u8,
+                ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
                     match l0 {
                         0 => {
@@ -3596,31 +9397,102 @@ pub mod exports {
                                     _rt::cabi_dealloc(base4, len4 * 1, 1);
                                 }
                             }
+                            let l5 = *arg0
+                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                .cast::<*mut u8>();
+                            let l6 = *arg0
+                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let base17 = l5;
+                            let len17 = l6;
+                            for i in 0..len17 {
+                                let base = base17
+                                    .add(i * (9 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let l7 = i32::from(*base.add(0).cast::<u8>());
+                                    match l7 {
+                                        0 => {}
+                                        1 => {}
+                                        2 => {}
+                                        3 => {}
+                                        4 => {}
+                                        5 => {}
+                                        _ => {
+                                            let l8 = *base
+                                                .add(::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l9 = *base
+                                                .add(2 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            _rt::cabi_dealloc(l8, l9, 1);
+                                        }
+                                    }
+                                    let l10 = i32::from(
+                                        *base
+                                            .add(3 * ::core::mem::size_of::<*const u8>())
+                                            .cast::<u8>(),
+                                    );
+                                    match l10 {
+                                        0 => {}
+                                        _ => {
+                                            let l11 = *base
+                                                .add(4 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l12 = *base
+                                                .add(5 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            let base13 = l11;
+                                            let len13 = l12;
+                                            _rt::cabi_dealloc(base13, len13 * 1, 1);
+                                        }
+                                    }
+                                    let l14 = i32::from(
+                                        *base
+                                            .add(6 * ::core::mem::size_of::<*const u8>())
+                                            .cast::<u8>(),
+                                    );
+                                    match l14 {
+                                        0 => {}
+                                        _ => {
+                                            let l15 = *base
+                                                .add(7 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<*mut u8>();
+                                            let l16 = *base
+                                                .add(8 * ::core::mem::size_of::<*const u8>())
+                                                .cast::<usize>();
+                                            _rt::cabi_dealloc(l15, l16, 1);
+                                        }
+                                    }
+                                }
+                            }
+                            _rt::cabi_dealloc(
+                                base17,
+                                len17 * (9 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
                         }
                         _ => {
-                            let l5 = *arg0
+                            let l18 = *arg0
                                 .add(::core::mem::size_of::<*const u8>())
                                 .cast::<*mut u8>();
-                            let l6 = *arg0
+                            let l19 = *arg0
                                 .add(2 * ::core::mem::size_of::<*const u8>())
                                 .cast::<usize>();
-                            _rt::cabi_dealloc(l5, l6, 1);
+                            _rt::cabi_dealloc(l18, l19, 1);
                         }
                     }
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_handle_child_external_stop_cabi<T: Guest>(
+                pub unsafe fn _export_handle_websocket_disconnect_cabi<T: Guest>(
                     arg0: i32,
                     arg1: *mut u8,
                     arg2: usize,
-                    arg3: *mut u8,
-                    arg4: usize,
+                    arg3: i64,
+                    arg4: i64,
                 ) -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
-                    let len1 = arg4;
-                    let bytes1 = _rt::Vec::from_raw_parts(arg3.cast(), len1, len1);
-                    let result2 = T::handle_child_external_stop(
+                    let result1 = T::handle_websocket_disconnect(
                         match arg0 {
                             0 => None,
                             1 => {
@@ -3632,55 +9504,55 @@ pub mod exports {
                             }
                             _ => _rt::invalid_enum_discriminant(),
                         },
-                        (_rt::string_lift(bytes1),),
+                        (arg3 as u64, arg4 as u64),
                     );
-                    let ptr3 = (&raw mut _RET_AREA.0).cast::<u8>();
-                    match result2 {
+                    let ptr2 = (&raw mut _RET_AREA.0).cast::<u8>();
+                    match result1 {
                         Ok(e) => {
-                            *ptr3.add(0).cast::<u8>() = (0i32) as u8;
-                            let (t4_0,) = e;
-                            match t4_0 {
+                            *ptr2.add(0).cast::<u8>() = (0i32) as u8;
+                            let (t3_0,) = e;
+                            match t3_0 {
                                 Some(e) => {
-                                    *ptr3
+                                    *ptr2
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (1i32) as u8;
-                                    let vec5 = (e).into_boxed_slice();
-                                    let ptr5 = vec5.as_ptr().cast::<u8>();
-                                    let len5 = vec5.len();
-                                    ::core::mem::forget(vec5);
-                                    *ptr3
+                                    let vec4 = (e).into_boxed_slice();
+                                    let ptr4 = vec4.as_ptr().cast::<u8>();
+                                    let len4 = vec4.len();
+                                    ::core::mem::forget(vec4);
+                                    *ptr2
                                         .add(3 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<usize>() = len5;
-                                    *ptr3
+                                        .cast::<usize>() = len4;
+                                    *ptr2
                                         .add(2 * ::core::mem::size_of::<*const u8>())
-                                        .cast::<*mut u8>() = ptr5.cast_mut();
+                                        .cast::<*mut u8>() = ptr4.cast_mut();
                                 }
                                 None => {
-                                    *ptr3
+                                    *ptr2
                                         .add(::core::mem::size_of::<*const u8>())
                                         .cast::<u8>() = (0i32) as u8;
                                 }
                             };
                         }
                         Err(e) => {
-                            *ptr3.add(0).cast::<u8>() = (1i32) as u8;
-                            let vec6 = (e.into_bytes()).into_boxed_slice();
-                            let ptr6 = vec6.as_ptr().cast::<u8>();
-                            let len6 = vec6.len();
-                            ::core::mem::forget(vec6);
-                            *ptr3
+                            *ptr2.add(0).cast::<u8>() = (1i32) as u8;
+                            let vec5 = (e.into_bytes()).into_boxed_slice();
+                            let ptr5 = vec5.as_ptr().cast::<u8>();
+                            let len5 = vec5.len();
+                            ::core::mem::forget(vec5);
+                            *ptr2
                                 .add(2 * ::core::mem::size_of::<*const u8>())
-                                .cast::<usize>() = len6;
-                            *ptr3
+                                .cast::<usize>() = len5;
+                            *ptr2
                                 .add(::core::mem::size_of::<*const u8>())
-                                .cast::<*mut u8>() = ptr6.cast_mut();
+                                .cast::<*mut u8>() = ptr5.cast_mut();
                         }
                     };
-                    ptr3
+                    ptr2
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn __post_return_handle_child_external_stop<T: Guest>(
+                pub unsafe fn __post_return_handle_websocket_disconnect<T: Guest>(
                     arg0: *mut u8,
                 ) {
                     let l0 = i32::from(*arg0.add(0).cast::<u8>());
@@ -3716,83 +9588,113 @@ pub mod exports {
                     }
                 }
                 pub trait Guest {
-                    /// # Handle a child actor error
-                    ///
-                    /// Processes an error from a child actor, allowing the parent to react or log the error.
-                    ///
-                    /// ## Parameters
-                    ///
-                    /// * `state` - Optional state of the parent actor (for context)
-                    /// * `params` - Tuple containing the child ID and error data
-                    ///
-                    /// ## Returns
-                    ///
-                    /// * `Ok(tuple<option<list<u8>>, string>)` - Updated state and result message
-                    /// * `Err(string)` - Error message if handling fails
-                    fn handle_child_error(
+                    fn handle_request(
                         state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String, WitActorError),
-                    ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
-                    fn handle_child_exit(
+                        params: (HandlerId, HttpRequest),
+                    ) -> Result<(Option<_rt::Vec<u8>>, (HttpResponse,)), _rt::String>;
+                    fn handle_middleware(
                         state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String, Option<_rt::Vec<u8>>),
+                        params: (HandlerId, HttpRequest),
+                    ) -> Result<
+                        (Option<_rt::Vec<u8>>, (MiddlewareResult,)),
+                        _rt::String,
+                    >;
+                    fn handle_websocket_connect(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (HandlerId, u64, _rt::String, Option<_rt::String>),
                     ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
-                    fn handle_child_external_stop(
+                    fn handle_websocket_message(
                         state: Option<_rt::Vec<u8>>,
-                        params: (_rt::String,),
+                        params: (HandlerId, u64, WebsocketMessage),
+                    ) -> Result<
+                        (Option<_rt::Vec<u8>>, (_rt::Vec<WebsocketMessage>,)),
+                        _rt::String,
+                    >;
+                    fn handle_websocket_disconnect(
+                        state: Option<_rt::Vec<u8>>,
+                        params: (HandlerId, u64),
                     ) -> Result<(Option<_rt::Vec<u8>>,), _rt::String>;
                 }
                 #[doc(hidden)]
-                macro_rules! __export_theater_simple_supervisor_handlers_cabi {
+                macro_rules! __export_theater_simple_http_handlers_cabi {
                     ($ty:ident with_types_in $($path_to_types:tt)*) => {
                         const _ : () = { #[unsafe (export_name =
-                        "theater:simple/supervisor-handlers#handle-child-error")] unsafe
-                        extern "C" fn export_handle_child_error(arg0 : i32, arg1 : * mut
-                        u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : i32, arg6
-                        : i32, arg7 : * mut u8, arg8 : usize,) -> * mut u8 { unsafe {
-                        $($path_to_types)*:: _export_handle_child_error_cabi::<$ty >
-                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8) } }
-                        #[unsafe (export_name =
-                        "cabi_post_theater:simple/supervisor-handlers#handle-child-error")]
-                        unsafe extern "C" fn _post_return_handle_child_error(arg0 : * mut
+                        "theater:simple/http-handlers#handle-request")] unsafe extern "C"
+                        fn export_handle_request(arg0 : i32, arg1 : * mut u8, arg2 :
+                        usize, arg3 : i64, arg4 : * mut u8, arg5 : usize, arg6 : * mut
+                        u8, arg7 : usize, arg8 : * mut u8, arg9 : usize, arg10 : i32,
+                        arg11 : * mut u8, arg12 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_request_cabi::<$ty > (arg0,
+                        arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10,
+                        arg11, arg12) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/http-handlers#handle-request")] unsafe
+                        extern "C" fn _post_return_handle_request(arg0 : * mut u8,) {
+                        unsafe { $($path_to_types)*:: __post_return_handle_request::<$ty
+                        > (arg0) } } #[unsafe (export_name =
+                        "theater:simple/http-handlers#handle-middleware")] unsafe extern
+                        "C" fn export_handle_middleware(arg0 : i32, arg1 : * mut u8, arg2
+                        : usize, arg3 : i64, arg4 : * mut u8, arg5 : usize, arg6 : * mut
+                        u8, arg7 : usize, arg8 : * mut u8, arg9 : usize, arg10 : i32,
+                        arg11 : * mut u8, arg12 : usize,) -> * mut u8 { unsafe {
+                        $($path_to_types)*:: _export_handle_middleware_cabi::<$ty >
+                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9,
+                        arg10, arg11, arg12) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/http-handlers#handle-middleware")]
+                        unsafe extern "C" fn _post_return_handle_middleware(arg0 : * mut
                         u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_child_error::<$ty > (arg0) } } #[unsafe
-                        (export_name =
-                        "theater:simple/supervisor-handlers#handle-child-exit")] unsafe
-                        extern "C" fn export_handle_child_exit(arg0 : i32, arg1 : * mut
-                        u8, arg2 : usize, arg3 : * mut u8, arg4 : usize, arg5 : i32, arg6
-                        : * mut u8, arg7 : usize,) -> * mut u8 { unsafe {
-                        $($path_to_types)*:: _export_handle_child_exit_cabi::<$ty >
-                        (arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7) } } #[unsafe
+                        __post_return_handle_middleware::<$ty > (arg0) } } #[unsafe
                         (export_name =
-                        "cabi_post_theater:simple/supervisor-handlers#handle-child-exit")]
-                        unsafe extern "C" fn _post_return_handle_child_exit(arg0 : * mut
-                        u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_child_exit::<$ty > (arg0) } } #[unsafe
+                        "theater:simple/http-handlers#handle-websocket-connect")] unsafe
+                        extern "C" fn export_handle_websocket_connect(arg0 : i32, arg1 :
+                        * mut u8, arg2 : usize, arg3 : i64, arg4 : i64, arg5 : * mut u8,
+                        arg6 : usize, arg7 : i32, arg8 : * mut u8, arg9 : usize,) -> *
+                        mut u8 { unsafe { $($path_to_types)*::
+                        _export_handle_websocket_connect_cabi::<$ty > (arg0, arg1, arg2,
+                        arg3, arg4, arg5, arg6, arg7, arg8, arg9) } } #[unsafe
                         (export_name =
-                        "theater:simple/supervisor-handlers#handle-child-external-stop")]
-                        unsafe extern "C" fn export_handle_child_external_stop(arg0 :
-                        i32, arg1 : * mut u8, arg2 : usize, arg3 : * mut u8, arg4 :
-                        usize,) -> * mut u8 { unsafe { $($path_to_types)*::
-                        _export_handle_child_external_stop_cabi::<$ty > (arg0, arg1,
+                        "cabi_post_theater:simple/http-handlers#handle-websocket-connect")]
+                        unsafe extern "C" fn _post_return_handle_websocket_connect(arg0 :
+                        * mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_websocket_connect::<$ty > (arg0) } }
+                        #[unsafe (export_name =
+                        "theater:simple/http-handlers#handle-websocket-message")] unsafe
+                        extern "C" fn export_handle_websocket_message(arg0 : i32, arg1 :
+                        * mut u8, arg2 : usize, arg3 : i64, arg4 : i64, arg5 : i32, arg6
+                        : * mut u8, arg7 : usize, arg8 : i32, arg9 : * mut u8, arg10 :
+                        usize, arg11 : i32, arg12 : * mut u8, arg13 : usize,) -> * mut u8
+                        { unsafe { $($path_to_types)*::
+                        _export_handle_websocket_message_cabi::<$ty > (arg0, arg1, arg2,
+                        arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10, arg11, arg12,
+                        arg13) } } #[unsafe (export_name =
+                        "cabi_post_theater:simple/http-handlers#handle-websocket-message")]
+                        unsafe extern "C" fn _post_return_handle_websocket_message(arg0 :
+                        * mut u8,) { unsafe { $($path_to_types)*::
+                        __post_return_handle_websocket_message::<$ty > (arg0) } }
+                        #[unsafe (export_name =
+                        "theater:simple/http-handlers#handle-websocket-disconnect")]
+                        unsafe extern "C" fn export_handle_websocket_disconnect(arg0 :
+                        i32, arg1 : * mut u8, arg2 : usize, arg3 : i64, arg4 : i64,) -> *
+                        mut u8 { unsafe { $($path_to_types)*::
+                        _export_handle_websocket_disconnect_cabi::<$ty > (arg0, arg1,
                         arg2, arg3, arg4) } } #[unsafe (export_name =
-                        "cabi_post_theater:simple/supervisor-handlers#handle-child-external-stop")]
-                        unsafe extern "C" fn _post_return_handle_child_external_stop(arg0
-                        : * mut u8,) { unsafe { $($path_to_types)*::
-                        __post_return_handle_child_external_stop::<$ty > (arg0) } } };
+                        "cabi_post_theater:simple/http-handlers#handle-websocket-disconnect")]
+                        unsafe extern "C" fn
+                        _post_return_handle_websocket_disconnect(arg0 : * mut u8,) {
+                        unsafe { $($path_to_types)*::
+                        __post_return_handle_websocket_disconnect::<$ty > (arg0) } } };
                     };
                 }
                 #[doc(hidden)]
-                pub(crate) use __export_theater_simple_supervisor_handlers_cabi;
+                pub(crate) use __export_theater_simple_http_handlers_cabi;
                 #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
                 #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
                 struct _RetArea(
                     [::core::mem::MaybeUninit<
                         u8,
-                    >; 4 * ::core::mem::size_of::<*const u8>()],
+                    >; 14 * ::core::mem::size_of::<*const u8>()],
                 );
                 static mut _RET_AREA: _RetArea = _RetArea(
-                    [::core::mem::MaybeUninit::uninit(); 4
+                    [::core::mem::MaybeUninit::uninit(); 14
                         * ::core::mem::size_of::<*const u8>()],
                 );
             }
@@ -3825,12 +9727,105 @@ mod _rt {
         let layout = alloc::Layout::from_size_align_unchecked(size, align);
         alloc::dealloc(ptr, layout);
     }
+    pub fn as_i32<T: AsI32>(t: T) -> i32 {
+        t.as_i32()
+    }
+    pub trait AsI32 {
+        fn as_i32(self) -> i32;
+    }
+    impl<'a, T: Copy + AsI32> AsI32 for &'a T {
+        fn as_i32(self) -> i32 {
+            (*self).as_i32()
+        }
+    }
+    impl AsI32 for i32 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for u32 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for i16 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for u16 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for i8 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for u8 {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for char {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    impl AsI32 for usize {
+        #[inline]
+        fn as_i32(self) -> i32 {
+            self as i32
+        }
+    }
+    pub fn as_i64<T: AsI64>(t: T) -> i64 {
+        t.as_i64()
+    }
+    pub trait AsI64 {
+        fn as_i64(self) -> i64;
+    }
+    impl<'a, T: Copy + AsI64> AsI64 for &'a T {
+        fn as_i64(self) -> i64 {
+            (*self).as_i64()
+        }
+    }
+    impl AsI64 for i64 {
+        #[inline]
+        fn as_i64(self) -> i64 {
+            self as i64
+        }
+    }
+    impl AsI64 for u64 {
+        #[inline]
+        fn as_i64(self) -> i64 {
+            self as i64
+        }
+    }
+    pub unsafe fn bool_lift(val: u8) -> bool {
+        if cfg!(debug_assertions) {
+            match val {
+                0 => false,
+                1 => true,
+                _ => panic!("invalid bool discriminant"),
+            }
+        } else {
+            val != 0
+        }
+    }
+    pub use alloc_crate::alloc;
     #[cfg(target_arch = "wasm32")]
     pub fn run_ctors_once() {
         wit_bindgen_rt::run_ctors_once();
     }
     extern crate alloc as alloc_crate;
-    pub use alloc_crate::alloc;
 }
 /// Generates `#[unsafe(no_mangle)]` functions to export the specified type as
 /// the root implementation of all generated traits.
@@ -3864,7 +9859,9 @@ macro_rules! __export_default_impl {
         exports::theater::simple::message_server_client); $($path_to_types_root)*::
         exports::theater::simple::supervisor_handlers::__export_theater_simple_supervisor_handlers_cabi!($ty
         with_types_in $($path_to_types_root)*::
-        exports::theater::simple::supervisor_handlers);
+        exports::theater::simple::supervisor_handlers); $($path_to_types_root)*::
+        exports::theater::simple::http_handlers::__export_theater_simple_http_handlers_cabi!($ty
+        with_types_in $($path_to_types_root)*:: exports::theater::simple::http_handlers);
     };
 }
 #[doc(inline)]
@@ -3875,61 +9872,141 @@ pub(crate) use __export_default_impl as export;
 )]
 #[doc(hidden)]
 #[allow(clippy::octal_escapes)]
-pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 2295] = *b"\
-\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\xf9\x10\x01A\x02\x01\
-A\x15\x01B\x16\x01s\x04\0\x08actor-id\x03\0\0\x01s\x04\0\x0achannel-id\x03\0\x02\
-\x01p}\x01k\x04\x01r\x02\x08accepted\x7f\x07message\x05\x04\0\x0echannel-accept\x03\
-\0\x06\x01kw\x01r\x03\x0aevent-types\x06parent\x08\x04data\x04\x04\0\x05event\x03\
-\0\x09\x01r\x02\x04hashw\x05event\x0a\x04\0\x0ameta-event\x03\0\x0b\x01p\x0c\x01\
-r\x01\x06events\x0d\x04\0\x05chain\x03\0\x0e\x01r\x05\x04hash\x04\x0bparent-hash\
-\x05\x0aevent-types\x04data\x04\x09timestampw\x04\0\x0bchain-event\x03\0\x10\x01\
-m\x09\x11operation-timeout\x0echannel-closed\x0dshutting-down\x12function-not-fo\
-und\x0dtype-mismatch\x08internal\x13serialization-error\x16update-component-erro\
-r\x06paused\x04\0\x0ewit-error-type\x03\0\x12\x01r\x02\x0aerror-type\x13\x04data\
-\x05\x04\0\x0fwit-actor-error\x03\0\x14\x03\0\x14theater:simple/types\x05\0\x02\x03\
-\0\0\x05chain\x02\x03\0\0\x08actor-id\x01B\x0d\x02\x03\x02\x01\x01\x04\0\x05chai\
-n\x03\0\0\x02\x03\x02\x01\x02\x04\0\x08actor-id\x03\0\x02\x01@\x01\x03msgs\x01\0\
-\x04\0\x03log\x01\x04\x01@\0\0\x01\x04\0\x09get-chain\x01\x05\x01p}\x01k\x06\x01\
-j\0\x01s\x01@\x01\x04data\x07\0\x08\x04\0\x08shutdown\x01\x09\x03\0\x16theater:s\
-imple/runtime\x05\x03\x02\x03\0\0\x0achannel-id\x01B\x19\x02\x03\x02\x01\x02\x04\
-\0\x08actor-id\x03\0\0\x02\x03\x02\x01\x04\x04\0\x0achannel-id\x03\0\x02\x01p}\x01\
-j\0\x01s\x01@\x02\x08actor-id\x01\x03msg\x04\0\x05\x04\0\x04send\x01\x06\x01j\x01\
-\x04\x01s\x01@\x02\x08actor-id\x01\x03msg\x04\0\x07\x04\0\x07request\x01\x08\x01\
-j\x01\x03\x01s\x01@\x02\x08actor-id\x01\x0binitial-msg\x04\0\x09\x04\0\x0copen-c\
-hannel\x01\x0a\x01@\x02\x0achannel-id\x03\x03msg\x04\0\x05\x04\0\x0fsend-on-chan\
-nel\x01\x0b\x01@\x01\x0achannel-id\x03\0\x05\x04\0\x0dclose-channel\x01\x0c\x01p\
-s\x01@\0\0\x0d\x04\0\x19list-outstanding-requests\x01\x0e\x01@\x02\x0arequest-id\
-s\x08response\x04\0\x05\x04\0\x12respond-to-request\x01\x0f\x01@\x01\x0arequest-\
-ids\0\x05\x04\0\x0ecancel-request\x01\x10\x03\0\"theater:simple/message-server-h\
-ost\x05\x05\x02\x03\0\0\x0bchain-event\x01B\x17\x02\x03\x02\x01\x06\x04\0\x0bcha\
-in-event\x03\0\0\x01p}\x01k\x02\x01j\x01s\x01s\x01@\x02\x08manifests\x0ainit-byt\
-es\x03\0\x04\x04\0\x05spawn\x01\x05\x01@\x02\x08manifests\x0ainit-state\x03\0\x04\
-\x04\0\x06resume\x01\x06\x01ps\x01@\0\0\x07\x04\0\x0dlist-children\x01\x08\x01j\0\
-\x01s\x01@\x01\x08child-ids\0\x09\x04\0\x0astop-child\x01\x0a\x04\0\x0drestart-c\
-hild\x01\x0a\x01j\x01\x03\x01s\x01@\x01\x08child-ids\0\x0b\x04\0\x0fget-child-st\
-ate\x01\x0c\x01p\x01\x01j\x01\x0d\x01s\x01@\x01\x08child-ids\0\x0e\x04\0\x10get-\
-child-events\x01\x0f\x03\0\x19theater:simple/supervisor\x05\x07\x01B\x07\x01p}\x01\
-k\0\x01o\x01s\x01o\x01\x01\x01j\x01\x03\x01s\x01@\x02\x05state\x01\x06params\x02\
-\0\x04\x04\0\x04init\x01\x05\x04\0\x14theater:simple/actor\x05\x08\x02\x03\0\0\x05\
-event\x02\x03\0\0\x0echannel-accept\x01B\x1d\x02\x03\x02\x01\x09\x04\0\x05event\x03\
-\0\0\x02\x03\x02\x01\x04\x04\0\x0achannel-id\x03\0\x02\x02\x03\x02\x01\x0a\x04\0\
-\x0echannel-accept\x03\0\x04\x01p}\x01k\x06\x01o\x01\x06\x01o\x01\x07\x01j\x01\x09\
-\x01s\x01@\x02\x05state\x07\x06params\x08\0\x0a\x04\0\x0bhandle-send\x01\x0b\x01\
-o\x02s\x06\x01o\x02\x07\x09\x01j\x01\x0d\x01s\x01@\x02\x05state\x07\x06params\x0c\
-\0\x0e\x04\0\x0ehandle-request\x01\x0f\x01o\x01\x05\x01o\x02\x07\x10\x01j\x01\x11\
-\x01s\x01@\x02\x05state\x07\x06params\x0c\0\x12\x04\0\x13handle-channel-open\x01\
-\x13\x01o\x02\x03\x06\x01@\x02\x05state\x07\x06params\x14\0\x0a\x04\0\x16handle-\
-channel-message\x01\x15\x01o\x01\x03\x01@\x02\x05state\x07\x06params\x16\0\x0a\x04\
-\0\x14handle-channel-close\x01\x17\x04\0$theater:simple/message-server-client\x05\
-\x0b\x02\x03\0\0\x0fwit-actor-error\x01B\x0f\x02\x03\x02\x01\x0c\x04\0\x0fwit-ac\
-tor-error\x03\0\0\x01p}\x01k\x02\x01o\x02s\x01\x01o\x01\x03\x01j\x01\x05\x01s\x01\
-@\x02\x05state\x03\x06params\x04\0\x06\x04\0\x12handle-child-error\x01\x07\x01o\x02\
-s\x03\x01@\x02\x05state\x03\x06params\x08\0\x06\x04\0\x11handle-child-exit\x01\x09\
-\x01o\x01s\x01@\x02\x05state\x03\x06params\x0a\0\x06\x04\0\x1ahandle-child-exter\
-nal-stop\x01\x0b\x04\0\"theater:simple/supervisor-handlers\x05\x0d\x04\0\x20coli\
-nrozzi:git-mcp-actor/default\x04\0\x0b\x0d\x01\0\x07default\x03\0\0\0G\x09produc\
-ers\x01\x0cprocessed-by\x02\x0dwit-component\x070.227.1\x10wit-bindgen-rust\x060\
-.41.0";
+pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 5783] = *b"\
+\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\x99,\x01A\x02\x01A/\x01\
+B\x16\x01s\x04\0\x08actor-id\x03\0\0\x01s\x04\0\x0achannel-id\x03\0\x02\x01p}\x01\
+k\x04\x01r\x02\x08accepted\x7f\x07message\x05\x04\0\x0echannel-accept\x03\0\x06\x01\
+kw\x01r\x03\x0aevent-types\x06parent\x08\x04data\x04\x04\0\x05event\x03\0\x09\x01\
+r\x02\x04hashw\x05event\x0a\x04\0\x0ameta-event\x03\0\x0b\x01p\x0c\x01r\x01\x06e\
+vents\x0d\x04\0\x05chain\x03\0\x0e\x01r\x05\x04hash\x04\x0bparent-hash\x05\x0aev\
+ent-types\x04data\x04\x09timestampw\x04\0\x0bchain-event\x03\0\x10\x01m\x09\x11o\
+peration-timeout\x0echannel-closed\x0dshutting-down\x12function-not-found\x0dtyp\
+e-mismatch\x08internal\x13serialization-error\x16update-component-error\x06pause\
+d\x04\0\x0ewit-error-type\x03\0\x12\x01r\x02\x0aerror-type\x13\x04data\x05\x04\0\
+\x0fwit-actor-error\x03\0\x14\x03\0\x14theater:simple/types\x05\0\x02\x03\0\0\x05\
+chain\x02\x03\0\0\x08actor-id\x01B\x0d\x02\x03\x02\x01\x01\x04\0\x05chain\x03\0\0\
+\x02\x03\x02\x01\x02\x04\0\x08actor-id\x03\0\x02\x01@\x01\x03msgs\x01\0\x04\0\x03\
+log\x01\x04\x01@\0\0\x01\x04\0\x09get-chain\x01\x05\x01p}\x01k\x06\x01j\0\x01s\x01\
+@\x01\x04data\x07\0\x08\x04\0\x08shutdown\x01\x09\x03\0\x16theater:simple/runtim\
+e\x05\x03\x02\x03\0\0\x0achannel-id\x01B\x19\x02\x03\x02\x01\x02\x04\0\x08actor-\
+id\x03\0\0\x02\x03\x02\x01\x04\x04\0\x0achannel-id\x03\0\x02\x01p}\x01j\0\x01s\x01\
+@\x02\x08actor-id\x01\x03msg\x04\0\x05\x04\0\x04send\x01\x06\x01j\x01\x04\x01s\x01\
+@\x02\x08actor-id\x01\x03msg\x04\0\x07\x04\0\x07request\x01\x08\x01j\x01\x03\x01\
+s\x01@\x02\x08actor-id\x01\x0binitial-msg\x04\0\x09\x04\0\x0copen-channel\x01\x0a\
+\x01@\x02\x0achannel-id\x03\x03msg\x04\0\x05\x04\0\x0fsend-on-channel\x01\x0b\x01\
+@\x01\x0achannel-id\x03\0\x05\x04\0\x0dclose-channel\x01\x0c\x01ps\x01@\0\0\x0d\x04\
+\0\x19list-outstanding-requests\x01\x0e\x01@\x02\x0arequest-ids\x08response\x04\0\
+\x05\x04\0\x12respond-to-request\x01\x0f\x01@\x01\x0arequest-ids\0\x05\x04\0\x0e\
+cancel-request\x01\x10\x03\0\"theater:simple/message-server-host\x05\x05\x02\x03\
+\0\0\x0bchain-event\x01B\x17\x02\x03\x02\x01\x06\x04\0\x0bchain-event\x03\0\0\x01\
+p}\x01k\x02\x01j\x01s\x01s\x01@\x02\x08manifests\x0ainit-bytes\x03\0\x04\x04\0\x05\
+spawn\x01\x05\x01@\x02\x08manifests\x0ainit-state\x03\0\x04\x04\0\x06resume\x01\x06\
+\x01ps\x01@\0\0\x07\x04\0\x0dlist-children\x01\x08\x01j\0\x01s\x01@\x01\x08child\
+-ids\0\x09\x04\0\x0astop-child\x01\x0a\x04\0\x0drestart-child\x01\x0a\x01j\x01\x03\
+\x01s\x01@\x01\x08child-ids\0\x0b\x04\0\x0fget-child-state\x01\x0c\x01p\x01\x01j\
+\x01\x0d\x01s\x01@\x01\x08child-ids\0\x0e\x04\0\x10get-child-events\x01\x0f\x03\0\
+\x19theater:simple/supervisor\x05\x07\x01B\x14\x01p}\x04\0\x05bytes\x03\0\0\x01o\
+\x02ss\x01p\x02\x01k\x01\x01r\x04\x06methods\x03uris\x07headers\x03\x04body\x04\x04\
+\0\x0chttp-request\x03\0\x05\x01r\x03\x06status{\x07headers\x03\x04body\x04\x04\0\
+\x0dhttp-response\x03\0\x07\x01r\x02\x09cert-paths\x08key-paths\x04\0\x0atls-con\
+fig\x03\0\x09\x01k{\x01ks\x01k\x0a\x01r\x03\x04port\x0b\x04host\x0c\x0atls-confi\
+g\x0d\x04\0\x0dserver-config\x03\0\x0e\x01r\x07\x02idw\x04port{\x04hosts\x07runn\
+ing\x7f\x0croutes-county\x10middleware-county\x11websocket-enabled\x7f\x04\0\x0b\
+server-info\x03\0\x10\x01r\x02\x07proceed\x7f\x07request\x06\x04\0\x11middleware\
+-result\x03\0\x12\x03\0\x19theater:simple/http-types\x05\x08\x01B\x07\x01q\x07\x04\
+text\0\0\x06binary\0\0\x07connect\0\0\x05close\0\0\x04ping\0\0\x04pong\0\0\x05ot\
+her\x01s\0\x04\0\x0cmessage-type\x03\0\0\x01p}\x01k\x02\x01ks\x01r\x03\x02ty\x01\
+\x04data\x03\x04text\x04\x04\0\x11websocket-message\x03\0\x05\x03\0\x1etheater:s\
+imple/websocket-types\x05\x09\x02\x03\0\x04\x0chttp-request\x02\x03\0\x04\x0dhtt\
+p-response\x02\x03\0\x04\x0dserver-config\x02\x03\0\x04\x0bserver-info\x02\x03\0\
+\x04\x0atls-config\x02\x03\0\x05\x11websocket-message\x01B5\x02\x03\x02\x01\x0a\x04\
+\0\x0chttp-request\x03\0\0\x02\x03\x02\x01\x0b\x04\0\x0dhttp-response\x03\0\x02\x02\
+\x03\x02\x01\x0c\x04\0\x0dserver-config\x03\0\x04\x02\x03\x02\x01\x0d\x04\0\x0bs\
+erver-info\x03\0\x06\x02\x03\x02\x01\x0e\x04\0\x0atls-config\x03\0\x08\x02\x03\x02\
+\x01\x0f\x04\0\x11websocket-message\x03\0\x0a\x01w\x04\0\x09server-id\x03\0\x0c\x01\
+w\x04\0\x0ahandler-id\x03\0\x0e\x01w\x04\0\x08route-id\x03\0\x10\x01w\x04\0\x0dm\
+iddleware-id\x03\0\x12\x01j\x01\x0d\x01s\x01@\x01\x06config\x05\0\x14\x04\0\x0dc\
+reate-server\x01\x15\x01j\x01\x07\x01s\x01@\x01\x09server-id\x0d\0\x16\x04\0\x0f\
+get-server-info\x01\x17\x01j\x01{\x01s\x01@\x01\x09server-id\x0d\0\x18\x04\0\x0c\
+start-server\x01\x19\x01j\0\x01s\x01@\x01\x09server-id\x0d\0\x1a\x04\0\x0bstop-s\
+erver\x01\x1b\x04\0\x0edestroy-server\x01\x1b\x01j\x01\x0f\x01s\x01@\x01\x0chand\
+ler-names\0\x1c\x04\0\x10register-handler\x01\x1d\x01j\x01\x11\x01s\x01@\x04\x09\
+server-id\x0d\x04paths\x06methods\x0ahandler-id\x0f\0\x1e\x04\0\x09add-route\x01\
+\x1f\x01@\x01\x08route-id\x11\0\x1a\x04\0\x0cremove-route\x01\x20\x01j\x01\x13\x01\
+s\x01@\x03\x09server-id\x0d\x04paths\x0ahandler-id\x0f\0!\x04\0\x0eadd-middlewar\
+e\x01\"\x01@\x01\x0dmiddleware-id\x13\0\x1a\x04\0\x11remove-middleware\x01#\x01k\
+\x0f\x01@\x05\x09server-id\x0d\x04paths\x12connect-handler-id$\x12message-handle\
+r-id\x0f\x15disconnect-handler-id$\0\x1a\x04\0\x10enable-websocket\x01%\x01@\x03\
+\x09server-id\x0d\x0dconnection-idw\x07message\x0b\0\x1a\x04\0\x16send-websocket\
+-message\x01&\x01@\x02\x09server-id\x0d\x0dconnection-idw\0\x1a\x04\0\x0fclose-w\
+ebsocket\x01'\x03\0\x1dtheater:simple/http-framework\x05\x10\x01B\x0d\x01p}\x01j\
+\x01\0\x01s\x01@\x01\x06lengthy\0\x01\x04\0\x0crandom-bytes\x01\x02\x01j\x01w\x01\
+s\x01@\x02\x03minw\x03maxw\0\x03\x04\0\x0crandom-range\x01\x04\x01j\x01u\x01s\x01\
+@\0\0\x05\x04\0\x0crandom-float\x01\x06\x01j\x01s\x01s\x01@\0\0\x07\x04\0\x0dgen\
+erate-uuid\x01\x08\x03\0\x15theater:simple/random\x05\x11\x01B\x1d\x01r\x03\x06s\
+tdouts\x06stderrs\x09exit-codez\x04\0\x0fcommand-success\x03\0\0\x01r\x01\x07mes\
+sages\x04\0\x0dcommand-error\x03\0\x02\x01q\x02\x07success\x01\x01\0\x05error\x01\
+\x03\0\x04\0\x0ecommand-result\x03\0\x04\x01p}\x01j\x01\x06\x01s\x01@\x01\x04pat\
+hs\0\x07\x04\0\x09read-file\x01\x08\x01j\0\x01s\x01@\x02\x04paths\x07contents\0\x09\
+\x04\0\x0awrite-file\x01\x0a\x01ps\x01j\x01\x0b\x01s\x01@\x01\x04paths\0\x0c\x04\
+\0\x0alist-files\x01\x0d\x01@\x01\x04paths\0\x09\x04\0\x0bdelete-file\x01\x0e\x04\
+\0\x0acreate-dir\x01\x0e\x04\0\x0adelete-dir\x01\x0e\x01j\x01\x7f\x01s\x01@\x01\x04\
+paths\0\x0f\x04\0\x0bpath-exists\x01\x10\x01j\x01\x05\x01s\x01@\x03\x03dirs\x07c\
+ommands\x04args\x0b\0\x11\x04\0\x0fexecute-command\x01\x12\x01@\x02\x03dirs\x07c\
+ommands\0\x11\x04\0\x13execute-nix-command\x01\x13\x03\0\x19theater:simple/files\
+ystem\x05\x12\x01B\x09\x01ks\x01@\x01\x04names\0\0\x04\0\x07get-var\x01\x01\x01o\
+\x02ss\x01p\x02\x01@\0\0\x03\x04\0\x09list-vars\x01\x04\x01@\x01\x04names\0\x7f\x04\
+\0\x06exists\x01\x05\x03\0\x1atheater:simple/environment\x05\x13\x01B(\x01r\x01\x04\
+hashs\x04\0\x0bcontent-ref\x03\0\0\x01j\x01s\x01s\x01@\0\0\x02\x04\0\x03new\x01\x03\
+\x01p}\x01j\x01\x01\x01s\x01@\x02\x08store-ids\x07content\x04\0\x05\x04\0\x05sto\
+re\x01\x06\x01j\x01\x04\x01s\x01@\x02\x08store-ids\x0bcontent-ref\x01\0\x07\x04\0\
+\x03get\x01\x08\x01j\x01\x7f\x01s\x01@\x02\x08store-ids\x0bcontent-ref\x01\0\x09\
+\x04\0\x06exists\x01\x0a\x01j\0\x01s\x01@\x03\x08store-ids\x05labels\x0bcontent-\
+ref\x01\0\x0b\x04\0\x05label\x01\x0c\x01k\x01\x01j\x01\x0d\x01s\x01@\x02\x08stor\
+e-ids\x05labels\0\x0e\x04\0\x0cget-by-label\x01\x0f\x01@\x02\x08store-ids\x05lab\
+els\0\x0b\x04\0\x0cremove-label\x01\x10\x04\0\x11remove-from-label\x01\x0c\x01@\x03\
+\x08store-ids\x05labels\x07content\x04\0\x05\x04\0\x0estore-at-label\x01\x11\x04\
+\0\x18replace-content-at-label\x01\x11\x04\0\x10replace-at-label\x01\x0c\x01ps\x01\
+j\x01\x12\x01s\x01@\x01\x08store-ids\0\x13\x04\0\x0blist-labels\x01\x14\x01p\x01\
+\x01j\x01\x15\x01s\x01@\x01\x08store-ids\0\x16\x04\0\x10list-all-content\x01\x17\
+\x01j\x01w\x01s\x01@\x01\x08store-ids\0\x18\x04\0\x14calculate-total-size\x01\x19\
+\x03\0\x14theater:simple/store\x05\x14\x01B\x07\x01@\0\0w\x04\0\x03now\x01\0\x01\
+j\0\x01s\x01@\x01\x08durationw\0\x01\x04\0\x05sleep\x01\x02\x01@\x01\x09timestam\
+pw\0\x01\x04\0\x08deadline\x01\x03\x03\0\x15theater:simple/timing\x05\x15\x01B\x07\
+\x01p}\x01k\0\x01o\x01s\x01o\x01\x01\x01j\x01\x03\x01s\x01@\x02\x05state\x01\x06\
+params\x02\0\x04\x04\0\x04init\x01\x05\x04\0\x14theater:simple/actor\x05\x16\x02\
+\x03\0\0\x05event\x02\x03\0\0\x0echannel-accept\x01B\x1d\x02\x03\x02\x01\x17\x04\
+\0\x05event\x03\0\0\x02\x03\x02\x01\x04\x04\0\x0achannel-id\x03\0\x02\x02\x03\x02\
+\x01\x18\x04\0\x0echannel-accept\x03\0\x04\x01p}\x01k\x06\x01o\x01\x06\x01o\x01\x07\
+\x01j\x01\x09\x01s\x01@\x02\x05state\x07\x06params\x08\0\x0a\x04\0\x0bhandle-sen\
+d\x01\x0b\x01o\x02s\x06\x01o\x02\x07\x09\x01j\x01\x0d\x01s\x01@\x02\x05state\x07\
+\x06params\x0c\0\x0e\x04\0\x0ehandle-request\x01\x0f\x01o\x01\x05\x01o\x02\x07\x10\
+\x01j\x01\x11\x01s\x01@\x02\x05state\x07\x06params\x0c\0\x12\x04\0\x13handle-cha\
+nnel-open\x01\x13\x01o\x02\x03\x06\x01@\x02\x05state\x07\x06params\x14\0\x0a\x04\
+\0\x16handle-channel-message\x01\x15\x01o\x01\x03\x01@\x02\x05state\x07\x06param\
+s\x16\0\x0a\x04\0\x14handle-channel-close\x01\x17\x04\0$theater:simple/message-s\
+erver-client\x05\x19\x02\x03\0\0\x0fwit-actor-error\x01B\x0f\x02\x03\x02\x01\x1a\
+\x04\0\x0fwit-actor-error\x03\0\0\x01p}\x01k\x02\x01o\x02s\x01\x01o\x01\x03\x01j\
+\x01\x05\x01s\x01@\x02\x05state\x03\x06params\x04\0\x06\x04\0\x12handle-child-er\
+ror\x01\x07\x01o\x02s\x03\x01@\x02\x05state\x03\x06params\x08\0\x06\x04\0\x11han\
+dle-child-exit\x01\x09\x01o\x01s\x01@\x02\x05state\x03\x06params\x0a\0\x06\x04\0\
+\x1ahandle-child-external-stop\x01\x0b\x04\0\"theater:simple/supervisor-handlers\
+\x05\x1b\x02\x03\0\x04\x11middleware-result\x02\x03\0\x06\x0ahandler-id\x01B'\x02\
+\x03\x02\x01\x0a\x04\0\x0chttp-request\x03\0\0\x02\x03\x02\x01\x0b\x04\0\x0dhttp\
+-response\x03\0\x02\x02\x03\x02\x01\x0f\x04\0\x11websocket-message\x03\0\x04\x02\
+\x03\x02\x01\x1c\x04\0\x11middleware-result\x03\0\x06\x02\x03\x02\x01\x1d\x04\0\x0a\
+handler-id\x03\0\x08\x01p}\x01k\x0a\x01o\x02\x09\x01\x01o\x01\x03\x01o\x02\x0b\x0d\
+\x01j\x01\x0e\x01s\x01@\x02\x05state\x0b\x06params\x0c\0\x0f\x04\0\x0ehandle-req\
+uest\x01\x10\x01o\x01\x07\x01o\x02\x0b\x11\x01j\x01\x12\x01s\x01@\x02\x05state\x0b\
+\x06params\x0c\0\x13\x04\0\x11handle-middleware\x01\x14\x01ks\x01o\x04\x09ws\x15\
+\x01o\x01\x0b\x01j\x01\x17\x01s\x01@\x02\x05state\x0b\x06params\x16\0\x18\x04\0\x18\
+handle-websocket-connect\x01\x19\x01o\x03\x09w\x05\x01p\x05\x01o\x01\x1b\x01o\x02\
+\x0b\x1c\x01j\x01\x1d\x01s\x01@\x02\x05state\x0b\x06params\x1a\0\x1e\x04\0\x18ha\
+ndle-websocket-message\x01\x1f\x01o\x02\x09w\x01@\x02\x05state\x0b\x06params\x20\
+\0\x18\x04\0\x1bhandle-websocket-disconnect\x01!\x04\0\x1ctheater:simple/http-ha\
+ndlers\x05\x1e\x04\0\x20colinrozzi:git-mcp-actor/default\x04\0\x0b\x0d\x01\0\x07\
+default\x03\0\0\0G\x09producers\x01\x0cprocessed-by\x02\x0dwit-component\x070.22\
+7.1\x10wit-bindgen-rust\x060.41.0";
 #[inline(never)]
 #[doc(hidden)]
 pub fn __link_custom_section_describing_imports() {
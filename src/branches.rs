@@ -0,0 +1,80 @@
+use crate::parse;
+use serde_json::Value;
+use serde_json::json;
+
+/// Stands in for the default branch until the listing step has found it.
+pub const DEFAULT_BRANCH: &str = "<default-branch>";
+
+/// Steps of `git-branch-list`: read every local branch and remote HEAD, then
+/// which branches are merged into `base`, or the default branch.
+pub fn list_steps(base: Option<&str>) -> Vec<Vec<String>> {
+    vec![
+        refs_step(),
+        vec![
+            "for-each-ref".to_string(),
+            format!("--merged={}", base.unwrap_or(DEFAULT_BRANCH)),
+            "--format=%(refname:short)".to_string(),
+            "refs/heads".to_string(),
+        ],
+    ]
+}
+
+/// Read every local branch and remote HEAD, which the default branch is
+/// found from.
+pub fn refs_step() -> Vec<String> {
+    [
+        "for-each-ref",
+        parse::BRANCH_FORMAT,
+        "refs/heads",
+        "refs/remotes/*/HEAD",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// The branch merges are checked against: `base` if given, else the default
+/// branch, else HEAD.
+pub fn base(base: Option<&str>, outputs: &[String]) -> String {
+    base.map(str::to_string)
+        .or_else(|| {
+            outputs
+                .first()
+                .and_then(|output| parse::default_branch(output))
+        })
+        .unwrap_or_else(|| "HEAD".to_string())
+}
+
+/// Put the default branch into the `--merged` step once it is known.
+pub fn prepare(requested: Option<&str>, outputs: &[String], mut step: Vec<String>) -> Vec<String> {
+    let placeholder = format!("--merged={}", DEFAULT_BRANCH);
+    if let Some(arg) = step.iter_mut().find(|arg| **arg == placeholder) {
+        *arg = format!("--merged={}", base(requested, outputs));
+    }
+    step
+}
+
+/// The branch listing, from the output of both steps.
+pub fn listing(requested: Option<&str>, outputs: &[String], merged_stdout: &str) -> Value {
+    let refs = outputs.first().map(String::as_str).unwrap_or("");
+    let merged: Vec<&str> = merged_stdout.lines().map(str::trim).collect();
+    let mut branches = parse::branches(refs);
+    for branch in &mut branches {
+        branch.merged = merged.contains(&branch.name.as_str());
+    }
+    json!({
+        "base": base(requested, outputs),
+        "branches": branches,
+    })
+}
+
+/// Name and former tip in `git branch --delete` output, e.g.
+/// "Deleted branch topic (was 1a2b3c4).".
+pub fn deleted(stdout: &str) -> Value {
+    let line = stdout.lines().next().unwrap_or("").trim();
+    let (name, sha) = line
+        .strip_prefix("Deleted branch ")
+        .and_then(|rest| rest.strip_suffix(")."))
+        .and_then(|rest| rest.rsplit_once(" (was "))
+        .unwrap_or_default();
+    json!({ "name": name, "sha": sha })
+}
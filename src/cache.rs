@@ -0,0 +1,522 @@
+use crate::State;
+use crate::bindings::theater::simple::environment::get_var;
+use crate::bindings::theater::simple::filesystem::read_file;
+use crate::bindings::theater::simple::runtime::log;
+use crate::git;
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Store labels of cached results, followed by a hash of the key.
+const LABEL_PREFIX: &str = "cache/";
+
+/// Results kept when `limits.cache_entries` is not set.
+const DEFAULT_ENTRIES: usize = 500;
+
+/// Options whose output depends on more than the named revisions: every
+/// ref, the reflog, other notes, the clock or the working tree.
+const UNPINNED_OPTIONS: &[&str] = &[
+    "--all",
+    "--branches",
+    "--tags",
+    "--remotes",
+    "--glob",
+    "--exclude",
+    "--reflog",
+    "-g",
+    "--walk-reflogs",
+    "--decorate",
+    "--decorate-refs",
+    "--decorate-refs-exclude",
+    "--source",
+    "--stdin",
+    "--no-index",
+    "--contents",
+    "--notes",
+    "--show-notes",
+    "--since",
+    "--after",
+    "--until",
+    "--before",
+    "--relative-date",
+    "--date=relative",
+    "--date=human",
+];
+
+/// Placeholders of `--format` that depend on the clock, or on every ref
+/// as decorations do.
+const UNPINNED_PLACEHOLDERS: &[&str] = &["%ar", "%cr", "%gr", "%d", "%D", "%(decorate"];
+
+/// A cached result, with the whole key to rule out hash collisions.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    result: Value,
+}
+
+/// What a read-only command reads besides its arguments.
+#[derive(Debug, PartialEq)]
+enum Input {
+    /// The object a revision such as `main` or `HEAD~2` starts from.
+    Revision(String),
+    /// The index, for `diff --cached` and `:path`.
+    Index,
+    /// `refs/notes/commits`, which `log` and `show` print.
+    Notes,
+}
+
+/// Cache key of the call `tool` running `steps` in `repository_path`: the
+/// arguments, the shas the revisions they name resolve to, and
+/// fingerprints of the configuration and `.mailmap` that shape the output,
+/// read from the git directory so a hit needs no child. `None` when a step
+/// may change something or reads what the key cannot pin down, such as the
+/// working tree or every ref.
+pub fn key(
+    state: &State,
+    repository_path: &str,
+    tool: &str,
+    steps: &[Vec<String>],
+) -> Option<String> {
+    if state.limits.cache_entries == Some(0) {
+        return None;
+    }
+    let git_dir = GitDir::open(repository_path)?;
+    let mut parts = vec![
+        tool.to_string(),
+        repository_path.to_string(),
+        format!("{:?}", state.limits.max_output_bytes),
+    ];
+    // Settings such as `log.date` and `log.decorate`, and the `.mailmap`
+    // that `log.mailmap` applies by default
+    let mut config_files = config_files(repository_path);
+    config_files.push(format!("{}/config.worktree", git_dir.path));
+    for file in config_files {
+        parts.push(format!("{}={}", file, fingerprint(&file)));
+    }
+    parts.push(match &git_dir.work_tree {
+        Some(work_tree) => format!(
+            "mailmap={}",
+            fingerprint(&format!("{}/.mailmap", work_tree))
+        ),
+        // Bare repositories read `HEAD:.mailmap`
+        None => format!("mailmap={}", git_dir.resolve("HEAD", 0).unwrap_or_default()),
+    });
+    for step in steps {
+        if !git::is_read_only(step) {
+            return None;
+        }
+        parts.push(step.join("\0"));
+        for input in inputs(step)? {
+            parts.push(match input {
+                Input::Revision(name) => format!("{}={}", name, git_dir.revision(&name)?),
+                Input::Index => format!("index={}", git_dir.index()),
+                Input::Notes => format!(
+                    "notes={}",
+                    git_dir.resolve("refs/notes/commits", 0).unwrap_or_default()
+                ),
+            });
+        }
+    }
+    serde_json::to_string(&parts).ok()
+}
+
+/// The sha `branch` of `repository_path` points at, read from the git
+/// directory.
+pub fn branch_head(repository_path: &str, branch: &str) -> Option<String> {
+    GitDir::open(repository_path)?.resolve(&format!("refs/heads/{}", branch), 0)
+}
+
+/// The configuration files git reads in `repository_path`: the system,
+/// global and repository ones. Included files are not followed.
+pub fn config_files(repository_path: &str) -> Vec<String> {
+    let home = get_var("HOME");
+    let xdg = get_var("XDG_CONFIG_HOME")
+        .or_else(|| home.as_ref().map(|home| format!("{}/.config", home)));
+    [
+        Some("/etc/gitconfig".to_string()),
+        xdg.map(|xdg| format!("{}/git/config", xdg)),
+        get_var("GIT_CONFIG_GLOBAL").or_else(|| home.map(|home| format!("{}/.gitconfig", home))),
+        // Shared by the repository's worktrees
+        GitDir::open(repository_path).map(|git_dir| format!("{}/config", git_dir.common)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// The result cached under `key`, if any.
+pub fn get(state: &State, key: &str) -> Option<Value> {
+    match storage::get::<Entry>(state, &label(key)) {
+        Ok(entry) => entry
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.result),
+        Err(message) => {
+            log(&format!("Failed to read cached result: {}", message));
+            None
+        }
+    }
+}
+
+/// Cache `result` under `key`, dropping the oldest results beyond
+/// `limits.cache_entries`.
+pub fn put(state: &mut State, key: String, result: &Value) {
+    let label = label(&key);
+    let entry = Entry {
+        key,
+        result: result.clone(),
+    };
+    if let Err(message) = storage::put(state, &label, &entry) {
+        log(&format!("Failed to cache result: {}", message));
+        return;
+    }
+    state.cache_labels.retain(|cached| *cached != label);
+    state.cache_labels.push_back(label);
+
+    let max = state.limits.cache_entries.unwrap_or(DEFAULT_ENTRIES);
+    while state.cache_labels.len() > max {
+        let Some(oldest) = state.cache_labels.pop_front() else {
+            break;
+        };
+        if let Err(message) = storage::remove(state, &oldest) {
+            log(&format!("Failed to drop cached result: {}", message));
+        }
+    }
+}
+
+fn label(key: &str) -> String {
+    format!("{}{:016x}", LABEL_PREFIX, hash(key.as_bytes()))
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What `args` reads besides themselves, or `None` if that cannot be
+/// pinned down.
+fn inputs(args: &[String]) -> Option<Vec<Input>> {
+    let index = git::subcommand_index(args)?;
+    // Another repository's refs would not be reflected in the key
+    let repository_option = args[..index]
+        .iter()
+        .any(|arg| git::is_repository_option(arg));
+    let rest = &args[index + 1..];
+    let unpinned = rest.iter().take_while(|arg| *arg != "--").any(|arg| {
+        UNPINNED_OPTIONS
+            .iter()
+            .any(|option| arg == option || arg.starts_with(&format!("{}=", option)))
+            || UNPINNED_PLACEHOLDERS
+                .iter()
+                .any(|placeholder| arg.contains(placeholder))
+    });
+    if repository_option || unpinned {
+        return None;
+    }
+
+    // Option values count as operands too: one that is not a revision
+    // cannot be resolved, which only costs a cache miss
+    let (operands, separated) = git::operands(rest, &[]);
+    let mut inputs = Vec::new();
+    let revisions: &[&str] = match args[index].as_str() {
+        "log" | "show" => {
+            inputs.push(Input::Notes);
+            match operands.is_empty() {
+                true => &["HEAD"],
+                false => &operands,
+            }
+        }
+        "rev-list" | "shortlog" | "cat-file" | "diff-tree" | "merge-base" | "range-diff"
+            if !operands.is_empty() =>
+        {
+            &operands
+        }
+        // The paths after the tree-ish need no resolving
+        "ls-tree" => &operands[..1.min(operands.len())],
+        // Without a revision, blame reads the working tree
+        "blame" if separated && !operands.is_empty() => &operands,
+        "blame" if operands.len() == 2 => &operands[..1],
+        "diff" => {
+            let cached = rest
+                .iter()
+                .take_while(|arg| *arg != "--")
+                .any(|arg| arg == "--cached" || arg == "--staged");
+            let count: usize = operands.iter().map(|operand| sides(operand).len()).sum();
+            match (cached, count) {
+                (_, 2) => &operands,
+                (true, 0) => {
+                    inputs.push(Input::Index);
+                    &["HEAD"]
+                }
+                (true, 1) => {
+                    inputs.push(Input::Index);
+                    &operands
+                }
+                // Anything else compares with the working tree
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    if revisions.is_empty() {
+        return None;
+    }
+    for revision in revisions {
+        for side in sides(revision) {
+            inputs.push(base(side)?);
+        }
+    }
+    Some(inputs)
+}
+
+/// The revisions of a range such as `main..topic`, with an empty side
+/// standing for `HEAD`.
+fn sides(operand: &str) -> Vec<&str> {
+    let sides: Vec<&str> = match operand.split_once("...") {
+        Some((left, right)) => vec![left, right],
+        None => match operand.split_once("..") {
+            Some((left, right)) => vec![left, right],
+            None => vec![operand],
+        },
+    };
+    sides
+        .into_iter()
+        .map(|side| if side.is_empty() { "HEAD" } else { side })
+        .collect()
+}
+
+/// What a revision such as `^main`, `HEAD~2:src` or `:path` starts from.
+fn base(revision: &str) -> Option<Input> {
+    let revision = revision.trim_start_matches('^');
+    // `:/message` searches every ref, `@{1}` the reflog
+    if revision.starts_with(":/") || revision.contains("@{") {
+        return None;
+    }
+    if revision.starts_with(':') {
+        return Some(Input::Index);
+    }
+    let end = revision.find(['~', '^', ':']).unwrap_or(revision.len());
+    match &revision[..end] {
+        "" => None,
+        "@" => Some(Input::Revision("HEAD".to_string())),
+        name => Some(Input::Revision(name.to_string())),
+    }
+}
+
+fn is_object_id(name: &str) -> bool {
+    (7..=64).contains(&name.len()) && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// A repository's git directory, read through the filesystem handler.
+struct GitDir {
+    /// Where HEAD and the other per-worktree refs live.
+    path: String,
+    /// Where the shared refs and `packed-refs` live, which differs from
+    /// `path` in linked worktrees.
+    common: String,
+    /// The working tree, `None` in a bare repository.
+    work_tree: Option<String>,
+}
+
+impl GitDir {
+    fn open(repository_path: &str) -> Option<GitDir> {
+        let root = repository_path.trim_end_matches('/');
+        let (path, work_tree) = if read_file(&format!("{}/.git/HEAD", root)).is_ok() {
+            (format!("{}/.git", root), Some(root.to_string()))
+        } else if let Ok(link) = read_file(&format!("{}/.git", root)) {
+            // A linked worktree or submodule: `gitdir: <path>`
+            let link = String::from_utf8(link).ok()?;
+            let path = join(root, link.trim().strip_prefix("gitdir:")?.trim());
+            (path, Some(root.to_string()))
+        } else if read_file(&format!("{}/HEAD", root)).is_ok() {
+            (root.to_string(), None)
+        } else {
+            return None;
+        };
+        let common = match read_file(&format!("{}/commondir", path)) {
+            Ok(common) => join(&path, String::from_utf8(common).ok()?.trim()),
+            Err(_) => path.clone(),
+        };
+        // Refs in a reftable cannot be read as files
+        if read_file(&format!("{}/reftable/tables.list", common)).is_ok() {
+            return None;
+        }
+        Some(GitDir {
+            path,
+            common,
+            work_tree,
+        })
+    }
+
+    /// The sha `name` resolves to, trying the refs git would, in order.
+    fn revision(&self, name: &str) -> Option<String> {
+        if is_object_id(name) {
+            return Some(name.to_string());
+        }
+        let pseudo =
+            name.starts_with("refs/") || name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        let candidates = [
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ];
+        pseudo
+            .then(|| name.to_string())
+            .into_iter()
+            .chain(candidates)
+            .find_map(|reference| self.resolve(&reference, 0))
+    }
+
+    /// The sha the full ref name `reference` points at, following symbolic
+    /// refs.
+    fn resolve(&self, reference: &str, depth: usize) -> Option<String> {
+        if depth > 5 {
+            return None;
+        }
+        let loose = [&self.path, &self.common]
+            .into_iter()
+            .find_map(|dir| read_file(&format!("{}/{}", dir, reference)).ok())
+            .and_then(|content| String::from_utf8(content).ok());
+        let target = match loose {
+            Some(content) => content.trim().to_string(),
+            None => self.packed(reference)?,
+        };
+        match target.strip_prefix("ref:") {
+            Some(target) => self.resolve(target.trim(), depth + 1),
+            None if is_object_id(&target) => Some(target),
+            None => None,
+        }
+    }
+
+    fn packed(&self, reference: &str) -> Option<String> {
+        let packed = read_file(&format!("{}/packed-refs", self.common)).ok()?;
+        let packed = String::from_utf8(packed).ok()?;
+        packed.lines().find_map(|line| {
+            let (sha, name) = line.split_once(' ')?;
+            (name == reference).then(|| sha.to_string())
+        })
+    }
+
+    /// Fingerprint of the index file.
+    fn index(&self) -> String {
+        fingerprint(&format!("{}/index", self.path))
+    }
+}
+
+/// Fingerprint of the file at `path`, which may not exist.
+fn fingerprint(path: &str) -> String {
+    match read_file(path) {
+        Ok(content) => format!("{}:{:016x}", content.len(), hash(&content)),
+        Err(_) => "none".to_string(),
+    }
+}
+
+/// `path` relative to `base` unless it is absolute.
+fn join(base: &str, path: &str) -> String {
+    match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("{}/{}", base.trim_end_matches('/'), path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs_of(args: &[&str]) -> Option<Vec<Input>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        inputs(&args)
+    }
+
+    fn revision(name: &str) -> Input {
+        Input::Revision(name.to_string())
+    }
+
+    #[test]
+    fn log_is_pinned_to_its_revisions_and_notes() {
+        assert_eq!(
+            inputs_of(&["log", "--oneline", "main..topic"]),
+            Some(vec![Input::Notes, revision("main"), revision("topic")])
+        );
+        assert_eq!(
+            inputs_of(&["log", "-5"]),
+            Some(vec![Input::Notes, revision("HEAD")])
+        );
+        assert_eq!(
+            inputs_of(&["show", "v1.0^{commit}~2:src"]),
+            Some(vec![Input::Notes, revision("v1.0")])
+        );
+    }
+
+    #[test]
+    fn decorations_and_relative_dates_are_unpinned() {
+        assert_eq!(inputs_of(&["log", "--format=%h %d", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--pretty=format:%D", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--format=%(decorate)", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--decorate", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--decorate=full", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--format=%h %ar", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--since=2.weeks", "main"]), None);
+        assert_eq!(inputs_of(&["log", "--all"]), None);
+        assert_eq!(inputs_of(&["log", "-g"]), None);
+    }
+
+    #[test]
+    fn absolute_dates_stay_pinned() {
+        assert_eq!(
+            inputs_of(&["log", "--format=%h %ad", "main"]),
+            Some(vec![Input::Notes, revision("main")])
+        );
+    }
+
+    #[test]
+    fn diff_pins_the_index_only_when_cached() {
+        assert_eq!(
+            inputs_of(&["diff", "--cached"]),
+            Some(vec![Input::Index, revision("HEAD")])
+        );
+        assert_eq!(
+            inputs_of(&["diff", "--staged", "main"]),
+            Some(vec![Input::Index, revision("main")])
+        );
+        assert_eq!(
+            inputs_of(&["diff", "main", "topic"]),
+            Some(vec![revision("main"), revision("topic")])
+        );
+        assert_eq!(
+            inputs_of(&["diff", "main..."]),
+            Some(vec![revision("main"), revision("HEAD")])
+        );
+        // Against the working tree
+        assert_eq!(inputs_of(&["diff"]), None);
+        assert_eq!(inputs_of(&["diff", "main"]), None);
+    }
+
+    #[test]
+    fn blame_needs_a_revision() {
+        assert_eq!(inputs_of(&["blame", "file.rs"]), None);
+        assert_eq!(
+            inputs_of(&["blame", "main", "--", "file.rs"]),
+            Some(vec![revision("main")])
+        );
+    }
+
+    #[test]
+    fn other_repositories_and_reflogs_are_unpinned() {
+        assert_eq!(inputs_of(&["-C", "/elsewhere", "log", "main"]), None);
+        assert_eq!(inputs_of(&["log", "main@{1}"]), None);
+        assert_eq!(inputs_of(&["show", ":/fix"]), None);
+        assert_eq!(inputs_of(&["status"]), None);
+    }
+
+    #[test]
+    fn index_paths_read_the_index() {
+        assert_eq!(
+            inputs_of(&["cat-file", "-p", ":src/lib.rs"]),
+            Some(vec![Input::Index])
+        );
+    }
+}
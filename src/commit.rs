@@ -0,0 +1,275 @@
+use crate::identity::Identity;
+use serde::{Deserialize, Serialize};
+
+/// Conventional Commits types accepted when `types` is not configured.
+const CONVENTIONAL_TYPES: &[&str] = &[
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test",
+];
+
+/// Rules every message passed to `git-commit` must follow.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CommitRules {
+    /// Subjects must read `type(scope)!: description`.
+    #[serde(default)]
+    pub conventional: bool,
+    /// Types allowed by `conventional`, instead of the usual ones.
+    #[serde(default)]
+    pub types: Option<Vec<String>>,
+    #[serde(default)]
+    pub max_subject_length: Option<usize>,
+    /// Trailer keys every message must carry, e.g. `Signed-off-by`.
+    #[serde(default)]
+    pub required_trailers: Vec<String>,
+}
+
+/// A rule a commit message breaks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Violation {
+    pub rule: String,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(rule: &str, message: String) -> Self {
+        Violation {
+            rule: rule.to_string(),
+            message,
+        }
+    }
+}
+
+impl CommitRules {
+    /// Every rule `message` breaks. Trailers git adds itself, from `signoff`
+    /// or the identity's co-authors, count as present.
+    pub fn check(&self, message: &str, signoff: bool, identity: &Identity) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut lines = message.trim().lines();
+        let subject = lines.next().unwrap_or("").trim();
+
+        if subject.is_empty() {
+            violations.push(Violation::new(
+                "subject",
+                "The message needs a subject line".to_string(),
+            ));
+            return violations;
+        }
+        // A matter of style, so only checked for repositories with rules
+        if self.is_configured() && lines.next().is_some_and(|line| !line.trim().is_empty()) {
+            violations.push(Violation::new(
+                "blank_line",
+                "Separate the subject from the body with a blank line".to_string(),
+            ));
+        }
+        if let Some(max) = self.max_subject_length
+            && subject.chars().count() > max
+        {
+            violations.push(Violation::new(
+                "max_subject_length",
+                format!(
+                    "The subject is {} characters long, more than the {} allowed",
+                    subject.chars().count(),
+                    max
+                ),
+            ));
+        }
+        if self.conventional {
+            violations.extend(self.check_conventional(subject));
+        }
+
+        let mut trailers = trailer_keys(message);
+        if signoff {
+            trailers.push("signed-off-by".to_string());
+        }
+        if !identity.co_authors.is_empty() {
+            trailers.push("co-authored-by".to_string());
+        }
+        for required in &self.required_trailers {
+            if !trailers.contains(&required.to_ascii_lowercase()) {
+                violations.push(Violation::new(
+                    "required_trailers",
+                    format!("The message needs a '{}:' trailer", required),
+                ));
+            }
+        }
+        violations
+    }
+
+    /// Whether any rule is set, beyond git's own need for a subject.
+    fn is_configured(&self) -> bool {
+        self.conventional
+            || self.types.is_some()
+            || self.max_subject_length.is_some()
+            || !self.required_trailers.is_empty()
+    }
+
+    fn check_conventional(&self, subject: &str) -> Option<Violation> {
+        let Some(parsed) = conventional(subject) else {
+            return Some(Violation::new(
+                "conventional",
+                format!(
+                    "'{}' does not follow Conventional Commits: expected 'type(scope): description'",
+                    subject
+                ),
+            ));
+        };
+        let kind = parsed.kind.as_str();
+
+        let allowed = match &self.types {
+            Some(types) => types.iter().any(|allowed| allowed == kind),
+            None => CONVENTIONAL_TYPES.contains(&kind),
+        };
+        match allowed {
+            true => None,
+            false => Some(Violation::new(
+                "types",
+                format!(
+                    "'{}' is not an allowed type; use one of {}",
+                    kind,
+                    match &self.types {
+                        Some(types) => types.join(", "),
+                        None => CONVENTIONAL_TYPES.join(", "),
+                    }
+                ),
+            )),
+        }
+    }
+}
+
+/// The parts of a Conventional Commits subject that decide a release.
+#[derive(Debug, Clone)]
+pub struct Conventional {
+    pub kind: String,
+    /// Marked with `!` after the type or scope.
+    pub breaking: bool,
+}
+
+/// Read `type(scope)!: description`, or `None` if `subject` is not in that form.
+pub fn conventional(subject: &str) -> Option<Conventional> {
+    let (prefix, description) = subject.split_once(": ")?;
+    let (prefix, breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+    let (kind, scope_ok) = match prefix.split_once('(') {
+        Some((kind, scope)) => (
+            kind,
+            scope
+                .strip_suffix(')')
+                .is_some_and(|scope| !scope.is_empty() && !scope.contains(['(', ')'])),
+        ),
+        None => (prefix, true),
+    };
+    let valid = !description.trim().is_empty()
+        && scope_ok
+        && !kind.is_empty()
+        && kind
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    valid.then(|| Conventional {
+        kind: kind.to_string(),
+        breaking,
+    })
+}
+
+/// Lowercased keys of the trailers in the last paragraph of `message`.
+fn trailer_keys(message: &str) -> Vec<String> {
+    let message = message.trim();
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return Vec::new();
+    };
+    last.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, _)| key.trim())
+        .filter(|key| !key.is_empty() && !key.contains(char::is_whitespace))
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(conventional: bool) -> CommitRules {
+        CommitRules {
+            conventional,
+            ..CommitRules::default()
+        }
+    }
+
+    fn broken(rules: &CommitRules, message: &str) -> Vec<String> {
+        rules
+            .check(message, false, &Identity::default())
+            .into_iter()
+            .map(|violation| violation.rule)
+            .collect()
+    }
+
+    #[test]
+    fn reads_conventional_subjects() {
+        let parsed = conventional("feat(parser): accept scopes").map(|c| (c.kind, c.breaking));
+        assert_eq!(parsed, Some(("feat".to_string(), false)));
+        let parsed = conventional("refactor!: drop the old API").map(|c| (c.kind, c.breaking));
+        assert_eq!(parsed, Some(("refactor".to_string(), true)));
+        let parsed = conventional("fix(api)!: reject empty ids").map(|c| (c.kind, c.breaking));
+        assert_eq!(parsed, Some(("fix".to_string(), true)));
+    }
+
+    #[test]
+    fn rejects_subjects_that_are_not_conventional() {
+        for subject in [
+            "Add a feature",
+            "feat: ",
+            "feat:missing space",
+            "Feat: capitalized type",
+            "feat(): empty scope",
+            "feat(a(b)): nested scope",
+            "feat(scope: unclosed",
+            ": no type",
+        ] {
+            assert!(conventional(subject).is_none(), "{}", subject);
+        }
+    }
+
+    #[test]
+    fn checks_types_against_the_configured_list() {
+        assert!(broken(&rules(true), "docs: explain rules").is_empty());
+        assert_eq!(broken(&rules(true), "wip: half done"), ["types"]);
+        assert_eq!(broken(&rules(true), "Half done"), ["conventional"]);
+        let custom = CommitRules {
+            types: Some(vec!["wip".to_string()]),
+            ..rules(true)
+        };
+        assert!(broken(&custom, "wip: half done").is_empty());
+        assert_eq!(broken(&custom, "feat: new"), ["types"]);
+    }
+
+    #[test]
+    fn blank_line_is_only_required_with_rules() {
+        let message = "Subject\nBody straight after";
+        assert!(broken(&CommitRules::default(), message).is_empty());
+        assert_eq!(broken(&rules(true), "fix: subject\nbody"), ["blank_line"]);
+        assert_eq!(broken(&CommitRules::default(), "  \n"), ["subject"]);
+    }
+
+    #[test]
+    fn limits_subject_length_in_characters() {
+        let rules = CommitRules {
+            max_subject_length: Some(10),
+            ..CommitRules::default()
+        };
+        assert!(broken(&rules, "ünïcödé ok").is_empty());
+        assert_eq!(broken(&rules, "eleven char"), ["max_subject_length"]);
+    }
+
+    #[test]
+    fn required_trailers_count_what_git_adds() {
+        let rules = CommitRules {
+            required_trailers: vec!["Signed-off-by".to_string()],
+            ..CommitRules::default()
+        };
+        let message = "Fix it\n\nBody.\n\nsigned-off-by: Ada <ada@example.com>";
+        assert!(broken(&rules, message).is_empty());
+        assert_eq!(broken(&rules, "Fix it\n\nBody."), ["required_trailers"]);
+        assert!(rules.check("Fix it", true, &Identity::default()).is_empty());
+    }
+}
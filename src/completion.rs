@@ -0,0 +1,151 @@
+use crate::GitCommandResult;
+use mcp_protocol::completion::{CompletionCompleteResult, CompletionInfo};
+use serde::{Deserialize, Serialize};
+
+/// Most values returned in a single completion response, as MCP recommends.
+const MAX_VALUES: usize = 100;
+
+/// What the completed argument belongs to. MCP defines prompt and resource
+/// references; `ref/tool` extends that to tool arguments.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum CompletionReference {
+    #[serde(rename = "ref/prompt")]
+    Prompt { name: String },
+    #[serde(rename = "ref/tool")]
+    Tool { name: String },
+    #[serde(rename = "ref/resource")]
+    Resource { uri: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ArgumentKind {
+    /// Branch, tag and remote names.
+    Revision,
+    /// Tracked file paths.
+    Path,
+    /// The git subcommand, i.e. the first element of `git-command`'s `args`.
+    Subcommand,
+    /// Names of registered repositories.
+    Repository,
+}
+
+/// Decide what kind of value an argument takes from its name, so typed tools
+/// and prompts get completions without registering each argument.
+pub fn argument_kind(reference: &CompletionReference, argument: &str) -> Option<ArgumentKind> {
+    match (reference, argument) {
+        (CompletionReference::Tool { name }, "args") if name == "git-command" => {
+            Some(ArgumentKind::Subcommand)
+        }
+        (CompletionReference::Resource { .. }, _) => None,
+        (_, "repo") => Some(ArgumentKind::Repository),
+        (_, "base" | "head" | "sha" | "revision" | "branch" | "ref" | "target" | "remote") => {
+            Some(ArgumentKind::Revision)
+        }
+        (_, "path" | "paths" | "file") => Some(ArgumentKind::Path),
+        _ => None,
+    }
+}
+
+/// Names suggested for a repository, cached per repository path until a call
+/// that may change them runs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RepositoryNames {
+    revisions: Option<Vec<String>>,
+    paths: Option<Vec<String>>,
+}
+
+impl RepositoryNames {
+    pub fn get(&self, kind: ArgumentKind) -> Option<&Vec<String>> {
+        match kind {
+            ArgumentKind::Revision => self.revisions.as_ref(),
+            ArgumentKind::Path => self.paths.as_ref(),
+            ArgumentKind::Subcommand | ArgumentKind::Repository => None,
+        }
+    }
+
+    pub fn set(&mut self, kind: ArgumentKind, values: Vec<String>) {
+        match kind {
+            ArgumentKind::Revision => self.revisions = Some(values),
+            ArgumentKind::Path => self.paths = Some(values),
+            ArgumentKind::Subcommand | ArgumentKind::Repository => {}
+        }
+    }
+}
+
+/// Git arguments that list the candidates for `kind`.
+pub fn git_args(kind: ArgumentKind) -> Vec<String> {
+    let args: &[&str] = match kind {
+        ArgumentKind::Revision => &[
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/heads",
+            "refs/tags",
+            "refs/remotes",
+        ],
+        ArgumentKind::Path | ArgumentKind::Subcommand | ArgumentKind::Repository => &["ls-files"],
+    };
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+/// Turn the output of `git_args(kind)` into candidate values.
+pub fn parse(kind: ArgumentKind, output: &GitCommandResult) -> Vec<String> {
+    let lines = output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty());
+
+    match kind {
+        ArgumentKind::Revision => {
+            let mut values = vec!["HEAD".to_string()];
+            let mut remotes = Vec::new();
+            for refname in lines {
+                if let Some(name) = refname
+                    .strip_prefix("refs/heads/")
+                    .or_else(|| refname.strip_prefix("refs/tags/"))
+                {
+                    values.push(name.to_string());
+                } else if let Some((remote, branch)) = refname
+                    .strip_prefix("refs/remotes/")
+                    .and_then(|name| name.split_once('/'))
+                {
+                    if !remotes.iter().any(|r| r == remote) {
+                        remotes.push(remote.to_string());
+                    }
+                    if branch != "HEAD" {
+                        values.push(format!("{}/{}", remote, branch));
+                    }
+                }
+            }
+            values.extend(remotes);
+            values
+        }
+        ArgumentKind::Path | ArgumentKind::Subcommand | ArgumentKind::Repository => {
+            lines.map(str::to_string).collect()
+        }
+    }
+}
+
+/// Completion result for the candidates starting with `value`.
+pub fn complete<'a>(
+    candidates: impl IntoIterator<Item = &'a str>,
+    value: &str,
+) -> CompletionCompleteResult {
+    let matches: Vec<&str> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(value))
+        .collect();
+
+    CompletionCompleteResult {
+        completion: CompletionInfo {
+            values: matches
+                .iter()
+                .take(MAX_VALUES)
+                .map(|s| s.to_string())
+                .collect(),
+            total: Some(matches.len() as u32),
+            has_more: matches.len() > MAX_VALUES,
+        },
+    }
+}
@@ -0,0 +1,351 @@
+use crate::bindings::theater::simple::environment::{get_var, list_vars};
+use crate::bindings::theater::simple::filesystem::read_file;
+use crate::bindings::theater::simple::runtime::log;
+use crate::policy::Policy;
+use crate::repos;
+use crate::{InitState, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Host environment variables read as defaults, and the setting each fills.
+const ENVIRONMENT: &[(&str, &str)] = &[
+    ("GIT_MCP_REPO", "/repository_path"),
+    ("GIT_MCP_READ_ONLY", "/policy/read_only"),
+    ("GIT_MCP_ALLOWED_ROOTS", "/allowed_roots"),
+    ("GIT_MCP_TIMEOUT_MS", "/limits/timeout_ms"),
+    ("GIT_MCP_MAX_OUTPUT_BYTES", "/limits/max_output_bytes"),
+    ("GIT_MCP_MANIFEST", "/git_command_manifest"),
+];
+
+/// Build the init configuration from the host environment, the init state
+/// bytes and the init parameter, each taking precedence over the one before.
+/// The parameter may be a JSON or TOML document, or the path of a `.json` or
+/// `.toml` file; its top-level settings replace those in the state bytes.
+/// Anything unreadable is an error, never an empty configuration.
+pub fn load(state_bytes: Option<&[u8]>, param: &str) -> Result<InitState, String> {
+    let state_config = match state_bytes {
+        Some(bytes) if !bytes.is_empty() => serde_json::from_slice::<Value>(bytes)
+            .map_err(|e| format!("Init state is not valid JSON: {}", e))?,
+        _ => json!({}),
+    };
+    if !state_config.is_object() {
+        return Err("Init state must be a JSON object".to_string());
+    }
+    let mut config = environment_defaults()?;
+    merge(&mut config, state_config);
+
+    if let Some(overrides) = parse_param(param)? {
+        for (key, value) in overrides {
+            config[key] = value;
+        }
+    }
+    let init_state = serde_json::from_value::<InitState>(config)
+        .map_err(|e| format!("Invalid configuration: {}", e))?;
+
+    validate(&init_state)?;
+    Ok(init_state)
+}
+
+/// Settings from `GIT_MCP_*` environment variables.
+fn environment_defaults() -> Result<Value, String> {
+    let mut defaults = json!({});
+    for (name, pointer) in ENVIRONMENT {
+        let Some(raw) = get_var(name) else {
+            continue;
+        };
+        let value = match *pointer {
+            "/policy/read_only" => match raw.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => json!(true),
+                "0" | "false" | "no" | "off" | "" => json!(false),
+                _ => return Err(format!("{} must be true or false, got '{}'", name, raw)),
+            },
+            "/limits/timeout_ms" | "/limits/max_output_bytes" => raw
+                .trim()
+                .parse::<u64>()
+                .map(|number| json!(number))
+                .map_err(|_| format!("{} must be a number, got '{}'", name, raw))?,
+            // Separated like PATH
+            "/allowed_roots" => json!(
+                raw.split(':')
+                    .filter(|root| !root.is_empty())
+                    .collect::<Vec<_>>()
+            ),
+            _ => json!(raw),
+        };
+        log(&format!("Using {} from the environment", name));
+
+        let (parent, key) = pointer.rsplit_once('/').unwrap_or(("", pointer));
+        let mut target = &mut defaults;
+        for part in parent.split('/').filter(|part| !part.is_empty()) {
+            target = &mut target[part];
+        }
+        target[key] = value;
+    }
+
+    for (name, _) in list_vars() {
+        if name.starts_with("GIT_MCP_") && !ENVIRONMENT.iter().any(|(known, _)| *known == name) {
+            log(&format!("Ignoring unknown environment variable {}", name));
+        }
+    }
+    Ok(defaults)
+}
+
+/// Merge `overlay` into `base`, keeping settings of `base` that `overlay`
+/// does not set.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The settings in the init parameter, or `None` if it is not a configuration.
+fn parse_param(param: &str) -> Result<Option<serde_json::Map<String, Value>>, String> {
+    let param = param.trim();
+    let (source, text) = if param.ends_with(".json") || param.ends_with(".toml") {
+        let bytes = read_file(param)
+            .map_err(|e| format!("Failed to read configuration file '{}': {}", param, e))?;
+        let text = String::from_utf8(bytes)
+            .map_err(|_| format!("Configuration file '{}' is not valid UTF-8", param))?;
+        (param, text)
+    } else if param.starts_with('{') || param.contains('=') {
+        ("init parameter", param.to_string())
+    } else {
+        if !param.is_empty() {
+            log("Init parameter is not a configuration, ignoring it");
+        }
+        return Ok(None);
+    };
+
+    let value: Value = if source.ends_with(".json") || text.trim_start().starts_with('{') {
+        serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid JSON configuration in {}: {}", source, e))?
+    } else {
+        toml::from_str(&text)
+            .map_err(|e| format!("Invalid TOML configuration in {}: {}", source, e))?
+    };
+
+    match value {
+        Value::Object(settings) => Ok(Some(settings)),
+        _ => Err(format!("Configuration in {} must be a table", source)),
+    }
+}
+
+/// Reject settings that would only fail later, when a call uses them.
+fn validate(init_state: &InitState) -> Result<(), String> {
+    if init_state.repository_path.as_deref() == Some("") {
+        return Err("'repository_path' must not be empty".to_string());
+    }
+    if let Some((name, _)) = init_state
+        .repositories
+        .iter()
+        .find(|(_, repository)| repository.path.is_empty())
+    {
+        return Err(format!("Repository '{}' has an empty 'path'", name));
+    }
+    let roots = init_state.allowed_roots.as_deref();
+    if let Some(path) = &init_state.repository_path {
+        repos::check_allowed(roots, path)?;
+    }
+    for repository in init_state.repositories.values() {
+        repos::check_allowed(roots, &repository.path)?;
+    }
+    init_state.identity.validate()?;
+    if let Some(approval) = &init_state.approval {
+        approval.validate()?;
+    }
+    if init_state
+        .protected_branches
+        .iter()
+        .any(|pattern| pattern.trim().is_empty())
+    {
+        return Err("'protected_branches' must not contain empty patterns".to_string());
+    }
+    if init_state.commit_rules.max_subject_length == Some(0) {
+        return Err("'commit_rules.max_subject_length' must be greater than zero".to_string());
+    }
+    if init_state.git_command_manifest.as_deref() == Some("") {
+        return Err("'git_command_manifest' must not be empty".to_string());
+    }
+    init_state.limits.validate()?;
+    if let Some(http) = &init_state.http {
+        if !http.path.starts_with('/') {
+            return Err(format!(
+                "'http.path' must start with '/', got '{}'",
+                http.path
+            ));
+        }
+        if let Some(path) = &http.websocket_path
+            && !path.starts_with('/')
+        {
+            return Err(format!(
+                "'http.websocket_path' must start with '/', got '{}'",
+                path
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Limits applied to every git command.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Limits {
+    /// Passed to the git-command child, which stops git after this long.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most bytes of stdout or stderr returned as text. Parsed results of the
+    /// typed tools are not cut.
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// Most results of read-only calls kept in the result cache; `0` turns
+    /// the cache off.
+    #[serde(default)]
+    pub cache_entries: Option<usize>,
+    /// Most git-command children running at once; more wait in a queue.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+/// Children running at once when `limits.max_concurrency` is not set.
+const MAX_CONCURRENCY: usize = 8;
+
+impl Limits {
+    /// Reject limits no command could run under.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timeout_ms == Some(0) {
+            return Err("'limits.timeout_ms' must be greater than zero".to_string());
+        }
+        if self.max_output_bytes == Some(0) {
+            return Err("'limits.max_output_bytes' must be greater than zero".to_string());
+        }
+        if self.max_concurrency == Some(0) {
+            return Err("'limits.max_concurrency' must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or(MAX_CONCURRENCY)
+    }
+
+    /// Cut `output` to `max_output_bytes`, on a character boundary.
+    pub fn truncate(&self, output: &mut String) {
+        let Some(max) = self.max_output_bytes else {
+            return;
+        };
+        if output.len() <= max {
+            return;
+        }
+        let mut end = max;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        let omitted = output.len() - end;
+        output.truncate(end);
+        output.push_str(&format!("\n[output truncated, {} bytes omitted]", omitted));
+    }
+}
+
+/// What the operator lets clients do. Only read from the init state, so no
+/// request can grant itself more.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Capabilities {
+    /// Accept `Configure` requests on the message-server.
+    #[serde(default)]
+    pub configure: bool,
+    /// Offer the `configure-server` tool to MCP clients.
+    #[serde(default)]
+    pub configure_tool: bool,
+}
+
+/// Settings changed by a `Configure` request. Fields left unset keep their
+/// current value.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Changes {
+    /// New default repository, checked with `rev-parse --git-dir` first.
+    #[serde(default)]
+    pub repository_path: Option<String>,
+    #[serde(default)]
+    pub policy: Option<Policy>,
+    /// Policies for registered repositories, by name.
+    #[serde(default)]
+    pub repository_policies: BTreeMap<String, Policy>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+}
+
+impl Changes {
+    /// Refuse changes init would refuse too, and changes that name
+    /// repositories that are not registered.
+    pub fn validate(&self, state: &State) -> Result<(), String> {
+        if let Some(path) = &self.repository_path {
+            if path.is_empty() {
+                return Err("'repository_path' must not be empty".to_string());
+            }
+            repos::check_allowed(state.allowed_roots.as_deref(), path)?;
+        }
+        let limits = Limits {
+            timeout_ms: self.timeout_ms,
+            max_output_bytes: self.max_output_bytes,
+            ..Limits::default()
+        };
+        limits.validate()?;
+        match self
+            .repository_policies
+            .keys()
+            .find(|name| !state.repositories.contains_key(*name))
+        {
+            Some(name) => Err(format!("Unknown repository '{}'", name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn apply(self, state: &mut State) {
+        if let Some(repository_path) = self.repository_path {
+            state.repository_path = Some(repository_path);
+        }
+        if let Some(policy) = self.policy {
+            state.policy = policy;
+        }
+        for (name, policy) in self.repository_policies {
+            if let Some(repository) = state.repositories.get_mut(&name) {
+                repository.policy = Some(policy);
+            }
+        }
+        if let Some(timeout_ms) = self.timeout_ms {
+            state.limits.timeout_ms = Some(timeout_ms);
+        }
+        if let Some(max_output_bytes) = self.max_output_bytes {
+            state.limits.max_output_bytes = Some(max_output_bytes);
+        }
+    }
+}
+
+/// The settings a `Configure` request can change, as they are now.
+pub fn current(state: &State) -> Value {
+    json!({
+        "repository_path": state.repository_path,
+        "policy": state.policy,
+        "repository_policies": state
+            .repositories
+            .iter()
+            .map(|(name, repository)| (name.clone(), repository.policy.clone()))
+            .collect::<BTreeMap<_, _>>(),
+        "timeout_ms": state.limits.timeout_ms,
+        "max_output_bytes": state.limits.max_output_bytes,
+    })
+}
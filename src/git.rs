@@ -0,0 +1,416 @@
+/// A git subcommand the actor knows about.
+pub struct Subcommand {
+    pub name: &'static str,
+    /// Never modifies the repository, its refs or the working tree.
+    pub read_only: bool,
+    /// Can discard work or rewrite history, e.g. `reset --hard` or `clean -f`.
+    pub destructive: bool,
+    /// Talks to a remote.
+    pub open_world: bool,
+}
+
+const fn read(name: &'static str) -> Subcommand {
+    Subcommand {
+        name,
+        read_only: true,
+        destructive: false,
+        open_world: false,
+    }
+}
+
+const fn write(name: &'static str) -> Subcommand {
+    Subcommand {
+        name,
+        read_only: false,
+        destructive: false,
+        open_world: false,
+    }
+}
+
+impl Subcommand {
+    const fn destructive(self) -> Self {
+        Subcommand {
+            destructive: true,
+            ..self
+        }
+    }
+
+    const fn network(self) -> Self {
+        Subcommand {
+            open_world: true,
+            ..self
+        }
+    }
+}
+
+pub const SUBCOMMANDS: &[Subcommand] = &[
+    write("add"),
+    write("am"),
+    write("apply"),
+    write("bisect"),
+    read("blame"),
+    write("branch").destructive(),
+    write("bundle"),
+    read("cat-file"),
+    write("checkout").destructive(),
+    read("cherry"),
+    write("cherry-pick"),
+    write("clean").destructive(),
+    write("clone").network(),
+    write("commit"),
+    write("config"),
+    read("describe"),
+    read("diff"),
+    read("diff-tree"),
+    write("fetch").network(),
+    read("for-each-ref"),
+    write("format-patch"),
+    read("fsck"),
+    write("gc").destructive(),
+    read("grep"),
+    write("init"),
+    read("log"),
+    read("ls-files"),
+    read("ls-remote").network(),
+    read("ls-tree"),
+    write("merge"),
+    read("merge-base"),
+    write("mv"),
+    write("notes"),
+    write("pull").network(),
+    write("push").destructive().network(),
+    read("range-diff"),
+    write("rebase").destructive(),
+    write("reflog").destructive(),
+    write("remote").network(),
+    write("reset").destructive(),
+    write("restore").destructive(),
+    read("rev-list"),
+    read("rev-parse"),
+    write("revert"),
+    write("rm").destructive(),
+    read("shortlog"),
+    read("show"),
+    read("show-ref"),
+    write("sparse-checkout"),
+    write("stash").destructive(),
+    read("status"),
+    write("submodule").network(),
+    write("switch"),
+    write("symbolic-ref").destructive(),
+    write("tag").destructive(),
+    write("update-ref").destructive(),
+    read("version"),
+    write("worktree").destructive(),
+];
+
+/// Global options that take their value as the following argument.
+const GLOBAL_OPTIONS_WITH_VALUE: &[&str] = &[
+    "-c",
+    "--config-env",
+    "-C",
+    "--git-dir",
+    "--work-tree",
+    "--namespace",
+];
+
+/// Position of the subcommand in `args`, skipping any global options before it.
+pub fn subcommand_index(args: &[String]) -> Option<usize> {
+    let mut index = 0;
+    while index < args.len() {
+        let arg = args[index].as_str();
+        if GLOBAL_OPTIONS_WITH_VALUE.contains(&arg) {
+            index += 2;
+        } else if arg.starts_with('-') {
+            index += 1;
+        } else {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Global options that point git at another repository or namespace than
+/// the one the call was checked against.
+pub const REPOSITORY_OPTIONS: &[&str] = &["-C", "--git-dir", "--work-tree", "--namespace"];
+
+/// Whether `arg` is one of `REPOSITORY_OPTIONS`, spaced or `--option=value`.
+pub fn is_repository_option(arg: &str) -> bool {
+    REPOSITORY_OPTIONS.iter().any(|option| {
+        arg == *option
+            || arg
+                .strip_prefix(option)
+                .is_some_and(|value| value.starts_with('='))
+    })
+}
+
+/// Configuration that points git at another working tree or loads other
+/// configuration files, matched case-insensitively; `includeIf.<cond>.path`
+/// counts as `includeif.`.
+const REPOSITORY_CONFIG: &[&str] = &["core.worktree", "include.", "includeif."];
+
+/// Refuse global options before the subcommand that would run `args`
+/// outside the repository `allowed_roots` and the policy were checked for.
+pub fn check_repository_options(args: &[String]) -> Result<(), String> {
+    let end = subcommand_index(args).unwrap_or(args.len());
+    if let Some(option) = args[..end].iter().find(|arg| is_repository_option(arg)) {
+        return Err(format!(
+            "Global option '{}' is not allowed; name the repository with 'repo' or 'repository_path' instead",
+            option
+        ));
+    }
+    for (setting, option) in config_settings(&args[..end]) {
+        let key = setting
+            .split('=')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let relocates = REPOSITORY_CONFIG
+            .iter()
+            .any(|config| key == *config || (config.ends_with('.') && key.starts_with(config)));
+        if relocates {
+            return Err(format!(
+                "'{} {}' is not allowed: it points git at other files than the repository's",
+                option, setting
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The `key=value` settings of `-c` and `--config-env` options in the
+/// global options `args`, with the option that gave each.
+fn config_settings(args: &[String]) -> Vec<(&str, &str)> {
+    let mut settings = Vec::new();
+    let mut args = args.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        let setting = match arg {
+            "-c" | "--config-env" => args.next(),
+            _ => arg
+                .strip_prefix("--config-env=")
+                .or_else(|| arg.strip_prefix("-c")),
+        };
+        if let Some(setting) = setting {
+            let option = match arg.starts_with("--") {
+                true => "--config-env",
+                false => "-c",
+            };
+            settings.push((setting, option));
+        }
+    }
+    settings
+}
+
+/// Options of common subcommands whose value is the next argument, which
+/// must not be read as an operand.
+pub const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-m",
+    "--message",
+    "-F",
+    "--file",
+    "-C",
+    "-c",
+    "--author",
+    "--onto",
+    "-s",
+    "--strategy",
+    "-X",
+    "--strategy-option",
+    "-x",
+    "--exec",
+    "-u",
+    "-o",
+    "--push-option",
+    "--repo",
+    "--receive-pack",
+];
+
+/// Arguments after the subcommand that are not options or the values of
+/// `options_with_value`, and whether a `--` was seen.
+pub fn operands<'a>(args: &'a [String], options_with_value: &[&str]) -> (Vec<&'a str>, bool) {
+    let mut operands = Vec::new();
+    let mut iter = args.iter().map(String::as_str);
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            return (operands, true);
+        }
+        if options_with_value.contains(&arg) {
+            iter.next();
+        } else if !arg.starts_with('-') {
+            operands.push(arg);
+        }
+    }
+    (operands, false)
+}
+
+pub fn subcommand(args: &[String]) -> Option<&str> {
+    subcommand_index(args).map(|index| args[index].as_str())
+}
+
+pub fn lookup(name: &str) -> Option<&'static Subcommand> {
+    SUBCOMMANDS
+        .iter()
+        .find(|subcommand| subcommand.name == name)
+}
+
+/// Whether `args` only reads from the repository. Unknown subcommands are
+/// assumed to write, and so are read-only subcommands given an
+/// `unsafe_option`.
+pub fn is_read_only(args: &[String]) -> bool {
+    if unsafe_option(args).is_some() {
+        return false;
+    }
+    match subcommand(args) {
+        Some(name) => lookup(name).is_some_and(|subcommand| subcommand.read_only),
+        // `git --version` and friends
+        None => true,
+    }
+}
+
+/// Configuration read-only commands may set with `-c`. Other keys can name
+/// commands git runs, such as `core.fsmonitor`.
+const SAFE_CONFIG_KEYS: &[&str] = &["core.quotepath"];
+
+/// Options of otherwise read-only subcommands that write files or run
+/// commands.
+const UNSAFE_OPTIONS: &[&str] = &[
+    "--output",
+    "--upload-pack",
+    "--receive-pack",
+    "--exec",
+    "--open-files-in-pager",
+];
+
+/// The first option in `args` that lets a read-only subcommand write files
+/// or run commands, if any.
+pub fn unsafe_option(args: &[String]) -> Option<&str> {
+    let index = subcommand_index(args).unwrap_or(args.len());
+    let globals = &args[..index];
+    if let Some(arg) = globals.iter().find(|arg| arg.starts_with("--exec-path=")) {
+        return Some(arg);
+    }
+    // Values of `--config-env` come from the environment, unseen here
+    let config = config_settings(globals)
+        .into_iter()
+        .find(|(setting, option)| {
+            let key = setting.split('=').next().unwrap_or_default();
+            *option == "--config-env"
+                || !SAFE_CONFIG_KEYS.contains(&key.to_ascii_lowercase().as_str())
+        });
+    if let Some((_, option)) = config {
+        return Some(option);
+    }
+
+    let grep = args.get(index).is_some_and(|name| name == "grep");
+    args.get(index + 1..)
+        .unwrap_or_default()
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .find(|arg| match arg.strip_prefix("--") {
+            // git accepts unambiguous prefixes of long options
+            Some(long) => {
+                let name = long.split('=').next().unwrap_or_default();
+                !name.is_empty()
+                    && UNSAFE_OPTIONS
+                        .iter()
+                        .any(|option| option[2..].starts_with(name))
+            }
+            // `grep -O<pager>`, possibly after other short flags
+            None => grep && arg.starts_with('-') && arg.contains('O'),
+        })
+        .map(String::as_str)
+}
+
+/// Whether `args` may talk to a remote.
+pub fn is_network(args: &[String]) -> bool {
+    subcommand(args)
+        .and_then(lookup)
+        .is_some_and(|subcommand| subcommand.open_world)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_that_runs_commands_is_unsafe() {
+        let fsmonitor = args(&["-c", "core.fsmonitor=touch /tmp/x", "status"]);
+        assert_eq!(unsafe_option(&fsmonitor), Some("-c"));
+        assert!(!is_read_only(&fsmonitor));
+        assert!(unsafe_option(&args(&["-ccore.pager=sh", "log"])).is_some());
+        assert!(unsafe_option(&args(&["--config-env=core.pager=PAGER", "log"])).is_some());
+        assert!(unsafe_option(&args(&["--exec-path=/tmp", "status"])).is_some());
+    }
+
+    #[test]
+    fn config_env_takes_a_value() {
+        let push = args(&["--config-env", "a=b", "push", "origin"]);
+        assert_eq!(subcommand(&push), Some("push"));
+        assert!(!is_read_only(&push));
+    }
+
+    #[test]
+    fn repository_options_are_refused() {
+        for refused in [
+            &["-C", "/elsewhere", "status"][..],
+            &["--git-dir=/elsewhere/.git", "log"],
+            &["-c", "core.worktree=/elsewhere", "status"],
+            &["-c", "include.path=/tmp/config", "log"],
+            &["-cincludeIf.gitdir:/srv/.path=/tmp/config", "log"],
+            &["--config-env=core.worktree=DIR", "status"],
+            &["--config-env", "Include.Path=CONFIG", "status"],
+        ] {
+            assert!(
+                check_repository_options(&args(refused)).is_err(),
+                "{:?}",
+                refused
+            );
+        }
+        let allowed = args(&["-c", "core.quotePath=false", "diff", "-C", "HEAD"]);
+        assert!(check_repository_options(&allowed).is_ok());
+    }
+
+    #[test]
+    fn quote_path_config_is_safe() {
+        let diff = args(&["-c", "core.quotePath=false", "diff", "--patch"]);
+        assert_eq!(unsafe_option(&diff), None);
+        assert!(is_read_only(&diff));
+    }
+
+    #[test]
+    fn options_that_write_or_run_commands_are_unsafe() {
+        for unsafe_args in [
+            &["log", "--output=/tmp/x"][..],
+            &["log", "--output", "/tmp/x"],
+            &["diff", "--out=/tmp/x"],
+            &["ls-remote", "--upload-pack=touch /tmp/x", "origin"],
+            &["ls-remote", "--exec", "touch /tmp/x", "origin"],
+            &["grep", "-Otouch", "pattern"],
+            &["grep", "-nO", "pattern"],
+            &["grep", "--open-files-in-pager", "pattern"],
+        ] {
+            let unsafe_args = args(unsafe_args);
+            assert!(unsafe_option(&unsafe_args).is_some(), "{:?}", unsafe_args);
+            assert!(!is_read_only(&unsafe_args), "{:?}", unsafe_args);
+        }
+    }
+
+    #[test]
+    fn plain_reads_are_read_only() {
+        for read in [
+            &["log", "--oneline", "-n", "5"][..],
+            &["diff", "-O", "orderfile"],
+            &["grep", "-n", "pattern"],
+            &["log", "--", "--output"],
+            &["--version"],
+        ] {
+            let read = args(read);
+            assert_eq!(unsafe_option(&read), None, "{:?}", read);
+            assert!(is_read_only(&read), "{:?}", read);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use crate::bindings::theater::simple::http_framework::{
+    add_middleware, add_route, create_server, enable_websocket, register_handler, start_server,
+};
+use crate::bindings::theater::simple::http_types::{HttpRequest, HttpResponse, ServerConfig};
+use crate::bindings::theater::simple::random::generate_uuid;
+use crate::bindings::theater::simple::runtime::log;
+use crate::bindings::theater::simple::timing::now;
+use crate::session::{self, Transport};
+use crate::{McpRequest, McpResponse, State, error_response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+/// How long clients should wait before resuming an event stream.
+const RETRY_MS: u32 = 1000;
+
+/// Settings for serving MCP over Streamable HTTP.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Port to listen on; the system picks one when unset.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Path the MCP endpoint is served at.
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// When set, every request must carry `Authorization: Bearer <token>`.
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    /// Origins requests may come from. When unset, only loopback origins
+    /// such as `http://localhost:3000` are accepted, against DNS rebinding.
+    /// Requests without an `Origin` header come from outside a browser and
+    /// are always accepted.
+    #[serde(default)]
+    pub allowed_origins: Option<Vec<String>>,
+    /// When set, MCP is also served over WebSocket connections to this path.
+    #[serde(default)]
+    pub websocket_path: Option<String>,
+}
+
+fn default_path() -> String {
+    "/mcp".to_string()
+}
+
+/// A running HTTP server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HttpServer {
+    pub server_id: u64,
+    pub handler_id: u64,
+    pub port: u16,
+}
+
+/// Create the server, route the MCP endpoint to this actor and start it.
+pub fn start(config: &HttpConfig) -> Result<HttpServer, String> {
+    let server_id = create_server(&ServerConfig {
+        port: config.port,
+        host: config.host.clone(),
+        tls_config: None,
+    })
+    .map_err(|e| format!("Failed to create HTTP server: {}", e))?;
+
+    let handler_id =
+        register_handler("mcp").map_err(|e| format!("Failed to register HTTP handler: {}", e))?;
+    for method in ["POST", "GET", "DELETE"] {
+        add_route(server_id, &config.path, method, handler_id)
+            .map_err(|e| format!("Failed to add {} route: {}", method, e))?;
+    }
+    if let Some(websocket_path) = &config.websocket_path {
+        // Connections are only checked on the upgrade request, which passes
+        // through middleware rather than a route
+        add_middleware(server_id, websocket_path, handler_id)
+            .map_err(|e| format!("Failed to add WebSocket middleware: {}", e))?;
+        enable_websocket(
+            server_id,
+            websocket_path,
+            Some(handler_id),
+            handler_id,
+            Some(handler_id),
+        )
+        .map_err(|e| format!("Failed to enable WebSocket: {}", e))?;
+        log(&format!("Serving MCP over WebSocket at {}", websocket_path));
+    }
+
+    let port =
+        start_server(server_id).map_err(|e| format!("Failed to start HTTP server: {}", e))?;
+    log(&format!(
+        "Serving MCP over HTTP on port {} at {}",
+        port, config.path
+    ));
+
+    Ok(HttpServer {
+        server_id,
+        handler_id,
+        port,
+    })
+}
+
+/// Serve one request to the MCP endpoint.
+///
+/// The framework cannot hold a response open, so a request whose result
+/// needs a git child is answered with an event stream that only primes the
+/// client to resume it, and closes. The client resumes it with `GET` and
+/// `Last-Event-ID` after `retry` milliseconds, until the response is there.
+pub fn handle(state: &mut State, request: HttpRequest) -> Result<HttpResponse, String> {
+    log(&format!("HTTP {} {}", request.method, request.uri));
+
+    let Some(config) = state.http.clone() else {
+        return Ok(status_response(404, "MCP over HTTP is not enabled"));
+    };
+    if let Err(response) = authorize(&config, &request, false) {
+        return Ok(response);
+    }
+
+    match request.method.as_str() {
+        "POST" => handle_post(state, &request),
+        "GET" => Ok(match session_id(state, &request) {
+            Ok(session_id) => {
+                let last_event_id = header(&request, "last-event-id").map(String::as_str);
+                event_stream(state, &session_id, last_event_id)
+            }
+            Err(response) => response,
+        }),
+        "DELETE" => Ok(match session_id(state, &request) {
+            Ok(session_id) => {
+                state.sessions.remove(&session_id);
+                log(&format!("Session {} closed by client", session_id));
+                status_response(200, "Session closed")
+            }
+            Err(response) => response,
+        }),
+        _ => {
+            let mut response = status_response(405, "Method not allowed");
+            response
+                .headers
+                .push(("Allow".to_string(), "POST, GET, DELETE".to_string()));
+            Ok(response)
+        }
+    }
+}
+
+/// Whether a WebSocket upgrade request may connect.
+pub fn authorize_upgrade(state: &State, request: &HttpRequest) -> bool {
+    match &state.http {
+        Some(config) => authorize(config, request, true).is_ok(),
+        None => false,
+    }
+}
+
+/// Check the request's origin and bearer token. Browsers cannot set headers
+/// on WebSocket upgrades, so those may pass the token as an `access_token`
+/// query parameter instead.
+fn authorize(
+    config: &HttpConfig,
+    request: &HttpRequest,
+    allow_query_token: bool,
+) -> Result<(), HttpResponse> {
+    if let Some(origin) = header(request, "origin") {
+        let allowed = match &config.allowed_origins {
+            Some(allowed) => allowed.iter().any(|allowed| allowed == origin),
+            None => is_loopback(origin),
+        };
+        if !allowed {
+            return Err(status_response(403, "Origin not allowed"));
+        }
+    }
+
+    let Some(token) = &config.bearer_token else {
+        return Ok(());
+    };
+    let from_header = header(request, "authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| same_secret(value, token));
+    let from_query = allow_query_token
+        && request.uri.split_once('?').is_some_and(|(_, query)| {
+            query.split('&').any(|pair| {
+                pair.strip_prefix("access_token=")
+                    .is_some_and(|value| same_secret(value, token))
+            })
+        });
+    if from_header || from_query {
+        return Ok(());
+    }
+
+    let mut response = status_response(401, "Missing or invalid bearer token");
+    response
+        .headers
+        .push(("WWW-Authenticate".to_string(), "Bearer".to_string()));
+    Err(response)
+}
+
+/// Compare a presented token with the configured one in time that does not
+/// depend on where they first differ.
+fn same_secret(presented: &str, token: &str) -> bool {
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Whether `origin`, such as `http://127.0.0.1:8080`, is on this machine.
+fn is_loopback(origin: &str) -> bool {
+    let Some((_, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+fn handle_post(state: &mut State, request: &HttpRequest) -> Result<HttpResponse, String> {
+    let message = request
+        .body
+        .as_deref()
+        .and_then(|body| serde_json::from_slice::<McpRequest>(body).ok());
+    let Some(message) = message else {
+        let response = error_response(Value::Null, -32700, "Parse error".to_string());
+        return json_response(400, &response, None);
+    };
+
+    // `initialize` is the one request that starts a session instead of naming one
+    let session_id = if message.method == "initialize" && message.id.is_some() {
+        let session_id =
+            generate_uuid().map_err(|e| format!("Failed to generate session id: {}", e))?;
+        if let Err(message) = session::open(state, session_id.clone()) {
+            return Ok(status_response(503, &message));
+        }
+        session_id
+    } else {
+        match session_id(state, request) {
+            Ok(session_id) => session_id,
+            Err(response) => return Ok(response),
+        }
+    };
+
+    // Only requests get a response; notifications and responses are
+    // just accepted
+    let Some(id) = message.id.clone() else {
+        session::dispatch(state, &session_id, session::STANDALONE_STREAM, message)?;
+        return Ok(status_response(202, ""));
+    };
+    let (stream, priming_event_id) = match state.sessions.get_mut(&session_id) {
+        Some(session) => session.open_stream(),
+        None => return Ok(status_response(404, "Unknown session")),
+    };
+    let response = match session::dispatch(state, &session_id, stream, message) {
+        Ok(Some(response)) => response,
+        Ok(None) => return Ok(priming_stream(&session_id, &priming_event_id)),
+        Err(message) => error_response(id, -32603, message),
+    };
+    json_response(200, &response, Some(&session_id))
+}
+
+/// An event stream holding only an event id to resume from, for a request
+/// that is answered later on the stream that id names.
+fn priming_stream(session_id: &str, event_id: &str) -> HttpResponse {
+    let body = format!("retry: {}\nid: {}\ndata: \n\n", RETRY_MS, event_id);
+    sse_response(session_id, body)
+}
+
+/// The session named by the request, or the response to send when there is none.
+fn session_id(state: &State, request: &HttpRequest) -> Result<String, HttpResponse> {
+    match header(request, SESSION_HEADER) {
+        None => Err(status_response(400, "Missing Mcp-Session-Id header")),
+        Some(session_id)
+            if state.sessions.get(session_id).is_none_or(|session| {
+                session.transport != Transport::Http || session.is_idle(now())
+            }) =>
+        {
+            Err(status_response(404, "Unknown session"))
+        }
+        Some(session_id) => Ok(session_id.to_string()),
+    }
+}
+
+/// What is queued on the stream `last_event_id` names after it, or on the
+/// standalone stream, as a server-sent event stream.
+fn event_stream(state: &mut State, session_id: &str, last_event_id: Option<&str>) -> HttpResponse {
+    let events = state
+        .sessions
+        .get_mut(session_id)
+        .map(|session| {
+            session.last_seen_ms = now();
+            session.replay(last_event_id)
+        })
+        .unwrap_or_default();
+
+    let mut body = format!("retry: {}\n\n", RETRY_MS);
+    for event in events {
+        body.push_str(&format!(
+            "id: {}\ndata: {}\n\n",
+            event.event_id(),
+            event.message
+        ));
+    }
+    sse_response(session_id, body)
+}
+
+fn sse_response(session_id: &str, body: String) -> HttpResponse {
+    HttpResponse {
+        status: 200,
+        headers: vec![
+            ("Content-Type".to_string(), "text/event-stream".to_string()),
+            ("Cache-Control".to_string(), "no-cache".to_string()),
+            ("Mcp-Session-Id".to_string(), session_id.to_string()),
+        ],
+        body: Some(body.into_bytes()),
+    }
+}
+
+fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a String> {
+    request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+fn json_response(
+    status: u16,
+    response: &McpResponse,
+    session_id: Option<&str>,
+) -> Result<HttpResponse, String> {
+    let body =
+        serde_json::to_vec(response).map_err(|e| format!("Failed to serialize response: {}", e))?;
+    let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    if let Some(session_id) = session_id {
+        headers.push(("Mcp-Session-Id".to_string(), session_id.to_string()));
+    }
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: Some(body),
+    })
+}
+
+fn status_response(status: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: Some(message.as_bytes().to_vec()),
+    }
+}
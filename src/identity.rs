@@ -0,0 +1,167 @@
+use crate::git;
+use serde::{Deserialize, Serialize};
+
+/// Who commits made through the actor are by, so they never depend on the
+/// host's git configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Identity {
+    /// Author of new commits and tags. Also the committer unless `committer`
+    /// is set.
+    #[serde(default)]
+    pub author: Option<Person>,
+    #[serde(default)]
+    pub committer: Option<Person>,
+    /// Added to every commit as `Co-authored-by` trailers, each written as
+    /// `Name <email>`.
+    #[serde(default)]
+    pub co_authors: Vec<String>,
+    #[serde(default)]
+    pub signing: Option<Signing>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Person {
+    pub name: String,
+    pub email: String,
+}
+
+/// Sign commits and tags with a GPG, SSH or X.509 key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signing {
+    /// Passed as `user.signingKey`: a key id, or the path of an SSH key.
+    pub key: String,
+    /// `openpgp`, `ssh` or `x509`, as `gpg.format`.
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default = "default_true")]
+    pub commits: bool,
+    /// Sign annotated tags that do not opt out with `--no-sign`. Off unless
+    /// set: `tag.gpgSign` would also turn lightweight tags into signed ones,
+    /// which need a message nobody can type.
+    #[serde(default)]
+    pub tags: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+const SIGNING_FORMATS: &[&str] = &["openpgp", "ssh", "x509"];
+
+impl Identity {
+    /// Reject identities git would refuse when it comes to commit.
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, person) in [("author", &self.author), ("committer", &self.committer)] {
+            let Some(person) = person else {
+                continue;
+            };
+            if person.name.trim().is_empty() {
+                return Err(format!("'identity.{}.name' must not be empty", field));
+            }
+            if !person.email.contains('@') || person.email.contains(['<', '>']) {
+                return Err(format!(
+                    "'identity.{}.email' is not an email address: '{}'",
+                    field, person.email
+                ));
+            }
+        }
+        if let Some(co_author) = self.co_authors.iter().find(|co_author| {
+            let co_author = co_author.trim();
+            !co_author.ends_with('>') || !co_author.contains(" <") || !co_author.contains('@')
+        }) {
+            return Err(format!(
+                "Co-author '{}' must be written as 'Name <email>'",
+                co_author
+            ));
+        }
+        if let Some(signing) = &self.signing {
+            if signing.key.is_empty() {
+                return Err("'identity.signing.key' must not be empty".to_string());
+            }
+            if let Some(format) = &signing.format
+                && !SIGNING_FORMATS.contains(&format.as_str())
+            {
+                return Err(format!(
+                    "'identity.signing.format' must be one of {}, got '{}'",
+                    SIGNING_FORMATS.join(", "),
+                    format
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `args` with this identity applied, if they may write to the
+    /// repository. Settings go in as `-c` options ahead of the subcommand,
+    /// and co-authors as trailers on `git commit`.
+    pub fn apply(&self, args: &[String]) -> Vec<String> {
+        if git::is_read_only(args) {
+            return args.to_vec();
+        }
+
+        let mut settings = Vec::new();
+        if let Some(author) = &self.author {
+            settings.push(("user.name", author.name.as_str()));
+            settings.push(("user.email", author.email.as_str()));
+            settings.push(("author.name", author.name.as_str()));
+            settings.push(("author.email", author.email.as_str()));
+        }
+        if let Some(committer) = &self.committer {
+            settings.push(("committer.name", committer.name.as_str()));
+            settings.push(("committer.email", committer.email.as_str()));
+        }
+        if let Some(signing) = &self.signing {
+            settings.push(("user.signingKey", signing.key.as_str()));
+            if let Some(format) = &signing.format {
+                settings.push(("gpg.format", format.as_str()));
+            }
+            settings.push(("commit.gpgSign", bool_setting(signing.commits)));
+            if signing.tags && is_annotated_tag(args) {
+                settings.push(("tag.gpgSign", "true"));
+            }
+        }
+
+        let mut applied: Vec<String> = settings
+            .into_iter()
+            .flat_map(|(key, value)| ["-c".to_string(), format!("{}={}", key, value)])
+            .collect();
+        applied.extend_from_slice(args);
+
+        if let Some(index) = git::subcommand_index(&applied)
+            && applied[index] == "commit"
+        {
+            let trailers = self.co_authors.iter().flat_map(|co_author| {
+                [
+                    "--trailer".to_string(),
+                    format!("Co-authored-by: {}", co_author),
+                ]
+            });
+            applied.splice(index + 1..index + 1, trailers);
+        }
+        applied
+    }
+}
+
+/// Whether `args` create an annotated tag that has not opted out of signing.
+fn is_annotated_tag(args: &[String]) -> bool {
+    let Some(index) = git::subcommand_index(args) else {
+        return false;
+    };
+    let rest = &args[index + 1..];
+    args[index] == "tag"
+        && !rest.iter().any(|arg| arg == "--no-sign")
+        && rest.iter().any(|arg| {
+            matches!(
+                arg.as_str(),
+                "-a" | "--annotate" | "-m" | "--message" | "-F" | "--file"
+            ) || arg.starts_with("--message=")
+                || arg.starts_with("--file=")
+        })
+}
+
+fn bool_setting(value: bool) -> &'static str {
+    match value {
+        true => "true",
+        false => "false",
+    }
+}
@@ -1,42 +1,323 @@
+mod approval;
+mod audit;
 #[allow(warnings)]
 mod bindings;
+mod branches;
+mod cache;
+mod commit;
+mod completion;
+mod config;
+mod git;
+mod http;
+mod identity;
+mod parse;
+mod persist;
+mod policy;
+mod preview;
+mod prompts;
+mod protected;
+mod remotes;
+mod repos;
+mod semver;
+mod session;
+mod snapshots;
+mod staging;
+mod storage;
+mod tags;
+mod tools;
+mod websocket;
 
 use bindings::exports::theater::simple::actor::Guest;
+use bindings::exports::theater::simple::http_handlers::Guest as HttpHandlers;
 use bindings::exports::theater::simple::message_server_client::ChannelAccept;
 use bindings::exports::theater::simple::message_server_client::Guest as MessageServerClient;
 use bindings::exports::theater::simple::supervisor_handlers::Guest as SupervisorHandlers;
 use bindings::exports::theater::simple::supervisor_handlers::WitActorError;
-use bindings::theater::simple::message_server_host::respond_to_request;
+use bindings::theater::simple::http_framework::HandlerId;
+use bindings::theater::simple::http_types::{HttpRequest, HttpResponse, MiddlewareResult};
+use bindings::theater::simple::message_server_host::{respond_to_request, send_on_channel};
 use bindings::theater::simple::runtime::log;
 use bindings::theater::simple::supervisor::spawn;
-use mcp_protocol::tool::Tool;
+use bindings::theater::simple::timing::now;
+use bindings::theater::simple::websocket_types::WebsocketMessage;
+use commit::CommitRules;
+use completion::{ArgumentKind, CompletionReference, RepositoryNames};
+use config::{Capabilities, Changes, Limits};
+use http::{HttpConfig, HttpServer};
+use identity::Identity;
+use mcp_protocol::JsonRpcMessage;
+use mcp_protocol::completion::{CompletionArgument, CompletionCompleteResult};
+use mcp_protocol::constants::methods;
 use mcp_protocol::tool::ToolCallResult;
 use mcp_protocol::tool::ToolContent;
+use policy::Policy;
+use repos::Repository;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_json::json;
-use std::collections::HashMap;
+use session::Session;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 struct Component;
 
 const GIT_COMMAND_MANIFEST: &str =
     "https://github.com/colinrozzi/git-command-actor/releases/latest/download/manifest.toml";
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 struct InitState {
     repository_path: Option<String>,
+    #[serde(default)]
+    policy: Policy,
+    /// Repositories callers can name with `repo`.
+    #[serde(default)]
+    repositories: BTreeMap<String, Repository>,
+    /// When set, every repository must be inside one of these directories.
+    #[serde(default)]
+    allowed_roots: Option<Vec<String>>,
+    /// Author, committer and signing settings for commits made through the actor.
+    #[serde(default)]
+    identity: Identity,
+    /// Rules messages passed to `git-commit` must follow.
+    #[serde(default)]
+    commit_rules: CommitRules,
+    /// Branch patterns such as `main` or `release/*` that no call may move or
+    /// delete, whether by committing, resetting, rebasing or pushing.
+    #[serde(default)]
+    protected_branches: Vec<String>,
+    /// Actor asked to approve calls the policy marks as requiring approval.
+    #[serde(default)]
+    approval: Option<approval::Approver>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    capabilities: Capabilities,
+    /// Manifest of the actor that runs git, if not the released git-command actor.
+    #[serde(default)]
+    git_command_manifest: Option<String>,
+    /// Serve MCP over Streamable HTTP as well as the message-server.
+    #[serde(default)]
+    http: Option<HttpConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct State {
+    /// Layout version, see `persist::VERSION`.
+    version: u64,
     repository_path: Option<String>,
-    outstanding_requests: HashMap<String, String>,
+    #[serde(default)]
+    policy: Policy,
+    #[serde(default)]
+    repositories: BTreeMap<String, Repository>,
+    #[serde(default)]
+    allowed_roots: Option<Vec<String>>,
+    #[serde(default)]
+    identity: Identity,
+    #[serde(default)]
+    commit_rules: CommitRules,
+    #[serde(default)]
+    protected_branches: Vec<String>,
+    #[serde(default)]
+    approval: Option<approval::Approver>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    capabilities: Capabilities,
+    #[serde(default)]
+    git_command_manifest: Option<String>,
+    outstanding_requests: HashMap<String, PendingRequest>,
+    /// `list-repositories` calls waiting on their children.
+    #[serde(default)]
+    listings: HashMap<u64, repos::Listing>,
+    #[serde(default)]
+    next_listing_id: u64,
+    /// Completion candidates per repository path.
+    #[serde(default)]
+    completion_cache: HashMap<String, RepositoryNames>,
+    /// Channels opened by clients, which receive server notifications.
+    #[serde(default)]
+    channels: HashSet<String>,
+    /// Fingerprint of the tool list clients were last told about.
+    #[serde(default)]
+    tools_fingerprint: String,
+    #[serde(default)]
+    http: Option<HttpConfig>,
+    #[serde(default)]
+    http_server: Option<HttpServer>,
+    /// MCP sessions on the HTTP and WebSocket transports, keyed by session id.
+    #[serde(default)]
+    sessions: HashMap<String, Session>,
+    /// Children waiting for a free slot, oldest first.
+    #[serde(default)]
+    spawn_queue: VecDeque<PendingRequest>,
+    /// Content store holding snapshot metadata, created on first use.
+    #[serde(default)]
+    store_id: Option<String>,
+    #[serde(default)]
+    next_snapshot: u64,
+    /// Store labels of cached results, oldest first.
+    #[serde(default)]
+    cache_labels: VecDeque<String>,
+    /// Repositories a call that may write is running in, held from its
+    /// first step to its last so no other write runs in between.
+    #[serde(default)]
+    repository_locks: HashMap<String, ReplyTo>,
+    /// Audit records written per UTC day, which `audit` numbers its
+    /// labels by.
+    #[serde(default)]
+    audit_days: BTreeMap<String, u64>,
+}
+
+/// A request waiting on a git-command child, keyed by the child's actor id,
+/// or queued until the child can start.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PendingRequest {
+    reply_to: ReplyTo,
+    repository_path: String,
+    git_args: Vec<String>,
+    kind: PendingKind,
+    /// When the child was spawned, in milliseconds since the epoch; zero
+    /// while queued.
+    #[serde(default)]
+    started_ms: u64,
+}
+
+/// Where the response to a request goes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+enum ReplyTo {
+    /// A message-server request, answered with `respond-to-request`.
+    Request { request_id: String },
+    /// A JSON-RPC request on an HTTP or WebSocket session, answered on
+    /// `stream`.
+    Session {
+        session_id: String,
+        id: Value,
+        #[serde(default)]
+        stream: u64,
+    },
+}
+
+impl ReplyTo {
+    /// The JSON-RPC id to put on the response.
+    fn id(&self) -> Value {
+        match self {
+            ReplyTo::Request { request_id } => Value::String(request_id.clone()),
+            ReplyTo::Session { id, .. } => id.clone(),
+        }
+    }
+
+    /// Who sent the request, as far as is known.
+    fn caller(&self) -> Option<String> {
+        match self {
+            ReplyTo::Request { .. } => None,
+            ReplyTo::Session { session_id, .. } => Some(session_id.clone()),
+        }
+    }
+}
+
+/// What the child's output should be turned into once it exits.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+enum PendingKind {
+    ToolCall {
+        /// Where to cache the result, for read-only calls.
+        #[serde(default)]
+        cache_key: Option<String>,
+        /// Let through by the approver, as the audit log records.
+        #[serde(default)]
+        approved: bool,
+    },
+    TypedToolCall {
+        name: String,
+        /// Commands still to run, in order, once this one succeeds.
+        #[serde(default)]
+        remaining: Vec<Vec<String>>,
+        /// The call's arguments and the stdout of each step so far, for
+        /// steps that depend on earlier output.
+        #[serde(default)]
+        args: Value,
+        #[serde(default)]
+        outputs: Vec<String>,
+        /// Previews of the steps that would change something, when the call
+        /// is a dry run.
+        #[serde(default)]
+        plans: Vec<preview::Plan>,
+        #[serde(default)]
+        cache_key: Option<String>,
+        #[serde(default)]
+        approved: bool,
+    },
+    /// A dry run of `git-command`.
+    Preview {
+        plan: preview::Plan,
+    },
+    Prompt {
+        name: String,
+        args: HashMap<String, String>,
+        /// Steps still to run before the prompt can be rendered.
+        #[serde(default)]
+        remaining: Vec<Vec<String>>,
+        /// Output of the steps that already ran.
+        #[serde(default)]
+        outputs: Vec<String>,
+    },
+    Completion {
+        kind: ArgumentKind,
+        value: String,
+    },
+    /// Checking the new default repository before applying `changes`.
+    Configure {
+        changes: Changes,
+        /// Whether the change came from the `configure-server` tool.
+        tool: bool,
+    },
+    /// One repository of a `list-repositories` call.
+    RepositoryStatus {
+        listing_id: u64,
+        name: String,
+    },
+    /// Reading the current branch before running `git_args`, which would
+    /// move it, so protected branches can be refused.
+    Guard {
+        git_args: Vec<String>,
+        then: Box<PendingKind>,
+    },
+    /// Taking a safety snapshot before running `git_args`, which can lose
+    /// work.
+    Snapshot {
+        progress: snapshots::Progress,
+        git_args: Vec<String>,
+        then: Box<PendingKind>,
+    },
+    /// Previewing `git_args`, and the steps `then` has after it, for the
+    /// approver, before running them.
+    Approval {
+        tool: String,
+        args: Value,
+        git_args: Vec<String>,
+        then: Box<PendingKind>,
+        /// Preview commands still to run, the plans they belong to and the
+        /// output of those run so far.
+        #[serde(default)]
+        remaining: Vec<Vec<String>>,
+        #[serde(default)]
+        plans: Vec<preview::Plan>,
+        #[serde(default)]
+        outputs: Vec<String>,
+        /// When the call is rejected for want of an answer, in milliseconds
+        /// since the epoch, once the previews have run and the call is
+        /// parked until the approver answers.
+        #[serde(default)]
+        expires_ms: Option<u64>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct McpRequest {
     jsonrpc: String,
-    id: String,
+    /// Absent on notifications.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
     method: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     params: Option<Value>,
@@ -47,13 +328,36 @@ struct McpRequest {
 #[serde(tag = "type")]
 enum McpActorRequest {
     ToolsList {},
-    ToolsCall { name: String, args: Value },
+    ToolsCall {
+        name: String,
+        args: Value,
+    },
+    PromptsList {},
+    PromptsGet {
+        name: String,
+        #[serde(default)]
+        args: HashMap<String, String>,
+    },
+    CompletionComplete {
+        #[serde(rename = "ref")]
+        reference: CompletionReference,
+        argument: CompletionArgument,
+        /// Arguments the client has already filled in, used to pick the repository.
+        #[serde(default)]
+        context: Option<Value>,
+    },
+    /// Change settings at runtime. Only accepted when the init state grants
+    /// the `configure` capability.
+    Configure {
+        #[serde(flatten)]
+        changes: Changes,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct McpResponse {
     jsonrpc: String,
-    id: String,
+    id: Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     result: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -89,39 +393,74 @@ impl Guest for Component {
             state, params
         ));
 
-        let init_state = match state {
-            Some(state_bytes) if !state_bytes.is_empty() => {
-                serde_json::from_slice::<InitState>(&state_bytes)
-                    .unwrap_or_else(|_| InitState { repository_path: None })
-            }
-            _ => InitState { repository_path: None },
-        };
+        // Resuming from stored state: keep it, but servers, sessions and
+        // channels did not survive the restart
+        if let Some(state_bytes) = state.as_deref()
+            && persist::is_saved(state_bytes)
+        {
+            let mut app_state = persist::decode(Some(state_bytes))?;
+            log(&format!("Resuming from state version {}", persist::VERSION));
+            app_state.sessions.clear();
+            app_state.channels.clear();
+            app_state.http_server = app_state.http.as_ref().map(http::start).transpose()?;
+            return Ok((Some(persist::encode(&app_state)?),));
+        }
+
+        let init_state = config::load(state.as_deref(), &params.0)?;
 
-        let app_state = State {
+        let mut app_state = State {
+            version: persist::VERSION,
             outstanding_requests: HashMap::new(),
             repository_path: init_state.repository_path,
+            policy: init_state.policy,
+            repositories: init_state.repositories,
+            allowed_roots: init_state.allowed_roots,
+            identity: init_state.identity,
+            commit_rules: init_state.commit_rules,
+            protected_branches: init_state.protected_branches,
+            approval: init_state.approval,
+            limits: init_state.limits,
+            capabilities: init_state.capabilities,
+            git_command_manifest: init_state.git_command_manifest,
+            listings: HashMap::new(),
+            next_listing_id: 0,
+            completion_cache: HashMap::new(),
+            channels: HashSet::new(),
+            tools_fingerprint: String::new(),
+            http_server: init_state.http.as_ref().map(http::start).transpose()?,
+            http: init_state.http,
+            sessions: HashMap::new(),
+            spawn_queue: VecDeque::new(),
+            store_id: None,
+            next_snapshot: 0,
+            cache_labels: VecDeque::new(),
+            repository_locks: HashMap::new(),
+            audit_days: BTreeMap::new(),
         };
+        app_state.tools_fingerprint = tools_fingerprint(&app_state);
 
-        Ok((Some(
-            serde_json::to_vec(&app_state).map_err(|e| e.to_string())?,
-        ),))
+        Ok((Some(persist::encode(&app_state)?),))
     }
 }
 
 impl MessageServerClient for Component {
     fn handle_send(
         state: Option<Vec<u8>>,
-        _params: (Vec<u8>,),
+        params: (Vec<u8>,),
     ) -> Result<(Option<Vec<u8>>,), String> {
         log("Handling send message");
 
-        let app_state: State = match state {
-            Some(state_bytes) if !state_bytes.is_empty() => serde_json::from_slice(&state_bytes)
-                .map_err(|e| format!("Failed to deserialize state: {}", e))?,
-            _ => return Err("Invalid state".to_string()),
-        };
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        if let Some(answer) = approval::answer(&params.0) {
+            let key = format!("approval/{}", answer.id);
+            resume(&mut app_state, &key, answer.verdict());
+        }
+        dispatch_queued(&mut app_state);
+
+        notify_tools_changed(&mut app_state);
 
-        let state_bytes = serde_json::to_vec(&app_state).map_err(|e| e.to_string())?;
+        let state_bytes = persist::encode(&app_state)?;
         Ok((Some(state_bytes),))
     }
 
@@ -136,11 +475,7 @@ impl MessageServerClient for Component {
         log(&format!("Request data: {:?}", request));
 
         // Parse the current state
-        let mut app_state: State = match state {
-            Some(state_bytes) if !state_bytes.is_empty() => serde_json::from_slice(&state_bytes)
-                .map_err(|e| format!("Failed to deserialize state: {}", e))?,
-            _ => return Err("Invalid state".to_string()),
-        };
+        let mut app_state = persist::decode(state.as_deref())?;
 
         // Parse the request
         let request = match serde_json::from_slice::<McpActorRequest>(&request) {
@@ -151,135 +486,16 @@ impl MessageServerClient for Component {
             }
         };
 
-        // Process the request based on its type
-        let response = match request {
-            McpActorRequest::ToolsList {} => {
-                log("Received tools_list request");
-
-                let description = match &app_state.repository_path {
-                    Some(repo_path) => format!(
-                        "Execute a git command in the configured repository: '{}'. Provide 'args' as an array of strings. You can optionally override the repository by providing 'repository_path'. Example: args: ['status', '--porcelain']",
-                        repo_path
-                    ),
-                    None => "Execute a git command. You must provide both 'repository_path' and 'args' as an array of strings. Example: repository_path: '/path/to/repo', args: ['status', '--porcelain']".to_string(),
-                };
-
-                let tools = vec![Tool {
-                    name: "git-command".to_string(),
-                    description: Some(description),
-                    input_schema: json!({
-                        "type": "object",
-                        "properties": {
-                            "repository_path": {
-                                "type": "string",
-                                "description": match &app_state.repository_path {
-                                    Some(_) => "Override the configured repository path (optional)",
-                                    None => "The path of the git repository (required)",
-                                }
-                            },
-                            "args" : {
-                                "type": "array",
-                                "items": {
-                                    "type": "string"
-                                },
-                                "description": "Array of command-line arguments to pass to git (e.g., ['status', '--porcelain'] or ['--version'])"
-                            }
-                        },
-                        "required": match &app_state.repository_path {
-                            Some(_) => vec![], // No required fields if repo is configured
-                            None => vec!["repository_path"], // Require repo path if not configured
-                        }
-                    }),
-                    annotations: None,
-                }];
-
-                log(&format!("Available tools: {:?}", tools));
-                let res = McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request_id,
-                    result: Some(json!({
-                        "tools": tools
-                    })),
-                    error: None,
-                };
-                Some(
-                    serde_json::to_vec(&res)
-                        .map_err(|e| format!("Failed to serialize response: {}", e))?,
-                )
-            }
-
-            McpActorRequest::ToolsCall { name, args } => {
-                log("Received tools_call request");
-                log(&format!("Tool name: {}", name));
-
-                match name.as_str() {
-                    "git-command" => {
-                        log("Processing git-command call");
-
-                        // Get repository path from call args or fall back to init state
-                        let repository_path = match args.get("repository_path").and_then(Value::as_str) {
-                            Some(path) => path.to_string(),
-                            None => app_state.repository_path.as_ref()
-                                .ok_or("No repository path provided in call arguments or initialization state")?
-                                .clone(),
-                        };
-
-                        let args_array = args.get("args").and_then(Value::as_array).ok_or(
-                            "Missing or invalid 'args' argument - expected array of strings",
-                        )?;
-
-                        let child_init_state = json!({
-                            "repository_path": repository_path,
-                            "git_args": args_array
-                                .iter()
-                                .filter_map(Value::as_str)
-                                .map(|s| s.to_string())
-                                .collect::<Vec<String>>(),
-                        });
-
-                        log(&format!(
-                            "Child init state: {}",
-                            child_init_state.to_string()
-                        ));
-
-                        let child_init_state_bytes = serde_json::to_vec(&child_init_state)
-                            .map_err(|e| format!("Failed to serialize child init state: {}", e))?;
-
-                        let actor_id = spawn(GIT_COMMAND_MANIFEST, Some(&child_init_state_bytes))
-                            .expect("Failed to spawn git-command actor");
-
-                        app_state
-                            .outstanding_requests
-                            .insert(actor_id.clone(), request_id.clone());
-
-                        None
-                    }
-                    _ => {
-                        log(&format!("Unknown tool name: {}", name));
-                        let err_response = McpResponse {
-                            jsonrpc: "2.0".to_string(),
-                            id: request_id,
-                            result: None,
-                            error: Some(McpError {
-                                code: -32601,
-                                message: format!("Method '{}' not implemented", name),
-                                data: None,
-                            }),
-                        };
+        let reply_to = ReplyTo::Request { request_id };
+        let response = handle_mcp(&mut app_state, &reply_to, request)?
+            .map(|response| serde_json::to_vec(&response))
+            .transpose()
+            .map_err(|e| format!("Failed to serialize response: {}", e))?;
 
-                        log(&format!("Error response: {:?}", err_response));
-                        Some(
-                            serde_json::to_vec(&err_response).map_err(|e| {
-                                format!("Failed to serialize error response: {}", e)
-                            })?,
-                        )
-                    }
-                }
-            }
-        };
+        notify_tools_changed(&mut app_state);
 
         // Serialize the app state
-        let updated_state = serde_json::to_vec(&app_state).map_err(|e| e.to_string())?;
+        let updated_state = persist::encode(&app_state)?;
 
         // Return updated state
         Ok((Some(updated_state), (response,)))
@@ -287,10 +503,17 @@ impl MessageServerClient for Component {
 
     fn handle_channel_open(
         state: Option<Vec<u8>>,
-        _params: (String, Vec<u8>),
+        params: (String, Vec<u8>),
     ) -> Result<(Option<Vec<u8>>, (ChannelAccept,)), String> {
+        let (channel_id, _) = params;
+        log(&format!("Channel {} opened", channel_id));
+
+        let mut app_state = persist::decode(state.as_deref())?;
+        app_state.channels.insert(channel_id);
+
+        let updated_state = persist::encode(&app_state)?;
         Ok((
-            state,
+            Some(updated_state),
             (ChannelAccept {
                 accepted: true,
                 message: None,
@@ -300,9 +523,16 @@ impl MessageServerClient for Component {
 
     fn handle_channel_close(
         state: Option<Vec<u8>>,
-        _params: (String,),
+        params: (String,),
     ) -> Result<(Option<Vec<u8>>,), String> {
-        Ok((state,))
+        let (channel_id,) = params;
+        log(&format!("Channel {} closed", channel_id));
+
+        let mut app_state = persist::decode(state.as_deref())?;
+        app_state.channels.remove(&channel_id);
+
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state),))
     }
 
     fn handle_channel_message(
@@ -327,44 +557,16 @@ impl SupervisorHandlers for Component {
             "Child actor {} encountered an error: {:?}",
             actor_id, result
         ));
-        let mut app_state: State = match state {
-            Some(state_bytes) if !state_bytes.is_empty() => serde_json::from_slice(&state_bytes)
-                .map_err(|e| format!("Failed to deserialize state: {}", e))?,
-            _ => return Err("Invalid state".to_string()),
-        };
-
-        // Check if the actor ID exists in outstanding requests
-        let request_id = app_state
-            .outstanding_requests
-            .get(&actor_id)
-            .cloned()
-            .ok_or_else(|| format!("No outstanding request found for actor ID {}", actor_id))?;
-
-        // Resolve the outstanding request, passing the error along
-        let response = McpResponse {
-            jsonrpc: "2.0".to_string(),
-            id: request_id.clone(),
-            result: None,
-            error: Some(McpError {
-                code: -32000, // Generic error code
-                message: format!("Child actor error: {:?}", result.data),
-                data: None,
-            }),
-        };
+        let mut app_state = persist::decode(state.as_deref())?;
 
-        log(&format!("Response to outstanding request: {:?}", response));
-        let response_bytes = serde_json::to_vec(&response)
-            .map_err(|e| format!("Failed to serialize response: {}", e))?;
-        respond_to_request(&request_id, &response_bytes).expect("Failed to respond to request");
+        fail_child(
+            &mut app_state,
+            &actor_id,
+            format!("Child actor error: {:?}", result.data),
+        )?;
 
-        // Remove the actor from outstanding requests
-        app_state
-            .outstanding_requests
-            .remove(&actor_id)
-            .ok_or_else(|| format!("Actor ID {} not found in outstanding requests", actor_id))?;
-
-        // Serialize the updated state
-        let updated_state = serde_json::to_vec(&app_state).map_err(|e| e.to_string())?;
+        dispatch_queued(&mut app_state);
+        let updated_state = persist::encode(&app_state)?;
         Ok((Some(updated_state),))
     }
 
@@ -376,7 +578,7 @@ impl SupervisorHandlers for Component {
 
         let (actor_id, result_bytes) = params;
 
-        let result = match result_bytes {
+        let mut result = match result_bytes {
             Some(bytes) => serde_json::from_slice::<GitCommandResult>(&bytes)
                 .map_err(|e| format!("Failed to deserialize result: {}", e))?,
             None => GitCommandResult {
@@ -391,35 +593,403 @@ impl SupervisorHandlers for Component {
             },
         };
 
-        let mut app_state: State = match state {
-            Some(state_bytes) if !state_bytes.is_empty() => serde_json::from_slice(&state_bytes)
-                .map_err(|e| format!("Failed to deserialize state: {}", e))?,
-            _ => return Err("Invalid state".to_string()),
-        };
+        let mut app_state = persist::decode(state.as_deref())?;
 
         log(&format!(
             "Child actor {} exited with result: {:?}",
             actor_id, result
         ));
 
-        let request_id = app_state
+        let pending = app_state
             .outstanding_requests
             .remove(&actor_id)
             .ok_or_else(|| format!("No outstanding request found for actor ID {}", actor_id))?;
+        audit_run(&mut app_state, &pending, Ok(&result));
+        if !result.success && matches!(pending.kind, PendingKind::TypedToolCall { .. }) {
+            staging::discard(&pending.git_args);
+        }
+        let reply_to = pending.reply_to;
+
+        let previewing = match &pending.kind {
+            PendingKind::Preview { .. } | PendingKind::Approval { .. } => true,
+            PendingKind::TypedToolCall { plans, .. } => !plans.is_empty(),
+            _ => false,
+        };
+        if previewing && preview::nothing_to_commit(&pending.git_args, result.exit_code) {
+            result.success = true;
+            result.error = None;
+        }
+
+        // Output returned as text is cut to the configured limit; parsed
+        // output needs all of it
+        if matches!(
+            pending.kind,
+            PendingKind::ToolCall { .. } | PendingKind::Prompt { .. }
+        ) && steps_of(&pending.git_args, &pending.kind).count() == 1
+        {
+            app_state.limits.truncate(&mut result.stdout);
+        }
+        app_state.limits.truncate(&mut result.stderr);
+
+        // Anything that may have moved refs or touched the index makes the
+        // cached completions stale
+        if !git::is_read_only(&pending.git_args) {
+            app_state.completion_cache.remove(&pending.repository_path);
+        }
 
         // Prepare the response
-        let response = match result.success {
-            true => {
-                let tool_call_result = ToolCallResult {
-                    content: vec![ToolContent::Text {
-                        text: serde_json::to_string(&result.stdout)
-                            .unwrap_or_else(|_| "No stdout".to_string()),
-                    }],
-                    is_error: None,
+        let response = match (result.success, pending.kind) {
+            (success, PendingKind::Guard { git_args, then }) => {
+                // `symbolic-ref --quiet` fails quietly when HEAD is detached
+                let current = result.stdout.trim();
+                let current = (success && !current.is_empty()).then_some(current);
+                let code = refusal_code(&then);
+                let refused = (git_args.clone(), purpose(&then));
+                let checked = steps_of(&git_args, &then).try_for_each(|step| {
+                    protected::check_args(&app_state.protected_branches, step, current)
+                });
+                let spawned = checked.and_then(|()| {
+                    spawn_step(
+                        &mut app_state,
+                        &reply_to,
+                        &pending.repository_path,
+                        git_args,
+                        *then,
+                    )
+                });
+                match spawned {
+                    Ok(()) => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                    Err(message) => {
+                        log(&format!("Protected branch check refused call: {}", message));
+                        let (git_args, purpose) = refused;
+                        audit_refusal(
+                            &mut app_state,
+                            &reply_to,
+                            &pending.repository_path,
+                            (git_args, &purpose),
+                            &message,
+                        );
+                        error_response(reply_to.id(), code, message)
+                    }
+                }
+            }
+            (
+                success,
+                PendingKind::Snapshot {
+                    mut progress,
+                    git_args,
+                    then,
+                },
+            ) => {
+                let code = refusal_code(&then);
+                let refused = (git_args.clone(), purpose(&then));
+                let spawned = progress
+                    .advance(success, &result.stdout, &result.stderr)
+                    .and_then(|next| match next {
+                        snapshots::Next::Run(step) => spawn_git_command(
+                            &mut app_state,
+                            &reply_to,
+                            &pending.repository_path,
+                            step,
+                            PendingKind::Snapshot {
+                                progress,
+                                git_args,
+                                then,
+                            },
+                        ),
+                        next => {
+                            if let snapshots::Next::Save = next {
+                                snapshots::save(&mut app_state, &progress)?;
+                                log(&format!("Took snapshot {}", progress.snapshot.id));
+                            }
+                            spawn_git_command(
+                                &mut app_state,
+                                &reply_to,
+                                &pending.repository_path,
+                                git_args,
+                                *then,
+                            )
+                        }
+                    });
+                match spawned {
+                    Ok(()) => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                    Err(message) => {
+                        log(&format!("Snapshot failed: {}", message));
+                        let (git_args, purpose) = refused;
+                        audit_refusal(
+                            &mut app_state,
+                            &reply_to,
+                            &pending.repository_path,
+                            (git_args, &purpose),
+                            &message,
+                        );
+                        error_response(reply_to.id(), code, message)
+                    }
+                }
+            }
+            (
+                success,
+                PendingKind::Approval {
+                    tool,
+                    args,
+                    git_args,
+                    then,
+                    mut remaining,
+                    plans,
+                    mut outputs,
+                    ..
+                },
+            ) => {
+                let code = refusal_code(&then);
+                if success {
+                    outputs.push(preview::output(&result.stdout, &result.stderr));
+                }
+                let spawned = match (success, remaining.is_empty()) {
+                    (true, false) => {
+                        let next = remaining.remove(0);
+                        tools::prepare(
+                            &app_state,
+                            &tool,
+                            &args,
+                            &outputs,
+                            &pending.repository_path,
+                            next,
+                        )
+                        .and_then(|next| {
+                            let kind = PendingKind::Approval {
+                                tool,
+                                args,
+                                git_args,
+                                then,
+                                remaining,
+                                plans,
+                                outputs,
+                                expires_ms: None,
+                            };
+                            spawn_git_command(
+                                &mut app_state,
+                                &reply_to,
+                                &pending.repository_path,
+                                next,
+                                kind,
+                            )
+                        })
+                    }
+                    _ => {
+                        let preview = match success {
+                            true => Ok(preview::result(&plans, &outputs)),
+                            false => Err(result
+                                .error
+                                .clone()
+                                .unwrap_or_else(|| result.stderr.trim().to_string())),
+                        };
+                        park(
+                            &mut app_state,
+                            &reply_to,
+                            &pending.repository_path,
+                            (&tool, &args),
+                            preview,
+                            git_args,
+                            *then,
+                        )
+                    }
+                };
+                match spawned {
+                    Ok(()) => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                    Err(message) => {
+                        log(&format!("Approval refused call: {}", message));
+                        error_response(reply_to.id(), code, message)
+                    }
+                }
+            }
+            (success, PendingKind::RepositoryStatus { listing_id, name }) => {
+                let outcome = match success {
+                    true => Ok(parse::status(&result.stdout)),
+                    false => Err(result
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| result.stderr.trim().to_string())),
+                };
+                match repos::record(&mut app_state, listing_id, &name, outcome) {
+                    Some(statuses) => repositories_response(reply_to.id(), statuses)?,
+                    None => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                }
+            }
+            (
+                true,
+                PendingKind::Prompt {
+                    name,
+                    args,
+                    mut remaining,
+                    mut outputs,
+                },
+            ) if !remaining.is_empty() => {
+                outputs.push(result.stdout);
+                let next = prompts::prepare(&outputs, remaining.remove(0));
+                let kind = PendingKind::Prompt {
+                    name,
+                    args,
+                    remaining,
+                    outputs,
+                };
+                match spawn_git_command(
+                    &mut app_state,
+                    &reply_to,
+                    &pending.repository_path,
+                    next,
+                    kind,
+                ) {
+                    Ok(()) => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                    Err(message) => error_response(reply_to.id(), -32602, message),
+                }
+            }
+            (
+                true,
+                PendingKind::Prompt {
+                    name,
+                    args,
+                    outputs,
+                    ..
+                },
+            ) => McpResponse {
+                jsonrpc: "2.0".to_string(),
+                id: reply_to.id(),
+                result: Some(
+                    serde_json::to_value(prompts::render(&name, &args, &outputs, &result))
+                        .map_err(|e| format!("Failed to serialize result: {}", e))?,
+                ),
+                error: None,
+            },
+            (true, PendingKind::Completion { kind, value }) => {
+                let values = completion::parse(kind, &result);
+                let completed = completion::complete(values.iter().map(String::as_str), &value);
+                app_state
+                    .completion_cache
+                    .entry(pending.repository_path)
+                    .or_default()
+                    .set(kind, values);
+                McpResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: reply_to.id(),
+                    result: Some(
+                        serde_json::to_value(completed)
+                            .map_err(|e| format!("Failed to serialize result: {}", e))?,
+                    ),
+                    error: None,
+                }
+            }
+            (
+                true,
+                PendingKind::TypedToolCall {
+                    name,
+                    mut remaining,
+                    args,
+                    mut outputs,
+                    plans,
+                    cache_key,
+                    approved,
+                },
+            ) if !remaining.is_empty() => {
+                outputs.push(match plans.is_empty() {
+                    true => result.stdout,
+                    false => preview::output(&result.stdout, &result.stderr),
+                });
+                let next = remaining.remove(0);
+                let spawned = tools::prepare(
+                    &app_state,
+                    &name,
+                    &args,
+                    &outputs,
+                    &pending.repository_path,
+                    next,
+                )
+                .and_then(|next| {
+                    let kind = PendingKind::TypedToolCall {
+                        name,
+                        remaining,
+                        args,
+                        outputs,
+                        plans,
+                        cache_key,
+                        approved,
+                    };
+                    let step = next.clone();
+                    spawn_step(
+                        &mut app_state,
+                        &reply_to,
+                        &pending.repository_path,
+                        next,
+                        kind,
+                    )
+                    .inspect_err(|_| staging::discard(&step))
+                });
+                match spawned {
+                    Ok(()) => {
+                        dispatch_queued(&mut app_state);
+                        let updated_state = persist::encode(&app_state)?;
+                        return Ok((Some(updated_state),));
+                    }
+                    Err(message) => error_response(reply_to.id(), -32602, message),
+                }
+            }
+            (
+                true,
+                PendingKind::TypedToolCall {
+                    name,
+                    args,
+                    mut outputs,
+                    plans,
+                    cache_key,
+                    ..
+                },
+            ) => {
+                let structured = match plans.is_empty() {
+                    true => tools::structured(&name, &args, &outputs, &result.stdout),
+                    false => {
+                        outputs.push(preview::output(&result.stdout, &result.stderr));
+                        preview::result(&plans, &outputs)
+                    }
                 };
+                let tool_call_result =
+                    serde_json::to_value(tools::StructuredToolCallResult::new(structured))
+                        .map_err(|e| format!("Failed to serialize result: {}", e))?;
+                if let Some(cache_key) = cache_key {
+                    cache::put(&mut app_state, cache_key, &tool_call_result);
+                }
                 McpResponse {
                     jsonrpc: "2.0".to_string(),
-                    id: request_id.clone(),
+                    id: reply_to.id(),
+                    result: Some(tool_call_result),
+                    error: None,
+                }
+            }
+            (true, PendingKind::Preview { plan }) => {
+                let output = preview::output(&result.stdout, &result.stderr);
+                let tool_call_result =
+                    tools::StructuredToolCallResult::new(preview::result(&[plan], &[output]));
+                McpResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: reply_to.id(),
                     result: Some(
                         serde_json::to_value(tool_call_result)
                             .map_err(|e| format!("Failed to serialize result: {}", e))?,
@@ -427,9 +997,41 @@ impl SupervisorHandlers for Component {
                     error: None,
                 }
             }
-            false => McpResponse {
+            (true, PendingKind::ToolCall { cache_key, .. }) => {
+                let tool_call_result = serde_json::to_value(ToolCallResult {
+                    content: vec![ToolContent::Text {
+                        text: serde_json::to_string(&result.stdout)
+                            .unwrap_or_else(|_| "No stdout".to_string()),
+                    }],
+                    is_error: None,
+                })
+                .map_err(|e| format!("Failed to serialize result: {}", e))?;
+                if let Some(cache_key) = cache_key {
+                    cache::put(&mut app_state, cache_key, &tool_call_result);
+                }
+                McpResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: reply_to.id(),
+                    result: Some(tool_call_result),
+                    error: None,
+                }
+            }
+            (true, PendingKind::Configure { changes, tool }) => {
+                changes.apply(&mut app_state);
+                configured_response(&app_state, reply_to.id(), tool)?
+            }
+            (false, PendingKind::Configure { changes, .. }) => error_response(
+                reply_to.id(),
+                -32602,
+                format!(
+                    "'{}' is not a git repository: {}",
+                    changes.repository_path.unwrap_or_default(),
+                    result.stderr.trim()
+                ),
+            ),
+            (false, _) => McpResponse {
                 jsonrpc: "2.0".to_string(),
-                id: request_id.clone(),
+                id: reply_to.id(),
                 result: None,
                 error: Some(McpError {
                     code: -32000, // Generic error code
@@ -442,13 +1044,12 @@ impl SupervisorHandlers for Component {
 
         log(&format!("Response to outstanding request: {:?}", response));
 
-        let response_bytes = serde_json::to_vec(&response)
-            .map_err(|e| format!("Failed to serialize response: {}", e))?;
+        deliver(&mut app_state, &reply_to, &response)?;
 
-        respond_to_request(&request_id, &response_bytes).expect("Failed to respond to request");
+        notify_tools_changed(&mut app_state);
 
-        let updated_state = serde_json::to_vec(&app_state)
-            .map_err(|e| format!("Failed to serialize updated state: {}", e))?;
+        dispatch_queued(&mut app_state);
+        let updated_state = persist::encode(&app_state)?;
         Ok((Some(updated_state),))
     }
 
@@ -459,7 +1060,1417 @@ impl SupervisorHandlers for Component {
         log("Handling child external stop in chat-state");
         let actor_id = params.0;
         log(&format!("Child actor {} requested external stop", actor_id));
-        Ok((state,))
+
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        if app_state.outstanding_requests.contains_key(&actor_id) {
+            let message = format!("Child actor {} was stopped externally", actor_id);
+            if let Err(message) = fail_child(&mut app_state, &actor_id, message) {
+                log(&format!(
+                    "Failed to answer stopped child's request: {}",
+                    message
+                ));
+            }
+        }
+
+        dispatch_queued(&mut app_state);
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state),))
+    }
+}
+
+/// Answer the request of child `actor_id`, which stopped without a result,
+/// with `message`.
+fn fail_child(state: &mut State, actor_id: &str, message: String) -> Result<(), String> {
+    let pending = state
+        .outstanding_requests
+        .remove(actor_id)
+        .ok_or_else(|| format!("No outstanding request found for actor ID {}", actor_id))?;
+    audit_run(state, &pending, Err(message.clone()));
+    if matches!(pending.kind, PendingKind::TypedToolCall { .. }) {
+        staging::discard(&pending.git_args);
+    }
+    let reply_to = pending.reply_to;
+
+    // One repository failing does not fail the whole listing
+    if let PendingKind::RepositoryStatus { listing_id, name } = pending.kind {
+        if let Some(statuses) = repos::record(state, listing_id, &name, Err(message)) {
+            let response = repositories_response(reply_to.id(), statuses)?;
+            deliver(state, &reply_to, &response)?;
+        }
+        return Ok(());
+    }
+
+    // Resolve the outstanding request, passing the error along
+    let response = McpResponse {
+        jsonrpc: "2.0".to_string(),
+        id: reply_to.id(),
+        result: None,
+        error: Some(McpError {
+            code: -32000, // Generic error code
+            message,
+            data: None,
+        }),
+    };
+
+    log(&format!("Response to outstanding request: {:?}", response));
+    deliver(state, &reply_to, &response)
+}
+
+/// Handle an MCP request from any transport. Returns `None` when a child was
+/// spawned and the response will be delivered to `reply_to` once it exits.
+fn handle_mcp(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    request: McpActorRequest,
+) -> Result<Option<McpResponse>, String> {
+    expire_approvals(state);
+
+    let response = match request {
+        McpActorRequest::ToolsList {} => {
+            log("Received tools_list request");
+
+            let tools = tools::list(state);
+
+            log(&format!("Available tools: {:?}", tools));
+            let res = McpResponse {
+                jsonrpc: "2.0".to_string(),
+                id: reply_to.id(),
+                result: Some(json!({
+                    "tools": tools
+                })),
+                error: None,
+            };
+            Some(res)
+        }
+
+        McpActorRequest::ToolsCall { name, args } => {
+            log("Received tools_call request");
+            log(&format!("Tool name: {}", name));
+
+            match name.as_str() {
+                "git-command" => {
+                    log("Processing git-command call");
+
+                    let args_array = args
+                        .get("args")
+                        .and_then(Value::as_array)
+                        .ok_or("Missing or invalid 'args' argument - expected array of strings")?;
+
+                    let git_args = args_array
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>();
+
+                    // Get the repository from call args or fall back to init state
+                    let target = match resolve_target(state, &args) {
+                        Ok(target) => target,
+                        Err(message) => {
+                            log(&format!("Refused git-command: {}", message));
+                            audit_refusal(
+                                state,
+                                reply_to,
+                                requested_repository(&args),
+                                (git_args, "git-command"),
+                                &message,
+                            );
+                            return Ok(Some(error_response(reply_to.id(), -32602, message)));
+                        }
+                    };
+
+                    let dry_run = args.get("dry_run").and_then(Value::as_bool) == Some(true);
+                    let plan = match dry_run {
+                        true => match preview::plan(&git_args) {
+                            Ok(plan) => plan,
+                            Err(message) => {
+                                return Ok(Some(error_response(reply_to.id(), -32602, message)));
+                            }
+                        },
+                        false => None,
+                    };
+
+                    // Read-only commands pinned to fixed revisions are answered
+                    // from the cache, once the policy allows them
+                    let cache_key = match plan {
+                        None if !target.policy.needs_approval(&git_args) => cache::key(
+                            state,
+                            &target.path,
+                            "git-command",
+                            std::slice::from_ref(&git_args),
+                        ),
+                        _ => None,
+                    };
+                    if let Some(cache_key) = &cache_key
+                        && git::check_repository_options(&git_args).is_ok()
+                        && target.policy.check(&git_args).is_ok()
+                        && let Some(cached) = cache::get(state, cache_key)
+                    {
+                        log("Answering git-command from the cache");
+                        return Ok(Some(McpResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id: reply_to.id(),
+                            result: Some(cached),
+                            error: None,
+                        }));
+                    }
+
+                    let audited = git_args.clone();
+                    let spawned = git::check_repository_options(&git_args)
+                        .and_then(|()| target.policy.check(&git_args))
+                        .and_then(|()| match plan {
+                            Some(plan) => spawn_git_command(
+                                state,
+                                reply_to,
+                                &target.path,
+                                plan.preview.clone(),
+                                PendingKind::Preview { plan },
+                            ),
+                            None if target.policy.needs_approval(&git_args) => request_approval(
+                                state,
+                                reply_to,
+                                &target.path,
+                                (&name, &args),
+                                git_args,
+                                PendingKind::ToolCall {
+                                    cache_key: None,
+                                    approved: false,
+                                },
+                            ),
+                            None => spawn_guarded(
+                                state,
+                                reply_to,
+                                &target.path,
+                                git_args,
+                                PendingKind::ToolCall {
+                                    cache_key,
+                                    approved: false,
+                                },
+                            ),
+                        });
+                    match spawned {
+                        Ok(()) => None,
+                        Err(message) => {
+                            log(&format!("Policy refused git-command: {}", message));
+                            audit_refusal(
+                                state,
+                                reply_to,
+                                &target.path,
+                                (audited, "git-command"),
+                                &message,
+                            );
+                            Some(error_response(reply_to.id(), -32000, message))
+                        }
+                    }
+                }
+                "git-commit" => {
+                    log("Processing git-commit call");
+
+                    let message = args.get("message").and_then(Value::as_str);
+                    let signoff = args.get("signoff").and_then(Value::as_bool) == Some(true);
+                    let violations = message
+                        .map(|message| state.commit_rules.check(message, signoff, &state.identity))
+                        .unwrap_or_default();
+                    match violations.is_empty() {
+                        true => call_typed_tool(state, reply_to, "git-commit", &args),
+                        false => {
+                            log(&format!("Refused commit message: {:?}", violations));
+                            Some(McpResponse {
+                                jsonrpc: "2.0".to_string(),
+                                id: reply_to.id(),
+                                result: None,
+                                error: Some(McpError {
+                                    code: -32602,
+                                    message: format!(
+                                        "The commit message breaks {} rule(s): {}",
+                                        violations.len(),
+                                        violations
+                                            .iter()
+                                            .map(|violation| violation.message.as_str())
+                                            .collect::<Vec<_>>()
+                                            .join("; ")
+                                    ),
+                                    data: Some(json!({ "violations": violations })),
+                                }),
+                            })
+                        }
+                    }
+                }
+                "git-undo" if args.get("snapshot").is_none() => {
+                    log("Processing git-undo listing");
+
+                    let listed = resolve_target(state, &args).and_then(|target| {
+                        let max_count = args.get("max_count").and_then(Value::as_u64).unwrap_or(20);
+                        snapshots::list(state, &target.path, max_count as usize)
+                    });
+                    match listed {
+                        Ok(snapshots) => Some(result_response(
+                            reply_to.id(),
+                            &tools::StructuredToolCallResult::new(
+                                json!({ "snapshots": snapshots }),
+                            ),
+                        )?),
+                        Err(message) => Some(error_response(reply_to.id(), -32602, message)),
+                    }
+                }
+                "git-audit-log" => {
+                    log("Processing git-audit-log call");
+
+                    let found = serde_json::from_value::<audit::Query>(args.clone())
+                        .map_err(|e| format!("Invalid arguments: {}", e))
+                        .and_then(|query| {
+                            let scoped =
+                                args.get("repo").is_some() || args.get("repository_path").is_some();
+                            let path = match scoped {
+                                true => Some(
+                                    resolve_target(state, &args)
+                                        .inspect_err(|message| {
+                                            audit_refusal(
+                                                state,
+                                                reply_to,
+                                                requested_repository(&args),
+                                                (Vec::new(), "git-audit-log"),
+                                                message,
+                                            );
+                                        })?
+                                        .path,
+                                ),
+                                false => None,
+                            };
+                            audit::query(state, path.as_deref(), &query)
+                        });
+                    match found {
+                        Ok(records) => Some(result_response(
+                            reply_to.id(),
+                            &tools::StructuredToolCallResult::new(json!({ "records": records })),
+                        )?),
+                        Err(message) => Some(error_response(reply_to.id(), -32602, message)),
+                    }
+                }
+                name if tools::is_typed(name) => {
+                    log(&format!("Processing {} call", name));
+                    call_typed_tool(state, reply_to, name, &args)
+                }
+                "list-repositories" => {
+                    log("Processing list-repositories call");
+                    list_repositories(state, reply_to)?
+                }
+                "configure-server" if state.capabilities.configure_tool => {
+                    log("Processing configure-server call");
+                    match serde_json::from_value::<Changes>(args) {
+                        Ok(changes) => configure(state, reply_to, changes, true)?,
+                        Err(e) => Some(error_response(
+                            reply_to.id(),
+                            -32602,
+                            format!("Invalid arguments: {}", e),
+                        )),
+                    }
+                }
+                _ => {
+                    log(&format!("Unknown tool name: {}", name));
+                    Some(error_response(
+                        reply_to.id(),
+                        -32601,
+                        format!("Method '{}' not implemented", name),
+                    ))
+                }
+            }
+        }
+
+        McpActorRequest::PromptsList {} => {
+            log("Received prompts_list request");
+
+            let res = McpResponse {
+                jsonrpc: "2.0".to_string(),
+                id: reply_to.id(),
+                result: Some(json!({
+                    "prompts": prompts::list()
+                })),
+                error: None,
+            };
+            Some(res)
+        }
+
+        McpActorRequest::PromptsGet { name, args } => {
+            log("Received prompts_get request");
+            log(&format!("Prompt name: {}", name));
+
+            // Prompts run git like tools do, so the policy applies to
+            // every step
+            let target = repos::resolve(
+                state,
+                args.get("repo").map(String::as_str),
+                args.get("repository_path").map(String::as_str),
+            );
+            let spawned = match prompts::exists(&name) {
+                true => prompts::steps(&name, &args).and_then(|mut steps| {
+                    let target = target.as_ref().map_err(String::clone)?;
+                    for step in &steps {
+                        target.policy.check(step)?;
+                    }
+                    let first = steps.remove(0);
+                    spawn_git_command(
+                        state,
+                        reply_to,
+                        &target.path,
+                        first,
+                        PendingKind::Prompt {
+                            name: name.clone(),
+                            args: args.clone(),
+                            remaining: steps,
+                            outputs: Vec::new(),
+                        },
+                    )
+                }),
+                false => Err(format!("Prompt '{}' not found", name)),
+            };
+
+            match spawned {
+                Ok(()) => None,
+                Err(message) => {
+                    log(&format!("Failed to get prompt: {}", message));
+                    let repository_path = target.map(|target| target.path).unwrap_or_default();
+                    audit_refusal(
+                        state,
+                        reply_to,
+                        &repository_path,
+                        (Vec::new(), &format!("prompt {}", name)),
+                        &message,
+                    );
+                    Some(error_response(reply_to.id(), -32602, message))
+                }
+            }
+        }
+
+        McpActorRequest::CompletionComplete {
+            reference,
+            argument,
+            context,
+        } => {
+            log("Received completion_complete request");
+            log(&format!(
+                "Completing '{}' for {:?}",
+                argument.name, reference
+            ));
+
+            let completed = complete_argument(state, reply_to, reference, argument, context)?;
+
+            match completed {
+                Some(completed) => Some(result_response(reply_to.id(), &completed)?),
+                None => None,
+            }
+        }
+
+        McpActorRequest::Configure { changes } => {
+            log("Received configure request");
+
+            match state.capabilities.configure {
+                true => configure(state, reply_to, changes, false)?,
+                false => Some(error_response(
+                    reply_to.id(),
+                    -32601,
+                    "Runtime configuration is not enabled".to_string(),
+                )),
+            }
+        }
+    };
+    Ok(response)
+}
+
+/// Apply `changes`, first checking that a new default repository is a git
+/// repository. Returns `None` while that check runs.
+fn configure(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    changes: Changes,
+    tool: bool,
+) -> Result<Option<McpResponse>, String> {
+    if let Err(message) = changes.validate(state) {
+        return Ok(Some(error_response(reply_to.id(), -32602, message)));
+    }
+
+    let Some(repository_path) = changes.repository_path.clone() else {
+        changes.apply(state);
+        return Ok(Some(configured_response(state, reply_to.id(), tool)?));
+    };
+
+    let git_args = vec!["rev-parse".to_string(), "--git-dir".to_string()];
+    spawn_git_command(
+        state,
+        reply_to,
+        &repository_path,
+        git_args,
+        PendingKind::Configure { changes, tool },
+    )?;
+    Ok(None)
+}
+
+/// Response to a configure request: the settings as they now are.
+fn configured_response(state: &State, id: Value, tool: bool) -> Result<McpResponse, String> {
+    log("Configuration updated");
+    let current = config::current(state);
+    match tool {
+        true => result_response(id, &tools::StructuredToolCallResult::new(current)),
+        false => result_response(id, &current),
+    }
+}
+
+/// Suggest values for a completion request. Returns `None` when the
+/// candidates are not cached yet and a child was spawned to list them.
+fn complete_argument(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    reference: CompletionReference,
+    argument: CompletionArgument,
+    context: Option<Value>,
+) -> Result<Option<CompletionCompleteResult>, String> {
+    let kind = match completion::argument_kind(&reference, &argument.name) {
+        Some(kind) => kind,
+        None => return Ok(Some(completion::complete([], &argument.value))),
+    };
+
+    match kind {
+        ArgumentKind::Subcommand => {
+            return Ok(Some(completion::complete(
+                git::SUBCOMMANDS.iter().map(|subcommand| subcommand.name),
+                &argument.value,
+            )));
+        }
+        ArgumentKind::Repository => {
+            return Ok(Some(completion::complete(
+                repos::names(state),
+                &argument.value,
+            )));
+        }
+        ArgumentKind::Revision | ArgumentKind::Path => {}
+    }
+
+    let arguments = context
+        .as_ref()
+        .and_then(|context| context.get("arguments"))
+        .cloned()
+        .unwrap_or_default();
+    // Completions are best effort, so no repository means no suggestions
+    let Ok(target) = resolve_target(state, &arguments) else {
+        return Ok(Some(completion::complete([], &argument.value)));
+    };
+    let repository_path = target.path;
+
+    let cached = state
+        .completion_cache
+        .get(&repository_path)
+        .and_then(|names| names.get(kind));
+    if let Some(values) = cached {
+        return Ok(Some(completion::complete(
+            values.iter().map(String::as_str),
+            &argument.value,
+        )));
+    }
+
+    spawn_git_command(
+        state,
+        reply_to,
+        &repository_path,
+        completion::git_args(kind),
+        PendingKind::Completion {
+            kind,
+            value: argument.value,
+        },
+    )?;
+    Ok(None)
+}
+
+/// Fingerprint of the tool list as clients would see it. Serializing through
+/// `Value` sorts object keys, so equal lists always give equal fingerprints.
+fn tools_fingerprint(state: &State) -> String {
+    let tools = serde_json::to_value(tools::list(state)).unwrap_or_default();
+    parse::fingerprint(&[&tools.to_string()])
+}
+
+/// Send `notifications/tools/list_changed` to every open channel and session if the tool
+/// list differs from the one clients were last told about.
+fn notify_tools_changed(state: &mut State) {
+    let fingerprint = tools_fingerprint(state);
+    if fingerprint == state.tools_fingerprint {
+        return;
+    }
+    state.tools_fingerprint = fingerprint;
+
+    log(&format!(
+        "Tool list changed, notifying {} channel(s) and {} session(s)",
+        state.channels.len(),
+        state.sessions.len()
+    ));
+    let notification = JsonRpcMessage::notification(methods::TOOLS_LIST_CHANGED, None);
+    let Ok(notification_bytes) = serde_json::to_vec(&notification) else {
+        return;
+    };
+    for channel_id in &state.channels {
+        if let Err(e) = send_on_channel(channel_id, &notification_bytes) {
+            log(&format!("Failed to notify channel {}: {}", channel_id, e));
+        }
+    }
+    if let Ok(message) = serde_json::to_value(&notification) {
+        let session_ids: Vec<String> = state.sessions.keys().cloned().collect();
+        for session_id in session_ids {
+            session::send(
+                state,
+                &session_id,
+                session::STANDALONE_STREAM,
+                message.clone(),
+            );
+        }
+    }
+}
+
+/// A successful response to `id`.
+fn result_response(id: Value, result: &impl Serialize) -> Result<McpResponse, String> {
+    Ok(McpResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: Some(
+            serde_json::to_value(result)
+                .map_err(|e| format!("Failed to serialize result: {}", e))?,
+        ),
+        error: None,
+    })
+}
+
+/// An error response to `id`.
+fn error_response(id: Value, code: i32, message: String) -> McpResponse {
+    let response = McpResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(McpError {
+            code,
+            message,
+            data: None,
+        }),
+    };
+    log(&format!("Error response: {:?}", response));
+    response
+}
+
+/// Send a response that could not be returned directly, once its child exits.
+fn deliver(state: &mut State, reply_to: &ReplyTo, response: &McpResponse) -> Result<(), String> {
+    match reply_to {
+        ReplyTo::Request { request_id } => {
+            let response_bytes = serde_json::to_vec(response)
+                .map_err(|e| format!("Failed to serialize response: {}", e))?;
+            respond_to_request(request_id, &response_bytes)
+                .map_err(|e| format!("Failed to respond to request: {}", e))
+        }
+        ReplyTo::Session {
+            session_id, stream, ..
+        } => {
+            let message = serde_json::to_value(response)
+                .map_err(|e| format!("Failed to serialize response: {}", e))?;
+            session::send(state, session_id, *stream, message);
+            Ok(())
+        }
+    }
+}
+
+/// Repository a tool call runs in, from its `repo` or `repository_path`
+/// argument, falling back to the configured default.
+fn resolve_target(state: &State, args: &Value) -> Result<repos::Target, String> {
+    repos::resolve(
+        state,
+        args.get("repo").and_then(Value::as_str),
+        args.get("repository_path").and_then(Value::as_str),
+    )
+}
+
+/// The repository a call asked for, by path or name, as the audit log
+/// records it when the call is refused before it resolves.
+fn requested_repository(args: &Value) -> &str {
+    args.get("repository_path")
+        .or_else(|| args.get("repo"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+/// Start `list-repositories`: one status child per registered repository,
+/// answered once the last one exits.
+fn list_repositories(state: &mut State, reply_to: &ReplyTo) -> Result<Option<McpResponse>, String> {
+    let listing_id = state.next_listing_id;
+    state.next_listing_id += 1;
+
+    let mut listing = repos::Listing::default();
+    let mut spawns = Vec::new();
+    for name in repos::names(state) {
+        let target = repos::resolve(state, Some(name), None)?;
+        let repository = &state.repositories[name];
+        let mut status = repos::RepositoryStatus::new(name, repository);
+        let git_args = tools::git_args("git-status", &json!({}))?;
+        match target.policy.check(&git_args) {
+            Ok(()) => spawns.push((name.to_string(), target.path, git_args)),
+            Err(message) => status.set(Err(message)),
+        }
+        listing.statuses.insert(name.to_string(), status);
+    }
+
+    if spawns.is_empty() {
+        let statuses = listing.statuses.into_values().collect();
+        return Ok(Some(repositories_response(reply_to.id(), statuses)?));
+    }
+
+    listing.remaining = spawns.len();
+    state.listings.insert(listing_id, listing);
+    let mut finished = None;
+    for (name, repository_path, git_args) in spawns {
+        let kind = PendingKind::RepositoryStatus {
+            listing_id,
+            name: name.clone(),
+        };
+        if let Err(message) = spawn_git_command(state, reply_to, &repository_path, git_args, kind) {
+            finished = repos::record(state, listing_id, &name, Err(message)).or(finished);
+        }
+    }
+    // Only set when no child could be spawned
+    finished
+        .map(|statuses| repositories_response(reply_to.id(), statuses))
+        .transpose()
+}
+
+fn repositories_response(
+    id: Value,
+    statuses: Vec<repos::RepositoryStatus>,
+) -> Result<McpResponse, String> {
+    let result = tools::StructuredToolCallResult::new(json!({ "repositories": statuses }));
+    result_response(id, &result)
+}
+
+/// Run the typed tool `name`, returning the error response if it cannot start.
+/// Every step is checked against the policy before the first one runs.
+fn call_typed_tool(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    name: &str,
+    args: &Value,
+) -> Option<McpResponse> {
+    let spawned = resolve_target(state, args).and_then(|target| {
+        let mut args = args.clone();
+        if args.get("remote").is_none()
+            && let Some(remote) = &target.default_remote
+        {
+            args["remote"] = json!(remote);
+        }
+        let steps = match name {
+            "git-undo" => snapshots::restore_steps(
+                state,
+                &target.path,
+                args.get("snapshot").and_then(Value::as_str).unwrap_or(""),
+            )?,
+            _ => tools::steps(name, &args)?,
+        };
+        target.policy.check_force(name, &args)?;
+        for step in &steps {
+            target.policy.check(step)?;
+        }
+        // A dry run checks the commands it previews, but runs only the previews
+        let (mut steps, plans) = match args.get("dry_run").and_then(Value::as_bool) {
+            Some(true) => preview::steps(steps)?,
+            _ => (steps, Vec::new()),
+        };
+        let needs_approval =
+            plans.is_empty() && steps.iter().any(|step| target.policy.needs_approval(step));
+        let cache_key = match plans.is_empty() && !needs_approval {
+            true => cache::key(state, &target.path, name, &steps),
+            false => None,
+        };
+        if let Some(cache_key) = &cache_key
+            && let Some(cached) = cache::get(state, cache_key)
+        {
+            log(&format!("Answering {} from the cache", name));
+            return Ok(Some(cached));
+        }
+        let first = steps.remove(0);
+        let kind = PendingKind::TypedToolCall {
+            name: name.to_string(),
+            remaining: steps,
+            args: args.clone(),
+            outputs: Vec::new(),
+            plans,
+            cache_key,
+            approved: false,
+        };
+        match needs_approval {
+            true => request_approval(state, reply_to, &target.path, (name, &args), first, kind),
+            false => spawn_guarded(state, reply_to, &target.path, first, kind),
+        }
+        .map(|()| None)
+    });
+
+    match spawned {
+        Ok(Some(cached)) => Some(McpResponse {
+            jsonrpc: "2.0".to_string(),
+            id: reply_to.id(),
+            result: Some(cached),
+            error: None,
+        }),
+        Ok(None) => None,
+        Err(message) => {
+            log(&format!("Failed to call {}: {}", name, message));
+            let repository_path = resolve_target(state, args)
+                .map(|target| target.path)
+                .unwrap_or_default();
+            audit_refusal(
+                state,
+                reply_to,
+                &repository_path,
+                (Vec::new(), name),
+                &message,
+            );
+            Some(error_response(reply_to.id(), -32602, message))
+        }
+    }
+}
+
+/// Hold a call the policy marks as requiring approval until the approver
+/// agrees. Its changes are previewed first, when they can be, so the
+/// approver sees what would happen; the preview runs as children of its own
+/// and the approver is asked in `handle_child_exit`.
+fn request_approval(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    (tool, args): (&str, &Value),
+    git_args: Vec<String>,
+    kind: PendingKind,
+) -> Result<(), String> {
+    if state.approval.is_none() {
+        return Err(format!(
+            "{} requires approval, but no approver is configured",
+            tool
+        ));
+    }
+    let steps = steps_of(&git_args, &kind).map(<[String]>::to_vec).collect();
+    let (mut previews, plans) = match preview::steps(steps) {
+        Ok((previews, plans)) if !plans.is_empty() => (previews, plans),
+        Ok(_) => {
+            let preview = Ok(Value::Null);
+            return park(
+                state,
+                reply_to,
+                repository_path,
+                (tool, args),
+                preview,
+                git_args,
+                kind,
+            );
+        }
+        Err(message) => {
+            let preview = Err(message);
+            return park(
+                state,
+                reply_to,
+                repository_path,
+                (tool, args),
+                preview,
+                git_args,
+                kind,
+            );
+        }
+    };
+    let first = previews.remove(0);
+    let approval = PendingKind::Approval {
+        tool: tool.to_string(),
+        args: args.clone(),
+        git_args,
+        then: Box::new(kind),
+        remaining: previews,
+        plans,
+        outputs: Vec::new(),
+        expires_ms: None,
+    };
+    spawn_git_command(state, reply_to, repository_path, first, approval)
+}
+
+/// Ask the approver about a held call, with its preview or why there is
+/// none, and park it until the answer arrives in `handle_send`, or
+/// `expire_approvals` finds it has waited too long.
+fn park(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    (tool, args): (&str, &Value),
+    preview: Result<Value, String>,
+    git_args: Vec<String>,
+    kind: PendingKind,
+) -> Result<(), String> {
+    let approver = state
+        .approval
+        .clone()
+        .ok_or_else(|| format!("{} requires approval, but no approver is configured", tool))?;
+    let call = approval::Call {
+        tool,
+        arguments: args,
+        repository_path,
+        commands: steps_of(&git_args, &kind).map(<[String]>::to_vec).collect(),
+    };
+    let expires_ms = now() + approval::timeout_ms(&approver);
+    let id = approval::send_request(&approver, &call, preview, expires_ms)?;
+    let key = format!("approval/{}", id);
+    log(&format!(
+        "Parked {} as {} until the approver answers",
+        tool, key
+    ));
+    state.outstanding_requests.insert(
+        key,
+        PendingRequest {
+            reply_to: reply_to.clone(),
+            repository_path: repository_path.to_string(),
+            git_args: git_args.clone(),
+            kind: PendingKind::Approval {
+                tool: tool.to_string(),
+                args: args.clone(),
+                git_args,
+                then: Box::new(kind),
+                remaining: Vec::new(),
+                plans: Vec::new(),
+                outputs: Vec::new(),
+                expires_ms: Some(expires_ms),
+            },
+            started_ms: now(),
+        },
+    );
+    Ok(())
+}
+
+/// Reject the parked calls whose approver did not answer in time. Nothing
+/// wakes the actor when that happens, so this runs as handlers start.
+fn expire_approvals(state: &mut State) {
+    let now_ms = now();
+    let expired: Vec<String> = state
+        .outstanding_requests
+        .iter()
+        .filter(|(_, pending)| {
+            matches!(
+                pending.kind,
+                PendingKind::Approval {
+                    expires_ms: Some(expires_ms),
+                    ..
+                } if expires_ms <= now_ms
+            )
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    let timeout_ms = state
+        .approval
+        .as_ref()
+        .map(approval::timeout_ms)
+        .unwrap_or_default();
+    for key in expired {
+        log(&format!("Approval of {} timed out", key));
+        resume(
+            state,
+            &key,
+            Err(format!(
+                "The approver did not answer within {} ms",
+                timeout_ms
+            )),
+        );
+    }
+}
+
+/// Finish the call parked under `key` with the approver's verdict: run it
+/// as `spawn_guarded` does once approved, or answer it with the refusal.
+fn resume(state: &mut State, key: &str, verdict: Result<(), String>) {
+    let Some(pending) = state.outstanding_requests.remove(key) else {
+        log(&format!("No call is parked as {}", key));
+        return;
+    };
+    let PendingKind::Approval {
+        tool,
+        git_args,
+        then,
+        ..
+    } = pending.kind
+    else {
+        return;
+    };
+    let reply_to = pending.reply_to;
+    let repository_path = pending.repository_path;
+    let mut record = audit::Record {
+        request_id: reply_to.id(),
+        caller: reply_to.caller(),
+        repository_path: repository_path.clone(),
+        args: git_args.clone(),
+        purpose: tool.clone(),
+        decision: audit::Decision::Approved,
+        reason: None,
+        started_ms: now(),
+        finished_ms: None,
+        exit_code: None,
+        success: None,
+        stdout_bytes: 0,
+        stderr_bytes: 0,
+    };
+    if let Err(message) = &verdict {
+        record.decision = audit::Decision::Refused;
+        record.reason = Some(message.clone());
+    }
+    audit::write(state, record);
+
+    let code = refusal_code(&then);
+    let spawned = verdict.and_then(|()| {
+        log(&format!("Approver allowed {}", tool));
+        let mut then = *then;
+        mark_approved(&mut then);
+        spawn_guarded(state, &reply_to, &repository_path, git_args, then)
+    });
+    if let Err(message) = spawned {
+        log(&format!("Approval refused call: {}", message));
+        let response = error_response(reply_to.id(), code, message);
+        if let Err(message) = deliver(state, &reply_to, &response) {
+            log(&format!("Failed to answer parked request: {}", message));
+        }
+    }
+}
+
+/// Whether the call `kind` is a step of was let through by the approver.
+fn approved(kind: &PendingKind) -> bool {
+    match kind {
+        PendingKind::ToolCall { approved, .. } | PendingKind::TypedToolCall { approved, .. } => {
+            *approved
+        }
+        PendingKind::Guard { then, .. } | PendingKind::Snapshot { then, .. } => approved(then),
+        _ => false,
+    }
+}
+
+fn mark_approved(kind: &mut PendingKind) {
+    match kind {
+        PendingKind::ToolCall { approved, .. } | PendingKind::TypedToolCall { approved, .. } => {
+            *approved = true
+        }
+        PendingKind::Guard { then, .. } | PendingKind::Snapshot { then, .. } => mark_approved(then),
+        _ => {}
+    }
+}
+
+/// Record in the audit log that the child of `pending` finished with
+/// `result`, or failed without one.
+fn audit_run(
+    state: &mut State,
+    pending: &PendingRequest,
+    result: Result<&GitCommandResult, String>,
+) {
+    let (exit_code, success, stdout_bytes, stderr_bytes, reason) = match result {
+        Ok(result) => (
+            result.exit_code,
+            result.success,
+            result.stdout.len(),
+            result.stderr.len(),
+            None,
+        ),
+        Err(message) => (None, false, 0, 0, Some(message)),
+    };
+    audit::write(
+        state,
+        audit::Record {
+            request_id: pending.reply_to.id(),
+            caller: pending.reply_to.caller(),
+            repository_path: pending.repository_path.clone(),
+            args: pending.git_args.clone(),
+            purpose: purpose(&pending.kind),
+            decision: match approved(&pending.kind) {
+                true => audit::Decision::Approved,
+                false => audit::Decision::Allowed,
+            },
+            reason,
+            started_ms: pending.started_ms,
+            finished_ms: Some(now()),
+            exit_code,
+            success: Some(success),
+            stdout_bytes,
+            stderr_bytes,
+        },
+    );
+}
+
+/// Record in the audit log that a call was refused before `git_args` ran.
+fn audit_refusal(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    (git_args, purpose): (Vec<String>, &str),
+    reason: &str,
+) {
+    let now = now();
+    audit::write(
+        state,
+        audit::Record {
+            request_id: reply_to.id(),
+            caller: reply_to.caller(),
+            repository_path: repository_path.to_string(),
+            args: git_args,
+            purpose: purpose.to_string(),
+            decision: audit::Decision::Refused,
+            reason: Some(reason.to_string()),
+            started_ms: now,
+            finished_ms: Some(now),
+            exit_code: None,
+            success: None,
+            stdout_bytes: 0,
+            stderr_bytes: 0,
+        },
+    );
+}
+
+/// What a child was spawned for, as recorded in the audit log.
+fn purpose(kind: &PendingKind) -> String {
+    match kind {
+        PendingKind::ToolCall { .. } | PendingKind::Preview { .. } => "git-command".to_string(),
+        PendingKind::TypedToolCall { name, .. } => name.clone(),
+        PendingKind::Prompt { name, .. } => format!("prompt {}", name),
+        PendingKind::Completion { .. } => "completion".to_string(),
+        PendingKind::Configure { .. } => "configure".to_string(),
+        PendingKind::RepositoryStatus { .. } => "list-repositories".to_string(),
+        PendingKind::Guard { .. } => "protected branch check".to_string(),
+        PendingKind::Snapshot { .. } => "snapshot".to_string(),
+        PendingKind::Approval { .. } => "approval preview".to_string(),
+    }
+}
+
+/// Spawn `git_args` as `spawn_git_command` does, first refusing it, or any
+/// step of a typed tool still to come, if it would move or delete a
+/// protected branch. When that depends on the checked-out branch, the
+/// branch is read first and the check finishes in `handle_child_exit`.
+fn spawn_guarded(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    git_args: Vec<String>,
+    kind: PendingKind,
+) -> Result<(), String> {
+    if !state.protected_branches.is_empty() {
+        let needs_current = steps_of(&git_args, &kind).any(protected::needs_current);
+        if needs_current {
+            let guard = PendingKind::Guard {
+                git_args,
+                then: Box::new(kind),
+            };
+            let read_branch = ["symbolic-ref", "--quiet", "--short", "HEAD"]
+                .map(String::from)
+                .to_vec();
+            return spawn_git_command(state, reply_to, repository_path, read_branch, guard);
+        }
+        for step in steps_of(&git_args, &kind) {
+            protected::check_args(&state.protected_branches, step, None)?;
+        }
+    }
+    spawn_step(state, reply_to, repository_path, git_args, kind)
+}
+
+/// Spawn `git_args` as `spawn_git_command` does, after taking a safety
+/// snapshot if it can lose work. The snapshot is taken by children of its
+/// own, and `git_args` runs once it is saved.
+fn spawn_step(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    git_args: Vec<String>,
+    kind: PendingKind,
+) -> Result<(), String> {
+    match snapshots::start(state, repository_path, &git_args) {
+        Some((progress, step)) => {
+            let kind = PendingKind::Snapshot {
+                progress,
+                git_args,
+                then: Box::new(kind),
+            };
+            spawn_git_command(state, reply_to, repository_path, step, kind)
+        }
+        None => spawn_git_command(state, reply_to, repository_path, git_args, kind),
+    }
+}
+
+/// Error code for a call refused before its command ran: policy refusals of
+/// `git-command`, invalid arguments of typed tools.
+fn refusal_code(kind: &PendingKind) -> i32 {
+    match kind {
+        PendingKind::ToolCall { .. } => -32000,
+        _ => -32602,
+    }
+}
+
+/// `git_args` followed by the steps `kind` still has to run.
+fn steps_of<'a>(
+    git_args: &'a [String],
+    kind: &'a PendingKind,
+) -> impl Iterator<Item = &'a [String]> {
+    let remaining: &[Vec<String>] = match kind {
+        PendingKind::TypedToolCall { remaining, .. } => remaining,
+        PendingKind::Prompt { remaining, .. } => remaining,
+        _ => &[],
+    };
+    std::iter::once(git_args).chain(remaining.iter().map(Vec::as_slice))
+}
+
+/// Spawn a git-command child running `git_args` in `repository_path`, under
+/// the configured identity, and record what its result is for. The result
+/// arrives in `handle_child_exit`.
+fn spawn_git_command(
+    state: &mut State,
+    reply_to: &ReplyTo,
+    repository_path: &str,
+    git_args: Vec<String>,
+    kind: PendingKind,
+) -> Result<(), String> {
+    let request = PendingRequest {
+        reply_to: reply_to.clone(),
+        repository_path: repository_path.to_string(),
+        git_args,
+        kind,
+        started_ms: 0,
+    };
+    if !can_start(state, &request) {
+        log(&format!(
+            "Queueing git {:?} until a child slot is free",
+            request.git_args
+        ));
+        state.spawn_queue.push_back(request);
+        return Ok(());
+    }
+    start_child(state, request)
+}
+
+/// Whether `request` may start now: a slot is free under
+/// `limits.max_concurrency`, and if its call may write, no other call that
+/// may holds its repository.
+fn can_start(state: &State, request: &PendingRequest) -> bool {
+    running(state) < state.limits.max_concurrency()
+        && (!call_writes(&request.git_args, &request.kind)
+            || state
+                .repository_locks
+                .get(&request.repository_path)
+                .is_none_or(|holder| *holder == request.reply_to))
+}
+
+/// Whether running `git_args`, or a step after it, may write. Guards and
+/// snapshots count as part of the call they run before, so it holds its
+/// repository from the first of them on; approval previews only read.
+fn call_writes(git_args: &[String], kind: &PendingKind) -> bool {
+    match kind {
+        PendingKind::Guard { git_args, then } | PendingKind::Snapshot { git_args, then, .. } => {
+            call_writes(git_args, then)
+        }
+        PendingKind::Approval { .. } | PendingKind::Preview { .. } => false,
+        _ => steps_of(git_args, kind).any(|step| !git::is_read_only(step)),
+    }
+}
+
+/// Release the repositories of calls none of whose steps is running or
+/// waiting any more.
+fn release_locks(state: &mut State) {
+    let active: Vec<&ReplyTo> = state
+        .outstanding_requests
+        .values()
+        .chain(&state.spawn_queue)
+        .map(|request| &request.reply_to)
+        .collect();
+    state
+        .repository_locks
+        .retain(|_, holder| active.contains(&&*holder));
+}
+
+/// Outstanding requests with a running child, leaving out calls parked
+/// until the approver answers.
+fn children(state: &State) -> impl Iterator<Item = &PendingRequest> {
+    state.outstanding_requests.values().filter(|pending| {
+        !matches!(
+            pending.kind,
+            PendingKind::Approval {
+                expires_ms: Some(_),
+                ..
+            }
+        )
+    })
+}
+
+fn running(state: &State) -> usize {
+    children(state).count()
+}
+
+/// Start queued children, oldest first, for the slots that have freed up.
+/// Reads may pass a write that is waiting on its repository. A child that
+/// fails to start answers its request with the error.
+fn dispatch_queued(state: &mut State) {
+    expire_approvals(state);
+    release_locks(state);
+    let mut index = 0;
+    while index < state.spawn_queue.len() && running(state) < state.limits.max_concurrency() {
+        if !can_start(state, &state.spawn_queue[index]) {
+            index += 1;
+            continue;
+        }
+        let Some(request) = state.spawn_queue.remove(index) else {
+            break;
+        };
+        let reply_to = request.reply_to.clone();
+        let listing = match &request.kind {
+            PendingKind::RepositoryStatus { listing_id, name } => Some((*listing_id, name.clone())),
+            _ => None,
+        };
+        let Err(message) = start_child(state, request) else {
+            continue;
+        };
+        log(&format!("Failed to start queued child: {}", message));
+        let response = match listing {
+            Some((listing_id, name)) => {
+                match repos::record(state, listing_id, &name, Err(message)) {
+                    Some(statuses) => repositories_response(reply_to.id(), statuses),
+                    // Other repositories of the listing are still running
+                    None => continue,
+                }
+            }
+            None => Ok(error_response(reply_to.id(), -32000, message)),
+        };
+        if let Err(message) = response.and_then(|response| deliver(state, &reply_to, &response)) {
+            log(&format!("Failed to answer queued request: {}", message));
+        }
+    }
+}
+
+/// Spawn the git-command child for `request` and record it as outstanding.
+fn start_child(state: &mut State, request: PendingRequest) -> Result<(), String> {
+    let PendingRequest {
+        reply_to,
+        repository_path,
+        git_args,
+        kind,
+        ..
+    } = request;
+    let mut spawned_args = state.identity.apply(&git_args);
+    let mut child_init_state = json!({
+        "repository_path": repository_path,
+    });
+    // Nobody can answer a credential prompt, so remote calls fail instead
+    if git::is_network(&git_args) {
+        child_init_state["env"] = json!({ "GIT_TERMINAL_PROMPT": "0" });
+        let mut settings: Vec<String> = remotes::NON_INTERACTIVE
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        settings.extend(remotes::ssh_settings(&repository_path));
+        spawned_args.splice(0..0, settings);
+    }
+    child_init_state["git_args"] = json!(spawned_args);
+    if let Some(timeout_ms) = state.limits.timeout_ms {
+        child_init_state["timeout_ms"] = json!(timeout_ms);
+    }
+
+    log(&format!("Child init state: {}", child_init_state));
+
+    let child_init_state_bytes = serde_json::to_vec(&child_init_state)
+        .map_err(|e| format!("Failed to serialize child init state: {}", e))?;
+
+    let manifest = state
+        .git_command_manifest
+        .as_deref()
+        .unwrap_or(GIT_COMMAND_MANIFEST);
+    let actor_id = spawn(manifest, Some(&child_init_state_bytes))
+        .map_err(|e| format!("Failed to spawn git-command actor: {}", e))?;
+
+    if call_writes(&git_args, &kind) {
+        state
+            .repository_locks
+            .insert(repository_path.clone(), reply_to.clone());
+    }
+
+    state.outstanding_requests.insert(
+        actor_id,
+        PendingRequest {
+            reply_to,
+            repository_path,
+            git_args,
+            kind,
+            started_ms: now(),
+        },
+    );
+    Ok(())
+}
+
+impl HttpHandlers for Component {
+    fn handle_request(
+        state: Option<Vec<u8>>,
+        params: (HandlerId, HttpRequest),
+    ) -> Result<(Option<Vec<u8>>, (HttpResponse,)), String> {
+        let (_handler_id, request) = params;
+
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        // A client polling for a parked call's response learns it timed out
+        expire_approvals(&mut app_state);
+        let response = http::handle(&mut app_state, request)?;
+
+        notify_tools_changed(&mut app_state);
+
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state), (response,)))
+    }
+
+    fn handle_middleware(
+        state: Option<Vec<u8>>,
+        params: (HandlerId, HttpRequest),
+    ) -> Result<(Option<Vec<u8>>, (MiddlewareResult,)), String> {
+        let (_handler_id, request) = params;
+
+        let app_state = persist::decode(state.as_deref())?;
+
+        let proceed = http::authorize_upgrade(&app_state, &request);
+        if !proceed {
+            log("Refused unauthorized WebSocket upgrade");
+        }
+
+        Ok((state, (MiddlewareResult { proceed, request },)))
+    }
+
+    fn handle_websocket_connect(
+        state: Option<Vec<u8>>,
+        params: (HandlerId, u64, String, Option<String>),
+    ) -> Result<(Option<Vec<u8>>,), String> {
+        let (_handler_id, connection_id, _path, _protocol) = params;
+
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        websocket::connect(&mut app_state, connection_id);
+
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state),))
+    }
+
+    fn handle_websocket_message(
+        state: Option<Vec<u8>>,
+        params: (HandlerId, u64, WebsocketMessage),
+    ) -> Result<(Option<Vec<u8>>, (Vec<WebsocketMessage>,)), String> {
+        let (_handler_id, connection_id, message) = params;
+
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        let replies = websocket::message(&mut app_state, connection_id, message)?;
+
+        notify_tools_changed(&mut app_state);
+
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state), (replies,)))
+    }
+
+    fn handle_websocket_disconnect(
+        state: Option<Vec<u8>>,
+        params: (HandlerId, u64),
+    ) -> Result<(Option<Vec<u8>>,), String> {
+        let (_handler_id, connection_id) = params;
+
+        let mut app_state = persist::decode(state.as_deref())?;
+
+        websocket::disconnect(&mut app_state, connection_id);
+
+        let updated_state = persist::encode(&app_state)?;
+        Ok((Some(updated_state),))
     }
 }
 
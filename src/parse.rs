@@ -0,0 +1,897 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Output of `status --porcelain=v2 --branch -z`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Status {
+    pub branch: BranchStatus,
+    pub entries: Vec<StatusEntry>,
+    pub clean: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BranchStatus {
+    /// Current branch, or `None` when HEAD is detached.
+    pub head: Option<String>,
+    /// Commit HEAD points at, or `None` before the first commit.
+    pub oid: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusEntry {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+    /// "changed", "renamed", "unmerged", "untracked" or "ignored".
+    pub kind: String,
+    /// Status of the path in the index, e.g. "M", "A", "D", or "." if unchanged.
+    pub index: String,
+    /// Status of the path in the working tree, as for `index`.
+    pub worktree: String,
+}
+
+pub fn status(stdout: &str) -> Status {
+    let mut status = Status::default();
+    let mut fields = stdout.split('\0').filter(|field| !field.is_empty());
+
+    while let Some(field) = fields.next() {
+        if let Some(header) = field.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" if value != "(initial)" => status.branch.oid = Some(value.into()),
+                "branch.head" if value != "(detached)" => status.branch.head = Some(value.into()),
+                "branch.upstream" => status.branch.upstream = Some(value.into()),
+                "branch.ab" => {
+                    let mut counts = value.split(' ');
+                    status.branch.ahead = counts.next().and_then(|c| c[1..].parse().ok());
+                    status.branch.behind = counts.next().and_then(|c| c[1..].parse().ok());
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let (kind, rest) = field.split_once(' ').unwrap_or((field, ""));
+        let entry = match kind {
+            // 1 XY sub mH mI mW hH hI path
+            "1" => status_entry("changed", rest, 6, None),
+            // 2 XY sub mH mI mW hH hI Xscore path, followed by the original path
+            "2" => status_entry("renamed", rest, 7, fields.next()),
+            // u XY sub m1 m2 m3 mW h1 h2 h3 path
+            "u" => status_entry("unmerged", rest, 8, None),
+            "?" => StatusEntry {
+                path: rest.to_string(),
+                original_path: None,
+                kind: "untracked".to_string(),
+                index: "?".to_string(),
+                worktree: "?".to_string(),
+            },
+            "!" => StatusEntry {
+                path: rest.to_string(),
+                original_path: None,
+                kind: "ignored".to_string(),
+                index: "!".to_string(),
+                worktree: "!".to_string(),
+            },
+            _ => continue,
+        };
+        status.entries.push(entry);
+    }
+
+    status.clean = status.entries.iter().all(|entry| entry.kind == "ignored");
+    status
+}
+
+/// Build an entry from `XY <skip fields> path`.
+fn status_entry(kind: &str, rest: &str, skip: usize, original_path: Option<&str>) -> StatusEntry {
+    let mut parts = rest.splitn(skip + 2, ' ');
+    let xy = parts.next().unwrap_or("..");
+    let path = parts.nth(skip).unwrap_or_default();
+    let (index, worktree) = xy.split_at(xy.len().min(1));
+
+    StatusEntry {
+        path: path.to_string(),
+        original_path: original_path.map(str::to_string),
+        kind: kind.to_string(),
+        index: index.to_string(),
+        worktree: worktree.to_string(),
+    }
+}
+
+/// Pretty format understood by `log`: fields separated by 0x1f, records by 0x1e.
+pub const LOG_FORMAT: &str = "--format=%H%x1f%P%x1f%an%x1f%ae%x1f%aI%x1f%s%x1f%b%x1e";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Commit {
+    pub sha: String,
+    pub parents: Vec<String>,
+    pub author_name: String,
+    pub author_email: String,
+    /// Author date in strict ISO 8601.
+    pub date: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub fn log(stdout: &str) -> Vec<Commit> {
+    stdout
+        .split('\x1e')
+        .map(|record| record.trim_start_matches('\n'))
+        .filter(|record| !record.is_empty())
+        .filter_map(|record| {
+            let fields: Vec<&str> = record.split('\x1f').collect();
+            let [sha, parents, author_name, author_email, date, subject, body] = fields[..] else {
+                return None;
+            };
+            Some(Commit {
+                sha: sha.to_string(),
+                parents: parents.split_whitespace().map(str::to_string).collect(),
+                author_name: author_name.to_string(),
+                author_email: author_email.to_string(),
+                date: date.to_string(),
+                subject: subject.to_string(),
+                body: body.trim_end().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Diff {
+    pub files: Vec<FileDiff>,
+    pub additions: u32,
+    pub deletions: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileDiff {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// "added", "deleted", "renamed" or "modified".
+    pub status: String,
+    pub binary: bool,
+    pub additions: u32,
+    pub deletions: u32,
+    /// Everything before the first hunk: `diff --git`, mode and `---`/`+++` lines.
+    #[serde(skip)]
+    pub header: String,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hunk {
+    /// Stable id derived from the file and hunk content.
+    pub id: String,
+    /// The `@@ -a,b +c,d @@` line.
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    /// Hunk body without the header line.
+    pub content: String,
+}
+
+/// Parse the output of `diff --patch`.
+pub fn diff(stdout: &str) -> Diff {
+    let mut diff = Diff::default();
+    let mut file: Option<FileDiff> = None;
+    let mut hunk: Option<(String, String)> = None;
+
+    for line in stdout.lines() {
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            if let Some(mut done) = file.take() {
+                finish_hunk(&mut done, hunk.take());
+                diff.files.push(done);
+            }
+            // Fallback for binary files, which have no ---/+++ lines
+            let path = names
+                .split_once(" b/")
+                .map(|(_, b)| b)
+                .unwrap_or(names)
+                .to_string();
+            file = Some(FileDiff {
+                path,
+                status: "modified".to_string(),
+                header: format!("{}\n", line),
+                ..FileDiff::default()
+            });
+            continue;
+        }
+
+        let Some(current) = file.as_mut() else {
+            continue;
+        };
+
+        if line.starts_with("@@") {
+            finish_hunk(current, hunk.take());
+            hunk = Some((line.to_string(), String::new()));
+        } else if let Some((_, body)) = hunk.as_mut() {
+            match line.chars().next() {
+                Some('+') => current.additions += 1,
+                Some('-') => current.deletions += 1,
+                _ => {}
+            }
+            body.push_str(line);
+            body.push('\n');
+        } else {
+            current.header.push_str(line);
+            current.header.push('\n');
+            if line.starts_with("new file mode") {
+                current.status = "added".to_string();
+            } else if line.starts_with("deleted file mode") {
+                current.status = "deleted".to_string();
+            } else if let Some(from) = line.strip_prefix("rename from ") {
+                current.status = "renamed".to_string();
+                current.old_path = Some(unquote(from));
+            } else if let Some(to) = line.strip_prefix("rename to ") {
+                current.path = unquote(to);
+            } else if let Some(old) = line.strip_prefix("--- ") {
+                if let Some(old) = old.strip_prefix("a/") {
+                    current.path = unquote(old);
+                }
+            } else if let Some(new) = line.strip_prefix("+++ ") {
+                if let Some(new) = new.strip_prefix("b/") {
+                    current.path = unquote(new);
+                }
+            } else if line.starts_with("Binary files") || line == "GIT binary patch" {
+                current.binary = true;
+            }
+        }
+    }
+
+    if let Some(mut done) = file.take() {
+        finish_hunk(&mut done, hunk.take());
+        diff.files.push(done);
+    }
+
+    diff.additions = diff.files.iter().map(|file| file.additions).sum();
+    diff.deletions = diff.files.iter().map(|file| file.deletions).sum();
+    diff
+}
+
+fn finish_hunk(file: &mut FileDiff, hunk: Option<(String, String)>) {
+    let Some((header, content)) = hunk else {
+        return;
+    };
+
+    // @@ -old_start[,old_lines] +new_start[,new_lines] @@ section
+    let mut ranges = header
+        .trim_start_matches('@')
+        .split_whitespace()
+        .take(2)
+        .map(|range| {
+            let range = &range[1..];
+            let (start, lines) = range.split_once(',').unwrap_or((range, "1"));
+            (start.parse().unwrap_or(0), lines.parse().unwrap_or(0))
+        });
+    let (old_start, old_lines) = ranges.next().unwrap_or((0, 0));
+    let (new_start, new_lines) = ranges.next().unwrap_or((0, 0));
+
+    file.hunks.push(Hunk {
+        id: fingerprint(&[&file.path, &header, &content]),
+        header,
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        content,
+    });
+}
+
+/// Paths with unusual characters are quoted by git.
+fn unquote(path: &str) -> String {
+    path.trim_matches('"').to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlameLine {
+    pub line: u32,
+    pub sha: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Author time in seconds since the epoch.
+    pub author_time: u64,
+    pub summary: String,
+    pub content: String,
+}
+
+/// Parse the output of `blame --porcelain`.
+pub fn blame(stdout: &str) -> Vec<BlameLine> {
+    // Commit details are only printed the first time a commit appears
+    let mut commits: HashMap<String, (String, String, u64, String)> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current: Option<(String, u32)> = None;
+
+    for line in stdout.lines() {
+        if let Some(content) = line.strip_prefix('\t') {
+            if let Some((sha, line)) = current.take() {
+                let (author_name, author_email, author_time, summary) =
+                    commits.get(&sha).cloned().unwrap_or_default();
+                lines.push(BlameLine {
+                    line,
+                    sha,
+                    author_name,
+                    author_email,
+                    author_time,
+                    summary,
+                    content: content.to_string(),
+                });
+            }
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match current.as_ref() {
+            None => {
+                // <sha> <original line> <final line> [<lines in group>]
+                let final_line = value.split(' ').nth(1).and_then(|l| l.parse().ok());
+                current = Some((key.to_string(), final_line.unwrap_or(0)));
+                commits.entry(key.to_string()).or_default();
+            }
+            Some((sha, _)) => {
+                let commit = commits.entry(sha.clone()).or_default();
+                match key {
+                    "author" => commit.0 = value.to_string(),
+                    "author-mail" => {
+                        commit.1 = value.trim_matches(|c| c == '<' || c == '>').to_string()
+                    }
+                    "author-time" => commit.2 = value.parse().unwrap_or(0),
+                    "summary" => commit.3 = value.to_string(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+/// Pretty format for the commit a tool just made, followed by `--shortstat`.
+pub const COMMIT_FORMAT: &str = "--format=%H%x1f%s";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CommitSummary {
+    pub sha: String,
+    pub subject: String,
+    pub files_changed: u32,
+    pub insertions: u32,
+    pub deletions: u32,
+}
+
+/// Output of `log -1 --shortstat` with `COMMIT_FORMAT`.
+pub fn commit_summary(stdout: &str) -> CommitSummary {
+    let mut lines = stdout.lines();
+    let (sha, subject) = lines
+        .next()
+        .and_then(|line| line.split_once('\x1f'))
+        .unwrap_or_default();
+    let mut summary = CommitSummary {
+        sha: sha.to_string(),
+        subject: subject.to_string(),
+        ..CommitSummary::default()
+    };
+
+    // " 2 files changed, 3 insertions(+), 1 deletion(-)", absent for empty commits
+    let stat = lines.find(|line| line.contains(" changed")).unwrap_or("");
+    for part in stat.split(',') {
+        let mut words = part.split_whitespace();
+        let (Some(count), Some(what)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Ok(count) = count.parse() else {
+            continue;
+        };
+        match what {
+            "file" | "files" => summary.files_changed = count,
+            what if what.starts_with("insertion") => summary.insertions = count,
+            what if what.starts_with("deletion") => summary.deletions = count,
+            _ => {}
+        }
+    }
+    summary
+}
+
+/// `for-each-ref` format for local branches and remote HEADs: fields
+/// separated by 0x1f, one ref per line.
+pub const BRANCH_FORMAT: &str = "--format=%(refname)%1f%(refname:short)%1f%(objectname)%1f%(upstream:short)%1f%(upstream:track,nobracket)%1f%(committerdate:iso-strict)%1f%(authorname)%1f%(authoremail:trim)%1f%(HEAD)%1f%(symref:short)";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Branch {
+    pub name: String,
+    pub sha: String,
+    /// Whether HEAD is on this branch.
+    pub current: bool,
+    pub upstream: Option<String>,
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+    /// The upstream is configured but no longer exists.
+    pub upstream_gone: bool,
+    /// Committer date of the tip, in strict ISO 8601.
+    pub date: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Whether the tip is reachable from the default branch.
+    pub merged: bool,
+}
+
+/// Local branches in the output of `for-each-ref` with `BRANCH_FORMAT`.
+pub fn branches(stdout: &str) -> Vec<Branch> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\x1f').collect();
+            let [
+                refname,
+                name,
+                sha,
+                upstream,
+                track,
+                date,
+                author_name,
+                author_email,
+                head,
+                _,
+            ] = fields[..]
+            else {
+                return None;
+            };
+            if !refname.starts_with("refs/heads/") {
+                return None;
+            }
+
+            let mut branch = Branch {
+                name: name.to_string(),
+                sha: sha.to_string(),
+                current: head == "*",
+                upstream: (!upstream.is_empty()).then(|| upstream.to_string()),
+                upstream_gone: track == "gone",
+                date: date.to_string(),
+                author_name: author_name.to_string(),
+                author_email: author_email.to_string(),
+                ..Branch::default()
+            };
+            // "ahead 1, behind 2", either part alone, or nothing when in sync
+            if branch.upstream.is_some() && !branch.upstream_gone {
+                branch.ahead = Some(0);
+                branch.behind = Some(0);
+                for part in track.split(", ") {
+                    match part.split_once(' ') {
+                        Some(("ahead", count)) => branch.ahead = count.parse().ok(),
+                        Some(("behind", count)) => branch.behind = count.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            Some(branch)
+        })
+        .collect()
+}
+
+/// The default branch named by a remote HEAD in the same output, preferring
+/// `origin`, or else `main` or `master` if they exist.
+pub fn default_branch(stdout: &str) -> Option<String> {
+    let mut remote_heads: Vec<(&str, &str)> = stdout
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\x1f').collect();
+            let refname = fields.first()?;
+            let target = fields.last()?;
+            let remote = refname
+                .strip_prefix("refs/remotes/")?
+                .strip_suffix("/HEAD")?;
+            let branch = target.strip_prefix(remote)?.strip_prefix('/')?;
+            Some((remote, branch))
+        })
+        .collect();
+    remote_heads.sort_by_key(|(remote, _)| *remote != "origin");
+    if let Some((_, branch)) = remote_heads.first() {
+        return Some(branch.to_string());
+    }
+
+    let names: Vec<String> = branches(stdout)
+        .into_iter()
+        .map(|branch| branch.name)
+        .collect();
+    ["main", "master"]
+        .into_iter()
+        .find(|name| names.iter().any(|existing| existing == name))
+        .map(str::to_string)
+}
+
+/// `for-each-ref` format for tags: fields separated by 0x1f, records by 0x1e.
+pub const TAG_FORMAT: &str = "--format=%(refname:short)%1f%(objecttype)%1f%(objectname)%1f%(*objectname)%1f%(taggername)%1f%(taggeremail:trim)%1f%(taggerdate:iso-strict)%1f%(contents)%1e";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Tag {
+    pub name: String,
+    /// Semantic version the name reads as, without any `v` prefix.
+    pub version: Option<String>,
+    /// Object the tag points at, through the tag object if annotated.
+    pub sha: String,
+    pub annotated: bool,
+    pub tagger_name: Option<String>,
+    pub tagger_email: Option<String>,
+    /// Tagger date in strict ISO 8601.
+    pub date: Option<String>,
+    /// Annotation message, for annotated tags.
+    pub message: Option<String>,
+}
+
+pub fn tags(stdout: &str) -> Vec<Tag> {
+    stdout
+        .split('\x1e')
+        .map(|record| record.trim_start_matches('\n'))
+        .filter(|record| !record.is_empty())
+        .filter_map(|record| {
+            let fields: Vec<&str> = record.split('\x1f').collect();
+            let [
+                name,
+                kind,
+                object,
+                peeled,
+                tagger_name,
+                tagger_email,
+                date,
+                contents,
+            ] = fields[..]
+            else {
+                return None;
+            };
+            let annotated = kind == "tag";
+            let present = |field: &str| (annotated && !field.is_empty()).then(|| field.to_string());
+            Some(Tag {
+                name: name.to_string(),
+                version: None,
+                sha: match peeled.is_empty() {
+                    true => object.to_string(),
+                    false => peeled.to_string(),
+                },
+                annotated,
+                tagger_name: present(tagger_name),
+                tagger_email: present(tagger_email),
+                date: present(date),
+                message: present(contents.trim_end()),
+            })
+        })
+        .collect()
+}
+
+/// `for-each-ref` format for a snapshot of refs: `*` on the current branch,
+/// then the object id and the full ref name.
+pub const REFS_FORMAT: &str = "--format=%(HEAD)%(objectname) %(refname)";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Refs {
+    /// Current branch, or `None` when HEAD is detached.
+    pub head: Option<String>,
+    pub refs: BTreeMap<String, String>,
+}
+
+pub fn refs(stdout: &str) -> Refs {
+    let mut refs = Refs::default();
+    for line in stdout.lines() {
+        let Some((marker, rest)) = line.split_at_checked(1) else {
+            continue;
+        };
+        let Some((sha, name)) = rest.split_once(' ') else {
+            continue;
+        };
+        if marker == "*" {
+            refs.head = name.strip_prefix("refs/heads/").map(str::to_string);
+        }
+        refs.refs.insert(name.to_string(), sha.to_string());
+    }
+    refs
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefUpdate {
+    pub name: String,
+    /// `None` for a ref that was created.
+    pub old: Option<String>,
+    /// `None` for a ref that was deleted.
+    pub new: Option<String>,
+}
+
+/// Refs that differ between two snapshots.
+pub fn ref_updates(before: &Refs, after: &Refs) -> Vec<RefUpdate> {
+    let names: BTreeSet<&String> = before.refs.keys().chain(after.refs.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.refs.get(name);
+            let new = after.refs.get(name);
+            (old != new).then(|| RefUpdate {
+                name: name.clone(),
+                old: old.cloned(),
+                new: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// 64-bit FNV-1a over `parts`, as 16 hex digits. Used for ids that must stay
+/// the same across calls, not for anything security sensitive.
+pub fn fingerprint(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.bytes().chain([0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_reads_porcelain_v2() {
+        let stdout = "# branch.oid acf372f20fe2aa1fb02895b8ede86688c092f821\0\
+             # branch.head main\0\
+             # branch.upstream origin/main\0\
+             # branch.ab +2 -1\0\
+             1 M. N... 100644 100644 100644 4cb29ea38f70d7c61b2a3a25b02e3bdf44905402 ea14db2cbfbc66490839aedb0930134deee503ad a.txt\0\
+             2 RM N... 100644 100644 100644 3367afdbbf91e638efe983616377c60477cc6612 3367afdbbf91e638efe983616377c60477cc6612 R100 new name.txt\0old.txt\0\
+             ? untracked.txt\0";
+        let status = status(stdout);
+        assert_eq!(status.branch.head.as_deref(), Some("main"));
+        assert_eq!(status.branch.upstream.as_deref(), Some("origin/main"));
+        assert_eq!(
+            (status.branch.ahead, status.branch.behind),
+            (Some(2), Some(1))
+        );
+        assert!(!status.clean);
+
+        let entries: Vec<(&str, &str, &str, &str)> = status
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.kind.as_str(),
+                    entry.path.as_str(),
+                    entry.index.as_str(),
+                    entry.worktree.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("changed", "a.txt", "M", "."),
+                ("renamed", "new name.txt", "R", "M"),
+                ("untracked", "untracked.txt", "?", "?"),
+            ]
+        );
+        assert_eq!(status.entries[1].original_path.as_deref(), Some("old.txt"));
+    }
+
+    #[test]
+    fn status_of_a_new_repository_with_detached_head() {
+        let status = status("# branch.oid (initial)\0# branch.head (detached)\0");
+        assert_eq!(status.branch.oid, None);
+        assert_eq!(status.branch.head, None);
+        assert!(status.clean);
+    }
+
+    #[test]
+    fn log_splits_records_and_fields() {
+        let stdout = "acf372f\x1fp1 p2\x1fAda Lovelace\x1fada@example.com\x1f2023-11-14T22:13:20+00:00\x1fAdd files\x1fBody line\n\n\x1e\n\
+             b0b0b0b\x1f\x1fAda Lovelace\x1fada@example.com\x1f2023-11-13T22:13:20+00:00\x1fRoot\x1f\x1e\n";
+        let commits = log(stdout);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].parents, ["p1", "p2"]);
+        assert_eq!(commits[0].subject, "Add files");
+        assert_eq!(commits[0].body, "Body line");
+        assert!(commits[1].parents.is_empty());
+        assert_eq!(commits[1].body, "");
+    }
+
+    #[test]
+    fn diff_reads_hunks_renames_and_binaries() {
+        let stdout = "\
+diff --git a/a.txt b/a.txt
+index 4cb29ea..ea14db2 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,4 @@
+ one
+-two
++2
+ three
++four
+diff --git a/old.txt b/new.txt
+similarity index 100%
+rename from old.txt
+rename to new.txt
+diff --git a/logo.png b/logo.png
+new file mode 100644
+index 0000000..e69de29
+Binary files /dev/null and b/logo.png differ
+";
+        let diff = diff(stdout);
+        assert_eq!((diff.additions, diff.deletions), (2, 1));
+        assert_eq!(diff.files.len(), 3);
+
+        let text = &diff.files[0];
+        assert_eq!(
+            (text.path.as_str(), text.status.as_str()),
+            ("a.txt", "modified")
+        );
+        assert_eq!(text.hunks.len(), 1);
+        let hunk = &text.hunks[0];
+        assert_eq!(
+            (
+                hunk.old_start,
+                hunk.old_lines,
+                hunk.new_start,
+                hunk.new_lines
+            ),
+            (1, 3, 1, 4)
+        );
+        assert_eq!(hunk.content, " one\n-two\n+2\n three\n+four\n");
+        // Ids stay the same for the same hunk
+        assert_eq!(hunk.id, super::diff(stdout).files[0].hunks[0].id);
+
+        let renamed = &diff.files[1];
+        assert_eq!(renamed.status, "renamed");
+        assert_eq!(renamed.path, "new.txt");
+        assert_eq!(renamed.old_path.as_deref(), Some("old.txt"));
+        assert!(renamed.hunks.is_empty());
+
+        let binary = &diff.files[2];
+        assert_eq!(
+            (binary.path.as_str(), binary.status.as_str()),
+            ("logo.png", "added")
+        );
+        assert!(binary.binary);
+    }
+
+    #[test]
+    fn hunk_ranges_default_to_one_line() {
+        let diff = diff("diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -3 +3,0 @@\n-gone\n");
+        let hunk = &diff.files[0].hunks[0];
+        assert_eq!(
+            (
+                hunk.old_start,
+                hunk.old_lines,
+                hunk.new_start,
+                hunk.new_lines
+            ),
+            (3, 1, 3, 0)
+        );
+    }
+
+    #[test]
+    fn blame_reuses_details_of_commits_seen_before() {
+        let stdout = "\
+acf372f20fe2aa1fb02895b8ede86688c092f821 1 1 2
+author Ada Lovelace
+author-mail <ada@example.com>
+author-time 1700000000
+author-tz +0000
+summary Add files
+boundary
+filename a.txt
+\tone
+acf372f20fe2aa1fb02895b8ede86688c092f821 2 2
+\ttwo
+";
+        let lines = blame(stdout);
+        assert_eq!(lines.len(), 2);
+        for (index, line) in lines.iter().enumerate() {
+            assert_eq!(line.line, index as u32 + 1);
+            assert_eq!(line.author_name, "Ada Lovelace");
+            assert_eq!(line.author_email, "ada@example.com");
+            assert_eq!(line.author_time, 1700000000);
+            assert_eq!(line.summary, "Add files");
+        }
+        assert_eq!(lines[1].content, "two");
+    }
+
+    #[test]
+    fn commit_summary_reads_shortstat() {
+        let summary = commit_summary(
+            "abc123\x1fAdd files\n\n 2 files changed, 3 insertions(+), 1 deletion(-)\n",
+        );
+        assert_eq!(summary.sha, "abc123");
+        assert_eq!(summary.subject, "Add files");
+        assert_eq!(
+            (summary.files_changed, summary.insertions, summary.deletions),
+            (2, 3, 1)
+        );
+    }
+
+    fn branch_line(
+        refname: &str,
+        short: &str,
+        upstream: &str,
+        track: &str,
+        head: &str,
+        symref: &str,
+    ) -> String {
+        [
+            refname,
+            short,
+            "abc123",
+            upstream,
+            track,
+            "2023-11-14T22:13:20+00:00",
+            "Ada",
+            "ada@example.com",
+            head,
+            symref,
+        ]
+        .join("\x1f")
+    }
+
+    #[test]
+    fn branches_read_tracking_and_default_branch() {
+        let stdout = [
+            branch_line(
+                "refs/heads/main",
+                "main",
+                "origin/main",
+                "ahead 1, behind 2",
+                "*",
+                "",
+            ),
+            branch_line("refs/heads/topic", "topic", "origin/topic", "gone", " ", ""),
+            branch_line("refs/heads/synced", "synced", "origin/synced", "", " ", ""),
+            branch_line(
+                "refs/remotes/origin/HEAD",
+                "origin",
+                "",
+                "",
+                " ",
+                "origin/trunk",
+            ),
+        ]
+        .join("\n");
+        let branches = branches(&stdout);
+        assert_eq!(branches.len(), 3);
+        assert!(branches[0].current);
+        assert_eq!((branches[0].ahead, branches[0].behind), (Some(1), Some(2)));
+        assert!(branches[1].upstream_gone);
+        assert_eq!(branches[1].ahead, None);
+        assert_eq!((branches[2].ahead, branches[2].behind), (Some(0), Some(0)));
+        assert_eq!(default_branch(&stdout).as_deref(), Some("trunk"));
+        assert_eq!(
+            default_branch(&branch_line("refs/heads/master", "master", "", "", "*", "")).as_deref(),
+            Some("master")
+        );
+    }
+
+    #[test]
+    fn ref_updates_list_created_moved_and_deleted_refs() {
+        let before = refs("*aaa refs/heads/main\n bbb refs/heads/old\n");
+        let after = refs("*ccc refs/heads/main\n ddd refs/heads/new\n");
+        assert_eq!(before.head.as_deref(), Some("main"));
+        let updates = ref_updates(&before, &after);
+        let updates: Vec<(&str, Option<&str>, Option<&str>)> = updates
+            .iter()
+            .map(|update| {
+                (
+                    update.name.as_str(),
+                    update.old.as_deref(),
+                    update.new.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            updates,
+            [
+                ("refs/heads/main", Some("aaa"), Some("ccc")),
+                ("refs/heads/new", None, Some("ddd")),
+                ("refs/heads/old", Some("bbb"), None),
+            ]
+        );
+    }
+}
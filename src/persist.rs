@@ -0,0 +1,89 @@
+use crate::State;
+use serde_json::Value;
+use serde_json::json;
+
+/// Layout version written with every state. Bump it and add a migration to
+/// `MIGRATIONS` whenever a change to `State` cannot be read by `serde(default)`
+/// alone.
+pub const VERSION: u64 = 1;
+
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` state to version `n + 1`.
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// Whether `bytes` hold a saved `State` rather than an init configuration,
+/// as when an actor is resumed from stored state.
+pub fn is_saved(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(bytes).is_ok_and(|value| {
+        value.get("version").is_some() || value.get("outstanding_requests").is_some()
+    })
+}
+
+/// Decode state bytes, upgrading older layouts. Anything that cannot be read
+/// is an error rather than a fresh state, so configuration is never dropped.
+pub fn decode(bytes: Option<&[u8]>) -> Result<State, String> {
+    let bytes = match bytes {
+        Some(bytes) if !bytes.is_empty() => bytes,
+        _ => return Err("Invalid state".to_string()),
+    };
+    let mut value: Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Failed to deserialize state: {}", e))?;
+
+    let version = match value.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("Invalid state version: {}", version))?,
+    };
+    if version > VERSION {
+        return Err(format!(
+            "State version {} is newer than this actor supports ({})",
+            version, VERSION
+        ));
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(&mut value)
+            .map_err(|e| format!("Failed to migrate state from version {}: {}", from, e))?;
+        value["version"] = json!(from as u64 + 1);
+    }
+
+    serde_json::from_value(value)
+        .map_err(|e| format!("Failed to deserialize state (version {}): {}", VERSION, e))
+}
+
+pub fn encode(state: &State) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(state).map_err(|e| format!("Failed to serialize state: {}", e))
+}
+
+/// Unversioned states track each outstanding child as either a bare request
+/// id or a pending request keyed by `request_id`, possibly without the
+/// repository and arguments it ran with.
+fn v0_to_v1(state: &mut Value) -> Result<(), String> {
+    let Some(outstanding) = state
+        .get_mut("outstanding_requests")
+        .and_then(Value::as_object_mut)
+    else {
+        return Ok(());
+    };
+
+    for (actor_id, pending) in outstanding.iter_mut() {
+        if let Value::String(request_id) = pending {
+            *pending = json!({ "request_id": request_id, "kind": { "type": "ToolCall" } });
+        }
+        let pending = pending
+            .as_object_mut()
+            .ok_or_else(|| format!("Outstanding request for {} is not an object", actor_id))?;
+        if let Some(request_id) = pending.remove("request_id") {
+            pending.insert(
+                "reply_to".to_string(),
+                json!({ "type": "Request", "request_id": request_id }),
+            );
+        }
+        pending
+            .entry("repository_path")
+            .or_insert_with(|| json!(""));
+        pending.entry("git_args").or_insert_with(|| json!([]));
+    }
+    Ok(())
+}
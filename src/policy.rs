@@ -0,0 +1,154 @@
+use crate::git;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Which git subcommands `git-command` may run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Policy {
+    /// Only allow subcommands that never modify the repository.
+    #[serde(default)]
+    pub read_only: bool,
+    /// When set, only these subcommands may run.
+    #[serde(default)]
+    pub allowed_subcommands: Option<Vec<String>>,
+    /// Subcommands that may never run.
+    #[serde(default)]
+    pub blocked_subcommands: Vec<String>,
+    /// Let typed tools use `force`, e.g. to delete unmerged branches, and
+    /// `force_with_lease` to overwrite remote branches.
+    #[serde(default)]
+    pub allow_force: bool,
+    /// Subcommands that only run once the configured approver agrees.
+    #[serde(default)]
+    pub requires_approval: Vec<String>,
+}
+
+/// MCP tool hints describing what a tool may do.
+#[derive(Debug, Clone, Copy)]
+pub struct Hints {
+    pub read_only: bool,
+    pub destructive: bool,
+    pub idempotent: bool,
+    pub open_world: bool,
+}
+
+impl Hints {
+    /// Hints for tools that only read from the repository.
+    pub const READ: Hints = Hints {
+        read_only: true,
+        destructive: false,
+        idempotent: true,
+        open_world: false,
+    };
+
+    /// Hints for a tool that may behave as either `self` or `other`.
+    pub fn either(self, other: Hints) -> Hints {
+        Hints {
+            read_only: self.read_only && other.read_only,
+            destructive: self.destructive || other.destructive,
+            idempotent: self.idempotent && other.idempotent,
+            open_world: self.open_world || other.open_world,
+        }
+    }
+}
+
+impl Policy {
+    /// Refuse `args` if their subcommand is not permitted.
+    pub fn check(&self, args: &[String]) -> Result<(), String> {
+        let Some(name) = git::subcommand(args) else {
+            return Ok(());
+        };
+
+        if self
+            .blocked_subcommands
+            .iter()
+            .any(|blocked| blocked == name)
+        {
+            return Err(format!("'git {}' is blocked by policy", name));
+        }
+        if let Some(allowed) = &self.allowed_subcommands
+            && !allowed.iter().any(|allowed| allowed == name)
+        {
+            return Err(format!(
+                "'git {}' is not in the allowed subcommands: {}",
+                name,
+                allowed.join(", ")
+            ));
+        }
+        if self.read_only
+            && let Some(option) = git::unsafe_option(args)
+        {
+            return Err(format!(
+                "'{}' can run commands or write files, which the read-only policy forbids",
+                option
+            ));
+        }
+        if self.read_only && !git::is_read_only(args) {
+            return Err(format!(
+                "'git {}' may modify the repository, which the read-only policy forbids",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuse a typed tool call asking for `force` or `force_with_lease`
+    /// unless this policy allows it.
+    pub fn check_force(&self, tool: &str, args: &Value) -> Result<(), String> {
+        let forced = ["force", "force_with_lease"]
+            .into_iter()
+            .find(|name| args.get(*name).and_then(Value::as_bool) == Some(true));
+        match forced {
+            Some(name) if !self.allow_force => {
+                Err(format!("'{}' on {} is not allowed by policy", name, tool))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether `args` must be approved before running.
+    pub fn needs_approval(&self, args: &[String]) -> bool {
+        git::subcommand(args).is_some_and(|name| {
+            self.requires_approval
+                .iter()
+                .any(|required| required == name)
+        })
+    }
+
+    fn permits(&self, subcommand: &git::Subcommand) -> bool {
+        let name = subcommand.name.to_string();
+        !self.blocked_subcommands.contains(&name)
+            && self
+                .allowed_subcommands
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(&name))
+            && (!self.read_only || subcommand.read_only)
+    }
+
+    /// Whether subcommands missing from the catalog can run, in which case
+    /// nothing can be promised about what `git-command` does.
+    fn permits_unknown(&self) -> bool {
+        !self.read_only
+            && self
+                .allowed_subcommands
+                .as_ref()
+                .is_none_or(|allowed| allowed.iter().any(|name| git::lookup(name).is_none()))
+    }
+
+    /// Hints for `git-command`, derived from the subcommands this policy lets it run.
+    pub fn hints(&self) -> Hints {
+        let unknown = self.permits_unknown();
+        let permitted: Vec<&git::Subcommand> = git::SUBCOMMANDS
+            .iter()
+            .filter(|subcommand| self.permits(subcommand))
+            .collect();
+
+        let read_only = !unknown && permitted.iter().all(|subcommand| subcommand.read_only);
+        Hints {
+            read_only,
+            destructive: unknown || permitted.iter().any(|subcommand| subcommand.destructive),
+            idempotent: read_only,
+            open_world: unknown || permitted.iter().any(|subcommand| subcommand.open_world),
+        }
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::git;
+use crate::parse;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json::json;
+
+/// How to read the output of a preview command.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+enum Reading {
+    /// `add`, `rm` or `mv` with `--dry-run`: `add 'path'`, `rm 'path'`,
+    /// `Renaming old to new`.
+    DryRunLines,
+    /// `clean --dry-run`: `Would remove path`.
+    WouldRemove,
+    /// `commit --dry-run --porcelain`: paths with a change to commit.
+    Staged,
+    /// `diff --name-status` and `stash show --name-status`.
+    NameStatus,
+    /// `apply --numstat`.
+    NumStat,
+    /// `push --dry-run --porcelain`.
+    Push,
+    /// `fetch --dry-run`, which reports on stderr.
+    Fetch,
+    /// `rev-parse`, one line per revision the changes point at.
+    Refs { changes: Vec<RefPlan> },
+    /// `log` with `parse::LOG_FORMAT`.
+    Commits,
+}
+
+/// A ref a command would change, with the `rev-parse` output lines giving
+/// its sha before and after.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RefPlan {
+    name: String,
+    old: Option<usize>,
+    new: Option<usize>,
+}
+
+/// A mutating git command and the read-only one that shows what it would
+/// change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Plan {
+    pub command: Vec<String>,
+    pub preview: Vec<String>,
+    /// Position of the command among a typed tool's steps.
+    #[serde(default)]
+    pub step: usize,
+    reading: Reading,
+}
+
+#[derive(Serialize, Debug)]
+struct FileChange {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_path: Option<String>,
+    change: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+struct RefChange {
+    name: String,
+    old: Option<String>,
+    new: Option<String>,
+    /// What git reported, e.g. `[new branch]` or `1a2b3c4..5d6e7f8`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn has(args: &[String], flags: &[&str]) -> bool {
+    args.iter().any(|arg| flags.contains(&arg.as_str()))
+}
+
+/// The value of `flags` in `args`, given as `--flag=value` or `flag value`.
+fn value_of<'a>(args: &'a [String], flags: &[&str]) -> Option<&'a str> {
+    args.iter().enumerate().find_map(|(index, arg)| {
+        flags.iter().find_map(|flag| match arg.strip_prefix(flag) {
+            Some("") => args.get(index + 1).map(String::as_str),
+            Some(value) if flag.starts_with("--") => value.strip_prefix('='),
+            _ => None,
+        })
+    })
+}
+
+/// Arguments after a `--`.
+fn paths(args: &[String]) -> Vec<String> {
+    args.iter()
+        .position(|arg| arg == "--")
+        .map(|index| args[index + 1..].to_vec())
+        .unwrap_or_default()
+}
+
+fn log(args: &[&str]) -> Vec<String> {
+    let mut preview = strings(&["log", parse::LOG_FORMAT]);
+    preview.extend(strings(args));
+    preview
+}
+
+fn name_status(args: &[&str]) -> Vec<String> {
+    let mut preview = strings(&["-c", "core.quotePath=false", "diff", "--name-status"]);
+    preview.extend(strings(args));
+    preview
+}
+
+fn rev_parse(revisions: &[&str]) -> Vec<String> {
+    let mut preview = vec!["rev-parse".to_string()];
+    preview.extend(strings(revisions));
+    preview
+}
+
+/// A ref `rev-parse` output line `line` would create.
+fn created(name: String, line: usize) -> RefPlan {
+    RefPlan {
+        name,
+        old: None,
+        new: Some(line),
+    }
+}
+
+/// A ref whose current sha is on `rev-parse` output line `line` that would
+/// be deleted.
+fn deleted(name: String, line: usize) -> RefPlan {
+    RefPlan {
+        name,
+        old: Some(line),
+        new: None,
+    }
+}
+
+fn unsupported(name: &str) -> String {
+    format!("dry_run is not supported for this form of 'git {}'", name)
+}
+
+/// How to preview `args` without changing anything: git's own `--dry-run`
+/// where it has one, otherwise a read-only command showing what would
+/// change. `None` for commands that change nothing anyway, an error for
+/// commands that cannot be previewed.
+pub fn plan(args: &[String]) -> Result<Option<Plan>, String> {
+    if git::is_read_only(args) {
+        return Ok(None);
+    }
+    let index = git::subcommand_index(args).ok_or("No git subcommand to preview")?;
+    let name = args[index].as_str();
+    let rest = &args[index + 1..];
+    let (operands, _) = git::operands(rest, git::OPTIONS_WITH_VALUE);
+    // The same command with extra flags
+    let native = |flags: &[&str]| {
+        let mut preview = vec![name.to_string()];
+        preview.extend(strings(flags));
+        preview.extend_from_slice(rest);
+        preview
+    };
+    let refs = |revisions: &[&str], changes: Vec<RefPlan>| {
+        (rev_parse(revisions), Reading::Refs { changes })
+    };
+    let in_progress = ["--continue", "--abort", "--quit", "--skip"];
+
+    let (preview, reading) = match name {
+        "add" | "rm" | "mv" => (native(&["--dry-run"]), Reading::DryRunLines),
+        "clean" => {
+            let mut preview = strings(&["-c", "core.quotePath=false"]);
+            preview.extend(native(&["--dry-run"]));
+            (preview, Reading::WouldRemove)
+        }
+        "commit" => (native(&["--dry-run", "--porcelain"]), Reading::Staged),
+        "apply" => {
+            // `--numstat` only reads the patch, unless these make git apply
+            // it as well
+            let writes = [
+                "--apply",
+                "--index",
+                "--cached",
+                "--3way",
+                "-3",
+                "--intent-to-add",
+                "-N",
+            ];
+            let mut preview = strings(&["apply", "--numstat"]);
+            preview.extend(
+                rest.iter()
+                    .filter(|arg| !writes.contains(&arg.as_str()))
+                    .cloned(),
+            );
+            (preview, Reading::NumStat)
+        }
+        "push" => (native(&["--dry-run", "--porcelain"]), Reading::Push),
+        "fetch" => (native(&["--dry-run"]), Reading::Fetch),
+        "pull" => {
+            let mut preview = strings(&["fetch", "--dry-run"]);
+            preview.extend(operands.iter().map(|operand| operand.to_string()));
+            (preview, Reading::Fetch)
+        }
+        "restore" => {
+            let staged = has(rest, &["-S", "--staged"]);
+            let worktree = has(rest, &["-W", "--worktree"]);
+            let mut diff: Vec<&str> = Vec::new();
+            let source = value_of(rest, &["-s", "--source"]);
+            match (staged && !worktree, source) {
+                (true, source) => diff.extend(["--cached", source.unwrap_or("HEAD")]),
+                (false, Some(source)) => diff.push(source),
+                (false, None) => {}
+            }
+            diff.push("--");
+            let paths = paths(rest);
+            diff.extend(match paths.is_empty() {
+                true => operands.clone(),
+                false => paths.iter().map(String::as_str).collect(),
+            });
+            (name_status(&diff), Reading::NameStatus)
+        }
+        "reset" => {
+            let revision = operands.first().copied().unwrap_or("HEAD");
+            let mut paths = paths(rest);
+            paths.extend(operands.iter().skip(1).map(|path| path.to_string()));
+            if has(rest, &["--hard", "--keep", "--merge"]) {
+                (name_status(&[revision]), Reading::NameStatus)
+            } else if !paths.is_empty() {
+                let mut diff = vec!["--cached", revision, "--"];
+                diff.extend(paths.iter().map(String::as_str));
+                (name_status(&diff), Reading::NameStatus)
+            } else {
+                let target = format!("{}^{{commit}}", revision);
+                refs(
+                    &["HEAD", &target],
+                    vec![RefPlan {
+                        name: "HEAD".to_string(),
+                        old: Some(0),
+                        new: Some(1),
+                    }],
+                )
+            }
+        }
+        "rebase" if has(rest, &in_progress) => return Err(unsupported(name)),
+        "rebase" => {
+            let branch = operands.get(1).copied().unwrap_or("HEAD");
+            let range = match (has(rest, &["--root"]), operands.first()) {
+                (true, _) => branch.to_string(),
+                (false, Some(upstream)) => format!("{}..{}", upstream, branch),
+                (false, None) => format!("@{{upstream}}..{}", branch),
+            };
+            (log(&["--reverse", &range]), Reading::Commits)
+        }
+        "merge" | "cherry-pick" | "revert" if has(rest, &in_progress) => {
+            return Err(unsupported(name));
+        }
+        "merge" => {
+            let mut preview = log(&["--reverse", "^HEAD"]);
+            preview.extend(operands.iter().map(|operand| operand.to_string()));
+            (preview, Reading::Commits)
+        }
+        "cherry-pick" | "revert" => {
+            let (operands, _) = git::operands(rest, &["-m", "--mainline", "-X", "--strategy"]);
+            let walk = match operands.iter().any(|operand| operand.contains("..")) {
+                true => "--reverse",
+                false => "--no-walk=unsorted",
+            };
+            let mut preview = log(&[walk]);
+            preview.extend(operands.iter().map(|operand| operand.to_string()));
+            (preview, Reading::Commits)
+        }
+        "checkout" | "switch" => {
+            let (operands, _) = git::operands(rest, &["-b", "-B", "-c", "-C", "--orphan"]);
+            let create = value_of(
+                rest,
+                &["-b", "-B", "-c", "-C", "--create", "--force-create"],
+            );
+            let paths = paths(rest);
+            match create {
+                Some(branch) => {
+                    let start = format!("{}^{{commit}}", operands.first().unwrap_or(&"HEAD"));
+                    refs(
+                        &[&start],
+                        vec![created(format!("refs/heads/{}", branch), 0)],
+                    )
+                }
+                None if !paths.is_empty() => {
+                    let mut diff: Vec<&str> = operands.first().into_iter().copied().collect();
+                    diff.push("--");
+                    diff.extend(paths.iter().map(String::as_str));
+                    (name_status(&diff), Reading::NameStatus)
+                }
+                None => {
+                    let target = operands.first().copied().unwrap_or("HEAD");
+                    (name_status(&["HEAD", target]), Reading::NameStatus)
+                }
+            }
+        }
+        "branch" => {
+            let listing = [
+                "-l",
+                "--list",
+                "-a",
+                "--all",
+                "-r",
+                "--remotes",
+                "-v",
+                "-vv",
+                "--verbose",
+                "--show-current",
+                "--merged",
+                "--no-merged",
+                "--contains",
+                "--no-contains",
+                "--points-at",
+            ];
+            let (operands, _) = git::operands(rest, &[]);
+            if has(rest, &["-d", "-D", "--delete"]) {
+                let names: Vec<String> = operands
+                    .iter()
+                    .map(|branch| format!("refs/heads/{}", branch))
+                    .collect();
+                let names_ref: Vec<&str> = names.iter().map(String::as_str).collect();
+                refs(
+                    &names_ref,
+                    names
+                        .iter()
+                        .enumerate()
+                        .map(|(line, name)| deleted(name.clone(), line))
+                        .collect(),
+                )
+            } else if has(rest, &["-m", "-M", "--move", "-c", "-C", "--copy"]) {
+                let moving = has(rest, &["-m", "-M", "--move"]);
+                let (old, new) = match operands[..] {
+                    [old, new] => (format!("refs/heads/{}", old), new),
+                    [new] => ("HEAD".to_string(), new),
+                    _ => return Err(unsupported(name)),
+                };
+                let mut changes = vec![created(format!("refs/heads/{}", new), 0)];
+                if moving && old != "HEAD" {
+                    changes.push(deleted(old.clone(), 0));
+                }
+                refs(&[&old], changes)
+            } else if operands.is_empty() || has(rest, &listing) {
+                return Ok(None);
+            } else if has(
+                rest,
+                &[
+                    "-u",
+                    "--set-upstream-to",
+                    "--unset-upstream",
+                    "--edit-description",
+                ],
+            ) || rest.iter().any(|arg| arg.starts_with("--set-upstream-to="))
+            {
+                return Err(unsupported(name));
+            } else {
+                let start = format!("{}^{{commit}}", operands.get(1).unwrap_or(&"HEAD"));
+                refs(
+                    &[&start],
+                    vec![created(format!("refs/heads/{}", operands[0]), 0)],
+                )
+            }
+        }
+        "tag" => {
+            let (operands, _) = git::operands(
+                rest,
+                &["-m", "--message", "-F", "--file", "-u", "--local-user"],
+            );
+            if has(rest, &["-d", "--delete"]) {
+                let names: Vec<String> = operands
+                    .iter()
+                    .map(|tag| format!("refs/tags/{}", tag))
+                    .collect();
+                let names_ref: Vec<&str> = names.iter().map(String::as_str).collect();
+                refs(
+                    &names_ref,
+                    names
+                        .iter()
+                        .enumerate()
+                        .map(|(line, name)| deleted(name.clone(), line))
+                        .collect(),
+                )
+            } else if operands.is_empty() || has(rest, &["-l", "--list", "-v", "--verify"]) {
+                return Ok(None);
+            } else {
+                let target = format!("{}^{{commit}}", operands.get(1).unwrap_or(&"HEAD"));
+                refs(
+                    &[&target],
+                    vec![created(format!("refs/tags/{}", operands[0]), 0)],
+                )
+            }
+        }
+        "update-ref" if has(rest, &["--stdin"]) => return Err(unsupported(name)),
+        "update-ref" => {
+            let (operands, _) = git::operands(rest, &["-m"]);
+            match (has(rest, &["-d"]), &operands[..]) {
+                (true, [reference, ..]) => {
+                    refs(&[reference], vec![deleted(reference.to_string(), 0)])
+                }
+                (false, [reference, new, ..]) => {
+                    refs(&[new], vec![created(reference.to_string(), 0)])
+                }
+                _ => return Err(unsupported(name)),
+            }
+        }
+        "stash" => {
+            let entry =
+                |operands: &[&str]| operands.get(1).copied().unwrap_or("stash@{0}").to_string();
+            match operands.first().copied() {
+                Some("list" | "show") => return Ok(None),
+                Some("drop") => (log(&["--no-walk", &entry(&operands)]), Reading::Commits),
+                Some("clear") => (
+                    strings(&["stash", "list", parse::LOG_FORMAT]),
+                    Reading::Commits,
+                ),
+                Some("apply" | "pop") => (
+                    strings(&[
+                        "stash",
+                        "show",
+                        "--name-status",
+                        "--include-untracked",
+                        &entry(&operands),
+                    ]),
+                    Reading::NameStatus,
+                ),
+                Some("store") => {
+                    let commit = operands.get(1).ok_or_else(|| unsupported(name))?;
+                    refs(&[commit], vec![created("refs/stash".to_string(), 0)])
+                }
+                None | Some("push" | "save") => (name_status(&["HEAD"]), Reading::NameStatus),
+                Some(_) => return Err(unsupported(name)),
+            }
+        }
+        _ => return Err(unsupported(name)),
+    };
+
+    // Global options such as `-C` apply to the preview as well
+    let mut preview = preview;
+    preview.splice(0..0, args[..index].iter().cloned());
+    Ok(Some(Plan {
+        command: args.to_vec(),
+        preview,
+        step: 0,
+        reading,
+    }))
+}
+
+/// Replace the mutating steps of a typed tool with their previews, dropping
+/// the read-only steps after the last of them, which would only read back
+/// changes that are not made. Returns the steps to run and the plans, empty
+/// when the tool changes nothing.
+pub fn steps(steps: Vec<Vec<String>>) -> Result<(Vec<Vec<String>>, Vec<Plan>), String> {
+    let mut plans = Vec::new();
+    let mut previewed = Vec::new();
+    for (step, args) in steps.into_iter().enumerate() {
+        match plan(&args)? {
+            Some(plan) => {
+                previewed.push(plan.preview.clone());
+                plans.push(Plan { step, ..plan });
+            }
+            None => previewed.push(args),
+        }
+    }
+    if let Some(last) = plans.last() {
+        previewed.truncate(last.step + 1);
+    }
+    Ok((previewed, plans))
+}
+
+/// Whether `preview` failed only because there is nothing to commit, which
+/// `commit --dry-run` reports with exit code 1, and which previews as no
+/// changes.
+pub fn nothing_to_commit(preview: &[String], exit_code: Option<i32>) -> bool {
+    exit_code == Some(1)
+        && git::subcommand(preview) == Some("commit")
+        && has(preview, &["--dry-run"])
+}
+
+/// The output of a preview: stdout, or stderr for commands such as
+/// `fetch --dry-run` that report there.
+pub fn output(stdout: &str, stderr: &str) -> String {
+    match stdout.trim().is_empty() {
+        true => stderr.to_string(),
+        false => stdout.to_string(),
+    }
+}
+
+/// The result of a dry run: for each plan, what its command would change,
+/// read from the output of its preview.
+pub fn result(plans: &[Plan], outputs: &[String]) -> Value {
+    let previews: Vec<Value> = plans
+        .iter()
+        .map(|plan| {
+            let output = outputs.get(plan.step).map(String::as_str).unwrap_or("");
+            let mut preview = json!({
+                "command": plan.command,
+                "preview": plan.preview,
+            });
+            match &plan.reading {
+                Reading::Refs { changes } => preview["refs"] = json!(ref_changes(changes, output)),
+                Reading::Push => preview["refs"] = json!(pushed(output)),
+                Reading::Fetch => preview["refs"] = json!(fetched(output)),
+                Reading::Commits => preview["commits"] = json!(parse::log(output)),
+                reading => preview["files"] = json!(file_changes(reading, output)),
+            }
+            preview
+        })
+        .collect();
+    json!({ "dry_run": true, "previews": previews })
+}
+
+/// Word for a `--name-status` or porcelain status letter.
+fn change(letter: char) -> &'static str {
+    match letter {
+        'A' => "add",
+        'D' => "delete",
+        'R' => "rename",
+        'C' => "copy",
+        'T' => "type_change",
+        'U' => "unmerged",
+        _ => "modify",
+    }
+}
+
+fn file_changes(reading: &Reading, output: &str) -> Vec<FileChange> {
+    let file = |path: &str, change| FileChange {
+        path: path.to_string(),
+        original_path: None,
+        change,
+    };
+    output
+        .lines()
+        .filter_map(|line| match reading {
+            Reading::DryRunLines => {
+                if let Some((old, new)) = line
+                    .strip_prefix("Renaming ")
+                    .and_then(|rest| rest.split_once(" to "))
+                {
+                    return Some(FileChange {
+                        path: new.to_string(),
+                        original_path: Some(old.to_string()),
+                        change: "rename",
+                    });
+                }
+                let (action, path) = line.split_once(' ')?;
+                let path = path.strip_prefix('\'')?.strip_suffix('\'')?;
+                match action {
+                    "add" => Some(file(path, "add")),
+                    "remove" | "rm" => Some(file(path, "delete")),
+                    _ => None,
+                }
+            }
+            Reading::WouldRemove => line
+                .strip_prefix("Would remove ")
+                .map(|path| file(path, "delete")),
+            Reading::Staged => {
+                let staged = line
+                    .chars()
+                    .next()
+                    .filter(|x| !matches!(x, ' ' | '?' | '!'))?;
+                let path = line.get(3..)?;
+                match path.split_once(" -> ") {
+                    Some((old, new)) => Some(FileChange {
+                        path: new.to_string(),
+                        original_path: Some(old.to_string()),
+                        change: change(staged),
+                    }),
+                    None => Some(file(path, change(staged))),
+                }
+            }
+            Reading::NameStatus => {
+                let mut fields = line.split('\t');
+                let status = fields.next()?.chars().next()?;
+                let first = fields.next()?;
+                match fields.next() {
+                    Some(second) => Some(FileChange {
+                        path: second.to_string(),
+                        original_path: Some(first.to_string()),
+                        change: change(status),
+                    }),
+                    None => Some(file(first, change(status))),
+                }
+            }
+            Reading::NumStat => line.splitn(3, '\t').nth(2).map(|path| file(path, "modify")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn ref_changes(changes: &[RefPlan], output: &str) -> Vec<RefChange> {
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+    let sha = |line: Option<usize>| {
+        line.and_then(|line| lines.get(line))
+            .map(|sha| sha.to_string())
+    };
+    changes
+        .iter()
+        .map(|change| RefChange {
+            name: change.name.clone(),
+            old: sha(change.old),
+            new: sha(change.new),
+            summary: None,
+        })
+        .collect()
+}
+
+/// Old and new shas in a summary such as `1a2b..3c4d` or `1a2b...3c4d`.
+fn range(summary: &str) -> (Option<String>, Option<String>) {
+    let range = summary
+        .split_once("...")
+        .or_else(|| summary.split_once(".."));
+    match range {
+        Some((old, new)) => (Some(old.to_string()), Some(new.to_string())),
+        None => (None, None),
+    }
+}
+
+/// Lines of `push --porcelain`: `<flag>\t<from>:<to>\t<summary>`.
+fn pushed(output: &str) -> Vec<RefChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let flag = fields.next()?;
+            let (_, name) = fields.next()?.split_once(':')?;
+            let summary = fields.next()?.trim();
+            // Refs that would not move
+            if flag == "=" {
+                return None;
+            }
+            let (old, new) = range(summary);
+            Some(RefChange {
+                name: name.to_string(),
+                old,
+                new,
+                summary: Some(summary.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Lines of `fetch --dry-run` such as ` * [new branch]  main -> origin/main`
+/// or `   1a2b..3c4d  main -> origin/main`.
+fn fetched(output: &str) -> Vec<RefChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" -> ")?;
+            let name = right
+                .split_whitespace()
+                .next()
+                .filter(|name| *name != "FETCH_HEAD")?;
+            let left = left.trim_start();
+            let left = match left.split_once(' ') {
+                Some((flag, rest)) if flag.len() == 1 => rest.trim_start(),
+                _ => left,
+            };
+            let summary = match left.strip_prefix('[') {
+                Some(rest) => format!("[{}]", rest.split_once(']')?.0),
+                None => left.split_whitespace().next()?.to_string(),
+            };
+            let (old, new) = range(&summary);
+            Some(RefChange {
+                name: name.to_string(),
+                old,
+                new,
+                summary: Some(summary),
+            })
+        })
+        .collect()
+}
+
+/// Output schema of a dry run.
+pub fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "dry_run": { "const": true },
+            "previews": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "array", "items": { "type": "string" }, "description": "The git command that would run" },
+                        "preview": { "type": "array", "items": { "type": "string" }, "description": "The read-only git command that previewed it" },
+                        "files": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "path": { "type": "string" },
+                                    "original_path": { "type": "string" },
+                                    "change": { "enum": ["add", "delete", "modify", "rename", "copy", "type_change", "unmerged"] }
+                                },
+                                "required": ["path", "change"]
+                            }
+                        },
+                        "refs": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "name": { "type": "string" },
+                                    "old": { "type": ["string", "null"], "description": "Sha before, null for a new ref or when git does not say" },
+                                    "new": { "type": ["string", "null"], "description": "Sha after, null for a deleted ref or when git does not say" },
+                                    "summary": { "type": "string" }
+                                },
+                                "required": ["name", "old", "new"]
+                            }
+                        },
+                        "commits": { "type": "array", "items": { "type": "object" }, "description": "Commits that would be replayed, merged, picked or dropped" }
+                    },
+                    "required": ["command", "preview"]
+                }
+            }
+        },
+        "required": ["dry_run", "previews"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan_of(args: &[&str]) -> Result<Option<Plan>, String> {
+        plan(&strings(args))
+    }
+
+    fn preview_of(args: &[&str]) -> Vec<String> {
+        plan_of(args)
+            .expect("previewable")
+            .expect("mutating")
+            .preview
+    }
+
+    #[test]
+    fn read_only_commands_have_no_plan() {
+        for args in [
+            &["status"][..],
+            &["log", "-5"],
+            &["branch"],
+            &["branch", "--list", "feature/*"],
+            &["tag", "-l"],
+            &["stash", "list"],
+        ] {
+            assert!(plan_of(args).unwrap().is_none(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn native_dry_runs() {
+        assert_eq!(
+            preview_of(&["add", "src/lib.rs"]),
+            ["add", "--dry-run", "src/lib.rs"]
+        );
+        assert_eq!(
+            preview_of(&["clean", "-fd"]),
+            ["-c", "core.quotePath=false", "clean", "--dry-run", "-fd"]
+        );
+        assert_eq!(
+            preview_of(&["commit", "-m", "Fix"]),
+            ["commit", "--dry-run", "--porcelain", "-m", "Fix"]
+        );
+        assert_eq!(
+            preview_of(&["push", "origin", "main"]),
+            ["push", "--dry-run", "--porcelain", "origin", "main"]
+        );
+        assert_eq!(
+            preview_of(&["pull", "--rebase", "origin", "main"]),
+            ["fetch", "--dry-run", "origin", "main"]
+        );
+    }
+
+    #[test]
+    fn global_options_apply_to_the_preview() {
+        assert_eq!(
+            preview_of(&["-C", "repo", "add", "a.txt"]),
+            ["-C", "repo", "add", "--dry-run", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn apply_preview_drops_options_that_write() {
+        assert_eq!(
+            preview_of(&["apply", "--index", "-3", "fix.patch"]),
+            ["apply", "--numstat", "fix.patch"]
+        );
+    }
+
+    #[test]
+    fn reset_previews() {
+        assert_eq!(
+            preview_of(&["reset", "--hard", "HEAD~1"]),
+            [
+                "-c",
+                "core.quotePath=false",
+                "diff",
+                "--name-status",
+                "HEAD~1"
+            ]
+        );
+        assert_eq!(
+            preview_of(&["reset", "HEAD~1"]),
+            ["rev-parse", "HEAD", "HEAD~1^{commit}"]
+        );
+    }
+
+    #[test]
+    fn history_previews_list_commits() {
+        let format = parse::LOG_FORMAT;
+        assert_eq!(
+            preview_of(&["rebase", "main"]),
+            ["log", format, "--reverse", "main..HEAD"]
+        );
+        assert_eq!(
+            preview_of(&["merge", "topic"]),
+            ["log", format, "--reverse", "^HEAD", "topic"]
+        );
+        assert_eq!(
+            preview_of(&["cherry-pick", "1a2b3c4"]),
+            ["log", format, "--no-walk=unsorted", "1a2b3c4"]
+        );
+    }
+
+    #[test]
+    fn ref_previews() {
+        assert_eq!(
+            preview_of(&["branch", "topic", "main"]),
+            ["rev-parse", "main^{commit}"]
+        );
+        assert_eq!(
+            preview_of(&["branch", "-D", "old"]),
+            ["rev-parse", "refs/heads/old"]
+        );
+        assert_eq!(preview_of(&["tag", "v1.0"]), ["rev-parse", "HEAD^{commit}"]);
+        assert_eq!(
+            preview_of(&["switch", "-c", "topic"]),
+            ["rev-parse", "HEAD^{commit}"]
+        );
+    }
+
+    #[test]
+    fn unsupported_forms_are_refused() {
+        for args in [
+            &["rebase", "--continue"][..],
+            &["merge", "--abort"],
+            &["branch", "-u", "origin/main"],
+            &["update-ref", "--stdin"],
+            &["stash", "branch", "topic"],
+        ] {
+            assert!(plan_of(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn steps_drop_reads_after_the_last_change() {
+        let (previewed, plans) = steps(vec![
+            strings(&["status"]),
+            strings(&["add", "a.txt"]),
+            strings(&["commit", "-m", "Add a"]),
+            strings(&["log", "-1"]),
+        ])
+        .unwrap();
+        assert_eq!(
+            previewed,
+            [
+                strings(&["status"]),
+                strings(&["add", "--dry-run", "a.txt"]),
+                strings(&["commit", "--dry-run", "--porcelain", "-m", "Add a"]),
+            ]
+        );
+        let positions: Vec<usize> = plans.iter().map(|plan| plan.step).collect();
+        assert_eq!(positions, [1, 2]);
+
+        let (previewed, plans) = steps(vec![strings(&["status"])]).unwrap();
+        assert_eq!(previewed, [strings(&["status"])]);
+        assert!(plans.is_empty());
+    }
+
+    #[test]
+    fn nothing_to_commit_only_for_commit_dry_runs() {
+        let commit = preview_of(&["commit", "-m", "Fix"]);
+        assert!(nothing_to_commit(&commit, Some(1)));
+        assert!(!nothing_to_commit(&commit, Some(128)));
+        let add = preview_of(&["add", "a.txt"]);
+        assert!(!nothing_to_commit(&add, Some(1)));
+    }
+
+    #[test]
+    fn output_falls_back_to_stderr() {
+        assert_eq!(output("add 'a'\n", "warning"), "add 'a'\n");
+        assert_eq!(output("\n", "From origin\n"), "From origin\n");
+    }
+
+    /// The `result` of running `args`' preview with `output`.
+    fn result_of(args: &[&str], output: &str) -> Value {
+        let plan = plan_of(args).unwrap().unwrap();
+        let result = result(&[plan], &[output.to_string()]);
+        assert_eq!(result["dry_run"], true);
+        result["previews"][0].clone()
+    }
+
+    #[test]
+    fn reads_file_changes() {
+        let preview = result_of(&["add", "-A"], "add 'new file.txt'\nremove 'old.txt'\n");
+        assert_eq!(
+            preview["files"],
+            json!([
+                { "path": "new file.txt", "change": "add" },
+                { "path": "old.txt", "change": "delete" },
+            ])
+        );
+        let preview = result_of(&["mv", "a.txt", "b.txt"], "Renaming a.txt to b.txt\n");
+        assert_eq!(
+            preview["files"],
+            json!([{ "path": "b.txt", "original_path": "a.txt", "change": "rename" }])
+        );
+        let preview = result_of(&["clean", "-f"], "Would remove build/\n");
+        assert_eq!(
+            preview["files"],
+            json!([{ "path": "build/", "change": "delete" }])
+        );
+        let preview = result_of(
+            &["commit", "-m", "Fix"],
+            "M  src/lib.rs\nR  old.rs -> new.rs\n M unstaged.rs\n?? untracked.rs\n",
+        );
+        assert_eq!(
+            preview["files"],
+            json!([
+                { "path": "src/lib.rs", "change": "modify" },
+                { "path": "new.rs", "original_path": "old.rs", "change": "rename" },
+            ])
+        );
+        let preview = result_of(&["reset", "--hard"], "D\tgone.rs\nR100\told.rs\tnew.rs\n");
+        assert_eq!(
+            preview["files"],
+            json!([
+                { "path": "gone.rs", "change": "delete" },
+                { "path": "new.rs", "original_path": "old.rs", "change": "rename" },
+            ])
+        );
+        let preview = result_of(&["apply", "fix.patch"], "3\t1\tsrc/lib.rs\n");
+        assert_eq!(
+            preview["files"],
+            json!([{ "path": "src/lib.rs", "change": "modify" }])
+        );
+    }
+
+    #[test]
+    fn reads_ref_changes() {
+        let preview = result_of(&["reset", "HEAD~1"], "1a2b3c4\n5d6e7f8\n");
+        assert_eq!(
+            preview["refs"],
+            json!([{ "name": "HEAD", "old": "1a2b3c4", "new": "5d6e7f8" }])
+        );
+        let preview = result_of(&["branch", "-m", "old", "new"], "1a2b3c4\n");
+        assert_eq!(
+            preview["refs"],
+            json!([
+                { "name": "refs/heads/new", "old": null, "new": "1a2b3c4" },
+                { "name": "refs/heads/old", "old": "1a2b3c4", "new": null },
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_pushed_refs() {
+        let preview = result_of(
+            &["push", "origin", "--all"],
+            "To /srv/origin.git\n\
+             *\trefs/heads/topic:refs/heads/topic\t[new branch]\n \
+             \trefs/heads/main:refs/heads/main\t1a2b3c4..5d6e7f8\n\
+             =\trefs/tags/v1:refs/tags/v1\t[up to date]\n\
+             Done\n",
+        );
+        assert_eq!(
+            preview["refs"],
+            json!([
+                {
+                    "name": "refs/heads/topic",
+                    "old": null,
+                    "new": null,
+                    "summary": "[new branch]",
+                },
+                {
+                    "name": "refs/heads/main",
+                    "old": "1a2b3c4",
+                    "new": "5d6e7f8",
+                    "summary": "1a2b3c4..5d6e7f8",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_fetched_refs() {
+        let preview = result_of(
+            &["fetch", "origin"],
+            "From /srv/origin\n \
+             * [new branch]      topic      -> origin/topic\n   \
+             1a2b3c4..5d6e7f8  main       -> origin/main\n \
+             + 1a2b3c4...9e8f7a6 wip        -> origin/wip  (forced update)\n \
+             * branch            main       -> FETCH_HEAD\n",
+        );
+        assert_eq!(
+            preview["refs"],
+            json!([
+                {
+                    "name": "origin/topic",
+                    "old": null,
+                    "new": null,
+                    "summary": "[new branch]",
+                },
+                {
+                    "name": "origin/main",
+                    "old": "1a2b3c4",
+                    "new": "5d6e7f8",
+                    "summary": "1a2b3c4..5d6e7f8",
+                },
+                {
+                    "name": "origin/wip",
+                    "old": "1a2b3c4",
+                    "new": "9e8f7a6",
+                    "summary": "1a2b3c4...9e8f7a6",
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::GitCommandResult;
+use crate::branches;
+use mcp_protocol::prompt::{
+    Prompt, PromptArgument, PromptGetResult, PromptMessage, PromptMessageContent,
+};
+use std::collections::HashMap;
+
+/// Built-in prompts served through `prompts/list` and `prompts/get`.
+///
+/// Every prompt gathers its own git context: `prompts/get` spawns a
+/// git-command child per step and the prompt messages are rendered once the
+/// last one exits.
+struct PromptSpec {
+    name: &'static str,
+    description: &'static str,
+    arguments: &'static [(&'static str, &'static str, bool)],
+}
+
+const REPOSITORY_PATH_ARG: (&str, &str, bool) = (
+    "repository_path",
+    "Override the configured repository path",
+    false,
+);
+
+const REPO_ARG: (&str, &str, bool) = (
+    "repo",
+    "Name of a registered repository, instead of 'repository_path'",
+    false,
+);
+
+const PROMPTS: &[PromptSpec] = &[
+    PromptSpec {
+        name: "write-commit-message",
+        description: "Write a commit message for the currently staged changes",
+        arguments: &[REPO_ARG, REPOSITORY_PATH_ARG],
+    },
+    PromptSpec {
+        name: "summarize-branch",
+        description: "Summarize the commits on a branch as a pull request description",
+        arguments: &[
+            REPO_ARG,
+            REPOSITORY_PATH_ARG,
+            (
+                "base",
+                "Branch the work is based on (defaults to the repository's default branch)",
+                false,
+            ),
+            (
+                "head",
+                "Branch or revision to summarize (defaults to 'HEAD')",
+                false,
+            ),
+        ],
+    },
+    PromptSpec {
+        name: "review-staged-changes",
+        description: "Review the currently staged changes before they are committed",
+        arguments: &[REPO_ARG, REPOSITORY_PATH_ARG],
+    },
+    PromptSpec {
+        name: "explain-commit",
+        description: "Explain what a commit changes and why",
+        arguments: &[
+            REPO_ARG,
+            REPOSITORY_PATH_ARG,
+            ("sha", "Commit to explain", true),
+        ],
+    },
+];
+
+pub fn list() -> Vec<Prompt> {
+    PROMPTS
+        .iter()
+        .map(|spec| Prompt {
+            name: spec.name.to_string(),
+            description: Some(spec.description.to_string()),
+            arguments: Some(
+                spec.arguments
+                    .iter()
+                    .map(|(name, description, required)| PromptArgument {
+                        name: name.to_string(),
+                        description: Some(description.to_string()),
+                        required: Some(*required),
+                    })
+                    .collect(),
+            ),
+            annotations: None,
+        })
+        .collect()
+}
+
+pub fn exists(name: &str) -> bool {
+    PROMPTS.iter().any(|spec| spec.name == name)
+}
+
+/// Git commands that gather the context for the prompt `name`, in order.
+pub fn steps(name: &str, args: &HashMap<String, String>) -> Result<Vec<Vec<String>>, String> {
+    let git_args = match name {
+        "write-commit-message" => vec!["diff", "--cached", "--stat", "--patch"],
+        "review-staged-changes" => vec!["diff", "--cached", "--stat", "--patch", "-U10"],
+        "summarize-branch" => {
+            let base = revision_arg(args, "base")?;
+            let head = revision_arg(args, "head")?.unwrap_or("HEAD");
+            let log = vec![
+                "log".to_string(),
+                "--stat".to_string(),
+                "--format=commit %H%nAuthor: %an <%ae>%n%n%B".to_string(),
+                format!("{}..{}", base.unwrap_or(branches::DEFAULT_BRANCH), head),
+            ];
+            // Without a base, the default branch is found first
+            return Ok(match base {
+                Some(_) => vec![log],
+                None => vec![branches::refs_step(), log],
+            });
+        }
+        "explain-commit" => {
+            let sha = revision_arg(args, "sha")?.ok_or("Missing required argument 'sha'")?;
+            vec!["show", "--stat", "--patch", sha]
+        }
+        _ => return Err(format!("Prompt '{}' not found", name)),
+    };
+
+    Ok(vec![git_args.into_iter().map(|s| s.to_string()).collect()])
+}
+
+/// Put the default branch into `step` once the steps before it have found it.
+pub fn prepare(outputs: &[String], mut step: Vec<String>) -> Vec<String> {
+    let placeholder = format!("{}..", branches::DEFAULT_BRANCH);
+    if let Some(arg) = step.iter_mut().find(|arg| arg.starts_with(&placeholder)) {
+        let head = &arg[placeholder.len()..];
+        *arg = format!("{}..{}", branches::base(None, outputs), head);
+    }
+    step
+}
+
+/// Render the prompt messages for `name` around the output of its last git
+/// command, given the `outputs` of the steps before it.
+pub fn render(
+    name: &str,
+    args: &HashMap<String, String>,
+    outputs: &[String],
+    output: &GitCommandResult,
+) -> PromptGetResult {
+    let context = output.stdout.trim();
+
+    let (description, text) = match name {
+        "write-commit-message" if context.is_empty() => (
+            "Write a commit message for the staged changes",
+            "There are no staged changes in this repository. Ask me to stage the changes \
+             that should be committed before writing a commit message."
+                .to_string(),
+        ),
+        "write-commit-message" => (
+            "Write a commit message for the staged changes",
+            format!(
+                "Write a commit message for the following staged changes. Use a concise \
+                 subject line of at most 72 characters in the imperative mood, followed by \
+                 a blank line and a body that explains what changed and why.\n\n```diff\n{}\n```",
+                context
+            ),
+        ),
+        "review-staged-changes" if context.is_empty() => (
+            "Review the staged changes",
+            "There are no staged changes to review.".to_string(),
+        ),
+        "review-staged-changes" => (
+            "Review the staged changes",
+            format!(
+                "Review the following staged changes as a careful code reviewer. Point out \
+                 bugs, missing error handling, unclear naming and anything that should not \
+                 be committed, referencing files and lines where possible.\n\n```diff\n{}\n```",
+                context
+            ),
+        ),
+        "summarize-branch" => {
+            let base = branches::base(revision_arg(args, "base").ok().flatten(), outputs);
+            let head = args.get("head").map(String::as_str).unwrap_or("HEAD");
+            let text = if context.is_empty() {
+                format!(
+                    "'{}' has no commits that are not already in '{}'.",
+                    head, base
+                )
+            } else {
+                format!(
+                    "Summarize the work on '{}' since it diverged from '{}' as a pull \
+                     request description. Start with a short title, then describe the \
+                     overall change, notable implementation details and anything reviewers \
+                     should pay attention to.\n\n```\n{}\n```",
+                    head, base, context
+                )
+            };
+            ("Summarize a branch as a pull request description", text)
+        }
+        "explain-commit" => {
+            let sha = args.get("sha").map(String::as_str).unwrap_or_default();
+            (
+                "Explain a commit",
+                format!(
+                    "Explain what commit {} changes and why. Describe the intent of the \
+                     change, how it is implemented and any risks it introduces.\n\n```diff\n{}\n```",
+                    sha, context
+                ),
+            )
+        }
+        _ => ("", String::new()),
+    };
+
+    PromptGetResult {
+        description: Some(description.to_string()),
+        messages: vec![PromptMessage {
+            role: "user".to_string(),
+            content: PromptMessageContent::Text { text },
+        }],
+    }
+}
+
+/// Revisions are passed straight to git, so refuse anything that would be
+/// parsed as an option.
+fn revision_arg<'a>(
+    args: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<Option<&'a str>, String> {
+    match args.get(name).map(|value| value.trim()) {
+        Some(value) if value.starts_with('-') => Err(format!(
+            "Invalid value for '{}': revisions must not start with '-'",
+            name
+        )),
+        Some("") | None => Ok(None),
+        Some(value) => Ok(Some(value)),
+    }
+}
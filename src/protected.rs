@@ -0,0 +1,363 @@
+use crate::git;
+
+/// Whether `branch` matches `pattern`, where `*` stands for any run of
+/// characters within one path component, so `release/*` matches
+/// `release/1.0` but not `release/1.0/hotfix`.
+pub fn matches(pattern: &str, branch: &str) -> bool {
+    let pattern = pattern.strip_prefix("refs/heads/").unwrap_or(pattern);
+    let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+    let patterns: Vec<&str> = pattern.split('/').collect();
+    let parts: Vec<&str> = branch.split('/').collect();
+    patterns.len() == parts.len()
+        && patterns
+            .iter()
+            .zip(&parts)
+            .all(|(pattern, part)| matches_component(pattern, part))
+}
+
+fn matches_component(pattern: &str, part: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or("");
+    let Some(mut rest) = part.strip_prefix(first) else {
+        return false;
+    };
+    let pieces: Vec<&str> = pieces.collect();
+    let Some((last, middle)) = pieces.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for piece in middle {
+        match rest.find(piece) {
+            Some(index) => rest = &rest[index + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Refuse `action` on `branch` if it is protected.
+pub fn check(patterns: &[String], branch: &str, action: &str) -> Result<(), String> {
+    match patterns.iter().find(|pattern| matches(pattern, branch)) {
+        Some(pattern) => Err(format!(
+            "Refusing to {} '{}': it is a protected branch (matches '{}')",
+            action, branch, pattern
+        )),
+        None => Ok(()),
+    }
+}
+
+/// A branch a git command would move or delete.
+#[derive(Debug, Clone, PartialEq)]
+enum Move {
+    /// Whichever branch is checked out.
+    Current(&'static str),
+    /// The branch a rebase in progress was started on, which HEAD does not
+    /// name until it finishes.
+    Rebasing(&'static str),
+    Branch(String, &'static str),
+    /// Every branch, as `push --all` does.
+    All(&'static str),
+}
+
+fn has(args: &[String], flags: &[&str]) -> bool {
+    args.iter().any(|arg| flags.contains(&arg.as_str()))
+}
+
+/// The value given to the first of `flags` in `args`.
+fn value_of<'a>(args: &'a [String], flags: &[&str]) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| flags.contains(&arg.as_str()))
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+/// Options of `git push` whose value is the next argument; `-u` and
+/// `--set-upstream` are flags there.
+const PUSH_OPTIONS_WITH_VALUE: &[&str] =
+    &["-o", "--push-option", "--repo", "--receive-pack", "--exec"];
+
+fn local_branch(name: &str) -> Option<String> {
+    match name.strip_prefix("refs/") {
+        Some(name) => name.strip_prefix("heads/").map(str::to_string),
+        None => Some(name.to_string()),
+    }
+}
+
+/// What writing the ref `name` names moves: the checked-out branch for
+/// `HEAD`, every branch for a glob such as `refs/heads/*`.
+fn destination(name: &str, action: &'static str) -> Option<Move> {
+    match name {
+        "HEAD" | "@" => Some(Move::Current(action)),
+        name => local_branch(name).map(|branch| match branch.contains('*') {
+            true => Move::All(action),
+            false => Move::Branch(branch, action),
+        }),
+    }
+}
+
+/// Branches the `src:dst` refspecs of a fetch write. A refspec without a
+/// destination only updates `FETCH_HEAD` and remote-tracking branches.
+fn fetched(refspecs: &[&str]) -> Vec<Move> {
+    refspecs
+        .iter()
+        .filter_map(|refspec| {
+            let (_, target) = refspec.trim_start_matches('+').split_once(':')?;
+            match target {
+                "" => None,
+                target => destination(target, "fetch into"),
+            }
+        })
+        .collect()
+}
+
+/// Branches `args` would move or delete. Commands that only read, or only
+/// touch the index and working tree, move nothing.
+fn moves(args: &[String]) -> Vec<Move> {
+    let Some(index) = git::subcommand_index(args) else {
+        return Vec::new();
+    };
+    let name = args[index].as_str();
+    let rest = &args[index + 1..];
+    let (operands, dashdash) = git::operands(rest, git::OPTIONS_WITH_VALUE);
+
+    match name {
+        "commit" if !has(rest, &["--dry-run"]) => vec![Move::Current("commit to")],
+        "merge" | "cherry-pick" | "revert" | "am" if !has(rest, &["--abort", "--quit"]) => {
+            vec![Move::Current("move")]
+        }
+        "pull" => {
+            let mut moves = vec![Move::Current("move")];
+            moves.extend(fetched(operands.get(1..).unwrap_or_default()));
+            moves
+        }
+        "fetch" if has(rest, &["--all", "--multiple"]) => Vec::new(),
+        "fetch" => fetched(operands.get(1..).unwrap_or_default()),
+        "rebase" if has(rest, &["--continue", "--skip"]) => vec![Move::Rebasing("rebase")],
+        "rebase" if !has(rest, &["--abort", "--quit"]) => match operands.get(1) {
+            Some(branch) => vec![Move::Branch(branch.to_string(), "rebase")],
+            None => vec![Move::Current("rebase")],
+        },
+        // With paths, reset only touches the index
+        "reset" if !dashdash && !has(rest, &["-p", "--patch"]) => {
+            vec![Move::Current("reset")]
+        }
+        "branch" => {
+            // `-m` and `-C` take no value here
+            let (operands, _) = git::operands(rest, &["-u"]);
+            if has(rest, &["-d", "-D", "--delete"]) {
+                operands
+                    .iter()
+                    .map(|branch| Move::Branch(branch.to_string(), "delete"))
+                    .collect()
+            } else if has(rest, &["-m", "-M", "--move"]) {
+                match operands[..] {
+                    [old, _] => vec![Move::Branch(old.to_string(), "rename")],
+                    _ => vec![Move::Current("rename")],
+                }
+            } else if has(rest, &["-C"]) {
+                operands
+                    .last()
+                    .map(|branch| vec![Move::Branch(branch.to_string(), "overwrite")])
+                    .unwrap_or_default()
+            } else if has(rest, &["-f", "--force"]) {
+                operands
+                    .first()
+                    .map(|branch| vec![Move::Branch(branch.to_string(), "reset")])
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            }
+        }
+        "checkout" => value_of(rest, &["-B"])
+            .map(|branch| vec![Move::Branch(branch.to_string(), "reset")])
+            .unwrap_or_default(),
+        "switch" => value_of(rest, &["-C", "--force-create"])
+            .map(|branch| vec![Move::Branch(branch.to_string(), "reset")])
+            .unwrap_or_default(),
+        "update-ref" if has(rest, &["--stdin"]) => vec![Move::All("update refs of")],
+        "update-ref" => {
+            let action = match has(rest, &["-d"]) {
+                true => "delete",
+                false => "update",
+            };
+            match operands.first() {
+                // `--no-deref` detaches HEAD instead of moving its branch
+                Some(&"HEAD" | &"@") if has(rest, &["--no-deref"]) => Vec::new(),
+                Some(&"HEAD" | &"@") => vec![Move::Current(action)],
+                Some(name) => name
+                    .strip_prefix("refs/heads/")
+                    .map(|branch| vec![Move::Branch(branch.to_string(), action)])
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            }
+        }
+        "worktree" if operands.first() == Some(&"add") => value_of(rest, &["-B"])
+            .map(|branch| vec![Move::Branch(branch.to_string(), "reset")])
+            .unwrap_or_default(),
+        "push" if has(rest, &["--all", "--branches", "--mirror"]) => vec![Move::All("push")],
+        "push" => {
+            let deleting = has(rest, &["-d", "--delete"]);
+            let (operands, _) = git::operands(rest, PUSH_OPTIONS_WITH_VALUE);
+            // With `--repo`, every operand is a refspec
+            let repo = rest
+                .iter()
+                .any(|arg| arg == "--repo" || arg.starts_with("--repo="));
+            let refspecs = match repo {
+                true => &operands[..],
+                false => operands.get(1..).unwrap_or_default(),
+            };
+            if refspecs.is_empty() {
+                return match has(rest, &["--tags"]) {
+                    true => Vec::new(),
+                    false => vec![Move::Current("push to")],
+                };
+            }
+            refspecs
+                .iter()
+                .filter_map(|refspec| {
+                    let refspec = refspec.trim_start_matches('+');
+                    let (source, target) = refspec.split_once(':').unwrap_or((refspec, refspec));
+                    let action = match deleting || source.is_empty() {
+                        true => "delete",
+                        false => "push to",
+                    };
+                    destination(target, action)
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Whether checking `args` needs to know the current branch.
+pub fn needs_current(args: &[String]) -> bool {
+    moves(args)
+        .iter()
+        .any(|change| matches!(change, Move::Current(_) | Move::Rebasing(_)))
+}
+
+/// Refuse `args` if they would move or delete a protected branch. `current`
+/// is the checked-out branch, `None` when HEAD is detached.
+pub fn check_args(
+    patterns: &[String],
+    args: &[String],
+    current: Option<&str>,
+) -> Result<(), String> {
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    for change in moves(args) {
+        match change {
+            Move::Current(action) => {
+                if let Some(current) = current {
+                    check(patterns, current, action)?;
+                }
+            }
+            Move::Rebasing(action) => match current {
+                Some(current) => check(patterns, current, action)?,
+                None => {
+                    return Err(format!(
+                        "Refusing to {} with HEAD detached: the branch being rebased cannot be \
+                         checked against the protected branches",
+                        action
+                    ));
+                }
+            },
+            Move::Branch(branch, action) => check(patterns, &branch, action)?,
+            Move::All(action) => {
+                return Err(format!(
+                    "Refusing to {} every branch while protected branches are configured: {}",
+                    action,
+                    patterns.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn protected() -> Vec<String> {
+        vec!["main".to_string(), "release/*".to_string()]
+    }
+
+    fn check_on(command: &[&str], current: Option<&str>) -> Result<(), String> {
+        check_args(&protected(), &args(command), current)
+    }
+
+    #[test]
+    fn patterns_match_one_component_per_star() {
+        assert!(matches("main", "refs/heads/main"));
+        assert!(matches("release/*", "release/1.0"));
+        assert!(!matches("release/*", "release/1.0/hotfix"));
+        assert!(matches("feat-*-wip", "feat-x-wip"));
+        assert!(!matches("main", "mainline"));
+    }
+
+    #[test]
+    fn push_upstream_flags_take_no_value() {
+        assert!(check_on(&["push", "-u", "origin", "main"], Some("topic")).is_err());
+        assert!(check_on(&["push", "--set-upstream", "origin", "main"], Some("topic")).is_err());
+        assert!(check_on(&["push", "-u", "origin", "topic"], Some("main")).is_ok());
+    }
+
+    #[test]
+    fn push_refspec_destinations_are_checked() {
+        assert!(check_on(&["push", "origin", "HEAD:main"], Some("topic")).is_err());
+        assert!(check_on(&["push", "origin", "+topic:refs/heads/release/2.0"], None).is_err());
+        assert!(check_on(&["push", "origin", ":main"], Some("topic")).is_err());
+        assert!(check_on(&["push", "origin", "main:topic"], Some("main")).is_ok());
+        assert!(check_on(&["push", "--repo", "origin", "main"], Some("topic")).is_err());
+        assert!(check_on(&["push", "-o", "ci.skip", "origin", "main"], Some("topic")).is_err());
+    }
+
+    #[test]
+    fn push_without_refspecs_pushes_the_current_branch() {
+        assert!(needs_current(&args(&["push", "origin"])));
+        assert!(check_on(&["push", "origin"], Some("main")).is_err());
+        assert!(check_on(&["push", "origin"], Some("topic")).is_ok());
+        assert!(check_on(&["push", "--all", "origin"], Some("topic")).is_err());
+        assert!(check_on(&["push", "--tags", "origin"], Some("main")).is_ok());
+    }
+
+    #[test]
+    fn continuing_a_rebase_needs_the_branch() {
+        assert!(needs_current(&args(&["rebase", "--continue"])));
+        assert!(check_on(&["rebase", "--continue"], None).is_err());
+        assert!(check_on(&["rebase", "--skip"], Some("topic")).is_ok());
+        assert!(check_on(&["rebase", "--abort"], None).is_ok());
+        assert!(check_on(&["rebase", "origin/main", "main"], Some("topic")).is_err());
+    }
+
+    #[test]
+    fn fetch_and_pull_check_refspec_destinations() {
+        assert!(check_on(&["fetch", "origin", "main:main"], Some("topic")).is_err());
+        assert!(check_on(&["fetch", "origin", "main"], Some("topic")).is_ok());
+        assert!(check_on(&["pull", "origin", "topic"], Some("main")).is_err());
+    }
+
+    #[test]
+    fn branch_commands_name_the_branch_they_move() {
+        assert!(check_on(&["branch", "-D", "main"], Some("topic")).is_err());
+        assert!(check_on(&["branch", "-m", "main", "old"], Some("topic")).is_err());
+        assert!(check_on(&["branch", "-u", "origin/main", "topic"], Some("topic")).is_ok());
+        assert!(check_on(&["checkout", "-B", "main"], Some("topic")).is_err());
+        assert!(check_on(&["update-ref", "refs/heads/main", "HEAD~1"], Some("topic")).is_err());
+        assert!(
+            check_on(
+                &["update-ref", "--no-deref", "HEAD", "abc123"],
+                Some("main")
+            )
+            .is_ok()
+        );
+        assert!(check_on(&["reset", "--", "file"], Some("main")).is_ok());
+        assert!(check_on(&["reset", "--hard", "HEAD~1"], Some("main")).is_err());
+    }
+}
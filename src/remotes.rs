@@ -0,0 +1,129 @@
+use crate::State;
+use crate::bindings::theater::simple::environment::get_var;
+use crate::bindings::theater::simple::filesystem::read_file;
+use crate::cache;
+use crate::parse;
+use crate::protected;
+use serde_json::Value;
+use serde_json::json;
+
+/// Stands in for the push refspec until the current branch is known.
+const PUSH_REFSPEC: &str = "<push-refspec>";
+
+/// Host settings that make git fail instead of waiting for credentials no
+/// one can type: an askpass that always fails.
+pub const NON_INTERACTIVE: &[&str] = &["-c", "core.askPass=false"];
+
+/// ssh in batch mode, which fails instead of prompting. Only used when the
+/// host names no ssh command of its own, which this would override.
+const BATCH_SSH_COMMAND: &str = "core.sshCommand=ssh -o BatchMode=yes";
+
+/// Settings to add to `NON_INTERACTIVE` for a remote call in
+/// `repository_path`: ssh in batch mode, unless the environment or the
+/// system, global or repository configuration already sets an ssh command.
+/// Included configuration files are not followed.
+pub fn ssh_settings(repository_path: &str) -> Vec<String> {
+    if get_var("GIT_SSH_COMMAND").is_some() || get_var("GIT_SSH").is_some() {
+        return Vec::new();
+    }
+    let configured = cache::config_files(repository_path)
+        .into_iter()
+        .any(|path| {
+            read_file(&path)
+                .ok()
+                .and_then(|content| String::from_utf8(content).ok())
+                .is_some_and(|content| sets_ssh_command(&content))
+        });
+    match configured {
+        true => Vec::new(),
+        false => vec!["-c".to_string(), BATCH_SSH_COMMAND.to_string()],
+    }
+}
+
+/// Whether the git configuration `content` sets `core.sshCommand`.
+fn sets_ssh_command(content: &str) -> bool {
+    let mut in_core = false;
+    for line in content.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[') {
+            let name = section.split(']').next().unwrap_or("").trim();
+            in_core = name.eq_ignore_ascii_case("core");
+            continue;
+        }
+        let key = line.split(['=', ' ', '\t']).next().unwrap_or("");
+        if in_core && key.eq_ignore_ascii_case("sshCommand") {
+            return true;
+        }
+    }
+    false
+}
+
+/// The remote a call names, or `origin`.
+pub fn remote(args: &Value) -> &str {
+    args.get("remote")
+        .and_then(Value::as_str)
+        .filter(|remote| !remote.is_empty())
+        .unwrap_or("origin")
+}
+
+/// Refs a remote call can move: local branches, tags and the remote's
+/// tracking branches.
+fn snapshot(remote: &str) -> Vec<String> {
+    vec![
+        "for-each-ref".to_string(),
+        parse::REFS_FORMAT.to_string(),
+        "refs/heads".to_string(),
+        "refs/tags".to_string(),
+        format!("refs/remotes/{}", remote),
+    ]
+}
+
+/// Steps of `git-fetch`, `git-pull` and `git-push`: `operation` between two
+/// snapshots of the refs, which give the updated refs with exact shas
+/// whatever git prints.
+pub fn steps(args: &Value, operation: Vec<String>) -> Vec<Vec<String>> {
+    let remote = remote(args);
+    vec![snapshot(remote), operation, snapshot(remote)]
+}
+
+/// The placeholder `git-push` puts where its refspec goes.
+pub fn push_refspec() -> String {
+    PUSH_REFSPEC.to_string()
+}
+
+/// Fill in the push refspec from the first snapshot, refusing protected
+/// destinations.
+pub fn prepare(
+    state: &State,
+    args: &Value,
+    outputs: &[String],
+    mut step: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let Some(placeholder) = step.iter_mut().find(|arg| *arg == PUSH_REFSPEC) else {
+        return Ok(step);
+    };
+    let refs = parse::refs(outputs.first().map(String::as_str).unwrap_or(""));
+    let branch = match args.get("branch").and_then(Value::as_str) {
+        Some(branch) if !branch.is_empty() => branch.to_string(),
+        _ => refs
+            .head
+            .ok_or("HEAD is detached; pass 'branch' to say what to push")?,
+    };
+    let remote_branch = args
+        .get("remote_branch")
+        .and_then(Value::as_str)
+        .filter(|remote_branch| !remote_branch.is_empty())
+        .unwrap_or(&branch);
+
+    protected::check(&state.protected_branches, remote_branch, "push to")?;
+    *placeholder = format!("refs/heads/{}:refs/heads/{}", branch, remote_branch);
+    Ok(step)
+}
+
+/// Refs the call moved, from the snapshots before and after it.
+pub fn updated(args: &Value, outputs: &[String], after: &str) -> Value {
+    let before = parse::refs(outputs.first().map(String::as_str).unwrap_or(""));
+    json!({
+        "remote": remote(args),
+        "updated_refs": parse::ref_updates(&before, &parse::refs(after)),
+    })
+}
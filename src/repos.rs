@@ -0,0 +1,267 @@
+use crate::State;
+use crate::parse::Status;
+use crate::policy::Policy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A repository callers can name with `repo` instead of passing its path.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Repository {
+    pub path: String,
+    /// Replaces the top-level policy for calls in this repository.
+    #[serde(default)]
+    pub policy: Option<Policy>,
+    /// Remote used when a call does not name one.
+    #[serde(default)]
+    pub default_remote: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// The repository a call runs in and the policy that applies to it.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub path: String,
+    pub policy: Policy,
+    /// Remote used by remote tools when the call does not name one.
+    pub default_remote: Option<String>,
+}
+
+/// Pick the repository for a call: a registered `repo` name, a raw
+/// `repository_path`, or the configured default. Paths of registered
+/// repositories get that repository's policy however they are given.
+pub fn resolve(
+    state: &State,
+    repo: Option<&str>,
+    repository_path: Option<&str>,
+) -> Result<Target, String> {
+    let path = match (repo, repository_path) {
+        (Some(_), Some(_)) => {
+            return Err("Provide either 'repo' or 'repository_path', not both".to_string());
+        }
+        (Some(name), None) => {
+            return state
+                .repositories
+                .get(name)
+                .map(|repository| target(state, repository))
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown repository '{}'. Registered repositories: {}",
+                        name,
+                        names(state).join(", ")
+                    )
+                });
+        }
+        (None, Some(path)) => path.to_string(),
+        (None, None) => state.repository_path.clone().ok_or_else(|| {
+            "No repository given: pass 'repo' or 'repository_path', or configure a default"
+                .to_string()
+        })?,
+    };
+
+    check_allowed(state.allowed_roots.as_deref(), &path)?;
+
+    // The innermost registered repository the path is in
+    let path = normalize(&path);
+    let registered = state
+        .repositories
+        .values()
+        .map(|repository| (normalize(&repository.path), repository))
+        .filter(|(root, _)| contains(root, &path))
+        .max_by_key(|(root, _)| root.len());
+    Ok(match registered {
+        Some((root, repository)) => Target {
+            path: match root == path {
+                true => repository.path.clone(),
+                false => path,
+            },
+            ..target(state, repository)
+        },
+        None => Target {
+            path,
+            policy: state.policy.clone(),
+            default_remote: None,
+        },
+    })
+}
+
+/// `path` without empty and `.` components, with `..` applied, so the
+/// different ways of writing one directory compare equal.
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            // Above the root is still the root
+            ".." if path.starts_with('/') => {}
+            part => parts.push(part),
+        }
+    }
+    match (path.starts_with('/'), parts.is_empty()) {
+        (true, _) => format!("/{}", parts.join("/")),
+        (false, true) => ".".to_string(),
+        (false, false) => parts.join("/"),
+    }
+}
+
+/// Whether the normalized `path` is `root` or inside it.
+fn contains(root: &str, path: &str) -> bool {
+    path == root
+        || root == "/"
+        || path
+            .strip_prefix(root)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn target(state: &State, repository: &Repository) -> Target {
+    Target {
+        path: repository.path.clone(),
+        policy: repository
+            .policy
+            .clone()
+            .unwrap_or_else(|| state.policy.clone()),
+        default_remote: repository.default_remote.clone(),
+    }
+}
+
+/// Refuse paths outside `roots`, when roots are configured. Paths must be
+/// absolute and may not step out of a root with `..`.
+pub fn check_allowed(roots: Option<&[String]>, path: &str) -> Result<(), String> {
+    let Some(roots) = roots else {
+        return Ok(());
+    };
+    let inside = path.starts_with('/')
+        && !path.split('/').any(|part| part == "..")
+        && roots.iter().any(|root| {
+            let root = root.trim_end_matches('/');
+            path == root || path.starts_with(&format!("{}/", root))
+        });
+    match inside {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' is outside the allowed roots: {}",
+            path,
+            roots.join(", ")
+        )),
+    }
+}
+
+pub fn names(state: &State) -> Vec<&str> {
+    state.repositories.keys().map(String::as_str).collect()
+}
+
+/// Every policy a call might run under.
+pub fn policies(state: &State) -> impl Iterator<Item = &Policy> {
+    std::iter::once(&state.policy).chain(
+        state
+            .repositories
+            .values()
+            .filter_map(|repository| repository.policy.as_ref()),
+    )
+}
+
+/// One row of `list-repositories`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryStatus {
+    pub name: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_remote: Option<String>,
+    /// Current branch, or `None` when HEAD is detached or status failed.
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+    /// Whether anything is staged, modified or untracked.
+    pub dirty: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RepositoryStatus {
+    pub fn new(name: &str, repository: &Repository) -> Self {
+        RepositoryStatus {
+            name: name.to_string(),
+            path: repository.path.clone(),
+            description: repository.description.clone(),
+            default_remote: repository.default_remote.clone(),
+            branch: None,
+            upstream: None,
+            ahead: None,
+            behind: None,
+            dirty: None,
+            error: None,
+        }
+    }
+
+    pub fn set(&mut self, outcome: Result<Status, String>) {
+        match outcome {
+            Ok(status) => {
+                self.branch = status.branch.head;
+                self.upstream = status.branch.upstream;
+                self.ahead = status.branch.ahead;
+                self.behind = status.branch.behind;
+                self.dirty = Some(!status.clean);
+            }
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+/// A `list-repositories` call waiting on one status child per repository.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Listing {
+    pub statuses: BTreeMap<String, RepositoryStatus>,
+    /// Children that have not exited yet.
+    pub remaining: usize,
+}
+
+/// Record the status of `name` for a listing, returning every status once
+/// the last one is in.
+pub fn record(
+    state: &mut State,
+    listing_id: u64,
+    name: &str,
+    outcome: Result<Status, String>,
+) -> Option<Vec<RepositoryStatus>> {
+    let listing = state.listings.get_mut(&listing_id)?;
+    if let Some(status) = listing.statuses.get_mut(name) {
+        status.set(outcome);
+    }
+    listing.remaining = listing.remaining.saturating_sub(1);
+    if listing.remaining > 0 {
+        return None;
+    }
+    state
+        .listings
+        .remove(&listing_id)
+        .map(|listing| listing.statuses.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("/srv/prod/"), "/srv/prod");
+        assert_eq!(normalize("/srv/prod/."), "/srv/prod");
+        assert_eq!(normalize("/srv//prod/src/.."), "/srv/prod");
+        assert_eq!(normalize("/srv/other/../prod"), "/srv/prod");
+        assert_eq!(normalize("/.."), "/");
+        assert_eq!(normalize("../repo/./src"), "../repo/src");
+    }
+
+    #[test]
+    fn roots_contain_whole_components() {
+        assert!(contains("/srv/prod", "/srv/prod"));
+        assert!(contains("/srv/prod", "/srv/prod/src"));
+        assert!(!contains("/srv/prod", "/srv/production"));
+        assert!(contains("/", "/srv"));
+    }
+}
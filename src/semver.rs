@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+
+/// A semantic version read from a tag such as `v1.2.3` or `1.2.3-rc.1`.
+/// Build metadata is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// Text before the numbers, usually `v`, kept for the next tag.
+    pub prefix: String,
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Option<String>,
+}
+
+/// How far a version moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl Bump {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Bump::Patch => "patch",
+            Bump::Minor => "minor",
+            Bump::Major => "major",
+        }
+    }
+}
+
+impl Version {
+    pub fn parse(tag: &str) -> Option<Version> {
+        let (prefix, rest) = match tag.strip_prefix('v') {
+            Some(rest) => ("v", rest),
+            None => ("", tag),
+        };
+        let rest = rest.split_once('+').map_or(rest, |(version, _)| version);
+        let (numbers, pre) = match rest.split_once('-') {
+            Some((numbers, pre)) if !pre.is_empty() => (numbers, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (rest, None),
+        };
+        let parts: Vec<u64> = numbers
+            .split('.')
+            .map(|part| match part.len() > 1 && part.starts_with('0') {
+                true => None,
+                false => part.parse().ok(),
+            })
+            .collect::<Option<_>>()?;
+        let [major, minor, patch] = parts[..] else {
+            return None;
+        };
+        Some(Version {
+            prefix: prefix.to_string(),
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    /// The release after this one. A pre-release moves to its own release
+    /// when that is already far enough.
+    pub fn bump(&self, bump: Bump) -> Version {
+        let (major, minor, patch) = match (bump, &self.pre) {
+            (Bump::Major, Some(_)) if self.minor == 0 && self.patch == 0 => (self.major, 0, 0),
+            (Bump::Major, _) => (self.major + 1, 0, 0),
+            (Bump::Minor, Some(_)) if self.patch == 0 => (self.major, self.minor, 0),
+            (Bump::Minor, _) => (self.major, self.minor + 1, 0),
+            (Bump::Patch, Some(_)) => (self.major, self.minor, self.patch),
+            (Bump::Patch, None) => (self.major, self.minor, self.patch + 1),
+        };
+        Version {
+            prefix: self.prefix.clone(),
+            major,
+            minor,
+            patch,
+            pre: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}.{}.{}",
+            self.prefix, self.major, self.minor, self.patch
+        )?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Semver precedence: a pre-release sorts before its release, and its
+/// identifiers compare numerically when both are numbers.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(ours), Some(theirs)) => {
+                    let ours: Vec<&str> = ours.split('.').collect();
+                    let theirs: Vec<&str> = theirs.split('.').collect();
+                    ours.iter()
+                        .zip(&theirs)
+                        .map(|(a, b)| compare_identifiers(a, b))
+                        .find(|ordering| ordering.is_ne())
+                        .unwrap_or_else(|| ours.len().cmp(&theirs.len()))
+                }
+            })
+            .then_with(|| self.prefix.cmp(&other.prefix))
+    }
+}
+
+fn compare_identifiers(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(tag: &str) -> Version {
+        Version::parse(tag).unwrap_or_else(|| panic!("'{}' should parse", tag))
+    }
+
+    #[test]
+    fn parses_tags() {
+        let parsed = version("v1.2.3-rc.1+build.5");
+        assert_eq!(parsed.prefix, "v");
+        assert_eq!((parsed.major, parsed.minor, parsed.patch), (1, 2, 3));
+        assert_eq!(parsed.pre.as_deref(), Some("rc.1"));
+        assert_eq!(parsed.to_string(), "v1.2.3-rc.1");
+        assert_eq!(version("0.10.0").to_string(), "0.10.0");
+    }
+
+    #[test]
+    fn rejects_what_is_not_semver() {
+        for tag in [
+            "1.2",
+            "1.2.3.4",
+            "v01.2.3",
+            "1.2.3-",
+            "release-1",
+            "v1.x.0",
+            "",
+        ] {
+            assert_eq!(Version::parse(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn orders_by_precedence() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.2.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        let mut tags: Vec<Version> = ["v1.10.0", "v1.9.0", "v1.9.0-rc.1"]
+            .into_iter()
+            .map(version)
+            .collect();
+        tags.sort();
+        assert_eq!(
+            tags.last().map(Version::to_string).as_deref(),
+            Some("v1.10.0")
+        );
+    }
+
+    #[test]
+    fn bumps_releases() {
+        assert_eq!(version("v1.2.3").bump(Bump::Patch).to_string(), "v1.2.4");
+        assert_eq!(version("v1.2.3").bump(Bump::Minor).to_string(), "v1.3.0");
+        assert_eq!(version("v1.2.3").bump(Bump::Major).to_string(), "v2.0.0");
+    }
+
+    #[test]
+    fn bumps_pre_releases_to_their_release_when_far_enough() {
+        assert_eq!(version("2.0.0-rc.1").bump(Bump::Major).to_string(), "2.0.0");
+        assert_eq!(version("2.1.0-rc.1").bump(Bump::Major).to_string(), "3.0.0");
+        assert_eq!(version("1.3.0-beta").bump(Bump::Minor).to_string(), "1.3.0");
+        assert_eq!(version("1.3.1-beta").bump(Bump::Minor).to_string(), "1.4.0");
+        assert_eq!(version("1.3.1-beta").bump(Bump::Patch).to_string(), "1.3.1");
+    }
+}
@@ -0,0 +1,372 @@
+use crate::bindings::theater::simple::runtime::log;
+use crate::bindings::theater::simple::timing::now;
+use crate::websocket;
+use crate::{McpActorRequest, McpError, McpRequest, McpResponse, ReplyTo, State, handle_mcp};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json::json;
+
+/// Protocol versions this server speaks, newest first.
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] =
+    &["2025-11-25", "2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// The stream a client reads with a plain `GET`, which carries messages that
+/// answer no request. Every other stream belongs to one `POST`.
+pub const STANDALONE_STREAM: u64 = 0;
+
+/// Most notifications kept per session on the standalone stream; the oldest
+/// are dropped first. Responses are only dropped once they expire.
+const MAX_EVENTS: usize = 100;
+
+/// How long an event is kept for a client that has not acknowledged it.
+const EVENT_TTL_MS: u64 = 10 * 60 * 1000;
+
+/// Most HTTP sessions open at once; `initialize` is refused beyond it.
+const MAX_SESSIONS: usize = 64;
+
+/// How long an HTTP session may go unused before it is closed.
+const IDLE_TIMEOUT_MS: u64 = 30 * 60 * 1000;
+
+/// How messages reach a session's client.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "type")]
+pub enum Transport {
+    /// Queued until the client reads its event stream.
+    #[default]
+    Http,
+    /// Sent straight away on the connection.
+    Websocket { connection_id: u64 },
+}
+
+/// A message queued on one of an HTTP session's streams.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    pub id: u64,
+    pub stream: u64,
+    pub message: Value,
+    /// When it was queued, in milliseconds since the epoch.
+    pub queued_ms: u64,
+}
+
+impl Event {
+    /// The SSE event id, which names the stream so a client resuming with
+    /// `Last-Event-ID` gets the rest of the same stream.
+    pub fn event_id(&self) -> String {
+        event_id(self.stream, self.id)
+    }
+}
+
+fn event_id(stream: u64, id: u64) -> String {
+    format!("{}-{}", stream, id)
+}
+
+/// An MCP session on a transport that speaks plain JSON-RPC.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    #[serde(default)]
+    pub transport: Transport,
+    /// Negotiated by `initialize`; requests are refused until then.
+    pub protocol_version: Option<String>,
+    /// Set once the client sends `notifications/initialized`.
+    pub initialized: bool,
+    /// Messages kept until the client acknowledges them with
+    /// `Last-Event-ID`, or they expire.
+    #[serde(default)]
+    pub queued: Vec<Event>,
+    #[serde(default)]
+    pub next_event_id: u64,
+    #[serde(default)]
+    pub next_stream: u64,
+    /// When the client last used the session, in milliseconds since the epoch.
+    #[serde(default)]
+    pub last_seen_ms: u64,
+}
+
+impl Session {
+    pub fn new(transport: Transport) -> Self {
+        Session {
+            transport,
+            protocol_version: None,
+            initialized: false,
+            queued: Vec::new(),
+            next_event_id: 0,
+            next_stream: STANDALONE_STREAM,
+            last_seen_ms: now(),
+        }
+    }
+
+    /// Whether the client has left an HTTP session unused for longer than
+    /// `IDLE_TIMEOUT_MS`. WebSocket sessions last as long as their
+    /// connection.
+    pub fn is_idle(&self, now_ms: u64) -> bool {
+        self.transport == Transport::Http
+            && now_ms.saturating_sub(self.last_seen_ms) > IDLE_TIMEOUT_MS
+    }
+
+    /// Start the stream answering one `POST`. Returns it and the id of the
+    /// event that primes the client to resume it.
+    pub fn open_stream(&mut self) -> (u64, String) {
+        self.next_stream += 1;
+        self.next_event_id += 1;
+        (
+            self.next_stream,
+            event_id(self.next_stream, self.next_event_id),
+        )
+    }
+
+    /// Queue a message for the client on `stream`.
+    pub fn push(&mut self, stream: u64, message: Value) {
+        let now_ms = now();
+        self.expire(now_ms);
+        if stream == STANDALONE_STREAM {
+            let standalone: Vec<usize> = self
+                .queued
+                .iter()
+                .enumerate()
+                .filter(|(_, event)| event.stream == STANDALONE_STREAM)
+                .map(|(index, _)| index)
+                .collect();
+            if standalone.len() >= MAX_EVENTS {
+                let dropped = self.queued.remove(standalone[0]);
+                log(&format!(
+                    "Dropped unread notification {} after {} more queued",
+                    dropped.event_id(),
+                    MAX_EVENTS
+                ));
+            }
+        }
+        self.next_event_id += 1;
+        self.queued.push(Event {
+            id: self.next_event_id,
+            stream,
+            message,
+            queued_ms: now_ms,
+        });
+    }
+
+    /// The events of the stream `last_event_id` names that came after it,
+    /// or of the standalone stream when there is none. Events up to
+    /// `last_event_id` count as received and are dropped; the rest are kept
+    /// until the client acknowledges them.
+    pub fn replay(&mut self, last_event_id: Option<&str>) -> Vec<Event> {
+        self.expire(now());
+        let (stream, after) = last_event_id
+            .and_then(|id| id.split_once('-'))
+            .and_then(|(stream, id)| Some((stream.parse().ok()?, id.parse().ok()?)))
+            .unwrap_or((STANDALONE_STREAM, 0));
+        self.queued
+            .retain(|event| event.stream != stream || event.id > after);
+        self.queued
+            .iter()
+            .filter(|event| event.stream == stream)
+            .cloned()
+            .collect()
+    }
+
+    fn expire(&mut self, now_ms: u64) {
+        self.queued.retain(|event| {
+            let expired = now_ms.saturating_sub(event.queued_ms) > EVENT_TTL_MS;
+            if expired {
+                log(&format!("Event {} expired unread", event.event_id()));
+            }
+            !expired
+        });
+    }
+}
+
+/// Open an HTTP session under `session_id`, first closing idle ones. Fails
+/// while `MAX_SESSIONS` are still open.
+pub fn open(state: &mut State, session_id: String) -> Result<(), String> {
+    let now_ms = now();
+    state.sessions.retain(|id, session| {
+        let idle = session.is_idle(now_ms);
+        if idle {
+            log(&format!("Session {} closed after going idle", id));
+        }
+        !idle
+    });
+    let open = state
+        .sessions
+        .values()
+        .filter(|session| session.transport == Transport::Http)
+        .count();
+    if open >= MAX_SESSIONS {
+        return Err(format!(
+            "Too many open sessions (at most {}); close one with DELETE first",
+            MAX_SESSIONS
+        ));
+    }
+    state
+        .sessions
+        .insert(session_id, Session::new(Transport::Http));
+    Ok(())
+}
+
+/// Send a message to a session's client outside of a direct reply, on
+/// `stream` if it is an HTTP session.
+pub fn send(state: &mut State, session_id: &str, stream: u64, message: Value) {
+    let Some(session) = state.sessions.get_mut(session_id) else {
+        log(&format!(
+            "Session {} closed before its message could be sent",
+            session_id
+        ));
+        return;
+    };
+    match session.transport {
+        Transport::Http => session.push(stream, message),
+        Transport::Websocket { connection_id } => {
+            if let Err(e) = websocket::send(state, connection_id, &message) {
+                log(&format!("Failed to send to session {}: {}", session_id, e));
+            }
+        }
+    }
+}
+
+/// Handle a JSON-RPC request or notification from a session's client.
+/// Returns the response when it can be sent right away, and `None` for
+/// notifications and for requests answered later through `deliver`, on
+/// `stream`.
+pub fn dispatch(
+    state: &mut State,
+    session_id: &str,
+    stream: u64,
+    message: McpRequest,
+) -> Result<Option<McpResponse>, String> {
+    let McpRequest {
+        method, id, params, ..
+    } = message;
+    let session = state
+        .sessions
+        .get_mut(session_id)
+        .ok_or_else(|| format!("Unknown session {}", session_id))?;
+    session.last_seen_ms = now();
+
+    // Notifications need no reply
+    let Some(id) = id else {
+        if method == "notifications/initialized" {
+            session.initialized = true;
+        }
+        return Ok(None);
+    };
+
+    let result = match (method.as_str(), &session.protocol_version) {
+        ("initialize", Some(_)) => Err(McpError {
+            code: -32600,
+            message: "Session is already initialized".to_string(),
+            data: None,
+        }),
+        ("initialize", None) => {
+            let (protocol_version, result) = initialize(params.as_ref());
+            log(&format!(
+                "Session {} initialized with protocol {}",
+                session_id, protocol_version
+            ));
+            session.protocol_version = Some(protocol_version);
+            Ok(result)
+        }
+        ("ping", _) => Ok(json!({})),
+        (_, None) => Err(McpError {
+            code: -32600,
+            message: "Session is not initialized".to_string(),
+            data: None,
+        }),
+        (method, Some(_)) => match to_actor_request(method, params) {
+            Ok(actor_request) => {
+                let reply_to = ReplyTo::Session {
+                    session_id: session_id.to_string(),
+                    id: id.clone(),
+                    stream,
+                };
+                match handle_mcp(state, &reply_to, actor_request) {
+                    Ok(response) => return Ok(response),
+                    Err(message) => Err(McpError {
+                        code: -32603,
+                        message,
+                        data: None,
+                    }),
+                }
+            }
+            Err(mcp_error) => Err(mcp_error),
+        },
+    };
+
+    Ok(Some(match result {
+        Ok(result) => McpResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        },
+        Err(mcp_error) => McpResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(mcp_error),
+        },
+    }))
+}
+
+/// Negotiate the protocol version for an `initialize` request and build its
+/// result.
+fn initialize(params: Option<&Value>) -> (String, Value) {
+    let requested = params
+        .and_then(|params| params.get("protocolVersion"))
+        .and_then(Value::as_str);
+    let protocol_version = match requested {
+        Some(version) if SUPPORTED_PROTOCOL_VERSIONS.contains(&version) => version,
+        _ => SUPPORTED_PROTOCOL_VERSIONS[0],
+    }
+    .to_string();
+
+    let result = json!({
+        "protocolVersion": protocol_version,
+        "capabilities": {
+            "tools": { "listChanged": true },
+            "prompts": { "listChanged": false },
+            "completions": {}
+        },
+        "serverInfo": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION")
+        }
+    });
+    (protocol_version, result)
+}
+
+/// Map a JSON-RPC method onto the actor request that implements it.
+fn to_actor_request(method: &str, params: Option<Value>) -> Result<McpActorRequest, McpError> {
+    let params = params.unwrap_or_else(|| json!({}));
+    let request = match method {
+        "tools/list" => json!({ "type": "ToolsList" }),
+        "tools/call" => json!({
+            "type": "ToolsCall",
+            "name": params.get("name"),
+            "args": params.get("arguments").cloned().unwrap_or_else(|| json!({})),
+        }),
+        "prompts/list" => json!({ "type": "PromptsList" }),
+        "prompts/get" => json!({
+            "type": "PromptsGet",
+            "name": params.get("name"),
+            "args": params.get("arguments").cloned().unwrap_or_else(|| json!({})),
+        }),
+        "completion/complete" => json!({
+            "type": "CompletionComplete",
+            "ref": params.get("ref"),
+            "argument": params.get("argument"),
+            "context": params.get("context"),
+        }),
+        _ => {
+            return Err(McpError {
+                code: -32601,
+                message: format!("Method '{}' not found", method),
+                data: None,
+            });
+        }
+    };
+
+    serde_json::from_value(request).map_err(|e| McpError {
+        code: -32602,
+        message: format!("Invalid params for '{}': {}", method, e),
+        data: None,
+    })
+}
@@ -0,0 +1,570 @@
+use crate::State;
+use crate::bindings::theater::simple::runtime::log;
+use crate::bindings::theater::simple::timing::now;
+use crate::cache;
+use crate::git;
+use crate::storage;
+use crate::tools;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Refs that keep snapshots from being garbage collected, one directory per
+/// snapshot.
+const REF_PREFIX: &str = "refs/mcp-snapshots";
+
+/// Store labels of snapshot metadata, followed by the snapshot id.
+const LABEL_PREFIX: &str = "snapshot/";
+
+/// Snapshots kept per repository. Taking another prunes the oldest, refs
+/// and metadata both.
+const MAX_SNAPSHOTS: usize = 20;
+
+/// What a snapshot saved.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// HEAD, the index and the changes to tracked files.
+    WorkingTree,
+    /// Untracked files `clean` was about to remove.
+    Untracked,
+    /// Stash entries `stash drop` or `stash clear` was about to remove.
+    Stash,
+}
+
+/// State saved before a command that can lose work, kept as refs under
+/// `refs/mcp-snapshots/<id>/` with this metadata in the store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub id: String,
+    pub kind: Kind,
+    pub repository_path: String,
+    /// Milliseconds since the epoch.
+    pub created_ms: u64,
+    /// Arguments of the git command the snapshot was taken before.
+    pub command: Vec<String>,
+    /// Checked-out branch, `None` when HEAD was detached.
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub head: Option<String>,
+    #[serde(default)]
+    pub index_tree: Option<String>,
+    /// Stash-like commit of the index and working tree, `None` when both
+    /// matched HEAD.
+    #[serde(default)]
+    pub worktree: Option<String>,
+    /// Stash-like commit of the untracked files.
+    #[serde(default)]
+    pub untracked: Option<String>,
+    /// Saved stash entries, newest first.
+    #[serde(default)]
+    pub stashes: Vec<String>,
+}
+
+/// Where taking a snapshot has got to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum Stage {
+    /// Listing the files `clean` would remove.
+    Preview,
+    /// Saving the working tree, or the untracked files, as a stash commit.
+    Stash,
+    /// Reading HEAD, the branch and the stash commit.
+    Resolve,
+    /// Listing the stash entries.
+    Stashes,
+    /// Writing these refs, in order.
+    Refs(Vec<(String, String)>),
+    /// Dropping the stash entry made for the untracked files, which now
+    /// lives under the snapshot's refs.
+    Drop,
+    /// Deleting these refs of older snapshots, in order.
+    Prune(Vec<String>),
+}
+
+/// A snapshot being taken, carried between the children that take it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Progress {
+    pub snapshot: Snapshot,
+    stage: Stage,
+    /// Nothing is kept when the working tree turns out to match HEAD.
+    only_if_dirty: bool,
+    /// Older snapshots of the repository beyond `MAX_SNAPSHOTS` once this
+    /// one is kept.
+    #[serde(default)]
+    pruned: Vec<Snapshot>,
+}
+
+/// What to do once a child taking a snapshot exits.
+pub enum Next {
+    /// Run this git command next.
+    Run(Vec<String>),
+    /// The snapshot is complete; save it and run the command.
+    Save,
+    /// There was nothing to save; run the command.
+    Skip,
+}
+
+/// What `args` can lose, and whether that is only so when the working tree
+/// has changes. Commands that only move refs the reflog keeps lose nothing.
+fn risk(args: &[String]) -> Option<(Kind, bool)> {
+    let index = git::subcommand_index(args)?;
+    let rest = &args[index + 1..];
+    let has = |flags: &[&str]| rest.iter().any(|arg| flags.contains(&arg.as_str()));
+    // Short flags can be bundled, as in `clean -fdx`
+    let has_short = |flag: char| {
+        rest.iter()
+            .any(|arg| !arg.starts_with("--") && arg.starts_with('-') && arg.contains(flag))
+    };
+    match args[index].as_str() {
+        "reset" => Some((Kind::WorkingTree, false)),
+        "rebase"
+            if !has(&[
+                "--continue",
+                "--quit",
+                "--edit-todo",
+                "--show-current-patch",
+            ]) =>
+        {
+            Some((Kind::WorkingTree, false))
+        }
+        // Staged-only restores take changes out of the index, not the files
+        "restore"
+            if (has(&["--staged"]) || has_short('S'))
+                && !(has(&["--worktree"]) || has_short('W')) =>
+        {
+            None
+        }
+        "checkout" | "restore" => Some((Kind::WorkingTree, true)),
+        "switch" if has(&["-f", "--force", "--discard-changes"]) => Some((Kind::WorkingTree, true)),
+        // Without force, clean refuses to run
+        "clean"
+            if (has_short('f') || has(&["--force"]))
+                && !(has_short('n') || has(&["--dry-run"])) =>
+        {
+            Some((Kind::Untracked, true))
+        }
+        "stash" => match rest.first().map(String::as_str) {
+            Some("drop" | "clear") => Some((Kind::Stash, true)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Start a snapshot if `args` can lose work, returning the first command to
+/// run for it.
+pub fn start(
+    state: &mut State,
+    repository_path: &str,
+    args: &[String],
+) -> Option<(Progress, Vec<String>)> {
+    let (kind, only_if_dirty) = risk(args)?;
+    let created_ms = now();
+    let id = format!("{}-{}", created_ms, state.next_snapshot);
+    state.next_snapshot += 1;
+    let pruned = match list(state, repository_path, usize::MAX) {
+        Ok(snapshots) => snapshots.into_iter().skip(MAX_SNAPSHOTS - 1).collect(),
+        Err(message) => {
+            log(&format!("Not pruning snapshots: {}", message));
+            Vec::new()
+        }
+    };
+
+    let mut progress = Progress {
+        snapshot: Snapshot {
+            id,
+            kind,
+            repository_path: repository_path.to_string(),
+            created_ms,
+            command: args.to_vec(),
+            branch: None,
+            head: None,
+            index_tree: None,
+            worktree: None,
+            untracked: None,
+            stashes: Vec::new(),
+        },
+        stage: Stage::Stash,
+        only_if_dirty,
+        pruned,
+    };
+    let step = match kind {
+        Kind::WorkingTree => strings(&["stash", "create", &progress.message()]),
+        Kind::Untracked => {
+            // Same arguments, listing instead of removing
+            progress.stage = Stage::Preview;
+            let index = git::subcommand_index(args)?;
+            let mut preview = strings(&["-c", "core.quotePath=false"]);
+            preview.extend_from_slice(&args[..=index]);
+            preview.push("--dry-run".to_string());
+            preview.extend_from_slice(&args[index + 1..]);
+            preview
+        }
+        Kind::Stash => {
+            progress.stage = Stage::Stashes;
+            strings(&["stash", "list", "--format=%H"])
+        }
+    };
+    Some((progress, step))
+}
+
+impl Progress {
+    /// Message of the stash commits and ref updates the snapshot makes.
+    fn message(&self) -> String {
+        format!("git-mcp snapshot {}", self.snapshot.id)
+    }
+
+    /// Take in the result of the last command and say what comes next.
+    /// Failing to read state only skips the snapshot, so the command stays
+    /// usable in states git cannot stash, such as mid-conflict; failing to
+    /// keep what was read refuses the command.
+    pub fn advance(&mut self, success: bool, stdout: &str, stderr: &str) -> Result<Next, String> {
+        let stage = std::mem::replace(&mut self.stage, Stage::Drop);
+        let message = self.message();
+        let id = self.snapshot.id.clone();
+        let reference = |name: &str| format!("{}/{}/{}", REF_PREFIX, id, name);
+        let snapshot = &mut self.snapshot;
+        match stage {
+            Stage::Preview => {
+                let paths: Vec<&str> = stdout
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("Would remove "))
+                    .collect();
+                if !success || paths.is_empty() {
+                    return Ok(skip(success, stderr));
+                }
+                self.stage = Stage::Stash;
+                let mut step = strings(&["stash", "push", "--all", "--message"]);
+                step.push(message);
+                step.push("--".to_string());
+                step.extend(paths.iter().map(|path| path.to_string()));
+                Ok(Next::Run(step))
+            }
+            Stage::Stash if snapshot.kind == Kind::Untracked => match success {
+                true => {
+                    self.stage = Stage::Resolve;
+                    Ok(Next::Run(strings(&[
+                        "stash",
+                        "list",
+                        "--max-count=1",
+                        "--format=%H %s",
+                    ])))
+                }
+                false => Err(format!(
+                    "Could not save the files 'clean' would remove: {}",
+                    stderr.trim()
+                )),
+            },
+            Stage::Stash => {
+                snapshot.worktree = Some(stdout.trim())
+                    .filter(|sha| success && !sha.is_empty())
+                    .map(str::to_string);
+                if !success {
+                    log(&format!(
+                        "Snapshot {} leaves out the working tree: {}",
+                        snapshot.id,
+                        stderr.trim()
+                    ));
+                }
+                if self.only_if_dirty && snapshot.worktree.is_none() {
+                    return Ok(Next::Skip);
+                }
+                self.stage = Stage::Resolve;
+                let (head, index_tree) = match &snapshot.worktree {
+                    Some(worktree) => (
+                        format!("{}^1", worktree),
+                        format!("{}^2^{{tree}}", worktree),
+                    ),
+                    None => ("HEAD".to_string(), "HEAD^{tree}".to_string()),
+                };
+                Ok(Next::Run(vec![
+                    "rev-parse".to_string(),
+                    head,
+                    index_tree,
+                    "--abbrev-ref".to_string(),
+                    "HEAD".to_string(),
+                ]))
+            }
+            Stage::Resolve if snapshot.kind == Kind::Untracked => {
+                // Only drop the entry if it is the one just made
+                let Some(sha) = stdout
+                    .trim()
+                    .split_once(' ')
+                    .filter(|(_, subject)| success && subject.ends_with(&message))
+                    .map(|(sha, _)| sha.to_string())
+                else {
+                    return Ok(skip(success, stderr));
+                };
+                snapshot.untracked = Some(sha.clone());
+                self.stage = Stage::Refs(vec![(reference("untracked"), sha)]);
+                self.next_ref()
+            }
+            Stage::Resolve => {
+                let lines: Vec<&str> = stdout.lines().map(str::trim).collect();
+                let (true, [head, index_tree, branch]) = (success, &lines[..]) else {
+                    return Ok(skip(false, stderr));
+                };
+                snapshot.head = Some(head.to_string());
+                snapshot.index_tree = Some(index_tree.to_string());
+                snapshot.branch = Some(branch.to_string()).filter(|branch| branch != "HEAD");
+                let mut refs = vec![
+                    (reference("head"), head.to_string()),
+                    (reference("index"), index_tree.to_string()),
+                ];
+                if let Some(worktree) = &snapshot.worktree {
+                    refs.push((reference("worktree"), worktree.clone()));
+                }
+                self.stage = Stage::Refs(refs);
+                self.next_ref()
+            }
+            Stage::Stashes => {
+                let entries: Vec<&str> = stdout.lines().map(str::trim).collect();
+                snapshot.stashes = match dropped_entry(&snapshot.command) {
+                    Some(index) => entries.get(index).map(|sha| vec![sha.to_string()]),
+                    None => Some(entries.iter().map(|sha| sha.to_string()).collect()),
+                }
+                .unwrap_or_default();
+                if !success || snapshot.stashes.is_empty() {
+                    return Ok(skip(success, stderr));
+                }
+                let refs = snapshot
+                    .stashes
+                    .iter()
+                    .enumerate()
+                    .map(|(n, sha)| (reference(&format!("stash/{}", n)), sha.clone()))
+                    .collect();
+                self.stage = Stage::Refs(refs);
+                self.next_ref()
+            }
+            Stage::Refs(refs) => match success {
+                true => {
+                    self.stage = Stage::Refs(refs);
+                    self.next_ref()
+                }
+                false => Err(format!(
+                    "Could not record snapshot {}: {}",
+                    snapshot.id,
+                    stderr.trim()
+                )),
+            },
+            Stage::Drop => {
+                if !success {
+                    log(&format!(
+                        "Could not drop the stash entry of snapshot {}: {}",
+                        snapshot.id,
+                        stderr.trim()
+                    ));
+                }
+                Ok(self.prune())
+            }
+            Stage::Prune(refs) => {
+                if !success {
+                    log(&format!(
+                        "Could not prune a snapshot ref: {}",
+                        stderr.trim()
+                    ));
+                }
+                self.stage = Stage::Prune(refs);
+                Ok(self.next_prune())
+            }
+        }
+    }
+
+    /// Delete the refs of the snapshots `MAX_SNAPSHOTS` leaves out, then
+    /// finish.
+    fn prune(&mut self) -> Next {
+        self.stage = Stage::Prune(self.pruned.iter().flat_map(refs).collect());
+        self.next_prune()
+    }
+
+    fn next_prune(&mut self) -> Next {
+        match &mut self.stage {
+            Stage::Prune(refs) if !refs.is_empty() => Next::Run(vec![
+                "update-ref".to_string(),
+                "-d".to_string(),
+                refs.remove(0),
+            ]),
+            _ => Next::Save,
+        }
+    }
+
+    /// Write the next ref, or finish once all are written.
+    fn next_ref(&mut self) -> Result<Next, String> {
+        let Stage::Refs(refs) = &mut self.stage else {
+            return Ok(Next::Save);
+        };
+        if refs.is_empty() {
+            return match self.snapshot.kind {
+                Kind::Untracked => {
+                    self.stage = Stage::Drop;
+                    Ok(Next::Run(strings(&["stash", "drop", "--quiet"])))
+                }
+                _ => Ok(self.prune()),
+            };
+        }
+        let (name, sha) = refs.remove(0);
+        Ok(Next::Run(vec![
+            "update-ref".to_string(),
+            "-m".to_string(),
+            self.message(),
+            name,
+            sha,
+        ]))
+    }
+}
+
+fn skip(success: bool, stderr: &str) -> Next {
+    if !success {
+        log(&format!("Skipping snapshot: {}", stderr.trim()));
+    }
+    Next::Skip
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Index of the entry `stash drop` removes, or `None` for `stash clear`.
+fn dropped_entry(command: &[String]) -> Option<usize> {
+    let index = git::subcommand_index(command)?;
+    if command.get(index + 1).map(String::as_str) != Some("drop") {
+        return None;
+    }
+    let entry = command[index + 2..]
+        .iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map(String::as_str);
+    Some(match entry {
+        None => 0,
+        Some(entry) => entry
+            .strip_prefix("stash@{")
+            .and_then(|entry| entry.strip_suffix('}'))
+            .unwrap_or(entry)
+            .parse()
+            .unwrap_or(0),
+    })
+}
+
+/// Refs that keep `snapshot`, as `advance` wrote them.
+fn refs(snapshot: &Snapshot) -> Vec<String> {
+    let reference = |name: &str| format!("{}/{}/{}", REF_PREFIX, snapshot.id, name);
+    let mut refs = Vec::new();
+    if snapshot.head.is_some() {
+        refs.push(reference("head"));
+        refs.push(reference("index"));
+    }
+    if snapshot.worktree.is_some() {
+        refs.push(reference("worktree"));
+    }
+    if snapshot.untracked.is_some() {
+        refs.push(reference("untracked"));
+    }
+    refs.extend((0..snapshot.stashes.len()).map(|n| reference(&format!("stash/{}", n))));
+    refs
+}
+
+/// Keep the snapshot `progress` took, and drop the metadata of those it
+/// pruned.
+pub fn save(state: &mut State, progress: &Progress) -> Result<(), String> {
+    let snapshot = &progress.snapshot;
+    storage::put(state, &format!("{}{}", LABEL_PREFIX, snapshot.id), snapshot)?;
+    for pruned in &progress.pruned {
+        if let Err(message) = storage::remove(state, &format!("{}{}", LABEL_PREFIX, pruned.id)) {
+            log(&format!(
+                "Failed to prune snapshot {}: {}",
+                pruned.id, message
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Snapshots of `repository_path`, newest first.
+pub fn list(
+    state: &State,
+    repository_path: &str,
+    max_count: usize,
+) -> Result<Vec<Snapshot>, String> {
+    let mut snapshots = Vec::new();
+    for label in storage::labels(state, LABEL_PREFIX)? {
+        if let Some(snapshot) = storage::get::<Snapshot>(state, &label)?
+            && snapshot.repository_path == repository_path
+        {
+            snapshots.push(snapshot);
+        }
+    }
+    snapshots.sort_by(|a, b| b.created_ms.cmp(&a.created_ms).then(b.id.cmp(&a.id)));
+    snapshots.truncate(max_count);
+    Ok(snapshots)
+}
+
+/// Steps of `git-undo`: put back what the snapshot `id` saved, then read
+/// the status. Restoring a working tree discards what changed since, which
+/// takes a snapshot of its own.
+pub fn restore_steps(
+    state: &State,
+    repository_path: &str,
+    id: &str,
+) -> Result<Vec<Vec<String>>, String> {
+    let snapshot = storage::get::<Snapshot>(state, &format!("{}{}", LABEL_PREFIX, id))?
+        .filter(|snapshot| snapshot.repository_path == repository_path)
+        .ok_or_else(|| format!("No snapshot '{}' of this repository", id))?;
+    let branch_head = snapshot
+        .branch
+        .as_deref()
+        .and_then(|branch| cache::branch_head(repository_path, branch));
+    let mut steps = restore(&snapshot, branch_head.as_deref());
+    steps.push(tools::git_args("git-status", &json!({}))?);
+    Ok(steps)
+}
+
+/// Commands that put back what `snapshot` saved. `branch_head` is where
+/// its branch points now; the branch is only moved back when that differs,
+/// so restoring onto a protected branch that has not moved is allowed.
+pub fn restore(snapshot: &Snapshot, branch_head: Option<&str>) -> Vec<Vec<String>> {
+    let mut steps = Vec::new();
+    if let Some(head) = &snapshot.head
+        && snapshot.kind == Kind::WorkingTree
+    {
+        // Detached first, so the branch is not checked out while it moves
+        steps.push(strings(&["checkout", "--force", "--detach", head]));
+        if let Some(branch) = &snapshot.branch {
+            if branch_head != Some(head.as_str()) {
+                let message = format!("Restored by git-mcp snapshot {}", snapshot.id);
+                let reference = format!("refs/heads/{}", branch);
+                steps.push(strings(&["update-ref", "-m", &message, &reference, head]));
+            }
+            steps.push(strings(&["checkout", branch]));
+        }
+    }
+    if let Some(worktree) = &snapshot.worktree {
+        steps.push(strings(&["stash", "apply", "--index", worktree]));
+    }
+    if let Some(untracked) = &snapshot.untracked {
+        steps.push(strings(&["stash", "apply", untracked]));
+    }
+    // Oldest first, so the entries end up in their old order
+    for (n, sha) in snapshot.stashes.iter().enumerate().rev() {
+        let message = format!("Restored by git-mcp snapshot {} (entry {})", snapshot.id, n);
+        steps.push(strings(&["stash", "store", "--message", &message, sha]));
+    }
+    steps
+}
+
+/// A snapshot with every kind of content, for checking which policies
+/// allow `git-undo`.
+pub fn sample() -> Snapshot {
+    Snapshot {
+        id: "x".to_string(),
+        kind: Kind::WorkingTree,
+        repository_path: String::new(),
+        created_ms: 0,
+        command: Vec::new(),
+        branch: Some("x".to_string()),
+        head: Some("x".to_string()),
+        index_tree: None,
+        worktree: Some("x".to_string()),
+        untracked: Some("x".to_string()),
+        stashes: vec!["x".to_string()],
+    }
+}
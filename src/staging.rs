@@ -0,0 +1,151 @@
+use crate::bindings::theater::simple::filesystem::{delete_file, write_file};
+use crate::bindings::theater::simple::random::generate_uuid;
+use crate::bindings::theater::simple::runtime::log;
+use crate::parse;
+use crate::tools;
+use serde_json::Value;
+use serde_json::json;
+
+/// Stands in for the patch file in the `apply` step until the hunks are known.
+const PATCH_FILE: &str = "<patch>";
+
+/// Where the patch goes, relative to the git dir so worktrees get their own,
+/// followed by an id unique to the call so concurrent calls do not share it.
+const PATCH_PREFIX: &str = "git-mcp-hunks-";
+
+/// Hunk ids given to `git-add` or `git-unstage`, if any.
+pub fn hunk_ids(args: &Value) -> Result<Option<Vec<String>>, String> {
+    match args.get("hunks") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(ids)) if !ids.is_empty() => ids
+            .iter()
+            .map(|id| {
+                id.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| "Invalid value for 'hunks': expected hunk ids".to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        Some(_) => {
+            Err("Invalid value for 'hunks': expected a non-empty array of hunk ids".to_string())
+        }
+    }
+}
+
+/// Steps that stage hunks from the unstaged diff, or with `unstage` take
+/// them back out of the staged diff: find the patch file, diff as
+/// `git-diff` does so the ids match, apply the chosen hunks to the index,
+/// then read the status.
+pub fn hunk_steps(args: &Value, unstage: bool) -> Result<Vec<Vec<String>>, String> {
+    let diff_args = json!({
+        "staged": unstage,
+        "context_lines": args.get("context_lines"),
+        "paths": args.get("paths"),
+    });
+    let mut apply = vec!["apply".to_string(), "--cached".to_string()];
+    if unstage {
+        apply.push("--reverse".to_string());
+    }
+    apply.push(PATCH_FILE.to_string());
+    let id = generate_uuid().map_err(|e| format!("Failed to generate patch name: {}", e))?;
+
+    Ok(vec![
+        vec![
+            "rev-parse".to_string(),
+            "--git-path".to_string(),
+            format!("{}{}.patch", PATCH_PREFIX, id),
+        ],
+        tools::git_args("git-diff", &diff_args)?,
+        apply,
+        tools::git_args("git-status", &json!({}))?,
+    ])
+}
+
+/// Fill in `step` from the output of the steps before it: write the patch
+/// of the chosen hunks before `apply` runs, and remove it afterwards.
+pub fn prepare(
+    args: &Value,
+    outputs: &[String],
+    repository_path: &str,
+    mut step: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let Some(patch_path) = outputs.first().map(|output| {
+        let path = output.trim();
+        match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("{}/{}", repository_path.trim_end_matches('/'), path),
+        }
+    }) else {
+        return Ok(step);
+    };
+
+    if let Some(placeholder) = step.iter_mut().find(|arg| *arg == PATCH_FILE) {
+        let ids = hunk_ids(args)?.unwrap_or_default();
+        let patch = patch(outputs.get(1).map(String::as_str).unwrap_or(""), &ids)?;
+        write_file(&patch_path, &patch)
+            .map_err(|e| format!("Failed to write patch '{}': {}", patch_path, e))?;
+        *placeholder = patch_path;
+    } else if let Err(e) = delete_file(&patch_path) {
+        log(&format!("Failed to remove patch '{}': {}", patch_path, e));
+    }
+    Ok(step)
+}
+
+/// Remove the patch the `apply` step `step` was given when that step failed
+/// or never started; otherwise `prepare` removes it before the next step.
+pub fn discard(step: &[String]) {
+    if step.first().map(String::as_str) != Some("apply") {
+        return;
+    }
+    let Some(patch_path) = step.last().filter(|path| {
+        path.rsplit('/')
+            .next()
+            .is_some_and(|name| name.starts_with(PATCH_PREFIX) && name.ends_with(".patch"))
+    }) else {
+        return;
+    };
+    if let Err(e) = delete_file(patch_path) {
+        log(&format!("Failed to remove patch '{}': {}", patch_path, e));
+    }
+}
+
+/// A patch holding only the hunks with the given ids, under their files'
+/// headers.
+fn patch(diff_stdout: &str, ids: &[String]) -> Result<String, String> {
+    let diff = parse::diff(diff_stdout);
+    let unknown: Vec<&str> = ids
+        .iter()
+        .filter(|id| {
+            !diff
+                .files
+                .iter()
+                .any(|file| file.hunks.iter().any(|hunk| &hunk.id == *id))
+        })
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Unknown hunk ids: {}. Hunks change ids when the diff changes; call git-diff again for current ones",
+            unknown.join(", ")
+        ));
+    }
+
+    let mut patch = String::new();
+    for file in &diff.files {
+        let hunks: Vec<&parse::Hunk> = file
+            .hunks
+            .iter()
+            .filter(|hunk| ids.contains(&hunk.id))
+            .collect();
+        if hunks.is_empty() {
+            continue;
+        }
+        patch.push_str(&file.header);
+        for hunk in hunks {
+            patch.push_str(&hunk.header);
+            patch.push('\n');
+            patch.push_str(&hunk.content);
+        }
+    }
+    Ok(patch)
+}
@@ -0,0 +1,62 @@
+use crate::State;
+use crate::bindings::theater::simple::store;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// The actor's content store, created the first time something is saved.
+fn store_id(state: &mut State) -> Result<String, String> {
+    if let Some(store_id) = &state.store_id {
+        return Ok(store_id.clone());
+    }
+    let store_id = store::new().map_err(|e| format!("Failed to create store: {}", e))?;
+    state.store_id = Some(store_id.clone());
+    Ok(store_id)
+}
+
+/// Save `value` as JSON under `label`, replacing what was there.
+pub fn put<T: Serialize>(state: &mut State, label: &str, value: &T) -> Result<(), String> {
+    let store_id = store_id(state)?;
+    let content =
+        serde_json::to_vec(value).map_err(|e| format!("Failed to serialize '{}': {}", label, e))?;
+    store::replace_content_at_label(&store_id, label, &content)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to store '{}': {}", label, e))
+}
+
+/// The value saved under `label`, if any.
+pub fn get<T: DeserializeOwned>(state: &State, label: &str) -> Result<Option<T>, String> {
+    let Some(store_id) = &state.store_id else {
+        return Ok(None);
+    };
+    let Some(content_ref) = store::get_by_label(store_id, label)
+        .map_err(|e| format!("Failed to look up '{}': {}", label, e))?
+    else {
+        return Ok(None);
+    };
+    let content = store::get(store_id, &content_ref)
+        .map_err(|e| format!("Failed to read '{}': {}", label, e))?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| format!("Failed to deserialize '{}': {}", label, e))
+}
+
+/// Remove `label`, if it exists.
+pub fn remove(state: &State, label: &str) -> Result<(), String> {
+    let Some(store_id) = &state.store_id else {
+        return Ok(());
+    };
+    store::remove_label(store_id, label).map_err(|e| format!("Failed to remove '{}': {}", label, e))
+}
+
+/// Labels starting with `prefix`.
+pub fn labels(state: &State, prefix: &str) -> Result<Vec<String>, String> {
+    let Some(store_id) = &state.store_id else {
+        return Ok(Vec::new());
+    };
+    let labels =
+        store::list_labels(store_id).map_err(|e| format!("Failed to list labels: {}", e))?;
+    Ok(labels
+        .into_iter()
+        .filter(|label| label.starts_with(prefix))
+        .collect())
+}
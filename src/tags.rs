@@ -0,0 +1,122 @@
+use crate::commit;
+use crate::parse;
+use crate::semver::{Bump, Version};
+use serde_json::Value;
+use serde_json::json;
+
+/// Stands in for the range of commits since the latest version tag.
+const SINCE_LATEST: &str = "<since-latest>";
+
+/// Tags sorted newest version first, then tags that are not versions by name.
+pub fn listing(stdout: &str, semver_only: bool) -> Value {
+    let mut tags: Vec<(Option<Version>, parse::Tag)> = parse::tags(stdout)
+        .into_iter()
+        .map(|mut tag| {
+            let version = Version::parse(&tag.name);
+            tag.version = version.as_ref().map(|version| {
+                Version {
+                    prefix: String::new(),
+                    ..version.clone()
+                }
+                .to_string()
+            });
+            (version, tag)
+        })
+        .filter(|(version, _)| version.is_some() || !semver_only)
+        .collect();
+    tags.sort_by(|(a, a_tag), (b, b_tag)| match (a, b) {
+        (Some(a), Some(b)) => b.cmp(a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a_tag.name.cmp(&b_tag.name),
+    });
+    json!({ "tags": tags.into_iter().map(|(_, tag)| tag).collect::<Vec<_>>() })
+}
+
+/// Steps of `git-next-version`: the tags reachable from HEAD, then the
+/// commits since the latest version among them.
+pub fn next_version_steps() -> Vec<Vec<String>> {
+    vec![
+        [
+            "for-each-ref",
+            "--merged=HEAD",
+            "--format=%(refname:short)",
+            "refs/tags",
+        ]
+        .map(String::from)
+        .to_vec(),
+        vec![
+            "log".to_string(),
+            parse::LOG_FORMAT.to_string(),
+            SINCE_LATEST.to_string(),
+        ],
+    ]
+}
+
+/// Latest version tag in the output of the first step.
+fn latest(outputs: &[String]) -> Option<(String, Version)> {
+    outputs
+        .first()?
+        .lines()
+        .map(str::trim)
+        .filter_map(|name| Version::parse(name).map(|version| (name.to_string(), version)))
+        .max_by(|(_, a), (_, b)| a.cmp(b))
+}
+
+/// Put the range since the latest version tag into the `log` step.
+pub fn prepare(outputs: &[String], mut step: Vec<String>) -> Vec<String> {
+    if let Some(arg) = step.iter_mut().find(|arg| *arg == SINCE_LATEST) {
+        *arg = match latest(outputs) {
+            Some((tag, _)) => format!("{}..HEAD", tag),
+            None => "HEAD".to_string(),
+        };
+    }
+    step
+}
+
+/// The suggested release: breaking changes bump major, features minor and
+/// fixes or performance work patch. Before 1.0.0 breaking changes bump
+/// minor, as the major version stays at zero until the API settles.
+pub fn next_version(outputs: &[String], log_stdout: &str) -> Value {
+    let latest = latest(outputs);
+    let mut bump = None;
+    let commits: Vec<Value> = parse::log(log_stdout)
+        .into_iter()
+        .map(|entry| {
+            let parsed = commit::conventional(&entry.subject);
+            let breaking = parsed.as_ref().is_some_and(|parsed| parsed.breaking)
+                || entry.body.contains("BREAKING CHANGE:")
+                || entry.body.contains("BREAKING-CHANGE:");
+            let commit_bump = match parsed.as_ref().map(|parsed| parsed.kind.as_str()) {
+                _ if breaking => Some(Bump::Major),
+                Some("feat") => Some(Bump::Minor),
+                Some("fix" | "perf") => Some(Bump::Patch),
+                _ => None,
+            };
+            bump = bump.max(commit_bump);
+            json!({
+                "sha": entry.sha,
+                "subject": entry.subject,
+                "type": parsed.map(|parsed| parsed.kind),
+                "breaking": breaking,
+                "bump": commit_bump.map(Bump::as_str),
+            })
+        })
+        .collect();
+
+    let (bump, next) = match (&latest, bump) {
+        (_, None) => (None, None),
+        (None, Some(bump)) => (Some(bump), Version::parse("0.1.0")),
+        (Some((_, version)), Some(Bump::Major)) if version.major == 0 => {
+            (Some(Bump::Minor), Some(version.bump(Bump::Minor)))
+        }
+        (Some((_, version)), Some(bump)) => (Some(bump), Some(version.bump(bump))),
+    };
+
+    json!({
+        "latest_tag": latest.as_ref().map(|(tag, _)| tag),
+        "bump": bump.map(Bump::as_str),
+        "next_tag": next.as_ref().map(Version::to_string),
+        "commits": commits,
+    })
+}
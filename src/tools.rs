@@ -0,0 +1,1591 @@
+use crate::State;
+use crate::branches;
+use crate::parse;
+use crate::policy::{Hints, Policy};
+use crate::preview;
+use crate::remotes;
+use crate::repos;
+use crate::snapshots;
+use crate::staging;
+use crate::tags;
+use mcp_protocol::tool::{Tool, ToolCallResult, ToolContent};
+use serde::Serialize;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Tools that wrap git commands and return typed results.
+const TYPED_TOOLS: &[&str] = &[
+    "git-status",
+    "git-log",
+    "git-diff",
+    "git-blame",
+    "git-add",
+    "git-unstage",
+    "git-restore",
+    "git-commit",
+    "git-branch-list",
+    "git-branch-create",
+    "git-branch-delete",
+    "git-branch-rename",
+    "git-switch",
+    "git-tag-list",
+    "git-tag-create",
+    "git-next-version",
+    "git-fetch",
+    "git-pull",
+    "git-push",
+    "git-undo",
+];
+
+/// A tool as listed by `tools/list`. `mcp_protocol::tool::Tool` has no
+/// output schema, so it is carried alongside.
+#[derive(Serialize, Debug)]
+pub struct ToolDefinition {
+    #[serde(flatten)]
+    pub tool: Tool,
+    #[serde(rename = "outputSchema", skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
+}
+
+/// A tool result carrying `structuredContent` that matches the tool's
+/// output schema, with the same data as text for clients that ignore it.
+#[derive(Serialize, Debug)]
+pub struct StructuredToolCallResult {
+    #[serde(flatten)]
+    pub result: ToolCallResult,
+    #[serde(rename = "structuredContent")]
+    pub structured_content: Value,
+}
+
+impl StructuredToolCallResult {
+    pub fn new(structured_content: Value) -> Self {
+        let text = serde_json::to_string_pretty(&structured_content)
+            .unwrap_or_else(|_| structured_content.to_string());
+        StructuredToolCallResult {
+            result: ToolCallResult {
+                content: vec![ToolContent::Text { text }],
+                is_error: None,
+            },
+            structured_content,
+        }
+    }
+}
+
+/// Tools offered by `tools/list` for the current configuration.
+pub fn list(state: &State) -> Vec<ToolDefinition> {
+    let mut tools = vec![ToolDefinition {
+        tool: git_command(state),
+        output_schema: None,
+    }];
+    tools.extend(
+        TYPED_TOOLS
+            .iter()
+            .filter_map(|name| typed_tool(name, state)),
+    );
+    tools.extend(list_repositories(state));
+    tools.extend(configure_server(state));
+    tools.push(audit_log(state));
+    tools
+}
+
+pub fn is_typed(name: &str) -> bool {
+    TYPED_TOOLS.contains(&name)
+}
+
+fn git_command(state: &State) -> Tool {
+    let description = match &state.repository_path {
+        Some(repo_path) => format!(
+            "Execute a git command in the configured repository: '{}'. Provide 'args' as an array of strings. You can optionally override the repository by providing 'repository_path'. Example: args: ['status', '--porcelain']",
+            repo_path
+        ),
+        None => "Execute a git command. You must provide both 'repository_path' and 'args' as an array of strings. Example: repository_path: '/path/to/repo', args: ['status', '--porcelain']".to_string(),
+    };
+    let description = match state.repositories.is_empty() {
+        true => description,
+        false => format!(
+            "{} Registered repositories can be named with 'repo' instead of a path.",
+            description
+        ),
+    };
+
+    // The tool may run under any repository's policy
+    let hints = repos::policies(state)
+        .map(Policy::hints)
+        .reduce(Hints::either)
+        .unwrap_or(Hints::READ);
+
+    Tool {
+        name: "git-command".to_string(),
+        description: Some(description),
+        input_schema: input_schema(
+            state,
+            json!({
+                "args" : {
+                    "type": "array",
+                    "items": {
+                        "type": "string"
+                    },
+                    "description": "Array of command-line arguments to pass to git (e.g., ['status', '--porcelain'] or ['--version'])"
+                },
+                "dry_run": dry_run_property()
+            }),
+            vec![],
+        ),
+        annotations: Some(annotations("Run git command", hints)),
+    }
+}
+
+/// `list-repositories`, offered when repositories are registered.
+fn list_repositories(state: &State) -> Option<ToolDefinition> {
+    if state.repositories.is_empty() {
+        return None;
+    }
+    Some(ToolDefinition {
+        tool: Tool {
+            name: "list-repositories".to_string(),
+            description: Some(
+                "List the registered repositories with their current branch and whether they have uncommitted changes."
+                    .to_string(),
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {},
+                "required": [],
+            }),
+            annotations: Some(annotations("List repositories", Hints::READ)),
+        },
+        output_schema: Some(repositories_schema()),
+    })
+}
+
+/// `configure-server`, offered only when the init state grants it.
+fn configure_server(state: &State) -> Option<ToolDefinition> {
+    if !state.capabilities.configure_tool {
+        return None;
+    }
+    Some(ToolDefinition {
+        tool: Tool {
+            name: "configure-server".to_string(),
+            description: Some(
+                "Change the default repository, policies, timeout or output limit. Settings left out keep their current value; the response shows the resulting settings."
+                    .to_string(),
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "repository_path": {
+                        "type": "string",
+                        "description": "New default repository; must be a git repository"
+                    },
+                    "policy": policy_schema(),
+                    "repository_policies": {
+                        "type": "object",
+                        "additionalProperties": policy_schema(),
+                        "description": "Policies for registered repositories, by name"
+                    },
+                    "timeout_ms": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Stop git commands after this many milliseconds"
+                    },
+                    "max_output_bytes": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Most bytes of output returned as text"
+                    }
+                },
+                "required": [],
+                "additionalProperties": false,
+            }),
+            annotations: Some(annotations(
+                "Configure server",
+                Hints {
+                    read_only: false,
+                    destructive: false,
+                    idempotent: true,
+                    open_world: false,
+                },
+            )),
+        },
+        output_schema: None,
+    })
+}
+
+/// `git-audit-log`, which reads the records of past calls from the store.
+fn audit_log(state: &State) -> ToolDefinition {
+    let mut properties = json!({
+        "repository_path": {
+            "type": "string",
+            "description": "Only records of this repository"
+        },
+        "since": {
+            "type": "string",
+            "description": "First UTC date to include, YYYY-MM-DD"
+        },
+        "until": {
+            "type": "string",
+            "description": "Last UTC date to include, YYYY-MM-DD"
+        },
+        "subcommand": {
+            "type": "string",
+            "description": "Only invocations of this git subcommand, e.g. 'push'"
+        },
+        "purpose": {
+            "type": "string",
+            "description": "Only records for this tool, e.g. 'git-command', or internal step such as 'snapshot'"
+        },
+        "decision": {
+            "enum": ["allowed", "approved", "refused"],
+            "description": "Only records with this policy decision"
+        },
+        "request_id": {
+            "description": "Only records of this JSON-RPC request"
+        },
+        "max_count": {
+            "type": "integer",
+            "minimum": 1,
+            "description": "Most records to return, newest first (default 100)"
+        }
+    });
+    if !state.repositories.is_empty() {
+        properties["repo"] = json!({
+            "type": "string",
+            "enum": repos::names(state),
+            "description": "Only records of this registered repository"
+        });
+    }
+    ToolDefinition {
+        tool: Tool {
+            name: "git-audit-log".to_string(),
+            description: Some(
+                "Query the audit log of git commands the server ran or refused, newest first."
+                    .to_string(),
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": [],
+            }),
+            annotations: Some(annotations("Query audit log", Hints::READ)),
+        },
+        output_schema: Some(audit_schema()),
+    }
+}
+
+fn policy_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "read_only": {
+                "type": "boolean",
+                "description": "Only allow subcommands that never modify the repository"
+            },
+            "allowed_subcommands": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "When set, only these subcommands may run"
+            },
+            "blocked_subcommands": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Subcommands that may never run"
+            },
+            "allow_force": {
+                "type": "boolean",
+                "description": "Let typed tools use 'force', e.g. to delete unmerged branches"
+            },
+            "requires_approval": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Subcommands that only run once the configured approver agrees"
+            }
+        }
+    })
+}
+
+/// MCP annotations: a human-readable title plus the behaviour hints clients
+/// use to decide which calls need confirmation.
+fn annotations(title: &str, hints: Hints) -> HashMap<String, Value> {
+    HashMap::from([
+        ("title".to_string(), json!(title)),
+        ("readOnlyHint".to_string(), json!(hints.read_only)),
+        ("destructiveHint".to_string(), json!(hints.destructive)),
+        ("idempotentHint".to_string(), json!(hints.idempotent)),
+        ("openWorldHint".to_string(), json!(hints.open_world)),
+    ])
+}
+
+fn typed_tool(name: &str, state: &State) -> Option<ToolDefinition> {
+    let (title, description, properties, required, output_schema) = match name {
+        "git-status" => (
+            "Repository status",
+            "Show the current branch, its upstream and every changed, untracked or unmerged path.",
+            json!({}),
+            vec![],
+            status_schema(),
+        ),
+        "git-log" => (
+            "Commit history",
+            "List commits reachable from a revision, newest first.",
+            json!({
+                "revision": {
+                    "type": "string",
+                    "description": "Revision or range to list (defaults to 'HEAD')"
+                },
+                "max_count": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Maximum number of commits to return (defaults to 20)"
+                },
+                "skip": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Number of commits to skip before listing"
+                },
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Only list commits touching these paths"
+                }
+            }),
+            vec![],
+            log_schema(),
+        ),
+        "git-diff" => (
+            "Diff changes",
+            "Show changes as files and hunks. Without revisions, compares the working tree with the index, or the index with HEAD when 'staged' is set.",
+            json!({
+                "staged": {
+                    "type": "boolean",
+                    "description": "Diff the index instead of the working tree"
+                },
+                "base": {
+                    "type": "string",
+                    "description": "Revision to diff against"
+                },
+                "target": {
+                    "type": "string",
+                    "description": "Second revision to diff 'base' against"
+                },
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Limit the diff to these paths"
+                },
+                "context_lines": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Lines of context around each change (defaults to 3)"
+                }
+            }),
+            vec![],
+            diff_schema(),
+        ),
+        "git-blame" => (
+            "Blame file",
+            "Show which commit last changed each line of a file.",
+            json!({
+                "path": {
+                    "type": "string",
+                    "description": "File to blame"
+                },
+                "revision": {
+                    "type": "string",
+                    "description": "Revision to blame at (defaults to the working tree)"
+                },
+                "start_line": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "First line to blame"
+                },
+                "end_line": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Last line to blame"
+                }
+            }),
+            vec!["path"],
+            blame_schema(),
+        ),
+        "git-add" => (
+            "Stage changes",
+            "Stage paths or pathspecs, or with 'hunks' only the given hunks of the unstaged diff, using hunk ids from git-diff. Returns the resulting status.",
+            json!({
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Paths or pathspecs to stage; with 'hunks', limits the diff the hunks are taken from"
+                },
+                "hunks": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Ids of hunks from git-diff (without 'staged') to stage"
+                },
+                "context_lines": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Context lines of the git-diff call the hunk ids came from (defaults to 3)"
+                }
+            }),
+            vec![],
+            status_schema(),
+        ),
+        "git-unstage" => (
+            "Unstage changes",
+            "Take paths or pathspecs out of the index, or with 'hunks' only the given hunks of the staged diff, using hunk ids from git-diff with 'staged'. The working tree is left alone. Returns the resulting status.",
+            json!({
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Paths or pathspecs to unstage; with 'hunks', limits the diff the hunks are taken from"
+                },
+                "hunks": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Ids of hunks from git-diff with 'staged' to unstage"
+                },
+                "context_lines": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Context lines of the git-diff call the hunk ids came from (defaults to 3)"
+                }
+            }),
+            vec![],
+            status_schema(),
+        ),
+        "git-restore" => (
+            "Discard changes",
+            "Discard working tree changes to paths or pathspecs, restoring them from the index or from 'source'. Returns the resulting status.",
+            json!({
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Paths or pathspecs to restore"
+                },
+                "source": {
+                    "type": "string",
+                    "description": "Revision to restore from (defaults to the index)"
+                }
+            }),
+            vec!["paths"],
+            status_schema(),
+        ),
+        "git-commit" => (
+            "Commit changes",
+            "Commit the staged changes, staging 'paths' first if given. The message is checked against the configured commit rules before git runs. Returns the new commit and a short stat.",
+            json!({
+                "message": {
+                    "type": "string",
+                    "description": "Commit message: a subject line, then optionally a blank line and a body (required unless 'amend' is set)"
+                },
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Paths to stage before committing"
+                },
+                "amend": {
+                    "type": "boolean",
+                    "description": "Replace the last commit, keeping its message unless 'message' is given"
+                },
+                "allow_empty": {
+                    "type": "boolean",
+                    "description": "Commit even if nothing changed"
+                },
+                "signoff": {
+                    "type": "boolean",
+                    "description": "Add a Signed-off-by trailer"
+                }
+            }),
+            vec![],
+            commit_schema(),
+        ),
+        "git-branch-list" => (
+            "List branches",
+            "List local branches with their tip, upstream, ahead/behind counts, last commit and whether they are merged into the default branch.",
+            json!({
+                "base": {
+                    "type": "string",
+                    "description": "Branch to check merges against (defaults to the remote's default branch, then 'main' or 'master', then HEAD)"
+                }
+            }),
+            vec![],
+            branches_schema(),
+        ),
+        "git-branch-create" => (
+            "Create branch",
+            "Create a branch without switching to it.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "Name of the new branch"
+                },
+                "start_point": {
+                    "type": "string",
+                    "description": "Revision the branch starts at (defaults to HEAD)"
+                }
+            }),
+            vec!["name"],
+            branch_schema(),
+        ),
+        "git-branch-delete" => (
+            "Delete branch",
+            "Delete a branch. Branches that are not merged are refused unless 'force' is set and the policy allows it.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "Branch to delete"
+                },
+                "force": {
+                    "type": "boolean",
+                    "description": "Delete the branch even if it is not merged"
+                }
+            }),
+            vec!["name"],
+            branch_schema(),
+        ),
+        "git-branch-rename" => (
+            "Rename branch",
+            "Rename a branch, by default the current one. Refuses to overwrite an existing branch.",
+            json!({
+                "old_name": {
+                    "type": "string",
+                    "description": "Branch to rename (defaults to the current branch)"
+                },
+                "new_name": {
+                    "type": "string",
+                    "description": "New name for the branch"
+                }
+            }),
+            vec!["new_name"],
+            branch_schema(),
+        ),
+        "git-switch" => (
+            "Switch branch",
+            "Switch to a branch, creating it first with 'create'. Returns the resulting status.",
+            json!({
+                "branch": {
+                    "type": "string",
+                    "description": "Branch to switch to"
+                },
+                "create": {
+                    "type": "boolean",
+                    "description": "Create the branch before switching to it"
+                },
+                "start_point": {
+                    "type": "string",
+                    "description": "Revision a created branch starts at (defaults to HEAD)"
+                }
+            }),
+            vec!["branch"],
+            status_schema(),
+        ),
+        "git-tag-list" => (
+            "List tags",
+            "List tags with the commit they point at and, for annotated tags, the tagger and message. Semantic version tags come first, newest first.",
+            json!({
+                "pattern": {
+                    "type": "string",
+                    "description": "Only list tags matching this glob, e.g. 'v1.*'"
+                },
+                "semver_only": {
+                    "type": "boolean",
+                    "description": "Only list tags that read as semantic versions"
+                }
+            }),
+            vec![],
+            tags_schema(),
+        ),
+        "git-tag-create" => (
+            "Create tag",
+            "Create an annotated tag, or a signed one with 'sign'.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "Name of the tag, e.g. 'v1.2.0'"
+                },
+                "message": {
+                    "type": "string",
+                    "description": "Annotation message"
+                },
+                "target": {
+                    "type": "string",
+                    "description": "Revision to tag (defaults to HEAD)"
+                },
+                "sign": {
+                    "type": "boolean",
+                    "description": "Sign the tag with the configured signing key"
+                }
+            }),
+            vec!["name", "message"],
+            tags_schema(),
+        ),
+        "git-next-version" => (
+            "Suggest next version",
+            "Find the latest semantic version tag reachable from HEAD and suggest the next version from the Conventional Commits types of the commits since: breaking changes bump major, features minor, fixes and performance work patch.",
+            json!({}),
+            vec![],
+            next_version_schema(),
+        ),
+        "git-fetch" => (
+            "Fetch from remote",
+            "Download refs and objects from a remote. Returns every ref that moved, with its old and new sha.",
+            json!({
+                "remote": {
+                    "type": "string",
+                    "description": "Remote to fetch from (defaults to the repository's default remote, or 'origin')"
+                },
+                "prune": {
+                    "type": "boolean",
+                    "description": "Remove tracking branches that no longer exist on the remote"
+                },
+                "tags": {
+                    "type": "boolean",
+                    "description": "Fetch all tags"
+                }
+            }),
+            vec![],
+            updated_refs_schema(),
+        ),
+        "git-pull" => (
+            "Pull from remote",
+            "Fetch from a remote and integrate it into the current branch, fast-forward only unless 'mode' says otherwise. Returns every ref that moved, with its old and new sha.",
+            json!({
+                "remote": {
+                    "type": "string",
+                    "description": "Remote to pull from (defaults to the repository's default remote, or 'origin')"
+                },
+                "branch": {
+                    "type": "string",
+                    "description": "Remote branch to pull (defaults to the upstream of the current branch)"
+                },
+                "mode": {
+                    "enum": ["ff-only", "rebase", "merge"],
+                    "description": "How to integrate: only fast-forward (default), rebase local commits, or merge"
+                }
+            }),
+            vec![],
+            updated_refs_schema(),
+        ),
+        "git-push" => (
+            "Push to remote",
+            "Push a branch to a remote. Protected branches are refused, and a branch that does not fast-forward is only overwritten with 'force_with_lease' when the policy allows it. Returns every ref that moved, with its old and new sha.",
+            json!({
+                "remote": {
+                    "type": "string",
+                    "description": "Remote to push to (defaults to the repository's default remote, or 'origin')"
+                },
+                "branch": {
+                    "type": "string",
+                    "description": "Local branch to push (defaults to the current branch)"
+                },
+                "remote_branch": {
+                    "type": "string",
+                    "description": "Branch to update on the remote (defaults to the same name)"
+                },
+                "set_upstream": {
+                    "type": "boolean",
+                    "description": "Make the pushed branch the upstream of the local one"
+                },
+                "force_with_lease": {
+                    "type": "boolean",
+                    "description": "Overwrite the remote branch if it is still where the tracking branch says"
+                }
+            }),
+            vec![],
+            updated_refs_schema(),
+        ),
+        "git-undo" => (
+            "Undo",
+            "Before reset, rebase, checkout, restore, forced switch, clean, stash drop or stash clear, a snapshot of what the command could lose is kept under refs/mcp-snapshots/. Without 'snapshot', list recent snapshots of the repository, newest first. With it, put that snapshot back: the branch and HEAD, the index and the working tree changes, the untracked files clean removed, or the dropped stash entries. Returns the resulting status.",
+            json!({
+                "snapshot": {
+                    "type": "string",
+                    "description": "Id of the snapshot to restore; omit to list snapshots"
+                },
+                "max_count": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Maximum number of snapshots to list (default 20)"
+                }
+            }),
+            vec![],
+            snapshots_schema(),
+        ),
+        _ => return None,
+    };
+
+    let hints = match name {
+        "git-add" | "git-unstage" => Hints {
+            read_only: false,
+            destructive: false,
+            idempotent: true,
+            open_world: false,
+        },
+        "git-restore" | "git-branch-delete" => Hints {
+            read_only: false,
+            destructive: true,
+            idempotent: true,
+            open_world: false,
+        },
+        "git-branch-create" | "git-branch-rename" | "git-tag-create" => Hints {
+            read_only: false,
+            destructive: false,
+            idempotent: false,
+            open_world: false,
+        },
+        "git-switch" => Hints {
+            read_only: false,
+            destructive: false,
+            idempotent: true,
+            open_world: false,
+        },
+        "git-fetch" => Hints {
+            read_only: false,
+            destructive: false,
+            idempotent: true,
+            open_world: true,
+        },
+        "git-pull" => Hints {
+            read_only: false,
+            destructive: false,
+            idempotent: false,
+            open_world: true,
+        },
+        // With a lease, pushing can overwrite remote commits
+        "git-push" => Hints {
+            read_only: false,
+            destructive: true,
+            idempotent: false,
+            open_world: true,
+        },
+        // Restoring discards what changed since the snapshot
+        "git-undo" => Hints {
+            read_only: false,
+            destructive: true,
+            idempotent: false,
+            open_world: false,
+        },
+        // Amending rewrites the last commit
+        "git-commit" => Hints {
+            read_only: false,
+            destructive: true,
+            idempotent: false,
+            open_world: false,
+        },
+        _ => Hints::READ,
+    };
+    // Tools that write are only offered where some policy lets them run
+    let sample = json!({
+        "message": "x",
+        "paths": ["x"],
+        "name": "x",
+        "new_name": "x",
+        "branch": "x",
+    });
+    let steps = match name {
+        "git-undo" => snapshots::restore(&snapshots::sample(), Some("x")),
+        _ => steps(name, &sample).unwrap_or_default(),
+    };
+    if !hints.read_only
+        && !repos::policies(state).any(|policy| steps.iter().all(|step| policy.check(step).is_ok()))
+    {
+        return None;
+    }
+
+    // Tools that write can preview instead
+    let (mut properties, mut output_schema) = (properties, output_schema);
+    if !hints.read_only {
+        properties["dry_run"] = dry_run_property();
+        output_schema = json!({
+            "type": "object",
+            "anyOf": [output_schema, preview::schema()],
+        });
+    }
+
+    Some(ToolDefinition {
+        tool: Tool {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            input_schema: input_schema(state, properties, required),
+            annotations: Some(annotations(title, hints)),
+        },
+        output_schema: Some(output_schema),
+    })
+}
+
+fn dry_run_property() -> Value {
+    json!({
+        "type": "boolean",
+        "description": "Change nothing; return the files, refs or commits the call would change instead"
+    })
+}
+
+/// Input schema with the shared `repo` and `repository_path` properties added.
+fn input_schema(state: &State, mut properties: Value, mut required: Vec<&str>) -> Value {
+    let registered = !state.repositories.is_empty();
+    properties["repository_path"] = json!({
+        "type": "string",
+        "description": match (&state.repository_path, registered) {
+            (Some(_), _) => "Override the configured repository path (optional)",
+            (None, true) => "The path of the git repository (required unless 'repo' is given)",
+            (None, false) => "The path of the git repository (required)",
+        }
+    });
+    if registered {
+        properties["repo"] = json!({
+            "type": "string",
+            "enum": repos::names(state),
+            "description": "Name of a registered repository, instead of 'repository_path'"
+        });
+    }
+    if state.repository_path.is_none() && !registered {
+        required.push("repository_path");
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// Git commands a call to the typed tool `name` runs, in order. The result
+/// is built from the output of the last one.
+pub fn steps(name: &str, args: &Value) -> Result<Vec<Vec<String>>, String> {
+    match name {
+        "git-commit" => {
+            let mut steps = Vec::new();
+            let paths = paths_arg(args)?;
+            if !paths.is_empty() {
+                let mut add = vec!["add".to_string(), "--".to_string()];
+                add.extend(paths);
+                steps.push(add);
+            }
+            steps.push(git_args(name, args)?);
+            steps.push(
+                [
+                    "log",
+                    "-1",
+                    "--no-color",
+                    parse::COMMIT_FORMAT,
+                    "--shortstat",
+                    "HEAD",
+                ]
+                .map(String::from)
+                .to_vec(),
+            );
+            Ok(steps)
+        }
+        "git-add" | "git-unstage" | "git-restore" => {
+            if name != "git-restore" && staging::hunk_ids(args)?.is_some() {
+                return staging::hunk_steps(args, name == "git-unstage");
+            }
+            Ok(vec![
+                git_args(name, args)?,
+                git_args("git-status", &json!({}))?,
+            ])
+        }
+        "git-branch-list" => Ok(branches::list_steps(revision_arg(args, "base")?)),
+        "git-branch-create" | "git-branch-rename" => Ok(vec![
+            git_args(name, args)?,
+            vec![
+                "rev-parse".to_string(),
+                "--verify".to_string(),
+                format!("refs/heads/{}", created_branch(name, args)?),
+            ],
+        ]),
+        "git-switch" => Ok(vec![
+            git_args(name, args)?,
+            git_args("git-status", &json!({}))?,
+        ]),
+        "git-tag-create" => Ok(vec![
+            git_args(name, args)?,
+            vec![
+                "for-each-ref".to_string(),
+                parse::TAG_FORMAT.to_string(),
+                format!("refs/tags/{}", required_revision(args, "name")?),
+            ],
+        ]),
+        "git-next-version" => Ok(tags::next_version_steps()),
+        "git-fetch" | "git-pull" | "git-push" => Ok(remotes::steps(args, git_args(name, args)?)),
+        _ => Ok(vec![git_args(name, args)?]),
+    }
+}
+
+/// The branch `git-branch-create` or `git-branch-rename` leaves behind.
+fn created_branch<'a>(name: &str, args: &'a Value) -> Result<&'a str, String> {
+    match name {
+        "git-branch-rename" => required_revision(args, "new_name"),
+        _ => required_revision(args, "name"),
+    }
+}
+
+/// The step about to run, completed from the output of the steps before it.
+pub fn prepare(
+    state: &State,
+    name: &str,
+    args: &Value,
+    outputs: &[String],
+    repository_path: &str,
+    step: Vec<String>,
+) -> Result<Vec<String>, String> {
+    match name {
+        "git-add" | "git-unstage" if staging::hunk_ids(args)?.is_some() => {
+            staging::prepare(args, outputs, repository_path, step)
+        }
+        "git-branch-list" => Ok(branches::prepare(
+            revision_arg(args, "base")?,
+            outputs,
+            step,
+        )),
+        "git-next-version" => Ok(tags::prepare(outputs, step)),
+        "git-push" => remotes::prepare(state, args, outputs, step),
+        _ => Ok(step),
+    }
+}
+
+/// Git arguments for a call to the typed tool `name`.
+pub fn git_args(name: &str, args: &Value) -> Result<Vec<String>, String> {
+    let mut git_args: Vec<String> = Vec::new();
+    match name {
+        "git-status" => {
+            git_args.extend(
+                [
+                    "status",
+                    "--porcelain=v2",
+                    "--branch",
+                    "-z",
+                    "--untracked-files=all",
+                ]
+                .map(String::from),
+            );
+        }
+        "git-log" => {
+            let max_count = integer_arg(args, "max_count")?.unwrap_or(20);
+            git_args.extend(["log".to_string(), parse::LOG_FORMAT.to_string()]);
+            git_args.push(format!("--max-count={}", max_count));
+            if let Some(skip) = integer_arg(args, "skip")? {
+                git_args.push(format!("--skip={}", skip));
+            }
+            git_args.push(
+                revision_arg(args, "revision")?
+                    .unwrap_or("HEAD")
+                    .to_string(),
+            );
+            git_args.push("--".to_string());
+            git_args.extend(paths_arg(args)?);
+        }
+        "git-diff" => {
+            git_args.extend(
+                [
+                    "-c",
+                    "core.quotePath=false",
+                    "diff",
+                    "--patch",
+                    "-M",
+                    "--no-color",
+                    "--no-ext-diff",
+                ]
+                .map(String::from),
+            );
+            if let Some(context_lines) = integer_arg(args, "context_lines")? {
+                git_args.push(format!("-U{}", context_lines));
+            }
+            if bool_arg(args, "staged") {
+                git_args.push("--cached".to_string());
+            }
+            let base = revision_arg(args, "base")?;
+            let target = revision_arg(args, "target")?;
+            if target.is_some() && base.is_none() {
+                return Err("'target' requires 'base'".to_string());
+            }
+            git_args.extend(base.into_iter().chain(target).map(str::to_string));
+            git_args.push("--".to_string());
+            git_args.extend(paths_arg(args)?);
+        }
+        "git-blame" => {
+            let path = args
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or("Missing required argument 'path'")?;
+            git_args.extend(["blame", "--porcelain"].map(String::from));
+            match (
+                integer_arg(args, "start_line")?,
+                integer_arg(args, "end_line")?,
+            ) {
+                (Some(start), Some(end)) => git_args.push(format!("-L{},{}", start, end)),
+                (Some(start), None) => git_args.push(format!("-L{},", start)),
+                (None, Some(end)) => git_args.push(format!("-L1,{}", end)),
+                (None, None) => {}
+            }
+            git_args.extend(revision_arg(args, "revision")?.map(str::to_string));
+            git_args.extend(["--".to_string(), path.to_string()]);
+        }
+        "git-add" => {
+            git_args.extend(["add", "--"].map(String::from));
+            git_args.extend(required_paths(args)?);
+        }
+        "git-unstage" => {
+            git_args.extend(["restore", "--staged", "--"].map(String::from));
+            git_args.extend(required_paths(args)?);
+        }
+        "git-restore" => {
+            git_args.extend(["restore", "--worktree"].map(String::from));
+            if let Some(source) = revision_arg(args, "source")? {
+                git_args.push(format!("--source={}", source));
+            }
+            git_args.push("--".to_string());
+            git_args.extend(required_paths(args)?);
+        }
+        "git-branch-create" => {
+            git_args.push("branch".to_string());
+            git_args.push(required_revision(args, "name")?.to_string());
+            git_args.extend(revision_arg(args, "start_point")?.map(str::to_string));
+        }
+        "git-branch-delete" => {
+            git_args.extend(["branch", "--delete"].map(String::from));
+            if bool_arg(args, "force") {
+                git_args.push("--force".to_string());
+            }
+            git_args.push(required_revision(args, "name")?.to_string());
+        }
+        "git-branch-rename" => {
+            git_args.extend(["branch", "--move"].map(String::from));
+            git_args.extend(revision_arg(args, "old_name")?.map(str::to_string));
+            git_args.push(required_revision(args, "new_name")?.to_string());
+        }
+        "git-switch" => {
+            git_args.push("switch".to_string());
+            let start_point = revision_arg(args, "start_point")?;
+            if bool_arg(args, "create") {
+                git_args.push("--create".to_string());
+            } else if start_point.is_some() {
+                return Err("'start_point' requires 'create'".to_string());
+            }
+            git_args.push(required_revision(args, "branch")?.to_string());
+            git_args.extend(start_point.map(str::to_string));
+        }
+        "git-tag-list" => {
+            git_args.extend(["for-each-ref".to_string(), parse::TAG_FORMAT.to_string()]);
+            git_args.push(match revision_arg(args, "pattern")? {
+                Some(pattern) => format!("refs/tags/{}", pattern),
+                None => "refs/tags".to_string(),
+            });
+        }
+        "git-tag-create" => {
+            git_args.push("tag".to_string());
+            match bool_arg(args, "sign") {
+                true => git_args.push("--sign".to_string()),
+                // Whatever `tag.gpgSign` says
+                false => git_args.extend(["--annotate".to_string(), "--no-sign".to_string()]),
+            }
+            let message = args
+                .get("message")
+                .and_then(Value::as_str)
+                .filter(|message| !message.trim().is_empty())
+                .ok_or("Missing required argument 'message'")?;
+            git_args.push(format!("--message={}", message));
+            git_args.push(required_revision(args, "name")?.to_string());
+            git_args.extend(revision_arg(args, "target")?.map(str::to_string));
+        }
+        "git-fetch" => {
+            git_args.push("fetch".to_string());
+            if bool_arg(args, "prune") {
+                git_args.push("--prune".to_string());
+            }
+            if bool_arg(args, "tags") {
+                git_args.push("--tags".to_string());
+            }
+            revision_arg(args, "remote")?;
+            git_args.push(remotes::remote(args).to_string());
+        }
+        "git-pull" => {
+            git_args.push("pull".to_string());
+            git_args.push(
+                match args.get("mode").and_then(Value::as_str).unwrap_or("ff-only") {
+                    "ff-only" => "--ff-only",
+                    "rebase" => "--rebase",
+                    "merge" => "--no-rebase",
+                    mode => {
+                        return Err(format!(
+                            "Invalid value for 'mode': expected 'ff-only', 'rebase' or 'merge', got '{}'",
+                            mode
+                        ));
+                    }
+                }
+                .to_string(),
+            );
+            revision_arg(args, "remote")?;
+            git_args.push(remotes::remote(args).to_string());
+            git_args.extend(revision_arg(args, "branch")?.map(str::to_string));
+        }
+        "git-push" => {
+            git_args.push("push".to_string());
+            if bool_arg(args, "set_upstream") {
+                git_args.push("--set-upstream".to_string());
+            }
+            if bool_arg(args, "force_with_lease") {
+                git_args.push("--force-with-lease".to_string());
+            }
+            revision_arg(args, "remote")?;
+            revision_arg(args, "branch")?;
+            revision_arg(args, "remote_branch")?;
+            git_args.push(remotes::remote(args).to_string());
+            git_args.push(remotes::push_refspec());
+        }
+        "git-commit" => {
+            git_args.push("commit".to_string());
+            let amend = bool_arg(args, "amend");
+            match args.get("message").and_then(Value::as_str) {
+                Some(message) => git_args.push(format!("--message={}", message)),
+                None if amend => git_args.push("--no-edit".to_string()),
+                None => return Err("Missing required argument 'message'".to_string()),
+            }
+            for (name, flag) in [
+                ("amend", "--amend"),
+                ("allow_empty", "--allow-empty"),
+                ("signoff", "--signoff"),
+            ] {
+                if bool_arg(args, name) {
+                    git_args.push(flag.to_string());
+                }
+            }
+        }
+        _ => return Err(format!("Tool '{}' not found", name)),
+    }
+    Ok(git_args)
+}
+
+/// Structured content for the typed tool `name` from its git output.
+pub fn structured(name: &str, args: &Value, outputs: &[String], stdout: &str) -> Value {
+    match name {
+        "git-status" => json!(parse::status(stdout)),
+        "git-log" => json!({ "commits": parse::log(stdout) }),
+        "git-diff" => json!(parse::diff(stdout)),
+        "git-blame" => json!({ "lines": parse::blame(stdout) }),
+        "git-add" | "git-unstage" | "git-restore" | "git-switch" => json!(parse::status(stdout)),
+        "git-branch-list" => {
+            branches::listing(revision_arg(args, "base").ok().flatten(), outputs, stdout)
+        }
+        "git-branch-create" | "git-branch-rename" => json!({
+            "name": created_branch(name, args).unwrap_or_default(),
+            "sha": stdout.trim(),
+        }),
+        "git-branch-delete" => branches::deleted(stdout),
+        "git-tag-list" => tags::listing(stdout, bool_arg(args, "semver_only")),
+        "git-tag-create" => tags::listing(stdout, false),
+        "git-next-version" => tags::next_version(outputs, stdout),
+        "git-fetch" | "git-pull" | "git-push" => remotes::updated(args, outputs, stdout),
+        "git-commit" => json!(parse::commit_summary(stdout)),
+        "git-undo" => json!({
+            "restored": args.get("snapshot"),
+            "status": parse::status(stdout),
+        }),
+        _ => Value::Null,
+    }
+}
+
+/// Revisions are passed straight to git, so refuse anything that would be
+/// parsed as an option.
+fn revision_arg<'a>(args: &'a Value, name: &str) -> Result<Option<&'a str>, String> {
+    match args.get(name).and_then(Value::as_str).map(str::trim) {
+        Some(value) if value.starts_with('-') => Err(format!(
+            "Invalid value for '{}': revisions must not start with '-'",
+            name
+        )),
+        Some("") | None => Ok(None),
+        Some(value) => Ok(Some(value)),
+    }
+}
+
+fn required_revision<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    revision_arg(args, name)?.ok_or_else(|| format!("Missing required argument '{}'", name))
+}
+
+fn bool_arg(args: &Value, name: &str) -> bool {
+    args.get(name).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn integer_arg(args: &Value, name: &str) -> Result<Option<u64>, String> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64().map(Some).ok_or_else(|| {
+            format!(
+                "Invalid value for '{}': expected a non-negative integer",
+                name
+            )
+        }),
+    }
+}
+
+fn paths_arg(args: &Value) -> Result<Vec<String>, String> {
+    match args.get("paths") {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(paths)) => paths
+            .iter()
+            .map(|path| {
+                path.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| "Invalid value for 'paths': expected strings".to_string())
+            })
+            .collect(),
+        Some(_) => Err("Invalid value for 'paths': expected an array of strings".to_string()),
+    }
+}
+
+fn required_paths(args: &Value) -> Result<Vec<String>, String> {
+    let paths = paths_arg(args)?;
+    match paths.is_empty() {
+        true => Err("Missing required argument 'paths'".to_string()),
+        false => Ok(paths),
+    }
+}
+
+fn status_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "branch": {
+                "type": "object",
+                "properties": {
+                    "head": { "type": ["string", "null"], "description": "Current branch, null when detached" },
+                    "oid": { "type": ["string", "null"], "description": "Commit HEAD points at, null before the first commit" },
+                    "upstream": { "type": ["string", "null"] },
+                    "ahead": { "type": ["integer", "null"] },
+                    "behind": { "type": ["integer", "null"] }
+                },
+                "required": ["head", "oid", "upstream", "ahead", "behind"]
+            },
+            "entries": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "original_path": { "type": "string" },
+                        "kind": { "enum": ["changed", "renamed", "unmerged", "untracked", "ignored"] },
+                        "index": { "type": "string", "description": "Index status letter, '.' if unchanged" },
+                        "worktree": { "type": "string", "description": "Working tree status letter, '.' if unchanged" }
+                    },
+                    "required": ["path", "kind", "index", "worktree"]
+                }
+            },
+            "clean": { "type": "boolean" }
+        },
+        "required": ["branch", "entries", "clean"]
+    })
+}
+
+fn repositories_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "repositories": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "path": { "type": "string" },
+                        "description": { "type": "string" },
+                        "default_remote": { "type": "string" },
+                        "branch": { "type": ["string", "null"], "description": "Current branch, null when detached or unknown" },
+                        "upstream": { "type": ["string", "null"] },
+                        "ahead": { "type": ["integer", "null"] },
+                        "behind": { "type": ["integer", "null"] },
+                        "dirty": { "type": ["boolean", "null"], "description": "Whether anything is staged, modified or untracked, null when unknown" },
+                        "error": { "type": "string", "description": "Why the status could not be read" }
+                    },
+                    "required": ["name", "path", "branch", "upstream", "ahead", "behind", "dirty"]
+                }
+            }
+        },
+        "required": ["repositories"]
+    })
+}
+
+fn log_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "commits": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "sha": { "type": "string" },
+                        "parents": { "type": "array", "items": { "type": "string" } },
+                        "author_name": { "type": "string" },
+                        "author_email": { "type": "string" },
+                        "date": { "type": "string", "format": "date-time" },
+                        "subject": { "type": "string" },
+                        "body": { "type": "string" }
+                    },
+                    "required": ["sha", "parents", "author_name", "author_email", "date", "subject", "body"]
+                }
+            }
+        },
+        "required": ["commits"]
+    })
+}
+
+fn diff_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "files": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "old_path": { "type": "string" },
+                        "status": { "enum": ["added", "deleted", "renamed", "modified"] },
+                        "binary": { "type": "boolean" },
+                        "additions": { "type": "integer" },
+                        "deletions": { "type": "integer" },
+                        "hunks": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": { "type": "string" },
+                                    "header": { "type": "string" },
+                                    "old_start": { "type": "integer" },
+                                    "old_lines": { "type": "integer" },
+                                    "new_start": { "type": "integer" },
+                                    "new_lines": { "type": "integer" },
+                                    "content": { "type": "string" }
+                                },
+                                "required": ["id", "header", "old_start", "old_lines", "new_start", "new_lines", "content"]
+                            }
+                        }
+                    },
+                    "required": ["path", "status", "binary", "additions", "deletions", "hunks"]
+                }
+            },
+            "additions": { "type": "integer" },
+            "deletions": { "type": "integer" }
+        },
+        "required": ["files", "additions", "deletions"]
+    })
+}
+
+fn branches_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "base": { "type": "string", "description": "Branch merges were checked against" },
+            "branches": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "sha": { "type": "string" },
+                        "current": { "type": "boolean" },
+                        "upstream": { "type": ["string", "null"] },
+                        "ahead": { "type": ["integer", "null"] },
+                        "behind": { "type": ["integer", "null"] },
+                        "upstream_gone": { "type": "boolean" },
+                        "date": { "type": "string", "format": "date-time" },
+                        "author_name": { "type": "string" },
+                        "author_email": { "type": "string" },
+                        "merged": { "type": "boolean", "description": "Whether the tip is reachable from 'base'" }
+                    },
+                    "required": ["name", "sha", "current", "upstream", "ahead", "behind", "upstream_gone", "date", "author_name", "author_email", "merged"]
+                }
+            }
+        },
+        "required": ["base", "branches"]
+    })
+}
+
+fn branch_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "sha": { "type": "string", "description": "Tip of the branch; abbreviated for a deleted branch" }
+        },
+        "required": ["name", "sha"]
+    })
+}
+
+fn tags_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "tags": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "version": { "type": ["string", "null"], "description": "Semantic version the name reads as, without a 'v' prefix" },
+                        "sha": { "type": "string", "description": "Object the tag points at" },
+                        "annotated": { "type": "boolean" },
+                        "tagger_name": { "type": ["string", "null"] },
+                        "tagger_email": { "type": ["string", "null"] },
+                        "date": { "type": ["string", "null"], "format": "date-time" },
+                        "message": { "type": ["string", "null"] }
+                    },
+                    "required": ["name", "version", "sha", "annotated", "tagger_name", "tagger_email", "date", "message"]
+                }
+            }
+        },
+        "required": ["tags"]
+    })
+}
+
+fn next_version_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "latest_tag": { "type": ["string", "null"], "description": "Latest semantic version tag reachable from HEAD" },
+            "bump": { "enum": ["major", "minor", "patch", null] },
+            "next_tag": { "type": ["string", "null"], "description": "Suggested tag, null when no commit calls for a release" },
+            "commits": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "sha": { "type": "string" },
+                        "subject": { "type": "string" },
+                        "type": { "type": ["string", "null"], "description": "Conventional Commits type" },
+                        "breaking": { "type": "boolean" },
+                        "bump": { "enum": ["major", "minor", "patch", null] }
+                    },
+                    "required": ["sha", "subject", "type", "breaking", "bump"]
+                }
+            }
+        },
+        "required": ["latest_tag", "bump", "next_tag", "commits"]
+    })
+}
+
+fn updated_refs_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "remote": { "type": "string" },
+            "updated_refs": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string", "description": "Full ref name, e.g. 'refs/remotes/origin/main'" },
+                        "old": { "type": ["string", "null"], "description": "Sha before the call, null for a new ref" },
+                        "new": { "type": ["string", "null"], "description": "Sha after the call, null for a deleted ref" }
+                    },
+                    "required": ["name", "old", "new"]
+                }
+            }
+        },
+        "required": ["remote", "updated_refs"]
+    })
+}
+
+fn audit_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "records": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "request_id": { "description": "JSON-RPC id of the request" },
+                        "caller": { "type": ["string", "null"], "description": "Session the request came in on, when known" },
+                        "repository_path": { "type": "string" },
+                        "args": { "type": "array", "items": { "type": "string" } },
+                        "purpose": { "type": "string", "description": "Tool or internal step the invocation was for" },
+                        "decision": { "enum": ["allowed", "approved", "refused"] },
+                        "reason": { "type": ["string", "null"], "description": "Why the call was refused, or why git could not run" },
+                        "started_ms": { "type": "integer", "description": "Milliseconds since the epoch" },
+                        "finished_ms": { "type": ["integer", "null"] },
+                        "exit_code": { "type": ["integer", "null"] },
+                        "success": { "type": ["boolean", "null"] },
+                        "stdout_bytes": { "type": "integer" },
+                        "stderr_bytes": { "type": "integer" }
+                    },
+                    "required": ["request_id", "repository_path", "args", "purpose", "decision", "started_ms"]
+                }
+            }
+        },
+        "required": ["records"]
+    })
+}
+
+fn snapshots_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "snapshots": {
+                "type": "array",
+                "description": "Present when listing",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "kind": { "enum": ["working_tree", "untracked", "stash"] },
+                        "repository_path": { "type": "string" },
+                        "created_ms": { "type": "integer", "description": "Milliseconds since the epoch" },
+                        "command": { "type": "array", "items": { "type": "string" }, "description": "Git arguments the snapshot was taken before" },
+                        "branch": { "type": ["string", "null"], "description": "Checked-out branch, null when detached" },
+                        "head": { "type": ["string", "null"] },
+                        "index_tree": { "type": ["string", "null"] },
+                        "worktree": { "type": ["string", "null"], "description": "Stash-like commit of the index and working tree changes" },
+                        "untracked": { "type": ["string", "null"], "description": "Stash-like commit of the untracked files" },
+                        "stashes": { "type": "array", "items": { "type": "string" }, "description": "Saved stash entries, newest first" }
+                    },
+                    "required": ["id", "kind", "repository_path", "created_ms", "command"]
+                }
+            },
+            "restored": { "type": "string", "description": "Id of the restored snapshot, present when restoring" },
+            "status": {
+                "description": "Status after restoring",
+                "type": "object"
+            }
+        }
+    })
+}
+
+fn commit_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "sha": { "type": "string" },
+            "subject": { "type": "string" },
+            "files_changed": { "type": "integer" },
+            "insertions": { "type": "integer" },
+            "deletions": { "type": "integer" }
+        },
+        "required": ["sha", "subject", "files_changed", "insertions", "deletions"]
+    })
+}
+
+fn blame_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "lines": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "line": { "type": "integer" },
+                        "sha": { "type": "string" },
+                        "author_name": { "type": "string" },
+                        "author_email": { "type": "string" },
+                        "author_time": { "type": "integer", "description": "Seconds since the epoch" },
+                        "summary": { "type": "string" },
+                        "content": { "type": "string" }
+                    },
+                    "required": ["line", "sha", "author_name", "author_email", "author_time", "summary", "content"]
+                }
+            }
+        },
+        "required": ["lines"]
+    })
+}
@@ -0,0 +1,90 @@
+use crate::bindings::theater::simple::http_framework::send_websocket_message;
+use crate::bindings::theater::simple::runtime::log;
+use crate::bindings::theater::simple::websocket_types::{MessageType, WebsocketMessage};
+use crate::session::{self, Session, Transport};
+use crate::{McpRequest, State, error_response};
+use serde_json::Value;
+
+/// Id of the MCP session a connection carries.
+pub fn session_id(connection_id: u64) -> String {
+    format!("websocket-{}", connection_id)
+}
+
+/// Start a session for a new connection. It is initialized by the first
+/// `initialize` request the client sends on it.
+pub fn connect(state: &mut State, connection_id: u64) {
+    log(&format!("WebSocket connection {} opened", connection_id));
+    state.sessions.insert(
+        session_id(connection_id),
+        Session::new(Transport::Websocket { connection_id }),
+    );
+}
+
+pub fn disconnect(state: &mut State, connection_id: u64) {
+    log(&format!("WebSocket connection {} closed", connection_id));
+    state.sessions.remove(&session_id(connection_id));
+}
+
+/// Handle one JSON-RPC message from a connection, returning the messages to
+/// send back right away. Responses that need a git child are sent with
+/// `send` once it exits.
+pub fn message(
+    state: &mut State,
+    connection_id: u64,
+    message: WebsocketMessage,
+) -> Result<Vec<WebsocketMessage>, String> {
+    let text = match message.ty {
+        MessageType::Text => message.text,
+        MessageType::Binary => message.data.and_then(|data| String::from_utf8(data).ok()),
+        _ => return Ok(Vec::new()),
+    };
+    let Some(text) = text else {
+        return Ok(Vec::new());
+    };
+
+    let session_id = session_id(connection_id);
+    if !state.sessions.contains_key(&session_id) {
+        log(&format!(
+            "Message on WebSocket connection {} without a session",
+            connection_id
+        ));
+        return Ok(Vec::new());
+    }
+
+    let response = match serde_json::from_str::<McpRequest>(&text) {
+        Ok(request) => session::dispatch(state, &session_id, session::STANDALONE_STREAM, request)?,
+        Err(_) => Some(error_response(
+            Value::Null,
+            -32700,
+            "Parse error".to_string(),
+        )),
+    };
+
+    let Some(response) = response else {
+        return Ok(Vec::new());
+    };
+    let text = serde_json::to_string(&response)
+        .map_err(|e| format!("Failed to serialize response: {}", e))?;
+    Ok(vec![text_message(text)])
+}
+
+/// Send a message on a connection outside of a reply.
+pub fn send(state: &State, connection_id: u64, message: &Value) -> Result<(), String> {
+    let server = state
+        .http_server
+        .as_ref()
+        .ok_or("The HTTP server is not running")?;
+    send_websocket_message(
+        server.server_id,
+        connection_id,
+        &text_message(message.to_string()),
+    )
+}
+
+fn text_message(text: String) -> WebsocketMessage {
+    WebsocketMessage {
+        ty: MessageType::Text,
+        data: None,
+        text: Some(text),
+    }
+}